@@ -1,8 +1,19 @@
-use std::env;
+use std::{env, fs};
 
 fn main() {
     let linker_file = env::var("LINKER_FILE").unwrap_or_default();
 
     println!("cargo:rerun-if-changed={}", linker_file);
     println!("cargo:rerun-if-changed=build.rs");
+
+    // An archive to build in as initramfs, see src/fs/initramfs.rs
+    println!("cargo:rerun-if-env-changed=INITRAMFS");
+    let initramfs = env::var("INITRAMFS").unwrap_or_default();
+    if !initramfs.is_empty() {
+        let path = fs::canonicalize(&initramfs).expect("INITRAMFS does not exist");
+
+        println!("cargo:rerun-if-changed={}", path.display());
+        println!("cargo:rustc-cfg=initramfs");
+        println!("cargo:rustc-env=INITRAMFS_PATH={}", path.display());
+    }
 }
@@ -0,0 +1,8 @@
+// The kernel's `block`, without the device registry.
+
+#[path = "../../src/block/interface.rs"]
+mod interface;
+#[path = "../../src/block/partition.rs"]
+pub mod partition;
+
+pub use interface::*;
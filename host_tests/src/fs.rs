@@ -0,0 +1,8 @@
+// The kernel's `fs`, without the virtual file system.
+
+#[path = "../../src/fs/fat32.rs"]
+pub mod fat32;
+#[path = "../../src/fs/interface.rs"]
+mod interface;
+
+pub use interface::*;
@@ -0,0 +1,7 @@
+// The kernel's partition table and FAT32 code, built for the host.
+//
+// Of the modules they live in, only the interface files are taken along. All of it is the kernel's
+// own source.
+
+pub mod block;
+pub mod fs;
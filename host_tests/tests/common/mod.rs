@@ -0,0 +1,225 @@
+// Disks in memory, and the tools that make and check FAT images.
+
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+use host_tests::{block::BlockDevice, fs::fat32::Fat32};
+
+pub const SECTOR_SIZE: usize = 512;
+
+// Where the FAT32 partition starts on the disks made here
+pub const PARTITION_START: u64 = 2048;
+
+// The smallest FAT32 has 65525 clusters. With one sector per cluster, files of a few KiB span
+// many of them.
+pub const FAT32_SIZE_KIB: u64 = 40 * 1024;
+
+pub struct MemDisk {
+    data: Mutex<Vec<u8>>,
+}
+
+impl MemDisk {
+    pub fn new(data: Vec<u8>) -> &'static Self {
+        assert_eq!(data.len() % SECTOR_SIZE, 0);
+
+        Box::leak(Box::new(Self {
+            data: Mutex::new(data),
+        }))
+    }
+
+    pub fn bytes(&self, first_block: u64, num_blocks: u64) -> Vec<u8> {
+        let start = first_block as usize * SECTOR_SIZE;
+
+        self.data.lock().unwrap()[start..][..num_blocks as usize * SECTOR_SIZE].to_vec()
+    }
+}
+
+impl BlockDevice for MemDisk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn num_blocks(&self) -> u64 {
+        (self.data.lock().unwrap().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_blocks(&self, first_block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let data = self.data.lock().unwrap();
+        let start = first_block as usize * SECTOR_SIZE;
+
+        buf.copy_from_slice(
+            data.get(start..start + buf.len())
+                .ok_or("Read beyond the end")?,
+        );
+        Ok(())
+    }
+
+    fn write_blocks(&self, first_block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let mut data = self.data.lock().unwrap();
+        let start = first_block as usize * SECTOR_SIZE;
+
+        data.get_mut(start..start + buf.len())
+            .ok_or("Write beyond the end")?
+            .copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+// A FAT32 image file made by mkfs.vfat and filled by mtools.
+pub struct Image {
+    pub path: PathBuf,
+}
+
+// mkfs.vfat and fsck.vfat live in sbin, which is not always in `PATH`.
+fn find_tool(name: &str) -> PathBuf {
+    let path = env::var_os("PATH").unwrap_or_default();
+
+    env::split_paths(&path)
+        .chain(["/sbin", "/usr/sbin"].iter().map(PathBuf::from))
+        .map(|dir| dir.join(name))
+        .find(|tool| tool.is_file())
+        .unwrap_or_else(|| {
+            panic!(
+                "{} not found. The tests need dosfstools and mtools, see README.md",
+                name
+            )
+        })
+}
+
+// Run a tool, and return what it printed. Fails the test if the tool fails.
+pub fn run(name: &str, args: &[&str]) -> Vec<u8> {
+    let output = Command::new(find_tool(name))
+        .args(args)
+        .env("MTOOLS_SKIP_CHECK", "1")
+        .output()
+        .unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+    assert!(
+        output.status.success(),
+        "{} {:?} failed:\n{}{}",
+        name,
+        args,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    output.stdout
+}
+
+impl Image {
+    // An empty FAT32 with one sector per cluster, unique to `name`.
+    pub fn new(name: &str) -> Self {
+        let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.img", name));
+        let _ = fs::remove_file(&path);
+
+        let size = FAT32_SIZE_KIB.to_string();
+        run(
+            "mkfs.vfat",
+            &[
+                "-F",
+                "32",
+                "-s",
+                "1",
+                "-n",
+                "HOSTTEST",
+                "-C",
+                path_str(&path),
+                &size,
+            ],
+        );
+
+        Self { path }
+    }
+
+    fn target(&self, fat_path: &str) -> String {
+        format!("::/{}", fat_path)
+    }
+
+    pub fn mkdir(&self, fat_path: &str) {
+        run("mmd", &["-i", path_str(&self.path), &self.target(fat_path)]);
+    }
+
+    // Copy `contents` to a new file.
+    pub fn put(&self, fat_path: &str, contents: &[u8]) {
+        let source = self.path.with_extension("src");
+        fs::write(&source, contents).unwrap();
+
+        run(
+            "mcopy",
+            &[
+                "-i",
+                path_str(&self.path),
+                path_str(&source),
+                &self.target(fat_path),
+            ],
+        );
+        fs::remove_file(&source).unwrap();
+    }
+
+    // A file's contents, as mtools reads them.
+    pub fn get(&self, fat_path: &str) -> Vec<u8> {
+        run(
+            "mtype",
+            &["-i", path_str(&self.path), &self.target(fat_path)],
+        )
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        fs::read(&self.path).unwrap()
+    }
+
+    // Write back the file system of `disk` that starts at `first_block`, for the tools to check
+    // what the kernel's code did.
+    pub fn update(&self, disk: &MemDisk, first_block: u64) {
+        let num_blocks = FAT32_SIZE_KIB * 1024 / SECTOR_SIZE as u64;
+
+        fs::write(&self.path, disk.bytes(first_block, num_blocks)).unwrap();
+    }
+
+    // Fails the test if fsck.vfat finds anything wrong.
+    pub fn fsck(&self) {
+        run("fsck.vfat", &["-n", path_str(&self.path)]);
+    }
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+// A disk with an MBR that holds `image` as its only partition, of type FAT32 LBA.
+pub fn mbr_disk(image: &Image) -> &'static MemDisk {
+    let fat = image.bytes();
+    let num_blocks = (fat.len() / SECTOR_SIZE) as u32;
+    let mut data = vec![0u8; PARTITION_START as usize * SECTOR_SIZE];
+
+    let entry = &mut data[446..462];
+    entry[4] = 0x0C;
+    entry[8..12].copy_from_slice(&(PARTITION_START as u32).to_le_bytes());
+    entry[12..16].copy_from_slice(&num_blocks.to_le_bytes());
+    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    data.extend_from_slice(&fat);
+    MemDisk::new(data)
+}
+
+// Mount the FAT32 of `image`, on a disk of its own.
+pub fn mount(image: &Image) -> (&'static Fat32, &'static MemDisk) {
+    let disk = MemDisk::new(image.bytes());
+    let fat = Box::leak(Box::new(Fat32::new()));
+
+    fat.mount(disk, 0, disk.num_blocks()).unwrap();
+    (fat, disk)
+}
+
+// Contents that tell where in a file a byte came from, differing between files.
+pub fn pattern(seed: u8, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u32).wrapping_mul(31).wrapping_add(i as u32 >> 8) as u8 ^ seed)
+        .collect()
+}
@@ -0,0 +1,281 @@
+mod common;
+
+use common::{mount, pattern, Image};
+use host_tests::fs::fat32::{DirEntry, Fat32};
+
+// Read all of `file` in pieces of `chunk` bytes, starting at `offset`.
+fn read_from(fat: &Fat32, file: &DirEntry, offset: u64, chunk: usize) -> Vec<u8> {
+    let mut contents = Vec::new();
+    let mut buf = vec![0u8; chunk];
+
+    loop {
+        let len = fat
+            .read(file, offset + contents.len() as u64, &mut buf)
+            .unwrap();
+        if len == 0 {
+            return contents;
+        }
+        contents.extend_from_slice(&buf[..len]);
+    }
+}
+
+fn read_all(fat: &Fat32, path: &str) -> Vec<u8> {
+    let file = fat.lookup(path).unwrap();
+    let contents = read_from(fat, &file, 0, 4096);
+
+    assert_eq!(contents.len(), file.size() as usize);
+    contents
+}
+
+fn list(fat: &Fat32, path: &str) -> Vec<String> {
+    let dir = fat.lookup(path).unwrap();
+    let mut cursor = fat.read_dir(&dir).unwrap();
+    let mut names = Vec::new();
+
+    while let Some(entry) = fat.next_entry(&mut cursor).unwrap() {
+        names.push(entry.name().to_string());
+    }
+    names
+}
+
+#[test]
+fn long_names() {
+    let image = Image::new("long_names");
+    let long = "A file name that is longer than 8.3 and needs several LFN entries.data";
+
+    image.put("ReadMe.Md", b"mixed case");
+    image.put("short.txt", b"short");
+    image.put(long, b"long");
+    image.mkdir("Some Directory");
+    image.mkdir("Some Directory/nested.dir");
+    image.put(
+        "Some Directory/nested.dir/Deeply Nested File.txt",
+        b"nested",
+    );
+
+    let (fat, _) = mount(&image);
+
+    assert_eq!(read_all(fat, "ReadMe.Md"), b"mixed case");
+    assert_eq!(read_all(fat, "short.txt"), b"short");
+    assert_eq!(read_all(fat, long), b"long");
+    assert_eq!(
+        read_all(fat, "Some Directory/nested.dir/Deeply Nested File.txt"),
+        b"nested"
+    );
+
+    // Lookups ignore case, and the short names mtools made up still match
+    assert_eq!(read_all(fat, "README.MD"), b"mixed case");
+    assert_eq!(read_all(fat, &long.to_lowercase()), b"long");
+    assert_eq!(
+        read_all(fat, "SOME DIRECTORY/Nested.Dir/deeply nested file.TXT"),
+        b"nested"
+    );
+    assert_eq!(read_all(fat, "SOMEDI~1/NESTED.DIR/DEEPLY~1.TXT"), b"nested");
+    assert!(fat.lookup("Some Directory/..").unwrap().is_dir());
+    assert_eq!(
+        read_all(fat, "Some Directory/nested.dir/../../short.txt"),
+        b"short"
+    );
+
+    assert!(fat.lookup("missing.txt").is_err());
+    assert!(fat.lookup("short.txt/file").is_err());
+
+    // Listings show the long names as they were written
+    let root = list(fat, "");
+    for name in ["ReadMe.Md", long, "Some Directory"] {
+        assert!(root.iter().any(|n| n == name), "{} not in {:?}", name, root);
+    }
+    assert!(root.iter().any(|n| n.eq_ignore_ascii_case("short.txt")));
+
+    let nested = list(fat, "Some Directory/nested.dir");
+    assert!(nested.iter().any(|n| n == "Deeply Nested File.txt"));
+    assert!(nested.iter().any(|n| n == ".."));
+}
+
+#[test]
+fn fragmented_reads() {
+    let image = Image::new("fragmented_reads");
+    image.put("first.bin", b"");
+    image.put("second.bin", b"");
+    let (fat, disk) = mount(&image);
+
+    // Growing both files in turn interleaves their clusters
+    let cluster_size = fat.cluster_size().unwrap() as usize;
+    let first = pattern(1, 37 * cluster_size + 123);
+    let second = pattern(2, 29 * cluster_size + 7);
+    let mut first_file = fat.lookup("first.bin").unwrap();
+    let mut second_file = fat.lookup("second.bin").unwrap();
+
+    for i in 0.. {
+        let start = i * (cluster_size + 100);
+        if start >= first.len() && start >= second.len() {
+            break;
+        }
+
+        for (file, contents) in [(&mut first_file, &first), (&mut second_file, &second)] {
+            if start < contents.len() {
+                let end = (start + cluster_size + 100).min(contents.len());
+                fat.write(file, start as u64, &contents[start..end])
+                    .unwrap();
+            }
+        }
+    }
+
+    assert_eq!(read_all(fat, "first.bin"), first);
+    assert_eq!(read_all(fat, "second.bin"), second);
+
+    // Reads that start and end anywhere in a cluster
+    for (offset, chunk) in [(1, 511), (cluster_size as u64 - 1, 3), (5000, 1000)] {
+        assert_eq!(
+            read_from(fat, &first_file, offset, chunk),
+            &first[offset as usize..]
+        );
+    }
+    assert!(read_from(fat, &first_file, first.len() as u64 + 10, 16).is_empty());
+
+    image.update(disk, 0);
+    image.fsck();
+    assert_eq!(image.get("first.bin"), first);
+    assert_eq!(image.get("second.bin"), second);
+
+    // Files that mtools wrote around holes left by deleted ones
+    let image = Image::new("fragmented_reads_mtools");
+    let third = pattern(3, 20 * cluster_size);
+    for i in 0..8 {
+        image.put(&format!("filler{}.bin", i), &pattern(i, 3 * cluster_size));
+    }
+    for i in (0..8).step_by(2) {
+        common::run(
+            "mdel",
+            &[
+                "-i",
+                image.path.to_str().unwrap(),
+                &format!("::/filler{}.bin", i),
+            ],
+        );
+    }
+    image.put("third.bin", &third);
+
+    let (fat, _) = mount(&image);
+    assert_eq!(read_all(fat, "third.bin"), third);
+    for i in (1..8).step_by(2) {
+        assert_eq!(
+            read_all(fat, &format!("filler{}.bin", i)),
+            pattern(i, 3 * cluster_size)
+        );
+    }
+}
+
+#[test]
+fn create_write_truncate() {
+    let image = Image::new("create_write_truncate");
+    image.mkdir("dir");
+    image.put("dir/existing.txt", &pattern(4, 10_000));
+    let (fat, disk) = mount(&image);
+
+    let mut empty = fat.create("Empty File.txt").unwrap();
+    assert_eq!(empty.size(), 0);
+    assert!(fat.create("empty file.TXT").is_err());
+    assert!(fat.create("missing/file.txt").is_err());
+
+    // Short name, and a long one in a subdirectory
+    let mut small = fat.create("small.txt").unwrap();
+    fat.write(&mut small, 0, b"Hello, world!\n").unwrap();
+    fat.write(&mut small, 7, b"FAT32").unwrap();
+
+    let mut long = fat.create("dir/A Longer File Name.bin").unwrap();
+    let contents = pattern(5, 9000);
+    fat.write(&mut long, 0, &contents).unwrap();
+
+    // Overwrite across cluster boundaries, then shrink and grow
+    let mut existing = fat.lookup("dir/existing.txt").unwrap();
+    let mut expected = pattern(4, 10_000);
+    expected[500..3000].copy_from_slice(&pattern(6, 2500));
+    fat.write(&mut existing, 500, &pattern(6, 2500)).unwrap();
+
+    fat.truncate(&mut existing, 1500).unwrap();
+    expected.truncate(1500);
+    fat.truncate(&mut existing, 4000).unwrap();
+    expected.resize(4000, 0);
+    assert_eq!(existing.size(), 4000);
+
+    // Writing past the end leaves zeros in between
+    fat.write(&mut empty, 3000, b"tail").unwrap();
+    let mut sparse = vec![0u8; 3000];
+    sparse.extend_from_slice(b"tail");
+
+    // Cut to nothing, which frees all clusters
+    let mut gone = fat.create("gone.bin").unwrap();
+    fat.write(&mut gone, 0, &pattern(7, 5000)).unwrap();
+    fat.truncate(&mut gone, 0).unwrap();
+
+    assert_eq!(read_all(fat, "small.txt"), b"Hello, FAT32!\n");
+    assert_eq!(read_all(fat, "dir/a longer file name.bin"), contents);
+    assert_eq!(read_all(fat, "dir/existing.txt"), expected);
+    assert_eq!(read_all(fat, "Empty File.txt"), sparse);
+    assert_eq!(read_all(fat, "gone.bin"), b"");
+
+    image.update(disk, 0);
+    image.fsck();
+    assert_eq!(image.get("small.txt"), b"Hello, FAT32!\n");
+    assert_eq!(image.get("dir/A Longer File Name.bin"), contents);
+    assert_eq!(image.get("dir/existing.txt"), expected);
+    assert_eq!(image.get("Empty File.txt"), sparse);
+    assert_eq!(image.get("gone.bin"), b"");
+}
+
+#[test]
+fn directory_growth() {
+    let image = Image::new("directory_growth");
+    image.mkdir("many");
+    let (fat, disk) = mount(&image);
+
+    // Each name takes three LFN entries and a short one, so a one sector cluster holds four
+    let names: Vec<_> = (0..100)
+        .map(|i| format!("many/File number {:03} with a long name.txt", i))
+        .collect();
+    for (i, name) in names.iter().enumerate() {
+        let mut file = fat.create(name).unwrap();
+        fat.write(&mut file, 0, format!("{}", i).as_bytes())
+            .unwrap();
+    }
+
+    let listed = list(fat, "many");
+    for (i, name) in names.iter().enumerate() {
+        let name = name.strip_prefix("many/").unwrap();
+        assert!(listed.iter().any(|n| n == name), "{} not listed", name);
+        assert_eq!(read_all(fat, &names[i]), format!("{}", i).as_bytes());
+    }
+
+    image.update(disk, 0);
+    image.fsck();
+    for (i, name) in names.iter().enumerate() {
+        assert_eq!(image.get(name), format!("{}", i).as_bytes());
+    }
+}
+
+#[test]
+fn long_utf8_names() {
+    let image = Image::new("long_utf8_names");
+    let (fat, disk) = mount(&image);
+
+    // Longer in UTF-8 than in UTF-16, up to the longest name there is
+    let names = [
+        format!("{}.txt", "Ω".repeat(200)),
+        format!("{}.txt", "€".repeat(251)),
+    ];
+    for name in &names {
+        let mut file = fat.create(name).unwrap();
+        fat.write(&mut file, 0, name.as_bytes()).unwrap();
+    }
+    assert!(fat.create(&format!("{}.txt", "€".repeat(252))).is_err());
+
+    let listed = list(fat, "");
+    for name in &names {
+        assert!(listed.iter().any(|n| n == name));
+        assert_eq!(read_all(fat, name), name.as_bytes());
+    }
+
+    image.update(disk, 0);
+    image.fsck();
+}
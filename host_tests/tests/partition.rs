@@ -0,0 +1,185 @@
+mod common;
+
+use common::{mbr_disk, Image, MemDisk, PARTITION_START, SECTOR_SIZE};
+use host_tests::{
+    block::partition::{PartitionTable, PartitionType},
+    fs::fat32::Fat32,
+};
+
+const GPT_TYPE_EFI_SYSTEM: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+const GPT_TYPE_LINUX: [u8; 16] = [
+    0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+];
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut [u8], offset: usize, value: u64) {
+    buf[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn mbr_entry(data: &mut [u8], index: usize, partition_type: u8, first_block: u32, num_blocks: u32) {
+    let entry = &mut data[446 + index * 16..][..16];
+
+    entry[4] = partition_type;
+    put_u32(entry, 8, first_block);
+    put_u32(entry, 12, num_blocks);
+    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+}
+
+// A GPT behind a protective MBR, with 128 byte entries from LBA 2. `partitions` are type, first
+// and last block, at entry index 0, 2, 4 and so on, so that unused entries are skipped.
+fn gpt_disk(num_blocks: usize, partitions: &[([u8; 16], u64, u64)]) -> Vec<u8> {
+    let mut data = vec![0u8; num_blocks * SECTOR_SIZE];
+    mbr_entry(&mut data, 0, 0xEE, 1, num_blocks as u32 - 1);
+
+    let header = &mut data[SECTOR_SIZE..2 * SECTOR_SIZE];
+    header[0..8].copy_from_slice(b"EFI PART");
+    put_u64(header, 72, 2);
+    put_u32(header, 80, 128);
+    put_u32(header, 84, 128);
+
+    for (i, &(type_guid, first_block, last_block)) in partitions.iter().enumerate() {
+        let entry = &mut data[2 * SECTOR_SIZE + i * 2 * 128..][..128];
+
+        entry[0..16].copy_from_slice(&type_guid);
+        put_u64(entry, 32, first_block);
+        put_u64(entry, 40, last_block);
+    }
+
+    data
+}
+
+#[test]
+fn mbr_partitions() {
+    let mut data = vec![0u8; 64 * SECTOR_SIZE];
+    mbr_entry(&mut data, 0, 0x0C, 8, 16);
+    mbr_entry(&mut data, 2, 0x83, 24, 40);
+    // Empty entries do not count
+    mbr_entry(&mut data, 3, 0x0B, 64, 0);
+
+    let table = PartitionTable::read(MemDisk::new(data)).unwrap();
+    let partitions: Vec<_> = table.partitions().collect();
+
+    assert!(!table.is_gpt());
+    assert_eq!(partitions.len(), 2);
+
+    assert_eq!(partitions[0].number, 1);
+    assert_eq!(partitions[0].first_block, 8);
+    assert_eq!(partitions[0].num_blocks, 16);
+    assert!(partitions[0].partition_type == PartitionType::Mbr(0x0C));
+    assert!(partitions[0].may_be_fat());
+
+    assert_eq!(partitions[1].number, 3);
+    assert_eq!(partitions[1].first_block, 24);
+    assert_eq!(partitions[1].num_blocks, 40);
+    assert!(!partitions[1].may_be_fat());
+}
+
+#[test]
+fn gpt_partitions() {
+    let data = gpt_disk(
+        128,
+        &[(GPT_TYPE_EFI_SYSTEM, 34, 63), (GPT_TYPE_LINUX, 64, 127)],
+    );
+
+    let table = PartitionTable::read(MemDisk::new(data)).unwrap();
+    let partitions: Vec<_> = table.partitions().collect();
+
+    assert!(table.is_gpt());
+    assert_eq!(partitions.len(), 2);
+
+    assert_eq!(partitions[0].number, 1);
+    assert_eq!(partitions[0].first_block, 34);
+    assert_eq!(partitions[0].num_blocks, 30);
+    assert!(partitions[0].may_be_fat());
+
+    assert_eq!(partitions[1].number, 3);
+    assert_eq!(partitions[1].first_block, 64);
+    assert_eq!(partitions[1].num_blocks, 64);
+    assert!(!partitions[1].may_be_fat());
+}
+
+#[test]
+fn bad_tables() {
+    let data = vec![0u8; 8 * SECTOR_SIZE];
+    assert!(PartitionTable::read(MemDisk::new(data)).is_err());
+
+    // Protective MBR without a GPT header behind it
+    let mut data = vec![0u8; 8 * SECTOR_SIZE];
+    mbr_entry(&mut data, 0, 0xEE, 1, 7);
+    assert!(PartitionTable::read(MemDisk::new(data)).is_err());
+
+    let data = gpt_disk(128, &[(GPT_TYPE_LINUX, 64, 63)]);
+    assert!(PartitionTable::read(MemDisk::new(data)).is_err());
+}
+
+// The FAT32 of a partition, as the kernel finds and mounts it.
+fn mount_first_fat(disk: &'static MemDisk) -> &'static Fat32 {
+    let table = PartitionTable::read(disk).unwrap();
+    let partition = table.partitions().find(|p| p.may_be_fat()).unwrap();
+    let fat = Box::leak(Box::new(Fat32::new()));
+
+    fat.mount(disk, partition.first_block, partition.num_blocks)
+        .unwrap();
+    fat
+}
+
+#[test]
+fn fat32_in_mbr_partition() {
+    let image = Image::new("fat32_in_mbr_partition");
+    image.put("hello.txt", b"Hello from an MBR partition\n");
+
+    let disk = mbr_disk(&image);
+    let fat = mount_first_fat(disk);
+    let file = fat.lookup("hello.txt").unwrap();
+    let mut buf = [0u8; 64];
+    let len = fat.read(&file, 0, &mut buf).unwrap();
+
+    assert_eq!(&buf[..len], b"Hello from an MBR partition\n");
+
+    // Writes stay within the partition
+    let mut file = fat.create("new.txt").unwrap();
+    fat.write(&mut file, 0, b"Written through the partition")
+        .unwrap();
+    assert_eq!(
+        disk.bytes(0, PARTITION_START),
+        mbr_disk(&image).bytes(0, PARTITION_START)
+    );
+
+    image.update(disk, PARTITION_START);
+    image.fsck();
+    assert_eq!(image.get("new.txt"), b"Written through the partition");
+}
+
+#[test]
+fn fat32_in_gpt_partition() {
+    let image = Image::new("fat32_in_gpt_partition");
+    image.put("hello.txt", b"Hello from a GPT partition\n");
+
+    let fat_image = image.bytes();
+    let fat_blocks = (fat_image.len() / SECTOR_SIZE) as u64;
+    let first_block = PARTITION_START;
+    let mut data = gpt_disk(
+        (first_block + fat_blocks) as usize,
+        &[
+            (GPT_TYPE_LINUX, 40, first_block - 1),
+            (
+                GPT_TYPE_EFI_SYSTEM,
+                first_block,
+                first_block + fat_blocks - 1,
+            ),
+        ],
+    );
+    data[first_block as usize * SECTOR_SIZE..].copy_from_slice(&fat_image);
+
+    let fat = mount_first_fat(MemDisk::new(data));
+    let file = fat.lookup("HELLO.TXT").unwrap();
+    let mut buf = [0u8; 64];
+    let len = fat.read(&file, 0, &mut buf).unwrap();
+
+    assert_eq!(&buf[..len], b"Hello from a GPT partition\n");
+}
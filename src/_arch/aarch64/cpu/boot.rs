@@ -1,8 +1,13 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use cortex_a::{asm, registers::*};
-use tock_registers::interfaces::Writeable;
+use tock_registers::interfaces::{Readable, Writeable};
 
 global_asm!(include_str!("boot.s"));
 
+// Physical address of the device tree blob, as handed over by the firmware in `x0`.
+static BOOT_DTB_ADDR: AtomicUsize = AtomicUsize::new(0);
+
 // # Safety
 //
 // - The `bss` section is not initialized yet. The code must not use or reference it in any way.
@@ -15,6 +20,9 @@ unsafe fn prepare_el2_to_el1_transition(phys_boot_core_stack_end_exclusive_addr:
     // No offset for reading the counters
     CNTVOFF_EL2.set(0);
 
+    // Let EL1 use the GICv3 CPU interface system registers, if the core has them
+    enable_el1_gic_sysregs();
+
     // Set EL1 execution state to AArch64
     HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
 
@@ -38,13 +46,56 @@ unsafe fn prepare_el2_to_el1_transition(phys_boot_core_stack_end_exclusive_addr:
     SP_EL1.set(phys_boot_core_stack_end_exclusive_addr);
 }
 
+// Set ICC_SRE_EL2.{Enable, SRE} if ID_AA64PFR0_EL1.GIC reports a GICv3 system register interface.
+// Otherwise, accesses to ICC_SRE_EL1 from EL1 trap to EL2.
+#[inline(always)]
+unsafe fn enable_el1_gic_sysregs() {
+    let pfr0: u64;
+    asm!("mrs {}, ID_AA64PFR0_EL1", out(reg) pfr0, options(nomem, nostack));
+
+    if (pfr0 >> 24) & 0xF != 0 {
+        asm!(
+            "mrs {tmp}, S3_4_C12_C9_5",
+            "orr {tmp}, {tmp}, #(1 << 0)",
+            "orr {tmp}, {tmp}, #(1 << 3)",
+            "msr S3_4_C12_C9_5, {tmp}",
+            "isb",
+            tmp = out(reg) _,
+            options(nostack)
+        );
+    }
+}
+
+// `_start` arrives here in EL2 or EL1. Entry in EL3 has already been dropped to one of them.
+//
 // # Safety
 //
 // - Exception return from EL2 must must continue execution in EL1 with `kernel_init()`.
 #[no_mangle]
-pub unsafe extern "C" fn _start_rust(phys_boot_core_stack_end_exclusive_addr: u64) -> ! {
+pub unsafe extern "C" fn _start_rust(
+    phys_boot_core_stack_end_exclusive_addr: u64,
+    phys_dtb_addr: u64,
+    entry_current_el: u64,
+) -> ! {
+    BOOT_DTB_ADDR.store(phys_dtb_addr as usize, Ordering::Relaxed);
+    crate::exception::record_boot_privilege_level(entry_current_el);
+
+    // Already in EL1, either entered here directly or dropped here from EL3 without an EL2
+    if CurrentEL.matches_all(CurrentEL::EL::EL1) {
+        crate::kernel_init()
+    }
+
     prepare_el2_to_el1_transition(phys_boot_core_stack_end_exclusive_addr);
 
     // Use `eret` to "return" to EL1. This results in execution of kernel_init() in EL1.
     asm::eret()
 }
+
+// The address of the device tree blob the firmware passed at boot, if any. Falls back to where the
+// BSP expects it otherwise.
+pub fn boot_dtb_addr() -> Option<usize> {
+    match BOOT_DTB_ADDR.load(Ordering::Relaxed) {
+        0 => crate::bsp::cpu::FALLBACK_DTB_ADDR,
+        addr => Some(addr),
+    }
+}
@@ -18,9 +18,33 @@
 	add	\register, \register, #:lo12:\symbol
 .endm
 
+.equ _EL1, 0x4
 .equ _EL2, 0x8
+.equ _EL3, 0xC
 .equ _core_id_mask, 0b11
 
+// SCR_EL3: lower ELs are Non-secure (NS) and AArch64 (RW), HVC is enabled (HCE). Bits 5:4 are RES1.
+.equ _SCR_EL3_VALUE, (1 << 10) | (1 << 8) | (0b11 << 4) | (1 << 0)
+
+// ICC_SRE_EL3: system register interface for EL3 (SRE) and the lower ELs (Enable), IRQ and FIQ
+// bypass disabled (DIB, DFB).
+.equ _ICC_SRE_EL3_VALUE, 0b1111
+
+// GICv3 register offsets, from the distributor and from the boot core's redistributor.
+.equ _GICD_TYPER, 0x4
+.equ _GICD_IGROUPR, 0x80
+.equ _GICR_WAKER, 0x14
+.equ _GICR_SGI_BASE, 0x10000
+.equ _GICR_IGROUPR0, 0x80
+
+// SPSR_ELx for an exception return with D, A, I and F masked, to EL2h or EL1h respectively.
+.equ _SPSR_EL2H_MASKED, 0x3c9
+.equ _SPSR_EL1H_MASKED, 0x3c5
+
+// Architecturally safe SCTLR values: MMU and caches off, little endian, RES1 bits set.
+.equ _SCTLR_EL2_VALUE, 0x30c50830
+.equ _SCTLR_EL1_VALUE, 0x30d00800
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -30,11 +54,23 @@
 // fn _start()
 //------------------------------------------------------------------------------
 _start:
-	// Only proceed if the cores executes in EL2. Park it otherwise.
-	mrs x0, CurrentEL
-	cmp x0, _EL2
-	b.ne .L_parking_loop
-	
+	// Preserve the device tree address handed over by the firmware.
+	mov	x19, x0
+
+	// Whatever the previous stage left behind, do not take any exceptions until we are ready.
+	msr	DAIFSet, #0xf
+
+	// Remember the exception level the core was entered in. Park it unless it is one we can boot
+	// from.
+	mrs	x20, CurrentEL
+	cmp	x20, _EL3
+	b.eq	.L_boot_core_check
+	cmp	x20, _EL2
+	b.eq	.L_boot_core_check
+	cmp	x20, _EL1
+	b.ne	.L_parking_loop
+
+.L_boot_core_check:
 	// Only proceed on the boot core. Park it otherwise.
 	mrs	x1, MPIDR_EL1
 	and	x1, x1, _core_id_mask
@@ -44,7 +80,78 @@ _start:
 
 	// If execution reaches here, it is the boot core.
 
+	// Entered in EL2 or EL1, the rest of the transition is done in Rust.
+	cmp	x20, _EL3
+	b.ne	.L_init_dram
+
+	// Entered in EL3. Configure the lower ELs and drop to EL2, or to EL1 if there is no EL2.
+	mov	x0, _SCR_EL3_VALUE
+	msr	SCR_EL3, x0
+
+	// With a GICv3, only EL3 can open the CPU interface system registers to the lower ELs and make
+	// interrupts Non-secure. ID_AA64PFR0_EL1.GIC is zero if the core has no such interface.
+	mrs	x0, ID_AA64PFR0_EL1
+	ubfx	x0, x0, #24, #4
+	cbz	x0, .L_el3_lower_el
+
+	mov	x0, _ICC_SRE_EL3_VALUE
+	msr	S3_6_C12_C12_5, x0    // ICC_SRE_EL3
+	isb
+
+	// Without a distributor to set up, leave the rest to the secure firmware.
+	ldr	x1, BOOT_GICD_START   // provided by bsp/__board_name__/cpu.rs
+	cbz	x1, .L_el3_lower_el
+
+	// All SPIs in Non-secure Group 1. GICD_TYPER.ITLinesNumber is the number of SPI registers.
+	ldr	w2, [x1, _GICD_TYPER]
+	and	w2, w2, #0x1f
+	mov	w3, #0xffffffff
+	add	x4, x1, _GICD_IGROUPR
+.L_el3_gicd_group_loop:
+	cbz	w2, .L_el3_gicr
+	str	w3, [x4, #4]!
+	sub	w2, w2, #1
+	b	.L_el3_gicd_group_loop
+
+	// Wake the boot core's redistributor, which only secure accesses can do, and put its SGIs and
+	// PPIs in Non-secure Group 1 too.
+.L_el3_gicr:
+	ldr	x1, BOOT_GICR_START   // provided by bsp/__board_name__/cpu.rs
+	ldr	w2, [x1, _GICR_WAKER]
+	bic	w2, w2, #(1 << 1)     // ProcessorSleep
+	str	w2, [x1, _GICR_WAKER]
+.L_el3_gicr_wake_loop:
+	ldr	w2, [x1, _GICR_WAKER]
+	tbnz	w2, #2, .L_el3_gicr_wake_loop   // ChildrenAsleep
+
+	add	x1, x1, _GICR_SGI_BASE
+	str	w3, [x1, _GICR_IGROUPR0]
+
+.L_el3_lower_el:
+	// ID_AA64PFR0_EL1.EL2 is zero if EL2 is not implemented.
+	mrs	x0, ID_AA64PFR0_EL1
+	ubfx	x0, x0, #8, #4
+	cbz	x0, .L_el3_to_el1
+
+	ldr	x0, =_SCTLR_EL2_VALUE
+	msr	SCTLR_EL2, x0
+	mov	x0, _SPSR_EL2H_MASKED
+	msr	SPSR_EL3, x0
+	b	.L_el3_eret
+
+.L_el3_to_el1:
+	ldr	x0, =_SCTLR_EL1_VALUE
+	msr	SCTLR_EL1, x0
+	mov	x0, _SPSR_EL1H_MASKED
+	msr	SPSR_EL3, x0
+
+.L_el3_eret:
+	adr	x0, .L_init_dram
+	msr	ELR_EL3, x0
+	eret
+
 	// Initialize DRAM.
+.L_init_dram:
 	ADR_REL	x0, __bss_start
 	ADR_REL x1, __bss_end_exclusive
 
@@ -56,13 +163,22 @@ _start:
 
 	// Prepare the jump to Rust code.
 .L_prepare_rust:
-	// Set the stack pointer.
+	// Set the stack pointer. A hypervisor may have entered EL1 with SP_EL0 selected, so select
+	// SP_ELx first, or exceptions would be taken with the wrong stack pointer.
 	ADR_REL	x0, __boot_core_stack_end_exclusive
+	msr	SPSel, #1
 	mov	sp, x0
 
+	// Pass on the device tree address and the entry exception level.
+	mov	x1, x19
+	mov	x2, x20
+
 	// Jump to Rust code.
 	b	_start_rust
 
+	// Literal pool for the `ldr =` pseudo-instructions above.
+.ltorg
+
 	// Infinitely wait for events (aka "park the core").
 .L_parking_loop:
 	wfe
@@ -1,12 +1,13 @@
 use core::cell::UnsafeCell;
 use core::fmt::{self, Display, Formatter};
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use cortex_a::registers::CurrentEL;
 use cortex_a::{asm::barrier, registers::*};
 use tock_registers::interfaces::Writeable;
 use tock_registers::{interfaces::Readable, registers::InMemoryRegister};
 
-use crate::exception::PrivilegeLevel;
+use crate::exception::{self, PrivilegeLevel};
 
 global_asm!(include_str!("exception.s"));
 
@@ -153,16 +154,36 @@ impl Display for ExceptionContext {
     }
 }
 
+// The raw CurrentEL value the boot core was entered with, before dropping to EL1.
+static BOOT_CURRENT_EL: AtomicU64 = AtomicU64::new(0);
+
+// The name tells the level the kernel was entered in by the previous boot stage, where that
+// differs.
 pub fn current_privillege_level() -> (PrivilegeLevel, &'static str) {
-    let el = CurrentEL.read_as_enum(CurrentEL::EL);
-    match el {
-        Some(CurrentEL::EL::Value::EL2) => (PrivilegeLevel::Hypervisor, "EL2"),
-        Some(CurrentEL::EL::Value::EL1) => (PrivilegeLevel::Kernel, "EL1"),
-        Some(CurrentEL::EL::Value::EL0) => (PrivilegeLevel::User, "EL0"),
+    use CurrentEL::EL::Value::*;
+
+    let entry_el =
+        InMemoryRegister::<u64, CurrentEL::Register>::new(BOOT_CURRENT_EL.load(Ordering::Relaxed));
+
+    match (
+        CurrentEL.read_as_enum(CurrentEL::EL),
+        entry_el.read_as_enum(CurrentEL::EL),
+    ) {
+        (Some(EL1), Some(EL3)) => (PrivilegeLevel::Kernel, "EL1, entered in EL3"),
+        (Some(EL1), Some(EL2)) => (PrivilegeLevel::Kernel, "EL1, entered in EL2"),
+        (Some(EL3), _) => (PrivilegeLevel::Monitor, "EL3"),
+        (Some(EL2), _) => (PrivilegeLevel::Hypervisor, "EL2"),
+        (Some(EL1), _) => (PrivilegeLevel::Kernel, "EL1"),
+        (Some(EL0), _) => (PrivilegeLevel::User, "EL0"),
         _ => (PrivilegeLevel::Unknown, "Unknown"),
     }
 }
 
+// Called once by the boot code with the CurrentEL value found at entry.
+pub fn record_boot_privilege_level(current_el: u64) {
+    BOOT_CURRENT_EL.store(current_el, Ordering::Relaxed);
+}
+
 // Now for the Exception Handlers!
 
 // Default - when we have no real handler
@@ -216,8 +237,9 @@ unsafe extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
 }
 
 #[no_mangle]
-unsafe extern "C" fn current_elx_irq(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+unsafe extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
+    let token = &exception::asynchronous::IRQContext::new();
+    exception::asynchronous::irq_manager().handle_pending_irqs(token);
 }
 
 #[no_mangle]
@@ -0,0 +1,143 @@
+// Smallest data cache line size of all caches, from CTR_EL0.DminLine.
+pub fn dcache_line_size() -> usize {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem, nostack)) };
+
+    4 << ((ctr >> 16) & 0xF)
+}
+
+// Smallest instruction cache line size, from CTR_EL0.IminLine.
+pub fn icache_line_size() -> usize {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem, nostack)) };
+
+    4 << (ctr & 0xF)
+}
+
+// Run `op` on every `line_size` line that `start..start + size` touches.
+fn for_each_line_of(line_size: usize, start: usize, size: usize, op: impl Fn(usize)) {
+    let end = start + size;
+    let mut addr = start & !(line_size - 1);
+
+    while addr < end {
+        op(addr);
+        addr += line_size;
+    }
+}
+
+// Run `op` on every cache line that `start..start + size` touches, then wait for all of them to
+// complete.
+fn for_each_line(start: usize, size: usize, op: impl Fn(usize)) {
+    for_each_line_of(dcache_line_size(), start, size, op);
+
+    unsafe { asm!("dsb sy", options(nostack)) };
+}
+
+// Run `op` on every set and way of the data and unified caches up to the point of coherency, as
+// the `dc *sw` instructions take them. Only the caches of this core and its cluster are affected.
+fn for_each_set_way(op: impl Fn(u64)) {
+    let clidr: u64;
+    unsafe { asm!("mrs {}, CLIDR_EL1", out(reg) clidr, options(nomem, nostack)) };
+
+    // Level of Coherency
+    let loc = (clidr >> 24) & 0x7;
+
+    for level in 0..loc {
+        // 0 is no cache and 1 an instruction cache only
+        if (clidr >> (level * 3)) & 0x7 < 2 {
+            continue;
+        }
+
+        let ccsidr: u64;
+        unsafe {
+            asm!(
+                "msr CSSELR_EL1, {}",
+                "isb",
+                "mrs {}, CCSIDR_EL1",
+                in(reg) level << 1,
+                out(reg) ccsidr,
+                options(nostack),
+            )
+        };
+
+        let line_shift = (ccsidr & 0x7) + 4;
+        let ways = ((ccsidr >> 3) & 0x3FF) + 1;
+        let sets = ((ccsidr >> 13) & 0x7FFF) + 1;
+        // The way goes in the top bits, as many as it takes
+        let way_shift = (ways as u32 - 1).leading_zeros();
+
+        for way in 0..ways {
+            for set in 0..sets {
+                op(way << way_shift | set << line_shift | level << 1);
+            }
+        }
+    }
+
+    unsafe { asm!("dsb sy", "isb", options(nostack)) };
+}
+
+// Write dirty lines of the range back to memory.
+pub fn clean_dcache_range(start: usize, size: usize) {
+    for_each_line(start, size, |addr| unsafe {
+        asm!("dc cvac, {}", in(reg) addr, options(nostack))
+    });
+}
+
+// Write dirty lines of the range back to memory and drop them from the caches.
+pub fn clean_invalidate_dcache_range(start: usize, size: usize) {
+    for_each_line(start, size, |addr| unsafe {
+        asm!("dc civac, {}", in(reg) addr, options(nostack))
+    });
+}
+
+/// Drop the lines of the range from the caches, without writing them back.
+///
+/// # Safety
+///
+/// - Writes to the range that are still in the caches are lost. So are writes to anything else in
+///   the first and the last cache line, unless the range is aligned to cache lines.
+pub unsafe fn invalidate_dcache_range(start: usize, size: usize) {
+    for_each_line(
+        start,
+        size,
+        |addr| asm!("dc ivac, {}", in(reg) addr, options(nostack)),
+    );
+}
+
+// Write all dirty lines of this core's data caches back to memory.
+pub fn clean_dcache_all() {
+    for_each_set_way(|set_way| unsafe { asm!("dc csw, {}", in(reg) set_way, options(nostack)) });
+}
+
+// Write all dirty lines of this core's data caches back to memory and empty the caches.
+pub fn clean_invalidate_dcache_all() {
+    for_each_set_way(|set_way| unsafe { asm!("dc cisw, {}", in(reg) set_way, options(nostack)) });
+}
+
+/// Empty this core's data caches, without writing anything back.
+///
+/// # Safety
+///
+/// - Every write that is still in the caches is lost. Only meant for before the caches are on.
+pub unsafe fn invalidate_dcache_all() {
+    for_each_set_way(|set_way| asm!("dc isw, {}", in(reg) set_way, options(nostack)));
+}
+
+// Drop everything from the instruction caches of all cores.
+pub fn invalidate_icache_all() {
+    unsafe { asm!("ic ialluis", "dsb ish", "isb", options(nostack)) };
+}
+
+// Make instructions written to the range visible to instruction fetches: the data goes out to
+// where the instruction caches fetch from, and stale instructions of the range are dropped.
+pub fn sync_icache_range(start: usize, size: usize) {
+    for_each_line_of(dcache_line_size(), start, size, |addr| unsafe {
+        asm!("dc cvau, {}", in(reg) addr, options(nostack))
+    });
+    unsafe { asm!("dsb ish", options(nostack)) };
+
+    for_each_line_of(icache_line_size(), start, size, |addr| unsafe {
+        asm!("ic ivau, {}", in(reg) addr, options(nostack))
+    });
+    unsafe { asm!("dsb ish", "isb", options(nostack)) };
+}
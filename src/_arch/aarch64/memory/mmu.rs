@@ -101,6 +101,12 @@ impl memory::mmu::MMU for MemoryManagementUnit {
 
         self.configure_translation_control();
 
+        // Nothing the caches or the TLBs might hold from before reset or from the firmware is to
+        // show up once they are on
+        memory::cache::invalidate_dcache_all();
+        memory::cache::invalidate_icache_all();
+        memory::mmu::tlb::invalidate_all();
+
         // Switch the MMU on
         // First, force all previous changes to be seen before the MMU is enabled
         barrier::isb(barrier::SY);
@@ -0,0 +1,45 @@
+// Drop all translations of the EL1&0 regime.
+pub fn invalidate_all() {
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vmalle1is",
+            "dsb ish",
+            "isb",
+            options(nostack)
+        )
+    };
+}
+
+// Drop the translations of the page at `addr`, for all ASIDs. Global ones go as well.
+pub fn invalidate_va(addr: usize) {
+    // The page number goes in the bottom bits, with 4 KiB pages whatever the granule
+    let operand = (addr >> 12) & 0xFFF_FFFF_FFFF;
+
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vaae1is, {}",
+            "dsb ish",
+            "isb",
+            in(reg) operand,
+            options(nostack),
+        )
+    };
+}
+
+// Drop the non-global translations of the address space `asid`.
+pub fn invalidate_asid(asid: u16) {
+    let operand = u64::from(asid) << 48;
+
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi aside1is, {}",
+            "dsb ish",
+            "isb",
+            in(reg) operand,
+            options(nostack),
+        )
+    };
+}
@@ -0,0 +1,95 @@
+// Block devices.
+//
+// Drivers for storage hardware implement `BlockDevice`. The BSP registers the devices it found
+// under a short name, and the rest of the kernel looks them up here.
+
+pub mod partition;
+
+use spin::Mutex;
+
+use crate::kinfo;
+
+mod interface;
+
+pub use interface::*;
+
+const MAX_BLOCK_DEVICES: usize = 4;
+
+#[derive(Clone, Copy)]
+struct RegisteredDevice {
+    name: &'static str,
+    device: &'static (dyn BlockDevice + Sync),
+}
+
+static BLOCK_DEVICES: Mutex<[Option<RegisteredDevice>; MAX_BLOCK_DEVICES]> =
+    Mutex::new([None; MAX_BLOCK_DEVICES]);
+
+// Check that a transfer of `len` bytes starting at `first_block` fits the device.
+pub fn check_request(
+    device: &dyn BlockDevice,
+    first_block: u64,
+    len: usize,
+) -> Result<(), &'static str> {
+    if len % device.block_size() != 0 {
+        return Err("Buffer length is not a multiple of the block size");
+    }
+
+    let num_blocks = (len / device.block_size()) as u64;
+    match first_block.checked_add(num_blocks) {
+        Some(end) if end <= device.num_blocks() => Ok(()),
+        _ => Err("Request beyond the end of the device"),
+    }
+}
+
+// Make `device` known under `name`.
+pub fn register_block_device(
+    name: &'static str,
+    device: &'static (dyn BlockDevice + Sync),
+) -> Result<(), &'static str> {
+    let mut devices = BLOCK_DEVICES.lock();
+
+    if devices.iter().flatten().any(|d| d.name == name) {
+        return Err("Block device name already taken");
+    }
+
+    match devices.iter_mut().find(|d| d.is_none()) {
+        Some(slot) => {
+            *slot = Some(RegisteredDevice { name, device });
+            Ok(())
+        }
+        None => Err("Too many block devices"),
+    }
+}
+
+// Look up a block device by name.
+pub fn block_device(name: &str) -> Option<&'static (dyn BlockDevice + Sync)> {
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .flatten()
+        .find(|d| d.name == name)
+        .map(|d| d.device)
+}
+
+// Call `f` for every registered block device. The registry is not locked while `f` runs.
+pub fn for_each_block_device(mut f: impl FnMut(&'static str, &'static (dyn BlockDevice + Sync))) {
+    let devices = *BLOCK_DEVICES.lock();
+
+    for d in devices.iter().flatten() {
+        f(d.name, d.device);
+    }
+}
+
+pub fn print_block_devices() {
+    for d in BLOCK_DEVICES.lock().iter().flatten() {
+        let size = d.device.num_blocks() * d.device.block_size() as u64;
+
+        kinfo!(
+            "      {}: {} blocks of {} bytes ({} MiB)",
+            d.name,
+            d.device.num_blocks(),
+            d.device.block_size(),
+            size / (1024 * 1024)
+        );
+    }
+}
@@ -0,0 +1,20 @@
+// The interface of block device drivers.
+//
+// Kept in a file of its own, so that the host-side tests build disks against it.
+
+// A device that is read and written in fixed-size blocks.
+pub trait BlockDevice {
+    // Size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    // Number of blocks on the device.
+    fn num_blocks(&self) -> u64;
+
+    // Read consecutive blocks, starting at `first_block`, into `buf`. The length of `buf` must
+    // be a multiple of the block size.
+    fn read_blocks(&self, first_block: u64, buf: &mut [u8]) -> Result<(), &'static str>;
+
+    // Write `buf` to consecutive blocks, starting at `first_block`. The length of `buf` must be
+    // a multiple of the block size.
+    fn write_blocks(&self, first_block: u64, buf: &[u8]) -> Result<(), &'static str>;
+}
@@ -0,0 +1,180 @@
+// MBR and GPT partition tables.
+//
+// A protective MBR, i.e. one with a single partition of type 0xEE, defers to the GPT behind it.
+// Extended MBR partitions are not followed.
+
+use super::BlockDevice;
+
+const SECTOR_SIZE: usize = 512;
+
+pub const MAX_PARTITIONS: usize = 16;
+
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_LBA: u64 = 1;
+
+// Partition type GUIDs, in their on-disk byte order.
+const GPT_TYPE_EFI_SYSTEM: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+const GPT_TYPE_BASIC_DATA: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    Mbr(u8),
+    Gpt([u8; 16]),
+}
+
+#[derive(Clone, Copy)]
+pub struct Partition {
+    // Index in the partition table, starting at 1.
+    pub number: usize,
+    pub first_block: u64,
+    pub num_blocks: u64,
+    pub partition_type: PartitionType,
+}
+
+pub struct PartitionTable {
+    partitions: [Option<Partition>; MAX_PARTITIONS],
+    is_gpt: bool,
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    (u32_at(buf, offset + 4) as u64) << 32 | u32_at(buf, offset) as u64
+}
+
+impl Partition {
+    // Whether the partition type is one that usually holds a FAT file system.
+    pub fn may_be_fat(&self) -> bool {
+        match self.partition_type {
+            // FAT12, FAT16, FAT32 (CHS and LBA), FAT16 LBA
+            PartitionType::Mbr(t) => matches!(t, 0x01 | 0x04 | 0x06 | 0x0B | 0x0C | 0x0E),
+            PartitionType::Gpt(guid) => guid == GPT_TYPE_EFI_SYSTEM || guid == GPT_TYPE_BASIC_DATA,
+        }
+    }
+}
+
+impl PartitionTable {
+    // Read the partition table of `device`.
+    pub fn read(device: &dyn BlockDevice) -> Result<Self, &'static str> {
+        if device.block_size() != SECTOR_SIZE {
+            return Err("Partition tables need 512 byte blocks");
+        }
+
+        let mut sector = [0u8; SECTOR_SIZE];
+        device.read_blocks(0, &mut sector)?;
+
+        if sector[510..512] != MBR_SIGNATURE {
+            return Err("No partition table");
+        }
+
+        let mut table = Self {
+            partitions: [None; MAX_PARTITIONS],
+            is_gpt: false,
+        };
+
+        for i in 0..4 {
+            let entry = &sector[MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+            let partition_type = entry[4];
+            let first_block = u32_at(entry, 8) as u64;
+            let num_blocks = u32_at(entry, 12) as u64;
+
+            if partition_type == MBR_TYPE_GPT_PROTECTIVE {
+                table.read_gpt(device)?;
+                return Ok(table);
+            }
+
+            if partition_type != 0 && num_blocks != 0 {
+                table.partitions[i] = Some(Partition {
+                    number: i + 1,
+                    first_block,
+                    num_blocks,
+                    partition_type: PartitionType::Mbr(partition_type),
+                });
+            }
+        }
+
+        Ok(table)
+    }
+
+    fn read_gpt(&mut self, device: &dyn BlockDevice) -> Result<(), &'static str> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        device.read_blocks(GPT_HEADER_LBA, &mut sector)?;
+
+        if &sector[0..8] != GPT_SIGNATURE {
+            return Err("Bad GPT header");
+        }
+
+        let entries_lba = u64_at(&sector, 72);
+        let num_entries = u32_at(&sector, 80) as usize;
+        let entry_size = u32_at(&sector, 84) as usize;
+
+        if entry_size < 128 || SECTOR_SIZE % entry_size != 0 {
+            return Err("Unsupported GPT entry size");
+        }
+
+        self.is_gpt = true;
+
+        let entries_per_sector = SECTOR_SIZE / entry_size;
+        let mut found = 0;
+
+        for i in 0..num_entries {
+            if found == MAX_PARTITIONS {
+                break;
+            }
+
+            if i % entries_per_sector == 0 {
+                let lba = entries_lba + (i / entries_per_sector) as u64;
+                device.read_blocks(lba, &mut sector)?;
+            }
+
+            let entry = &sector[(i % entries_per_sector) * entry_size..][..entry_size];
+            let mut type_guid = [0u8; 16];
+            type_guid.copy_from_slice(&entry[0..16]);
+
+            // Unused entry
+            if type_guid == [0; 16] {
+                continue;
+            }
+
+            let first_block = u64_at(entry, 32);
+            let last_block = u64_at(entry, 40);
+            if last_block < first_block {
+                return Err("Bad GPT partition entry");
+            }
+
+            self.partitions[found] = Some(Partition {
+                number: i + 1,
+                first_block,
+                num_blocks: last_block - first_block + 1,
+                partition_type: PartitionType::Gpt(type_guid),
+            });
+            found += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_gpt(&self) -> bool {
+        self.is_gpt
+    }
+
+    pub fn partitions(&self) -> impl Iterator<Item = &Partition> {
+        self.partitions.iter().flatten()
+    }
+}
@@ -0,0 +1,432 @@
+// Kernel command line and typed boot options.
+//
+// The command line is taken from the chainloader if it supplied one, otherwise from the
+// `/chosen/bootargs` property of the device tree the firmware handed over. It is a whitespace
+// separated list of `key=value` pairs; a bare `key` is shorthand for `key=on`. Values may be
+// double-quoted to include whitespace. Later occurrences of a key override earlier ones, and
+// keys nobody registered are ignored, since the firmware adds plenty of its own.
+//
+// Subsystems declare their options with `boot_option!`, which also registers them so that
+// `bootargs::init()` can fill them in and `bootargs::print_summary()` can list them.
+
+use core::{cell::UnsafeCell, fmt, mem::size_of, slice, str, time::Duration};
+
+use spin::Mutex;
+
+use crate::{fdt, kinfo, kwarn};
+
+const CMDLINE_MAX_LEN: usize = 1024;
+
+const CHAINLOADER_MAGIC: [u8; 8] = *b"PIEDARGS";
+
+// Where a chainloader can put a command line for the kernel.
+//
+// The chainloader locates the magic in the kernel image, then fills in `len` and `cmdline`
+// before jumping to the kernel. An untouched image has `len == 0`.
+#[repr(C)]
+pub struct ChainloaderCmdline {
+    magic: [u8; 8],
+    len: u32,
+    cmdline: [u8; CMDLINE_MAX_LEN],
+}
+
+#[no_mangle]
+#[link_section = ".text._start_arguments"]
+pub static CHAINLOADER_CMDLINE: ChainloaderCmdline = ChainloaderCmdline {
+    magic: CHAINLOADER_MAGIC,
+    len: 0,
+    cmdline: [0; CMDLINE_MAX_LEN],
+};
+
+// Where the effective command line came from.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CmdlineSource {
+    None,
+    Chainloader,
+    DeviceTree,
+}
+
+struct Cmdline {
+    buf: [u8; CMDLINE_MAX_LEN],
+    len: usize,
+    source: CmdlineSource,
+}
+
+// Written once by `init()`, read-only afterwards.
+static mut CMDLINE: Cmdline = Cmdline {
+    buf: [0; CMDLINE_MAX_LEN],
+    len: 0,
+    source: CmdlineSource::None,
+};
+
+#[derive(Clone, Copy)]
+enum OptionState<T> {
+    Default,
+    Set(T),
+    Invalid,
+}
+
+// A typed boot option with a default value.
+pub struct BootOption<T> {
+    name: &'static str,
+    default: T,
+    state: Mutex<OptionState<T>>,
+}
+
+// Helper to use `BootOptionValue::fmt_value()` with the formatting macros.
+struct ValueFmt<'a, T>(&'a T);
+
+mod interface {
+    use core::fmt;
+
+    // A type that can be parsed from a command line value.
+    pub trait BootOptionValue: Copy {
+        // Parse the text after `key=`. A bare `key` on the command line is passed in as "on".
+        fn parse(s: &str) -> Option<Self>;
+
+        fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+    }
+
+    // Type-erased view of a `BootOption`, used for the registry.
+    pub trait BootOptionDescriptor {
+        fn name(&self) -> &'static str;
+
+        // Take `value` for this option. On error, the option keeps its default.
+        fn apply(&self, value: &str) -> Result<(), &'static str>;
+
+        // Print the effective value with `kinfo!`.
+        fn print_effective(&self);
+    }
+}
+
+pub use interface::*;
+
+// Declare a boot option and register it with the command line parser.
+//
+// ```
+// boot_option! {
+//     // Duration of the timer self-test.
+//     pub static SPIN: Duration = ("selftest.spin", Duration::from_secs(1));
+// }
+// ```
+#[macro_export]
+macro_rules! boot_option {
+    ($(#[$meta:meta])* $vis:vis static $name:ident: $ty:ty = ($key:literal, $default:expr);) => {
+        $(#[$meta])*
+        $vis static $name: $crate::bootargs::BootOption<$ty> =
+            $crate::bootargs::BootOption::new($key, $default);
+
+        const _: () = {
+            #[used]
+            #[link_section = ".bootargs_options"]
+            static REGISTRATION: &'static (dyn $crate::bootargs::BootOptionDescriptor + Sync) =
+                &$name;
+        };
+    };
+}
+
+// Declare a plain enum that can be used as a boot option value.
+//
+// ```
+// boot_option_enum! {
+//     pub enum Mode {
+//         Fast => "fast",
+//         Safe => "safe",
+//     }
+// }
+// ```
+#[macro_export]
+macro_rules! boot_option_enum {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($variant:ident => $text:literal),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq)]
+        $vis enum $name {
+            $($variant),+
+        }
+
+        impl $crate::bootargs::BootOptionValue for $name {
+            fn parse(s: &str) -> Option<Self> {
+                match s {
+                    $($text => Some(Self::$variant),)+
+                    _ => None,
+                }
+            }
+
+            fn fmt_value(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                match self {
+                    $(Self::$variant => f.write_str($text),)+
+                }
+            }
+        }
+    };
+}
+
+impl<T> BootOption<T> {
+    pub const fn new(name: &'static str, default: T) -> Self {
+        Self {
+            name,
+            default,
+            state: Mutex::new(OptionState::Default),
+        }
+    }
+}
+
+impl<T: BootOptionValue> BootOption<T> {
+    // The effective value of the option.
+    pub fn get(&self) -> T {
+        match *self.state.lock() {
+            OptionState::Set(value) => value,
+            _ => self.default,
+        }
+    }
+}
+
+impl<T: BootOptionValue + Send> BootOptionDescriptor for BootOption<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn apply(&self, value: &str) -> Result<(), &'static str> {
+        let mut state = self.state.lock();
+
+        match T::parse(value) {
+            Some(v) => {
+                *state = OptionState::Set(v);
+                Ok(())
+            }
+            None => {
+                *state = OptionState::Invalid;
+                Err("Invalid value")
+            }
+        }
+    }
+
+    fn print_effective(&self) {
+        let state = *self.state.lock();
+        let note = match state {
+            OptionState::Default => "",
+            OptionState::Set(_) => " (command line)",
+            OptionState::Invalid => " (invalid value on command line, using default)",
+        };
+
+        kinfo!(
+            "      {:<24} = {}{}",
+            self.name,
+            ValueFmt(&self.get()),
+            note
+        );
+    }
+}
+
+impl<T: BootOptionValue> fmt::Display for ValueFmt<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_value(f)
+    }
+}
+
+impl fmt::Display for CmdlineSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CmdlineSource::None => write!(f, "none"),
+            CmdlineSource::Chainloader => write!(f, "chainloader"),
+            CmdlineSource::DeviceTree => write!(f, "device tree"),
+        }
+    }
+}
+
+impl BootOptionValue for bool {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "1" | "on" | "yes" | "true" => Some(true),
+            "0" | "off" | "no" | "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if *self { "on" } else { "off" })
+    }
+}
+
+macro_rules! impl_boot_option_value_int {
+    ($($t:ty),+) => {
+        $(
+            impl BootOptionValue for $t {
+                fn parse(s: &str) -> Option<Self> {
+                    match s.strip_prefix("0x") {
+                        Some(hex) => <$t>::from_str_radix(hex, 16).ok(),
+                        None => s.parse().ok(),
+                    }
+                }
+
+                fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    write!(f, "{}", self)
+                }
+            }
+        )+
+    };
+}
+
+impl_boot_option_value_int!(u8, u16, u32, u64, usize, i32, i64);
+
+// Durations are written as an integer with one of the suffixes `ns`, `us`, `ms` or `s`. A bare
+// number is taken as seconds.
+impl BootOptionValue for Duration {
+    fn parse(s: &str) -> Option<Self> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number.parse().ok()?;
+
+        match unit {
+            "ns" => Some(Duration::from_nanos(number)),
+            "us" => Some(Duration::from_micros(number)),
+            "ms" => Some(Duration::from_millis(number)),
+            "s" | "" => Some(Duration::from_secs(number)),
+            _ => None,
+        }
+    }
+
+    fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.subsec_nanos() == 0 {
+            write!(f, "{}s", self.as_secs())
+        } else if self.subsec_nanos() % 1_000_000 == 0 {
+            write!(f, "{}ms", self.as_millis())
+        } else if self.subsec_nanos() % 1_000 == 0 {
+            write!(f, "{}us", self.as_micros())
+        } else {
+            write!(f, "{}ns", self.as_nanos())
+        }
+    }
+}
+
+// All options declared through `boot_option!`, collected by the linker.
+fn registered_options() -> &'static [&'static (dyn BootOptionDescriptor + Sync)] {
+    extern "Rust" {
+        static __bootargs_options_start: UnsafeCell<()>;
+        static __bootargs_options_end_exclusive: UnsafeCell<()>;
+    }
+
+    unsafe {
+        let start = __bootargs_options_start.get() as usize;
+        let end = __bootargs_options_end_exclusive.get() as usize;
+        let len = (end - start) / size_of::<&'static (dyn BootOptionDescriptor + Sync)>();
+
+        slice::from_raw_parts(start as *const _, len)
+    }
+}
+
+// Split a command line into `(key, value)` pairs.
+fn params(cmdline: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut rest = cmdline;
+
+    core::iter::from_fn(move || {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+
+        // A double quote keeps whitespace from ending the parameter
+        let mut in_quotes = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c.is_ascii_whitespace() && !in_quotes
+            })
+            .map_or(rest.len(), |(i, _)| i);
+
+        let (param, remainder) = rest.split_at(end);
+        rest = remainder;
+
+        Some(match param.split_once('=') {
+            Some((key, value)) => (key, value.trim_matches('"')),
+            None => (param, "on"),
+        })
+    })
+}
+
+// Copy the chainloader-supplied command line, if there is one.
+fn chainloader_cmdline(buf: &mut [u8]) -> Option<usize> {
+    // The static is patched behind the compiler's back, so the reads must not be optimized
+    // based on its initializer.
+    let len = unsafe { core::ptr::read_volatile(&CHAINLOADER_CMDLINE.len) } as usize;
+    if len == 0 {
+        return None;
+    }
+
+    let len = len.min(buf.len());
+    for (i, b) in buf[..len].iter_mut().enumerate() {
+        *b = unsafe { core::ptr::read_volatile(&CHAINLOADER_CMDLINE.cmdline[i]) };
+    }
+
+    Some(len)
+}
+
+// Copy the device tree's `/chosen/bootargs`, if there is one.
+fn device_tree_cmdline(buf: &mut [u8]) -> Option<usize> {
+    let dt = fdt::DeviceTree::from_boot()?;
+    let bootargs = dt.property_str("/chosen", "bootargs")?.as_bytes();
+
+    let len = bootargs.len().min(buf.len());
+    buf[..len].copy_from_slice(&bootargs[..len]);
+
+    Some(len)
+}
+
+/// Fetch the command line and apply it to all registered options.
+///
+/// # Safety
+///
+/// - Must be called only once, during single-threaded kernel init, before any option is read.
+pub unsafe fn init() {
+    {
+        let cmdline = &mut CMDLINE;
+
+        if let Some(len) = chainloader_cmdline(&mut cmdline.buf) {
+            cmdline.len = len;
+            cmdline.source = CmdlineSource::Chainloader;
+        } else if let Some(len) = device_tree_cmdline(&mut cmdline.buf) {
+            cmdline.len = len;
+            cmdline.source = CmdlineSource::DeviceTree;
+        }
+
+        // Keep only the longest valid UTF-8 prefix
+        if let Err(e) = str::from_utf8(&cmdline.buf[..cmdline.len]) {
+            cmdline.len = e.valid_up_to();
+        }
+    }
+
+    for (key, value) in params(self::cmdline()) {
+        if let Some(option) = registered_options().iter().find(|o| o.name() == key) {
+            // Rejected values are reported by `print_summary()`
+            let _ = option.apply(value);
+        }
+    }
+}
+
+// The effective command line.
+pub fn cmdline() -> &'static str {
+    // Safe after `init()`, since the buffer is never written again.
+    unsafe { str::from_utf8_unchecked(&CMDLINE.buf[..CMDLINE.len]) }
+}
+
+// Where the effective command line came from.
+pub fn cmdline_source() -> CmdlineSource {
+    unsafe { CMDLINE.source }
+}
+
+// Print the command line and the effective value of every registered option.
+pub fn print_summary() {
+    if cmdline_source() == CmdlineSource::None {
+        kwarn!("No kernel command line, using defaults");
+    } else {
+        kinfo!("Command line ({}): {}", cmdline_source(), cmdline());
+    }
+
+    kinfo!("Boot options:");
+    for option in registered_options() {
+        option.print_effective();
+    }
+}
@@ -4,4 +4,10 @@ mod raspberrypi;
 #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 pub use raspberrypi::*;
 
+#[cfg(feature = "bsp_qemu_virt")]
+mod qemu_virt;
+
+#[cfg(feature = "bsp_qemu_virt")]
+pub use qemu_virt::*;
+
 mod device_driver;
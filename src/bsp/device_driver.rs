@@ -1,6 +1,12 @@
+mod arm;
 #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 mod bcm;
 mod common;
+#[cfg(feature = "bsp_qemu_virt")]
+mod virtio;
 
+pub use arm::*;
 #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 pub use bcm::*;
+#[cfg(feature = "bsp_qemu_virt")]
+pub use virtio::*;
@@ -0,0 +1,19 @@
+#[cfg(any(feature = "bsp_qemu_virt", feature = "bsp_rpi4"))]
+mod gicv2;
+#[cfg(feature = "bsp_qemu_virt")]
+mod gicv3;
+mod pl011_uart;
+
+#[cfg(any(feature = "bsp_qemu_virt", feature = "bsp_rpi4"))]
+pub use gicv2::*;
+#[cfg(feature = "bsp_qemu_virt")]
+pub use gicv3::*;
+pub use pl011_uart::*;
+
+// Number of regular interrupt IDs of a GIC. IDs from 1020 upwards are reserved for special
+// purposes.
+#[cfg(any(feature = "bsp_qemu_virt", feature = "bsp_rpi4"))]
+const GIC_NUM_IRQS: usize = 1020;
+
+#[cfg(any(feature = "bsp_qemu_virt", feature = "bsp_rpi4"))]
+pub type GICIRQNumber = crate::exception::asynchronous::IRQNumber<{ GIC_NUM_IRQS - 1 }>;
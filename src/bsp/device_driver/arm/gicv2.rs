@@ -0,0 +1,226 @@
+// GICv2 driver - ARM Generic Interrupt Controller v2.
+//
+// The driver covers the two parts of the GIC that the kernel needs:
+//
+// - The Distributor (GICD), which prioritizes interrupts and routes them to CPU interfaces.
+// - The CPU interface (GICC), through which a core acknowledges and completes interrupts.
+//
+// Only the boot core is supported. All SPIs are routed to it.
+
+use spin::Mutex;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+
+use super::{GICIRQNumber, GIC_NUM_IRQS};
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver,
+    exception::{self, asynchronous::exec_with_irq_masked},
+    kinfo,
+};
+
+register_bitfields! {
+    u32,
+
+    // Distributor Control Register
+    GICD_CTLR [
+        // Enables forwarding of pending interrupts to the CPU interfaces.
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Interrupt Controller Type Register
+    GICD_TYPER [
+        // The number of implemented interrupt lines is `32 * (ITLinesNumber + 1)`.
+        ITLinesNumber OFFSET(0) NUMBITS(5) []
+    ],
+
+    // Interrupt Processor Targets Registers
+    GICD_ITARGETSR [
+        Offset3 OFFSET(24) NUMBITS(8) [],
+        Offset2 OFFSET(16) NUMBITS(8) [],
+        Offset1 OFFSET(8)  NUMBITS(8) [],
+        Offset0 OFFSET(0)  NUMBITS(8) []
+    ],
+
+    // CPU Interface Control Register
+    GICC_CTLR [
+        // Enables signaling of interrupts to the connected core.
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Interrupt Priority Mask Register
+    GICC_PMR [
+        // Only interrupts with a higher priority (lower value) than this are signaled.
+        Priority OFFSET(0) NUMBITS(8) []
+    ],
+
+    // Interrupt Acknowledge Register
+    GICC_IAR [
+        InterruptID OFFSET(0) NUMBITS(10) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    DistributorRegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, GICD_CTLR::Register>),
+        (0x004 => TYPER: ReadOnly<u32, GICD_TYPER::Register>),
+        (0x008 => _reserved1),
+        (0x100 => ISENABLER: [ReadWrite<u32>; 32]),
+        (0x180 => ICENABLER: [ReadWrite<u32>; 32]),
+        (0x200 => _reserved2),
+        (0x800 => ITARGETSR: [ReadWrite<u32, GICD_ITARGETSR::Register>; 256]),
+        (0xC00 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    CPUInterfaceRegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, GICC_CTLR::Register>),
+        (0x004 => PMR: ReadWrite<u32, GICC_PMR::Register>),
+        (0x008 => _reserved1),
+        (0x00C => IAR: ReadOnly<u32, GICC_IAR::Register>),
+        (0x010 => EOIR: WriteOnly<u32>),
+        (0x014 => @END),
+    }
+}
+
+type DistributorRegisters = MMIODerefWrapper<DistributorRegisterBlock>;
+type CPUInterfaceRegisters = MMIODerefWrapper<CPUInterfaceRegisterBlock>;
+
+// INTIDs 1020 to 1023 are reserved for special purposes, like reporting spurious interrupts.
+const SPECIAL_INTID_START: u32 = GIC_NUM_IRQS as u32;
+
+// SGIs and PPIs (INTID 0 to 31) are banked per core and always target it.
+const FIRST_SPI: usize = 32;
+
+type HandlerTable = [Option<exception::asynchronous::IRQDescriptor>; GIC_NUM_IRQS];
+
+pub struct GICv2 {
+    gicd: DistributorRegisters,
+    gicc: CPUInterfaceRegisters,
+    handler_table: Mutex<HandlerTable>,
+}
+
+impl GICv2 {
+    // Create an instance.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide correct MMIO start addresses.
+    pub const unsafe fn new(gicd_mmio_start_addr: usize, gicc_mmio_start_addr: usize) -> Self {
+        Self {
+            gicd: DistributorRegisters::new(gicd_mmio_start_addr),
+            gicc: CPUInterfaceRegisters::new(gicc_mmio_start_addr),
+            handler_table: Mutex::new([None; GIC_NUM_IRQS]),
+        }
+    }
+
+    // The number of interrupt lines the distributor implements, including SGIs and PPIs.
+    fn num_irqs(&self) -> usize {
+        let it_lines_number = self.gicd.TYPER.read(GICD_TYPER::ITLinesNumber) as usize;
+
+        (32 * (it_lines_number + 1)).min(GIC_NUM_IRQS)
+    }
+
+    // Route all SPIs to the boot core.
+    fn route_spis_to_boot_core(&self) {
+        const CORE0: u32 = 0b0000_0001;
+
+        for reg in self.gicd.ITARGETSR[FIRST_SPI / 4..self.num_irqs() / 4].iter() {
+            reg.write(
+                GICD_ITARGETSR::Offset3.val(CORE0)
+                    + GICD_ITARGETSR::Offset2.val(CORE0)
+                    + GICD_ITARGETSR::Offset1.val(CORE0)
+                    + GICD_ITARGETSR::Offset0.val(CORE0),
+            );
+        }
+    }
+}
+
+impl driver::DeviceDriver for GICv2 {
+    fn compatible(&self) -> &'static str {
+        "ARM GICv2"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        // Everything starts out disabled
+        for reg in self.gicd.ICENABLER[..self.num_irqs() / 32].iter() {
+            reg.set(u32::MAX);
+        }
+
+        self.route_spis_to_boot_core();
+        self.gicd.CTLR.write(GICD_CTLR::Enable::SET);
+
+        // Let interrupts of any priority through
+        self.gicc.PMR.write(GICC_PMR::Priority.val(255));
+        self.gicc.CTLR.write(GICC_CTLR::Enable::SET);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::IRQManager for GICv2 {
+    type IRQNumberType = GICIRQNumber;
+
+    fn register_handler(
+        &self,
+        irq_number: Self::IRQNumberType,
+        descriptor: exception::asynchronous::IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        exec_with_irq_masked(|| {
+            let mut table = self.handler_table.lock();
+            let slot = &mut table[irq_number.get()];
+
+            if slot.is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            *slot = Some(descriptor);
+            Ok(())
+        })
+    }
+
+    fn enable(&self, irq_number: Self::IRQNumberType) {
+        let irq = irq_number.get();
+
+        self.gicd.ISENABLER[irq / 32].set(1 << (irq % 32));
+    }
+
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        // Reading IAR marks the interrupt active
+        let iar = self.gicc.IAR.get();
+        let intid = iar & GICC_IAR::InterruptID.mask;
+
+        // Spurious interrupt, nothing to complete
+        if intid >= SPECIAL_INTID_START {
+            return;
+        }
+
+        let descriptor = self.handler_table.lock()[intid as usize];
+        match descriptor {
+            None => panic!("No handler registered for IRQ {}", intid),
+            Some(descriptor) => {
+                descriptor.handler.handle().expect("Error handling IRQ");
+            }
+        }
+
+        self.gicc.EOIR.set(iar);
+    }
+
+    fn print_handlers(&self) {
+        kinfo!("      Peripheral handler:");
+
+        for (i, descriptor) in self.handler_table.lock().iter().enumerate() {
+            if let Some(descriptor) = descriptor {
+                kinfo!("            {: >3}. {}", i, descriptor.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+// GICv3 driver - ARM Generic Interrupt Controller v3.
+//
+// Compared to the GICv2, the per-core parts of the distributor moved into one Redistributor (GICR)
+// per core, and the CPU interface is accessed through system registers instead of MMIO.
+//
+// The driver runs the GIC with affinity routing enabled and all interrupts in Non-secure Group 1.
+// Only the boot core is supported: all SPIs are routed to it, and only its redistributor, which is
+// the first one in the redistributor region, is set up. LPIs are not supported.
+
+use spin::Mutex;
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    registers::{ReadOnly, ReadWrite},
+};
+
+use super::{GICIRQNumber, GIC_NUM_IRQS};
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    cpu, driver,
+    exception::{self, asynchronous::exec_with_irq_masked},
+    kinfo,
+};
+
+register_bitfields! {
+    u32,
+
+    // Distributor Control Register
+    GICD_CTLR [
+        // Register Write Pending. Set while the effects of a previous write to CTLR are not yet
+        // visible to all parts of the GIC.
+        RWP OFFSET(31) NUMBITS(1) [],
+
+        // Affinity Routing Enable.
+        ARE OFFSET(4) NUMBITS(1) [],
+
+        // Enables forwarding of pending Group 1 interrupts.
+        EnableGrp1 OFFSET(1) NUMBITS(1) []
+    ],
+
+    // Interrupt Controller Type Register
+    GICD_TYPER [
+        // The number of implemented SPI lines is `32 * (ITLinesNumber + 1)`, minus the 32 SGIs and
+        // PPIs.
+        ITLinesNumber OFFSET(0) NUMBITS(5) []
+    ],
+
+    // Redistributor Wake Register
+    GICR_WAKER [
+        // Read-only. Set while the core's interface to the redistributor is quiescent.
+        ChildrenAsleep OFFSET(2) NUMBITS(1) [],
+
+        // Set while the core is asleep. Must be cleared before the core can receive interrupts.
+        ProcessorSleep OFFSET(1) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    DistributorRegisterBlock {
+        (0x0000 => CTLR: ReadWrite<u32, GICD_CTLR::Register>),
+        (0x0004 => TYPER: ReadOnly<u32, GICD_TYPER::Register>),
+        (0x0008 => _reserved1),
+        (0x0080 => IGROUPR: [ReadWrite<u32>; 32]),
+        (0x0100 => ISENABLER: [ReadWrite<u32>; 32]),
+        (0x0180 => ICENABLER: [ReadWrite<u32>; 32]),
+        (0x0200 => _reserved2),
+        (0x0400 => IPRIORITYR: [ReadWrite<u32>; 255]),
+        (0x07FC => _reserved3),
+        // IROUTER<n> for n = 32 to 1019
+        (0x6100 => IROUTER: [ReadWrite<u64>; 988]),
+        (0x7FE0 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RedistributorRegisterBlock {
+        // RD_base frame
+        (0x0_0000 => _reserved1),
+        (0x0_0014 => WAKER: ReadWrite<u32, GICR_WAKER::Register>),
+        (0x0_0018 => _reserved2),
+
+        // SGI_base frame, starting 64 KiB in
+        (0x1_0080 => IGROUPR0: ReadWrite<u32>),
+        (0x1_0084 => _reserved3),
+        (0x1_0100 => ISENABLER0: ReadWrite<u32>),
+        (0x1_0104 => _reserved4),
+        (0x1_0180 => ICENABLER0: ReadWrite<u32>),
+        (0x1_0184 => _reserved5),
+        (0x1_0400 => IPRIORITYR: [ReadWrite<u32>; 8]),
+        (0x1_0420 => @END),
+    }
+}
+
+type DistributorRegisters = MMIODerefWrapper<DistributorRegisterBlock>;
+type RedistributorRegisters = MMIODerefWrapper<RedistributorRegisterBlock>;
+
+// INTIDs 1020 to 1023 are reserved for special purposes, like reporting spurious interrupts.
+const SPECIAL_INTID_START: u64 = GIC_NUM_IRQS as u64;
+
+// SGIs and PPIs (INTID 0 to 31) are configured in the redistributor.
+const FIRST_SPI: usize = 32;
+
+// A middle-of-the-road priority for all interrupts, four of them packed into one IPRIORITYR.
+const DEFAULT_PRIORITY_X4: u32 = 0xA0A0_A0A0;
+
+// The GIC CPU interface system registers. They are not known to the `cortex-a` crate, hence
+// accessed by their encoded names.
+mod icc {
+    #[inline(always)]
+    pub unsafe fn sre_el1() -> u64 {
+        let value;
+        asm!("mrs {}, S3_0_C12_C12_5", out(reg) value, options(nomem, nostack));
+        value
+    }
+
+    #[inline(always)]
+    pub unsafe fn set_sre_el1(value: u64) {
+        asm!("msr S3_0_C12_C12_5, {}", "isb", in(reg) value, options(nostack));
+    }
+
+    #[inline(always)]
+    pub unsafe fn set_pmr_el1(value: u64) {
+        asm!("msr S3_0_C4_C6_0, {}", in(reg) value, options(nostack));
+    }
+
+    #[inline(always)]
+    pub unsafe fn set_bpr1_el1(value: u64) {
+        asm!("msr S3_0_C12_C12_3, {}", in(reg) value, options(nostack));
+    }
+
+    #[inline(always)]
+    pub unsafe fn set_igrpen1_el1(value: u64) {
+        asm!("msr S3_0_C12_C12_7, {}", "isb", in(reg) value, options(nostack));
+    }
+
+    #[inline(always)]
+    pub fn iar1_el1() -> u64 {
+        let value;
+        unsafe { asm!("mrs {}, S3_0_C12_C12_0", out(reg) value, options(nostack)) };
+        value
+    }
+
+    #[inline(always)]
+    pub fn set_eoir1_el1(value: u64) {
+        unsafe { asm!("msr S3_0_C12_C12_1, {}", in(reg) value, options(nostack)) };
+    }
+}
+
+type HandlerTable = [Option<exception::asynchronous::IRQDescriptor>; GIC_NUM_IRQS];
+
+pub struct GICv3 {
+    gicd: DistributorRegisters,
+    gicr: RedistributorRegisters,
+    handler_table: Mutex<HandlerTable>,
+}
+
+impl GICv3 {
+    // Create an instance. `gicr_mmio_start_addr` is the start of the redistributor region.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide correct MMIO start addresses.
+    pub const unsafe fn new(gicd_mmio_start_addr: usize, gicr_mmio_start_addr: usize) -> Self {
+        Self {
+            gicd: DistributorRegisters::new(gicd_mmio_start_addr),
+            gicr: RedistributorRegisters::new(gicr_mmio_start_addr),
+            handler_table: Mutex::new([None; GIC_NUM_IRQS]),
+        }
+    }
+
+    // Whether the executing core has a GICv3 system register CPU interface, as reported by
+    // ID_AA64PFR0_EL1.GIC.
+    pub fn is_present() -> bool {
+        let pfr0: u64;
+        unsafe { asm!("mrs {}, ID_AA64PFR0_EL1", out(reg) pfr0, options(nomem, nostack)) };
+
+        (pfr0 >> 24) & 0xF != 0
+    }
+
+    // The number of interrupt lines the distributor implements, including SGIs and PPIs.
+    fn num_irqs(&self) -> usize {
+        let it_lines_number = self.gicd.TYPER.read(GICD_TYPER::ITLinesNumber) as usize;
+
+        (32 * (it_lines_number + 1)).min(GIC_NUM_IRQS)
+    }
+
+    fn wait_for_gicd_write(&self) {
+        while self.gicd.CTLR.is_set(GICD_CTLR::RWP) {
+            cpu::nop();
+        }
+    }
+
+    fn init_distributor(&self) {
+        let num_irqs = self.num_irqs();
+
+        // Affinity routing can only be switched on while the distributor is disabled
+        self.gicd.CTLR.set(0);
+        self.wait_for_gicd_write();
+
+        for i in FIRST_SPI / 32..num_irqs / 32 {
+            self.gicd.ICENABLER[i].set(u32::MAX);
+            self.gicd.IGROUPR[i].set(u32::MAX);
+        }
+
+        for reg in self.gicd.IPRIORITYR[FIRST_SPI / 4..num_irqs / 4].iter() {
+            reg.set(DEFAULT_PRIORITY_X4);
+        }
+
+        // Affinity 0.0.0.0 is the boot core
+        for reg in self.gicd.IROUTER[..num_irqs - FIRST_SPI].iter() {
+            reg.set(0);
+        }
+
+        self.gicd
+            .CTLR
+            .write(GICD_CTLR::ARE::SET + GICD_CTLR::EnableGrp1::SET);
+        self.wait_for_gicd_write();
+    }
+
+    fn init_redistributor(&self) {
+        self.gicr.WAKER.modify(GICR_WAKER::ProcessorSleep::CLEAR);
+        while self.gicr.WAKER.is_set(GICR_WAKER::ChildrenAsleep) {
+            cpu::nop();
+        }
+
+        self.gicr.ICENABLER0.set(u32::MAX);
+        self.gicr.IGROUPR0.set(u32::MAX);
+        for reg in self.gicr.IPRIORITYR.iter() {
+            reg.set(DEFAULT_PRIORITY_X4);
+        }
+    }
+
+    unsafe fn init_cpu_interface(&self) {
+        // Use the system register interface. Whoever ran in EL2 must have allowed this.
+        icc::set_sre_el1(icc::sre_el1() | 1);
+
+        // Let interrupts of any priority through, no preemption grouping
+        icc::set_pmr_el1(0xFF);
+        icc::set_bpr1_el1(0);
+        icc::set_igrpen1_el1(1);
+    }
+}
+
+impl driver::DeviceDriver for GICv3 {
+    fn compatible(&self) -> &'static str {
+        "ARM GICv3"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        if !Self::is_present() {
+            return Err("No GICv3 CPU interface");
+        }
+
+        self.init_distributor();
+        self.init_redistributor();
+        self.init_cpu_interface();
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::IRQManager for GICv3 {
+    type IRQNumberType = GICIRQNumber;
+
+    fn register_handler(
+        &self,
+        irq_number: Self::IRQNumberType,
+        descriptor: exception::asynchronous::IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        exec_with_irq_masked(|| {
+            let mut table = self.handler_table.lock();
+            let slot = &mut table[irq_number.get()];
+
+            if slot.is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            *slot = Some(descriptor);
+            Ok(())
+        })
+    }
+
+    fn enable(&self, irq_number: Self::IRQNumberType) {
+        let irq = irq_number.get();
+
+        if irq < FIRST_SPI {
+            self.gicr.ISENABLER0.set(1 << irq);
+        } else {
+            self.gicd.ISENABLER[irq / 32].set(1 << (irq % 32));
+        }
+    }
+
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        // Reading IAR marks the interrupt active
+        let iar = icc::iar1_el1();
+        let intid = iar & 0xFF_FFFF;
+
+        // Spurious interrupt, nothing to complete
+        if intid >= SPECIAL_INTID_START {
+            return;
+        }
+
+        let descriptor = self.handler_table.lock()[intid as usize];
+        match descriptor {
+            None => panic!("No handler registered for IRQ {}", intid),
+            Some(descriptor) => {
+                descriptor.handler.handle().expect("Error handling IRQ");
+            }
+        }
+
+        icc::set_eoir1_el1(iar);
+    }
+
+    fn print_handlers(&self) {
+        kinfo!("      Peripheral handler:");
+
+        for (i, descriptor) in self.handler_table.lock().iter().enumerate() {
+            if let Some(descriptor) = descriptor {
+                kinfo!("            {: >3}. {}", i, descriptor.name);
+            }
+        }
+    }
+}
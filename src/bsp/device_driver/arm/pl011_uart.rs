@@ -0,0 +1,767 @@
+use core::fmt::{self, Write};
+
+use spin::Mutex;
+use tock_registers::{
+    fields::FieldValue,
+    interfaces::{Readable, Writeable},
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+
+use crate::{
+    boot_option,
+    bootargs::BootOptionValue,
+    bsp::{device_driver::common::MMIODerefWrapper, exception::asynchronous::IRQNumber},
+    console, cpu, driver,
+    exception::{self, asynchronous::exec_with_irq_masked},
+    kprintln, kwarn,
+};
+
+register_bitfields! [
+    u32,
+    // Flag Register
+    FR [
+        // Transmit FIFO empty. The meaning of this bit depends on the state of the FEN bit in the
+        // Line Control Register, LCR_H.
+        //
+        // - If the FIFO is disabled, this bit is set when the transmit holding register is empty.
+        // - If the FIFO is enabled, the TXFE bit is set when the transmit FIFO is empty.
+        // - This bit does not indicate if there is data in the transmit shift register.
+        TXFE OFFSET(7) NUMBITS(1) [],
+
+        // Transmit FIFO full. The meaning of this bit depends on the state of the FEN bit in the
+        // LCR_H Register.
+        //
+        // - If the FIFO is disabled, this bit is set when the transmit holding register is full.
+        // - If the FIFO is enabled, the TXFF bit is set when the transmit FIFO is full.
+        TXFF OFFSET(5) NUMBITS(1) [],
+
+        // Receive FIFO empty. The meaning of this bit depends on the state of the FEN bit in the
+        // LCR_H Register.
+        //
+        // - If the FIFO is disabled, this bit is set when the receive holding register is empty.
+        // - If the FIFO is enabled, the RXFE bit is set when the receive FIFO is empty.
+        RXFE OFFSET(4) NUMBITS(1) [],
+
+        // UART busy. If this bit is set to 1, the UART is busy transmitting data. This bit remains
+        // set until the complete byte, including all the stop bits, has been sent from the shift
+        // register.
+        //
+        // This bit is set as soon as the transmit FIFO becomes non-empty, regardless of whether
+        // the UART is enabled or not.
+        BUSY OFFSET(3) NUMBITS(1) []
+    ],
+
+    // Integer Baud Rate divisor
+    IBRD [
+        // Integer Baud Rate divisor
+        BAUD_DIVINT OFFSET(0) NUMBITS(16) []
+    ],
+
+    // Fractional Baud Rate Divisor.
+    FBRD [
+        //  The fractional baud rate divisor.
+        BAUD_DIVFRAC OFFSET(0) NUMBITS(6) []
+    ],
+
+    // Line Control Register
+    LCR_H [
+        // Word length. These bits indicate the number of data bits transmitted or received in a
+        // frame
+        #[allow(clippy::enum_variant_names)]
+        WLEN OFFSET(5) NUMBITS(2) [
+            FiveBit = 0b00,
+            SixBit = 0b01,
+            SevenBit = 0b10,
+            EightBit = 0b11,
+        ],
+
+        // Enable FIFO
+        FEN OFFSET(4) NUMBITS(1) [
+            FifosDisabled = 0,
+            FifosEnabled = 1,
+        ],
+
+        // Two stop bits select. If this bit is set to 1, two stop bits are transmitted at the end
+        // of the frame. The receive logic does not check for two stop bits being received.
+        STP2 OFFSET(3) NUMBITS(1) [],
+
+        // Even parity select. Controls the type of parity the UART uses during transmission and
+        // reception. Has no effect when PEN is cleared.
+        EPS OFFSET(2) NUMBITS(1) [
+            Odd = 0,
+            Even = 1
+        ],
+
+        // Parity enable. If this bit is set to 1, parity checking and generation is enabled.
+        PEN OFFSET(1) NUMBITS(1) []
+    ],
+
+    // Control register
+    CR [
+        // CTS hardware flow control enable. If this bit is set to 1, data is only transmitted
+        // when the nUARTCTS signal is asserted.
+        CTSEN OFFSET(15) NUMBITS(1) [],
+
+        // RTS hardware flow control enable. If this bit is set to 1, data is only requested when
+        // there is space in the receive FIFO for it to be received.
+        RTSEN OFFSET(14) NUMBITS(1) [],
+
+        // Receive enable. If this bit is set to 1, the receive section of the UART is enabled.
+        // Data reception occurs for either UART signals or SIR signals depending on the setting of
+        // the SIREN bit. When the UART is disabled in the middle of reception, it completes the
+        // current character before stopping.
+        RXE OFFSET(9) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        // Transmit enable. If this bit is set to 1, the transmit section of the UART is enabled.
+        // Data transmission occurs for either UART signals, or SIR signals depending on the
+        // setting of the SIREN bit. When the UART is disabled in the middle of transmission, it
+        // completes the current character before stopping.
+        TXE OFFSET(8) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        // UART enable:
+        //
+        // 0 = UART is disabled. If the UART is disabled in the middle of transmission or
+        // reception, it completes the current character before stopping.
+        //
+        // 1 = The UART is enabled. Data transmission and reception occurs for either UART signals
+        // or SIR signals depending on the setting of the SIREN bit
+        UARTEN OFFSET(0) NUMBITS(1) [
+            // If the UART is disabled in the middle of transmission or reception, it completes the
+            // current character before stopping.
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    // Interrupt FIFO Level Select Register.
+    IFLS [
+        // Receive interrupt FIFO level select. The trigger points for the receive interrupt are as
+        // follows.
+        RXIFLSEL OFFSET(3) NUMBITS(5) [
+            OneEigth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEights = 0b100
+        ]
+    ],
+
+    // Interrupt Mask Set/Clear Register.
+    IMSC [
+        // Receive timeout interrupt mask. A read returns the current mask for the UARTRTINTR
+        // interrupt.
+        //
+        // - On a write of 1, the mask of the UARTRTINTR interrupt is set.
+        // - A write of 0 clears the mask.
+        RTIM OFFSET(6) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        // Receive interrupt mask. A read returns the current mask for the UARTRXINTR interrupt.
+        //
+        // - On a write of 1, the mask of the UARTRXINTR interrupt is set.
+        // - A write of 0 clears the mask.
+        RXIM OFFSET(4) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    // Masked Interrupt Status Register.
+    MIS [
+        // Receive timeout masked interrupt status. Returns the masked interrupt state of the
+        // UARTRTINTR interrupt.
+        RTMIS OFFSET(6) NUMBITS(1) [],
+
+        // Receive masked interrupt status. Returns the masked interrupt state of the UARTRXINTR
+        // interrupt.
+        RXMIS OFFSET(4) NUMBITS(1) []
+    ],
+
+    // Interrupt Clear Register.
+    ICR [
+        // Meta field for all pending interrupts.
+        ALL OFFSET(0) NUMBITS(11) []
+    ]
+];
+
+register_structs! {
+    #[allow(non_snake_case)]
+    pub RegisterBlock {
+        (0x00 => DR: ReadWrite<u32>),
+        (0x04 => _reserved1),
+        (0x18 => FR: ReadOnly<u32, FR::Register>),
+        (0x1c => _reserved2),
+        (0x24 => IBRD: ReadWrite<u32, IBRD::Register>),
+        (0x28 => FBRD: ReadWrite<u32, FBRD::Register>),
+        (0x2c => LCR_H: ReadWrite<u32, LCR_H::Register>),
+        (0x30 => CR: ReadWrite<u32, CR::Register>),
+        (0x34 => IFLS: ReadWrite<u32, IFLS::Register>),
+        (0x38 => IMSC: ReadWrite<u32, IMSC::Register>),
+        (0x3C => _reserved3),
+        (0x40 => MIS: ReadOnly<u32, MIS::Register>),
+        (0x44 => ICR: WriteOnly<u32, ICR::Register>),
+        (0x48 => @END),
+    }
+}
+
+// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+// Characters received in IRQ context, waiting to be read.
+const RX_BUFFER_SIZE: usize = 64;
+
+// Refuse baud rates the divisor can only approximate worse than this, in hundredths of a percent.
+// Both ends together may be off by roughly half a bit over a frame.
+const MAX_BAUD_ERROR: i64 = 300;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+// Line settings, written like `921600,8N1` or `115200,7E2,rtscts` on the command line.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LineSettings {
+    pub baud_rate: u32,
+    // 5 to 8
+    pub data_bits: u8,
+    pub parity: Parity,
+    // 1 or 2
+    pub stop_bits: u8,
+    // RTS/CTS hardware flow control
+    pub flow_control: bool,
+}
+
+// The baud rate divisor `clock / (16 * baud_rate)`, split into the integer part and 1/64ths.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct BaudDivisor {
+    integer: u32,
+    fraction: u32,
+}
+
+boot_option! {
+    // Line settings the driver switches to when it is initialized.
+    static LINE_SETTINGS: LineSettings = ("pl011", LineSettings::DEFAULT);
+}
+
+pub struct PL01UartInner {
+    registers: Registers,
+    clock_hz: u32,
+    settings: LineSettings,
+    chars_read: usize,
+    chars_written: usize,
+    rx_buffer: [u8; RX_BUFFER_SIZE],
+    rx_head: usize,
+    rx_len: usize,
+}
+
+pub use PL01UartInner as PanicUart;
+pub use PL01UartInner as EarlyUart;
+
+pub struct PL011Uart {
+    inner: Mutex<PL01UartInner>,
+    irq_number: Option<IRQNumber>,
+}
+
+impl LineSettings {
+    pub const DEFAULT: Self = Self {
+        baud_rate: 921_600,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
+        flow_control: false,
+    };
+
+    fn is_valid(&self) -> bool {
+        self.baud_rate > 0 && (5..=8).contains(&self.data_bits) && (1..=2).contains(&self.stop_bits)
+    }
+
+    // The frame format in the usual notation, e.g. `8N1`.
+    fn parse_frame(&mut self, s: &str) -> Option<()> {
+        match s.as_bytes() {
+            [data_bits @ b'5'..=b'8', parity, stop_bits @ b'1'..=b'2'] => {
+                self.data_bits = data_bits - b'0';
+                self.stop_bits = stop_bits - b'0';
+                self.parity = match parity.to_ascii_uppercase() {
+                    b'N' => Parity::None,
+                    b'E' => Parity::Even,
+                    b'O' => Parity::Odd,
+                    _ => return None,
+                };
+
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    fn line_control(&self) -> FieldValue<u32, LCR_H::Register> {
+        let parity = match self.parity {
+            Parity::None => LCR_H::PEN::CLEAR,
+            Parity::Even => LCR_H::PEN::SET + LCR_H::EPS::Even,
+            Parity::Odd => LCR_H::PEN::SET + LCR_H::EPS::Odd,
+        };
+        let stop_bits = if self.stop_bits == 2 {
+            LCR_H::STP2::SET
+        } else {
+            LCR_H::STP2::CLEAR
+        };
+
+        LCR_H::WLEN.val(u32::from(self.data_bits) - 5)
+            + parity
+            + stop_bits
+            + LCR_H::FEN::FifosEnabled
+    }
+
+    fn control(&self) -> FieldValue<u32, CR::Register> {
+        let flow_control = if self.flow_control {
+            CR::RTSEN::SET + CR::CTSEN::SET
+        } else {
+            CR::RTSEN::CLEAR + CR::CTSEN::CLEAR
+        };
+
+        CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled + flow_control
+    }
+}
+
+impl BootOptionValue for LineSettings {
+    fn parse(s: &str) -> Option<Self> {
+        let mut settings = Self::DEFAULT;
+        let mut parts = s.split(',');
+
+        settings.baud_rate = parts.next()?.parse().ok()?;
+        for part in parts {
+            match part {
+                "rtscts" => settings.flow_control = true,
+                _ => settings.parse_frame(part)?,
+            }
+        }
+
+        if !settings.is_valid() {
+            return None;
+        }
+
+        Some(settings)
+    }
+
+    fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+
+        write!(
+            f,
+            "{},{}{}{}",
+            self.baud_rate, self.data_bits, parity, self.stop_bits
+        )?;
+        if self.flow_control {
+            f.write_str(",rtscts")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for LineSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_value(f)
+    }
+}
+
+impl BaudDivisor {
+    // The divisor closest to the one for `baud_rate`, within what IBRD and FBRD can hold.
+    //
+    // From the PL011 Technical Reference Manual, the fractional part is
+    // `INTEGER((fraction * 64) + 0.5)`. In 1/64ths, the whole divisor is
+    // `clock * 64 / (16 * baud_rate) = clock * 4 / baud_rate`, rounded the same way.
+    fn nearest(clock_hz: u32, baud_rate: u32) -> Self {
+        let baud_rate = u64::from(baud_rate.max(1));
+        let sixty_fourths = (u64::from(clock_hz) * 4 + baud_rate / 2) / baud_rate;
+
+        // IBRD must be at least 1, and with IBRD at its maximum of 0xFFFF, FBRD must be 0.
+        let sixty_fourths = sixty_fourths.max(1 << 6).min(0xFFFF << 6);
+
+        Self {
+            integer: (sixty_fourths >> 6) as u32,
+            fraction: (sixty_fourths & 0x3F) as u32,
+        }
+    }
+
+    // The baud rate this divisor actually generates.
+    fn baud_rate(&self, clock_hz: u32) -> u32 {
+        let sixty_fourths = u64::from(self.integer << 6 | self.fraction);
+
+        ((u64::from(clock_hz) * 4 + sixty_fourths / 2) / sixty_fourths) as u32
+    }
+}
+
+// How far off the actual baud rate is, in hundredths of a percent.
+fn baud_error(requested: u32, actual: u32) -> i64 {
+    (i64::from(actual) - i64::from(requested)) * 10_000 / i64::from(requested)
+}
+
+impl PL01UartInner {
+    // Create an instance for a UART whose reference clock, UARTCLK, runs at `clock_hz`.
+    pub const unsafe fn new(mmio_start_addr: usize, clock_hz: u32) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            clock_hz,
+            settings: LineSettings::DEFAULT,
+            chars_read: 0,
+            chars_written: 0,
+            rx_buffer: [0; RX_BUFFER_SIZE],
+            rx_head: 0,
+            rx_len: 0,
+        }
+    }
+
+    // Turn the UART on with `LineSettings::DEFAULT`.
+    //
+    // If it is on already, it keeps whatever line settings it has. The other end of the line is set
+    // up for those, and there is nothing to gain from turning the UART off and on again. This is
+    // the case when the early console set it up, or when a panic happens after the kernel changed
+    // the settings.
+    //
+    // For the default of 921_600 baud at the 48 MHz clock the RPi firmware is asked for in
+    // config.txt, the divisor is `(48_000_000 / 16) / 921_600 = 3.2552083`. `IBRD` gets the
+    // integer part `3`, and `FBRD` the fractional part in 1/64ths: `INTEGER((0.2552083 * 64) +
+    // 0.5) = 16`.
+    //
+    // The generated baud rate is `48_000_000 / (16 * (3 + 16/64)) = 923_077`, an error of 0.16%.
+    pub fn init(&mut self) {
+        self.reset_irqs();
+
+        if !self.registers.CR.matches_all(CR::UARTEN::Enabled) {
+            let divisor = BaudDivisor::nearest(self.clock_hz, self.settings.baud_rate);
+
+            self.program(self.settings, divisor);
+        }
+    }
+
+    // Switch to new line settings. Returns the baud rate actually generated, which is as close to
+    // the requested one as the UART clock allows.
+    pub fn configure(&mut self, settings: LineSettings) -> Result<u32, &'static str> {
+        if !settings.is_valid() {
+            return Err("Invalid line settings");
+        }
+
+        let divisor = BaudDivisor::nearest(self.clock_hz, settings.baud_rate);
+        let actual = divisor.baud_rate(self.clock_hz);
+        if baud_error(settings.baud_rate, actual).abs() > MAX_BAUD_ERROR {
+            return Err("Baud rate not achievable with this UART clock");
+        }
+
+        if !self.is_configured(&settings, divisor) {
+            self.program(settings, divisor);
+        }
+        self.settings = settings;
+
+        Ok(actual)
+    }
+
+    // Mask and clear all interrupts. RX interrupts are turned on later if an IRQ handler gets
+    // registered.
+    fn reset_irqs(&mut self) {
+        self.registers.IMSC.set(0);
+        self.registers.ICR.write(ICR::ALL::CLEAR);
+
+        // Raise an RX interrupt as soon as the FIFO is 1/8 full. Stragglers are caught by the
+        // receive timeout interrupt.
+        self.registers.IFLS.write(IFLS::RXIFLSEL::OneEigth);
+    }
+
+    fn program(&mut self, settings: LineSettings, divisor: BaudDivisor) {
+        // Execution can arrive here while there are still characters queued in the TX FIFO and
+        // actively being sent out by the UART hardware. If the UART is turned off in this case,
+        // those queued characters would be lost.
+        //
+        // For example, this happens when the line settings are changed right after printing the
+        // shell prompt.
+        //
+        // Hence, flush first to ensure all pending characters are transmitted.
+        self.flush();
+
+        // Turn the UART off temporarily
+        self.registers.CR.set(0);
+
+        // From the PL011 Technical Reference Manual:
+        //
+        // The LCR_H, IBRD, and FBRD registers form the single 30-bit wide LCR Register that is
+        // updated on a single write strobe generated by a LCR_H write. So, to internally update the
+        // contents of IBRD or FBRD, a LCR_H write must always be performed at the end.
+        self.registers
+            .IBRD
+            .write(IBRD::BAUD_DIVINT.val(divisor.integer));
+        self.registers
+            .FBRD
+            .write(FBRD::BAUD_DIVFRAC.val(divisor.fraction));
+        self.registers.LCR_H.write(settings.line_control());
+
+        // Turn the UART on
+        self.registers.CR.write(settings.control());
+    }
+
+    // Whether the UART is on with the given settings.
+    fn is_configured(&self, settings: &LineSettings, divisor: BaudDivisor) -> bool {
+        self.registers.CR.get() == settings.control().value
+            && self.registers.IBRD.read(IBRD::BAUD_DIVINT) == divisor.integer
+            && self.registers.FBRD.read(FBRD::BAUD_DIVFRAC) == divisor.fraction
+            && self.registers.LCR_H.get() == settings.line_control().value
+    }
+
+    fn write_char(&mut self, c: char) {
+        // Spin while TX FIFO full is set, waiting for an empty slot
+        while self.registers.FR.matches_all(FR::TXFF::SET) {
+            cpu::nop();
+        }
+
+        // Write the character to the buffer
+        self.registers.DR.set(c as u32);
+
+        self.chars_written += 1;
+    }
+
+    // Block execution until the last buffered character has been physically put on the TX wire.
+    fn flush(&self) {
+        // Spin until busy bit is cleared
+        while self.registers.FR.matches_all(FR::BUSY::SET) {
+            cpu::nop();
+        }
+    }
+
+    // Unmask the RX and receive timeout interrupts.
+    fn enable_rx_irq(&mut self) {
+        self.registers
+            .IMSC
+            .write(IMSC::RXIM::Enabled + IMSC::RTIM::Enabled);
+    }
+
+    // Move everything from the RX FIFO into the RX buffer. Characters that do not fit are dropped.
+    fn handle_rx_irq(&mut self) {
+        let pending = self.registers.MIS.extract();
+        if !(pending.is_set(MIS::RXMIS) || pending.is_set(MIS::RTMIS)) {
+            return;
+        }
+
+        while !self.registers.FR.matches_all(FR::RXFE::SET) {
+            let c = self.registers.DR.get() as u8;
+
+            if self.rx_len < RX_BUFFER_SIZE {
+                self.rx_buffer[(self.rx_head + self.rx_len) % RX_BUFFER_SIZE] = c;
+                self.rx_len += 1;
+            }
+        }
+
+        self.registers.ICR.write(ICR::ALL::CLEAR);
+    }
+
+    // Take the oldest received character, if there is one.
+    fn read_byte(&mut self) -> Option<u8> {
+        // Characters buffered in IRQ context are older than those still in the FIFO
+        if self.rx_len > 0 {
+            let c = self.rx_buffer[self.rx_head];
+            self.rx_head = (self.rx_head + 1) % RX_BUFFER_SIZE;
+            self.rx_len -= 1;
+
+            return Some(c);
+        }
+
+        if self.registers.FR.matches_all(FR::RXFE::SET) {
+            return None;
+        }
+
+        Some(self.registers.DR.get() as u8)
+    }
+
+    fn read_char_converting(&mut self) -> Option<char> {
+        // Read one character
+        let mut ret = self.read_byte()? as char;
+
+        // Convert carriage return to newline
+        if ret == '\r' {
+            ret = '\n';
+        }
+
+        self.chars_read += 1;
+        Some(ret)
+    }
+}
+
+impl fmt::Write for PL01UartInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+impl PL011Uart {
+    // Create an instance for a UART whose reference clock runs at `clock_hz`. Without an
+    // `irq_number`, input is polled only.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(
+        mmio_start_addr: usize,
+        clock_hz: u32,
+        irq_number: Option<IRQNumber>,
+    ) -> Self {
+        Self {
+            inner: Mutex::new(PL01UartInner::new(mmio_start_addr, clock_hz)),
+            irq_number,
+        }
+    }
+
+    pub fn line_settings(&self) -> LineSettings {
+        self.locked(|inner| inner.settings)
+    }
+
+    // Switch to new line settings, see `PL01UartInner::configure()`.
+    pub fn set_line_settings(&self, settings: LineSettings) -> Result<u32, &'static str> {
+        self.locked(|inner| inner.configure(settings))
+    }
+
+    // Tell the driver the actual rate of the reference clock, and program the divisor for it.
+    // Returns the baud rate actually generated.
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    pub fn set_clock_hz(&self, clock_hz: u32) -> Result<u32, &'static str> {
+        self.locked(|inner| {
+            let previous = inner.clock_hz;
+
+            inner.clock_hz = clock_hz;
+            inner.configure(inner.settings).map_err(|msg| {
+                inner.clock_hz = previous;
+                msg
+            })
+        })
+    }
+
+    // Shell command to show or change the line settings, for the BSP to register.
+    pub fn line_settings_command(&self, args: &[&str]) -> Result<(), &'static str> {
+        let requested = match args.get(1) {
+            Some(arg) => LineSettings::parse(arg).ok_or("Invalid line settings")?,
+            None => self.line_settings(),
+        };
+        let actual = self.set_line_settings(requested)?;
+        let error = baud_error(requested.baud_rate, actual);
+        kprintln!(
+            "  {} ({} baud, {}{}.{:02}% off)",
+            requested,
+            actual,
+            if error < 0 { "-" } else { "+" },
+            error.abs() / 100,
+            error.abs() % 100
+        );
+
+        Ok(())
+    }
+
+    // All accesses go through here. The lock is also taken in IRQ context, so IRQs must be masked
+    // while it is held.
+    fn locked<R>(&self, f: impl FnOnce(&mut PL01UartInner) -> R) -> R {
+        exec_with_irq_masked(|| f(&mut self.inner.lock()))
+    }
+}
+
+impl driver::DeviceDriver for PL011Uart {
+    fn compatible(&self) -> &'static str {
+        "ARM PL011 UART"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.locked(|inner| inner.init());
+
+        if let Err(msg) = self.set_line_settings(LINE_SETTINGS.get()) {
+            kwarn!("PL011 UART: {}: {}", LINE_SETTINGS.get(), msg);
+            self.set_line_settings(LineSettings::DEFAULT)?;
+        }
+
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQDescriptor};
+
+        let irq_number = match self.irq_number {
+            Some(irq_number) => irq_number,
+            None => return Ok(()),
+        };
+
+        let descriptor = IRQDescriptor {
+            name: "PL011 UART",
+            handler: self,
+        };
+
+        irq_manager().register_handler(irq_number, descriptor)?;
+        irq_manager().enable(irq_number);
+        self.locked(|inner| inner.enable_rx_irq());
+
+        Ok(())
+    }
+}
+
+impl console::Write for PL011Uart {
+    fn write_char(&self, c: char) -> fmt::Result {
+        self.locked(|inner| inner.write_char(c));
+        Ok(())
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.locked(|inner| inner.write_fmt(args))
+    }
+
+    fn flush(&self) -> fmt::Result {
+        self.locked(|inner| inner.flush());
+        Ok(())
+    }
+}
+
+impl console::Read for PL011Uart {
+    fn read_char(&self) -> Result<char, fmt::Error> {
+        // Poll without holding the lock, so that IRQs can be taken while waiting
+        loop {
+            if let Some(c) = self.locked(|inner| inner.read_char_converting()) {
+                return Ok(c);
+            }
+
+            cpu::nop();
+        }
+    }
+
+    fn clear_rx(&self) -> fmt::Result {
+        while self.locked(|inner| inner.read_char_converting()).is_some() {}
+        Ok(())
+    }
+}
+
+impl console::Statistics for PL011Uart {
+    fn chars_written(&self) -> usize {
+        self.locked(|inner| inner.chars_written)
+    }
+
+    fn chars_read(&self) -> usize {
+        self.locked(|inner| inner.chars_read)
+    }
+}
+
+impl exception::asynchronous::IRQHandler for PL011Uart {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.locked(|inner| inner.handle_rx_irq());
+        Ok(())
+    }
+}
@@ -1,5 +1,24 @@
+mod bcm2xxx_bus;
+mod bcm2xxx_dma;
+mod bcm2xxx_emmc;
 mod bcm2xxx_gpio;
-mod bcm2xxx_pl011_uart;
+mod bcm2xxx_i2c;
+#[cfg(feature = "bsp_rpi3")]
+mod bcm2xxx_interrupt_controller;
+mod bcm2xxx_mailbox;
+mod bcm2xxx_mini_uart;
+mod bcm2xxx_spi;
+mod bcm2xxx_system_timer;
+mod bcm2xxx_watchdog;
 
+pub use bcm2xxx_dma::*;
+pub use bcm2xxx_emmc::*;
 pub use bcm2xxx_gpio::*;
-pub use bcm2xxx_pl011_uart::*;
+pub use bcm2xxx_i2c::*;
+#[cfg(feature = "bsp_rpi3")]
+pub use bcm2xxx_interrupt_controller::*;
+pub use bcm2xxx_mailbox::*;
+pub use bcm2xxx_mini_uart::*;
+pub use bcm2xxx_spi::*;
+pub use bcm2xxx_system_timer::*;
+pub use bcm2xxx_watchdog::*;
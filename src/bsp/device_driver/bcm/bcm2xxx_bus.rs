@@ -0,0 +1,21 @@
+// The VideoCore's view of the ARM's RAM, shared by the drivers that hand it addresses.
+//
+// The VideoCore, and the DMA engine with it, sees the ARM's RAM from bus address 0xC000_0000 on,
+// bypassing its own L2 cache. Only the first GiB of RAM is reachable that way.
+
+use core::convert::TryFrom;
+
+const RAM_ALIAS: u32 = 0xC000_0000;
+
+// The bus address RAM at `addr` is seen at, or `None` if it is out of the VideoCore's reach.
+pub fn ram_bus_address(addr: usize) -> Option<u32> {
+    u32::try_from(addr)
+        .ok()
+        .filter(|addr| addr & RAM_ALIAS == 0)
+        .map(|addr| addr | RAM_ALIAS)
+}
+
+// The RAM address behind a bus address the VideoCore handed out.
+pub fn ram_address(bus_addr: u32) -> usize {
+    (bus_addr & !RAM_ALIAS) as usize
+}
@@ -0,0 +1,563 @@
+// BCM2835 DMA controller driver.
+//
+// The controller has 15 channels that each run a chain of control blocks from memory. The driver
+// hands channels out one at a time, and runs a single control block on them per transfer. Channels
+// 7 and up are "lite" ones, which move at most 64 KiB per block. The BCM2711's channels 11 to 14
+// are DMA4 engines with another register layout, the BSP leaves them out.
+//
+// The engine addresses everything through the VideoCore's bus: peripherals at 0x7E00_0000 and the
+// ARM's RAM through the alias at 0xC000_0000, which bypasses the VideoCore's L2 cache. Only the
+// first GiB of RAM is reachable. RAM stays mapped cacheable for the CPU, so buffers the engine
+// works on need explicit cache maintenance around each transfer, which `DmaBuffer` does.
+//
+// ```
+// let channel = DMA.allocate()?;
+// dst.sync_for_device();
+// src.sync_for_device();
+// channel.start(Endpoint::Memory(src.bus_address()?), Endpoint::Memory(dst.bus_address()?), len)?;
+// channel.wait(timeout)?;
+// dst.sync_for_cpu();
+// ```
+
+use core::{
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+
+use spin::Mutex;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::{ReadOnly, ReadWrite},
+};
+
+use super::bcm2xxx_bus;
+use crate::{
+    bsp::{device_driver::common::MMIODerefWrapper, exception::asynchronous::IRQNumber},
+    driver,
+    exception::{
+        self,
+        asynchronous::{exec_with_irq_masked, IRQDescriptor, IRQHandler},
+    },
+    kprintln,
+    memory::cache,
+    shell::parse_number,
+    time::{self, TimeManager},
+};
+
+register_bitfields! {
+    u32,
+
+    // Control and Status. END and INT are cleared by writing 1.
+    CS [
+        RESET OFFSET(31) NUMBITS(1) [],
+        ABORT OFFSET(30) NUMBITS(1) [],
+        WAIT_FOR_OUTSTANDING_WRITES OFFSET(28) NUMBITS(1) [],
+        PANIC_PRIORITY OFFSET(20) NUMBITS(4) [],
+        PRIORITY OFFSET(16) NUMBITS(4) [],
+        ERROR OFFSET(8) NUMBITS(1) [],
+        INT OFFSET(2) NUMBITS(1) [],
+        END OFFSET(1) NUMBITS(1) [],
+        ACTIVE OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Debug. The error flags are cleared by writing 1.
+    DEBUG [
+        LITE OFFSET(28) NUMBITS(1) [],
+        READ_ERROR OFFSET(2) NUMBITS(1) [],
+        FIFO_ERROR OFFSET(1) NUMBITS(1) [],
+        READ_LAST_NOT_SET_ERROR OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    ChannelRegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => CONBLK_AD: ReadWrite<u32>),
+        (0x08 => TI: ReadOnly<u32>),
+        (0x0C => SOURCE_AD: ReadOnly<u32>),
+        (0x10 => DEST_AD: ReadOnly<u32>),
+        (0x14 => TXFR_LEN: ReadOnly<u32>),
+        (0x18 => STRIDE: ReadOnly<u32>),
+        (0x1C => NEXTCONBK: ReadOnly<u32>),
+        (0x20 => DEBUG: ReadWrite<u32, DEBUG::Register>),
+        (0x24 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => _reserved1),
+        // A bit per channel with a pending interrupt
+        (0xFE0 => INT_STATUS: ReadOnly<u32>),
+        (0xFE4 => _reserved2),
+        // A bit per channel, clear to turn the channel off
+        (0xFF0 => ENABLE: ReadWrite<u32>),
+        (0xFF4 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+type ChannelRegisters = MMIODerefWrapper<ChannelRegisterBlock>;
+
+// Transfer Information, the first word of a control block
+mod ti {
+    pub const INTEN: u32 = 1 << 0;
+    pub const WAIT_RESP: u32 = 1 << 3;
+    pub const DEST_INC: u32 = 1 << 4;
+    pub const DEST_DREQ: u32 = 1 << 6;
+    pub const SRC_INC: u32 = 1 << 8;
+    pub const SRC_DREQ: u32 = 1 << 10;
+
+    // The peripheral whose DREQ paces the transfer
+    pub const fn permap(dreq: u32) -> u32 {
+        (dreq & 0x1F) << 16
+    }
+}
+
+pub const NUM_CHANNELS: usize = 15;
+
+const CHANNEL_STRIDE: usize = 0x100;
+
+// Where the peripherals are on the VideoCore's bus
+const BUS_ADDRESS_PERIPHERALS: u32 = 0x7E00_0000;
+
+// The most a control block moves on a full channel, and on a lite one
+const MAX_TRANSFER: usize = 0x3FFF_FFFF;
+const MAX_TRANSFER_LITE: usize = 0xFFFF;
+
+// Channels the firmware leaves to the ARM on current firmware, used until the BSP sets the mask it
+// got from the firmware
+const DEFAULT_USABLE_CHANNELS: u16 = 0x7F35;
+
+// The `dma copy` shell command's buffers
+const TEST_BUFFER_SIZE: usize = 4096;
+
+// Bytes per second the engine is assumed to move at least, for timeouts
+const MIN_THROUGHPUT: u64 = 10_000_000;
+
+// Added to the time a transfer takes at `MIN_THROUGHPUT`
+const TRANSFER_TIMEOUT_MARGIN: Duration = Duration::from_millis(10);
+
+// A control block, as the engine reads it from memory
+#[repr(C, align(32))]
+#[derive(Clone, Copy)]
+struct ControlBlock {
+    ti: u32,
+    source: u32,
+    dest: u32,
+    len: u32,
+    stride: u32,
+    next: u32,
+    _reserved: [u32; 2],
+}
+
+// Where a transfer reads from or writes to.
+#[derive(Clone, Copy)]
+pub enum Endpoint {
+    // Memory at this bus address, stepped through
+    Memory(u32),
+    // A peripheral register at this bus address, paced by the peripheral's DREQ line
+    Peripheral { bus_addr: u32, dreq: u32 },
+}
+
+// Memory for the engine to read or write. It takes whole cache lines, so that the maintenance
+// around transfers does not touch anything else.
+#[repr(C, align(64))]
+pub struct DmaBuffer<const N: usize> {
+    data: [u8; N],
+}
+
+struct DMAControllerInner {
+    // Channels the driver handles at all
+    supported: u16,
+    usable: u16,
+    allocated: u16,
+    // Completion interrupts per channel
+    completions: [u32; NUM_CHANNELS],
+    control_blocks: [ControlBlock; NUM_CHANNELS],
+}
+
+pub struct DMAController {
+    registers: Registers,
+    channels_start_addr: usize,
+    peripherals_start_addr: usize,
+    irq_numbers: [IRQNumber; NUM_CHANNELS],
+    inner: Mutex<DMAControllerInner>,
+}
+
+// A channel taken for exclusive use, freed on drop.
+pub struct DmaChannel {
+    controller: &'static DMAController,
+    number: usize,
+}
+
+static TEST_BUFFERS: Mutex<(DmaBuffer<TEST_BUFFER_SIZE>, DmaBuffer<TEST_BUFFER_SIZE>)> =
+    Mutex::new((DmaBuffer::new(), DmaBuffer::new()));
+
+// The bus address the engine reaches RAM at `addr` through.
+fn ram_bus_address(addr: usize) -> Result<u32, &'static str> {
+    bcm2xxx_bus::ram_bus_address(addr).ok_or("Memory out of the DMA engine's reach")
+}
+
+impl ControlBlock {
+    const ZERO: Self = Self {
+        ti: 0,
+        source: 0,
+        dest: 0,
+        len: 0,
+        stride: 0,
+        next: 0,
+        _reserved: [0; 2],
+    };
+}
+
+impl Endpoint {
+    // The endpoint's address and its part of the transfer information, as source or destination.
+    fn split(self, source: bool) -> (u32, u32) {
+        match (self, source) {
+            (Endpoint::Memory(bus_addr), true) => (bus_addr, ti::SRC_INC),
+            (Endpoint::Memory(bus_addr), false) => (bus_addr, ti::DEST_INC),
+            (Endpoint::Peripheral { bus_addr, dreq }, true) => {
+                (bus_addr, ti::SRC_DREQ | ti::permap(dreq))
+            }
+            (Endpoint::Peripheral { bus_addr, dreq }, false) => {
+                (bus_addr, ti::DEST_DREQ | ti::permap(dreq))
+            }
+        }
+    }
+}
+
+impl<const N: usize> DmaBuffer<N> {
+    pub const fn new() -> Self {
+        Self { data: [0; N] }
+    }
+
+    pub fn bus_address(&self) -> Result<u32, &'static str> {
+        ram_bus_address(self.data.as_ptr() as usize)
+    }
+
+    // Hand the buffer to the engine: what the CPU wrote reaches memory, and no dirty line is left
+    // to be written back over what the engine writes.
+    pub fn sync_for_device(&mut self) {
+        cache::clean_invalidate_dcache_range(
+            self as *const _ as usize,
+            core::mem::size_of::<Self>(),
+        );
+    }
+
+    // Take the buffer back after the engine is done with it, dropping lines the CPU may have
+    // fetched in the meantime. Writes by the CPU since `sync_for_device` are lost.
+    pub fn sync_for_cpu(&mut self) {
+        unsafe {
+            cache::invalidate_dcache_range(self as *const _ as usize, core::mem::size_of::<Self>())
+        };
+    }
+}
+
+impl<const N: usize> Deref for DmaBuffer<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl<const N: usize> DerefMut for DmaBuffer<N> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl DMAController {
+    // Create an instance. `irq_numbers` are the channels' interrupts, some channels share one.
+    // Only the channels in `supported` are ever used.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide correct MMIO start addresses.
+    pub const unsafe fn new(
+        mmio_start_addr: usize,
+        peripherals_start_addr: usize,
+        irq_numbers: [IRQNumber; NUM_CHANNELS],
+        supported: u16,
+    ) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            channels_start_addr: mmio_start_addr,
+            peripherals_start_addr,
+            irq_numbers,
+            inner: Mutex::new(DMAControllerInner {
+                supported,
+                usable: DEFAULT_USABLE_CHANNELS & supported,
+                allocated: 0,
+                completions: [0; NUM_CHANNELS],
+                control_blocks: [ControlBlock::ZERO; NUM_CHANNELS],
+            }),
+        }
+    }
+
+    fn locked<R>(&self, f: impl FnOnce(&mut DMAControllerInner) -> R) -> R {
+        exec_with_irq_masked(|| f(&mut self.inner.lock()))
+    }
+
+    fn channel_registers(&self, number: usize) -> ChannelRegisters {
+        unsafe { ChannelRegisters::new(self.channels_start_addr + number * CHANNEL_STRIDE) }
+    }
+
+    // Restrict allocation to the channels in `mask`, as the firmware reports them. Channels that
+    // are taken already stay so.
+    pub fn set_usable_channels(&self, mask: u32) {
+        self.locked(|inner| inner.usable = mask as u16 & inner.supported);
+    }
+
+    // The bus address of the peripheral register at the physical address `addr`.
+    pub fn peripheral_bus_address(&self, addr: usize) -> u32 {
+        BUS_ADDRESS_PERIPHERALS + (addr - self.peripherals_start_addr) as u32
+    }
+
+    // Take a free channel, preferring full ones over lite ones.
+    pub fn allocate(&'static self) -> Result<DmaChannel, &'static str> {
+        let number = self.locked(|inner| {
+            let free = inner.usable & !inner.allocated;
+            let number = (0..NUM_CHANNELS).find(|n| free & (1 << n) != 0)?;
+
+            inner.allocated |= 1 << number;
+            self.registers
+                .ENABLE
+                .set(self.registers.ENABLE.get() | 1 << number);
+            Some(number)
+        });
+        let number = number.ok_or("No free DMA channel")?;
+
+        let channel = DmaChannel {
+            controller: self,
+            number,
+        };
+        channel.abort();
+
+        Ok(channel)
+    }
+
+    // How long a transfer of `len` bytes may take.
+    pub fn transfer_timeout(len: usize) -> Duration {
+        Duration::from_micros(len as u64 * 1_000_000 / MIN_THROUGHPUT) + TRANSFER_TIMEOUT_MARGIN
+    }
+
+    // `dma [copy <len>]`: list the channels, or copy between two buffers and check the result.
+    pub fn command(&'static self, args: &[&str]) -> Result<(), &'static str> {
+        match (args.get(1).copied(), args.get(2)) {
+            (None, _) => {
+                let (usable, allocated, completions) =
+                    self.locked(|inner| (inner.usable, inner.allocated, inner.completions));
+
+                for number in (0..NUM_CHANNELS).filter(|n| usable & (1 << n) != 0) {
+                    let lite = self.channel_registers(number).DEBUG.is_set(DEBUG::LITE);
+
+                    kprintln!(
+                        "  {:>2} {:<4} {:<9} {} completions",
+                        number,
+                        if lite { "lite" } else { "full" },
+                        if allocated & (1 << number) != 0 {
+                            "allocated"
+                        } else {
+                            "free"
+                        },
+                        completions[number]
+                    );
+                }
+                Ok(())
+            }
+            (Some("copy"), Some(len)) => {
+                let len = match parse_number(len)? as usize {
+                    len @ 1..=TEST_BUFFER_SIZE => len,
+                    _ => return Err("Invalid length"),
+                };
+
+                self.copy_test(len)
+            }
+            _ => Err("Invalid arguments"),
+        }
+    }
+
+    fn copy_test(&'static self, len: usize) -> Result<(), &'static str> {
+        let channel = self.allocate()?;
+        let mut buffers = TEST_BUFFERS.lock();
+        let (src, dst) = &mut *buffers;
+
+        for (i, byte) in src.iter_mut().enumerate() {
+            *byte = (i * 7 + 3) as u8;
+        }
+        dst.fill(0);
+        src.sync_for_device();
+        dst.sync_for_device();
+
+        let start = time::time_manager().uptime();
+        channel.start(
+            Endpoint::Memory(src.bus_address()?),
+            Endpoint::Memory(dst.bus_address()?),
+            len,
+        )?;
+        channel.wait(Self::transfer_timeout(len))?;
+        let elapsed = time::time_manager().uptime() - start;
+        dst.sync_for_cpu();
+
+        if src[..len] != dst[..len] || dst[len..].iter().any(|&byte| byte != 0) {
+            return Err("Copy differs from the original");
+        }
+        kprintln!(
+            "  Channel {}: {} bytes in {} us",
+            channel.number,
+            len,
+            elapsed.as_micros()
+        );
+
+        Ok(())
+    }
+}
+
+impl driver::DeviceDriver for DMAController {
+    fn compatible(&self) -> &'static str {
+        "BCM DMA"
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use exception::asynchronous::irq_manager;
+
+        let usable = self.locked(|inner| inner.usable);
+        let descriptor = IRQDescriptor {
+            name: "BCM DMA",
+            handler: self,
+        };
+
+        // Channels sharing an interrupt are next to each other
+        let mut last = None;
+        for number in (0..NUM_CHANNELS).filter(|n| usable & (1 << n) != 0) {
+            let irq_number = self.irq_numbers[number];
+
+            if last != Some(irq_number.get()) {
+                irq_manager().register_handler(irq_number, descriptor)?;
+                irq_manager().enable(irq_number);
+                last = Some(irq_number.get());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl IRQHandler for DMAController {
+    fn handle(&self) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock();
+        // The firmware's channels raise their interrupts on the VideoCore
+        let pending = self.registers.INT_STATUS.get() & u32::from(inner.allocated);
+
+        for number in (0..NUM_CHANNELS).filter(|n| pending & (1 << n) != 0) {
+            self.channel_registers(number).CS.write(CS::INT::SET);
+            inner.completions[number] += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl DmaChannel {
+    fn registers(&self) -> ChannelRegisters {
+        self.controller.channel_registers(self.number)
+    }
+
+    // Stop whatever runs, and clear the status.
+    pub fn abort(&self) {
+        let registers = self.registers();
+
+        registers.CS.write(CS::RESET::SET);
+        registers.CS.write(CS::END::SET + CS::INT::SET);
+        registers.DEBUG.write(
+            DEBUG::READ_ERROR::SET + DEBUG::FIFO_ERROR::SET + DEBUG::READ_LAST_NOT_SET_ERROR::SET,
+        );
+    }
+
+    pub fn max_transfer(&self) -> usize {
+        if self.registers().DEBUG.is_set(DEBUG::LITE) {
+            MAX_TRANSFER_LITE
+        } else {
+            MAX_TRANSFER
+        }
+    }
+
+    // Start moving `len` bytes from `source` to `dest`. The channel raises its interrupt when done.
+    pub fn start(&self, source: Endpoint, dest: Endpoint, len: usize) -> Result<(), &'static str> {
+        if len == 0 || len > self.max_transfer() {
+            return Err("Invalid DMA transfer length");
+        }
+        if self.is_busy() {
+            return Err("DMA channel busy");
+        }
+
+        let (source, source_ti) = source.split(true);
+        let (dest, dest_ti) = dest.split(false);
+        let block = ControlBlock {
+            ti: ti::INTEN | ti::WAIT_RESP | source_ti | dest_ti,
+            source,
+            dest,
+            len: len as u32,
+            stride: 0,
+            next: 0,
+            _reserved: [0; 2],
+        };
+
+        let block_addr = self.controller.locked(|inner| {
+            let slot = &mut inner.control_blocks[self.number];
+            *slot = block;
+
+            let addr = slot as *const ControlBlock as usize;
+            cache::clean_dcache_range(addr, core::mem::size_of::<ControlBlock>());
+            addr
+        });
+
+        let registers = self.registers();
+        registers.CS.write(CS::END::SET + CS::INT::SET);
+        registers.CONBLK_AD.set(ram_bus_address(block_addr)?);
+        registers.CS.write(
+            CS::ACTIVE::SET
+                + CS::WAIT_FOR_OUTSTANDING_WRITES::SET
+                + CS::PRIORITY.val(8)
+                + CS::PANIC_PRIORITY.val(15),
+        );
+
+        Ok(())
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.registers().CS.is_set(CS::ACTIVE)
+    }
+
+    // Wait for the transfer to end, and report how it went. A transfer still running at the
+    // timeout is aborted.
+    pub fn wait(&self, timeout: Duration) -> Result<(), &'static str> {
+        let deadline = time::time_manager().uptime() + timeout;
+
+        while self.is_busy() {
+            if time::time_manager().uptime() > deadline {
+                self.abort();
+                return Err("DMA transfer timeout");
+            }
+        }
+
+        if self.registers().CS.is_set(CS::ERROR) {
+            self.abort();
+            return Err("DMA transfer error");
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for DmaChannel {
+    fn drop(&mut self) {
+        self.abort();
+        self.controller
+            .locked(|inner| inner.allocated &= !(1 << self.number));
+    }
+}
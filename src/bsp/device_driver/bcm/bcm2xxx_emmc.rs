@@ -0,0 +1,734 @@
+// EMMC driver for the SD card slot - BCM2837 EMMC and BCM2711 EMMC2.
+//
+// Both are SDHCI-style host controllers. The driver brings up one SD card (SDSC, SDHC or SDXC) and
+// moves data by PIO through the data port, one 512 byte block at a time. Interrupts are not used,
+// the controller's status bits are polled.
+//
+// The pins are expected to be routed to the controller already, which the firmware does for the
+// card it booted from.
+
+use core::time::Duration;
+
+use spin::Mutex;
+use tock_registers::{
+    fields::Field,
+    interfaces::{ReadWriteable, Readable, Writeable},
+    registers::{ReadOnly, ReadWrite},
+};
+
+use crate::{
+    block,
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver, kwarn,
+    time::{self, TimeManager},
+};
+
+register_bitfields! {
+    u32,
+
+    // Block Size and Count
+    BLKSIZECNT [
+        BLKCNT OFFSET(16) NUMBITS(16) [],
+        BLKSIZE OFFSET(0) NUMBITS(10) []
+    ],
+
+    // Command and Transfer Mode
+    CMDTM [
+        // Index of the command to be issued to the card.
+        CMD_INDEX OFFSET(24) NUMBITS(6) [],
+
+        // Whether the command involves a data transfer.
+        CMD_ISDATA OFFSET(21) NUMBITS(1) [],
+
+        // Check that the response has the same index as the command.
+        CMD_IXCHK_EN OFFSET(20) NUMBITS(1) [],
+
+        // Check the response's CRC.
+        CMD_CRCCHK_EN OFFSET(19) NUMBITS(1) [],
+
+        CMD_RSPNS_TYPE OFFSET(16) NUMBITS(2) [
+            None = 0b00,
+            Bits136 = 0b01,
+            Bits48 = 0b10,
+            Bits48Busy = 0b11
+        ],
+
+        // Multi-block transfer.
+        TM_MULTI_BLOCK OFFSET(5) NUMBITS(1) [],
+
+        // Direction of the data transfer.
+        TM_DAT_DIR OFFSET(4) NUMBITS(1) [
+            HostToCard = 0,
+            CardToHost = 1
+        ],
+
+        // Command to send automatically after a multi-block transfer.
+        TM_AUTO_CMD_EN OFFSET(2) NUMBITS(2) [
+            None = 0b00,
+            Cmd12 = 0b01
+        ],
+
+        // Enable the block counter.
+        TM_BLKCNT_EN OFFSET(1) NUMBITS(1) []
+    ],
+
+    // Status
+    STATUS [
+        // Data can be read from the data port.
+        READ_AVAILABLE OFFSET(11) NUMBITS(1) [],
+
+        // Data can be written to the data port.
+        WRITE_AVAILABLE OFFSET(10) NUMBITS(1) [],
+
+        // The data lines are still in use by a previous transfer.
+        DAT_INHIBIT OFFSET(1) NUMBITS(1) [],
+
+        // The command line is still in use by a previous command.
+        CMD_INHIBIT OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Host Configuration 0
+    CONTROL0 [
+        // SD bus voltage select. EMMC2 only.
+        SD_BUS_VOLTAGE OFFSET(9) NUMBITS(3) [
+            V3_3 = 0b111
+        ],
+
+        // SD bus power. EMMC2 only.
+        SD_BUS_POWER OFFSET(8) NUMBITS(1) [],
+
+        // Use 4 data lines.
+        HCTL_DWIDTH OFFSET(1) NUMBITS(1) []
+    ],
+
+    // Host Configuration 1
+    CONTROL1 [
+        // Reset the data handling circuit.
+        SRST_DATA OFFSET(26) NUMBITS(1) [],
+
+        // Reset the command handling circuit.
+        SRST_CMD OFFSET(25) NUMBITS(1) [],
+
+        // Reset the complete host circuit.
+        SRST_HC OFFSET(24) NUMBITS(1) [],
+
+        // Data timeout unit exponent. The timeout is `TMCLK * 2^(DATA_TOUNIT + 13)`.
+        DATA_TOUNIT OFFSET(16) NUMBITS(4) [
+            Max = 0b1110
+        ],
+
+        // The lower 8 bits of the 10 bit clock divider.
+        CLK_FREQ8 OFFSET(8) NUMBITS(8) [],
+
+        // The upper 2 bits of the 10 bit clock divider.
+        CLK_FREQ_MS2 OFFSET(6) NUMBITS(2) [],
+
+        // SD clock enable.
+        CLK_EN OFFSET(2) NUMBITS(1) [],
+
+        // The internal clock is stable.
+        CLK_STABLE OFFSET(1) NUMBITS(1) [],
+
+        // Internal clock enable.
+        CLK_INTLEN OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Interrupt Flags. Bits are cleared by writing 1 to them.
+    INTERRUPT [
+        // Data timeout, CRC or end bit error
+        DATA_ERR OFFSET(20) NUMBITS(3) [],
+
+        // Command timeout.
+        CTO_ERR OFFSET(16) NUMBITS(1) [],
+
+        // Any error occurred.
+        ERR OFFSET(15) NUMBITS(1) [],
+
+        // The data port can be read.
+        READ_RDY OFFSET(5) NUMBITS(1) [],
+
+        // The data port can be written.
+        WRITE_RDY OFFSET(4) NUMBITS(1) [],
+
+        // Data transfer finished.
+        DATA_DONE OFFSET(1) NUMBITS(1) [],
+
+        // Command finished.
+        CMD_DONE OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Capabilities, as far as implemented.
+    CAPABILITIES_0 [
+        // Base clock frequency in MHz. Zero if not reported.
+        BASE_CLOCK OFFSET(8) NUMBITS(8) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x04 => BLKSIZECNT: ReadWrite<u32, BLKSIZECNT::Register>),
+        (0x08 => ARG1: ReadWrite<u32>),
+        (0x0C => CMDTM: ReadWrite<u32, CMDTM::Register>),
+        (0x10 => RESP: [ReadOnly<u32>; 4]),
+        (0x20 => DATA: ReadWrite<u32>),
+        (0x24 => STATUS: ReadOnly<u32, STATUS::Register>),
+        (0x28 => CONTROL0: ReadWrite<u32, CONTROL0::Register>),
+        (0x2C => CONTROL1: ReadWrite<u32, CONTROL1::Register>),
+        (0x30 => INTERRUPT: ReadWrite<u32, INTERRUPT::Register>),
+        (0x34 => IRPT_MASK: ReadWrite<u32>),
+        (0x38 => IRPT_EN: ReadWrite<u32>),
+        (0x3C => CONTROL2: ReadWrite<u32>),
+        (0x40 => CAPABILITIES_0: ReadOnly<u32, CAPABILITIES_0::Register>),
+        (0x44 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+const BLOCK_SIZE: usize = 512;
+
+// Clock rates of the SD bus during card identification and afterwards.
+const CLOCK_IDENTIFICATION_HZ: u32 = 400_000;
+const CLOCK_TRANSFER_HZ: u32 = 25_000_000;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(100);
+const DATA_TIMEOUT: Duration = Duration::from_millis(500);
+const RESET_TIMEOUT: Duration = Duration::from_millis(100);
+const POWER_UP_TIMEOUT: Duration = Duration::from_secs(1);
+
+// SEND_IF_COND argument: 2.7 - 3.6 V and a check pattern the card echoes back.
+const IF_COND_ARG: u32 = 0x1AA;
+
+// SD_SEND_OP_COND argument and response bits.
+const OCR_VOLTAGE_WINDOW: u32 = 0x00FF_8000;
+const OCR_HCS: u32 = 1 << 30;
+const OCR_BUSY_DONE: u32 = 1 << 31;
+
+#[derive(Clone, Copy)]
+enum Response {
+    None,
+    // R1, R6, R7
+    R1,
+    // R1 with busy signaling on the data line
+    R1b,
+    // CID or CSD register
+    R2,
+    // OCR register, no CRC or index
+    R3,
+}
+
+#[derive(Clone, Copy)]
+enum Transfer {
+    None,
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy)]
+struct Command {
+    index: u32,
+    response: Response,
+    // Application specific commands are preceded by APP_CMD.
+    app: bool,
+}
+
+impl Command {
+    const fn new(index: u32, response: Response) -> Self {
+        Self {
+            index,
+            response,
+            app: false,
+        }
+    }
+
+    const fn app(index: u32, response: Response) -> Self {
+        Self {
+            index,
+            response,
+            app: true,
+        }
+    }
+}
+
+mod cmd {
+    use super::{Command, Response};
+
+    pub(super) const GO_IDLE_STATE: Command = Command::new(0, Response::None);
+    pub(super) const ALL_SEND_CID: Command = Command::new(2, Response::R2);
+    pub(super) const SEND_RELATIVE_ADDR: Command = Command::new(3, Response::R1);
+    pub(super) const SELECT_CARD: Command = Command::new(7, Response::R1b);
+    pub(super) const SEND_IF_COND: Command = Command::new(8, Response::R1);
+    pub(super) const SEND_CSD: Command = Command::new(9, Response::R2);
+    pub(super) const SET_BLOCKLEN: Command = Command::new(16, Response::R1);
+    pub(super) const READ_SINGLE_BLOCK: Command = Command::new(17, Response::R1);
+    pub(super) const READ_MULTIPLE_BLOCK: Command = Command::new(18, Response::R1);
+    pub(super) const WRITE_BLOCK: Command = Command::new(24, Response::R1);
+    pub(super) const WRITE_MULTIPLE_BLOCK: Command = Command::new(25, Response::R1);
+    pub(super) const APP_CMD: Command = Command::new(55, Response::R1);
+
+    pub(super) const SET_BUS_WIDTH: Command = Command::app(6, Response::R1);
+    pub(super) const SD_SEND_OP_COND: Command = Command::app(41, Response::R3);
+}
+
+#[derive(PartialEq, Eq)]
+enum CommandError {
+    // The card did not answer. Expected in some places during identification.
+    Timeout,
+    Failed(&'static str),
+}
+
+impl From<CommandError> for &'static str {
+    fn from(e: CommandError) -> Self {
+        match e {
+            CommandError::Timeout => "SD command timeout",
+            CommandError::Failed(msg) => msg,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Card {
+    rca: u32,
+    // SDHC and SDXC cards are addressed in blocks, SDSC cards in bytes.
+    block_addressing: bool,
+    num_blocks: u64,
+}
+
+struct EMMCInner {
+    registers: Registers,
+    base_clock_hz: u32,
+    card: Option<Card>,
+}
+
+pub struct EMMCController {
+    inner: Mutex<EMMCInner>,
+}
+
+// Spin until `done()` returns true or `timeout` passed. Returns whether `done()` returned true.
+fn wait_for(timeout: Duration, mut done: impl FnMut() -> bool) -> bool {
+    let deadline = time::time_manager().uptime() + timeout;
+
+    loop {
+        if done() {
+            return true;
+        }
+
+        if time::time_manager().uptime() > deadline {
+            return false;
+        }
+    }
+}
+
+impl EMMCInner {
+    const unsafe fn new(mmio_start_addr: usize, base_clock_hz: u32) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            base_clock_hz,
+            card: None,
+        }
+    }
+
+    fn reset_host(&mut self) -> Result<(), &'static str> {
+        self.registers.CONTROL2.set(0);
+        self.registers.CONTROL1.write(CONTROL1::SRST_HC::SET);
+
+        let reset_done = wait_for(RESET_TIMEOUT, || {
+            !self.registers.CONTROL1.is_set(CONTROL1::SRST_HC)
+        });
+        if !reset_done {
+            return Err("EMMC host reset timeout");
+        }
+
+        #[cfg(feature = "bsp_rpi4")]
+        self.registers
+            .CONTROL0
+            .write(CONTROL0::SD_BUS_VOLTAGE::V3_3 + CONTROL0::SD_BUS_POWER::SET);
+
+        // Report all events in INTERRUPT, but do not signal any of them
+        self.registers.IRPT_EN.set(0);
+        self.registers.IRPT_MASK.set(u32::MAX);
+        self.registers.INTERRUPT.set(u32::MAX);
+
+        Ok(())
+    }
+
+    // Reset the command and data circuits after an error.
+    fn reset_lines(&mut self) {
+        self.registers
+            .CONTROL1
+            .modify(CONTROL1::SRST_CMD::SET + CONTROL1::SRST_DATA::SET);
+
+        wait_for(RESET_TIMEOUT, || {
+            !self.registers.CONTROL1.is_set(CONTROL1::SRST_CMD)
+                && !self.registers.CONTROL1.is_set(CONTROL1::SRST_DATA)
+        });
+        self.registers.INTERRUPT.set(u32::MAX);
+    }
+
+    // Set the SD clock to at most `hz`.
+    fn set_clock(&mut self, hz: u32) -> Result<(), &'static str> {
+        // The SD clock is `base / (2 * divider)`, or `base` if the divider is zero
+        let divider = if hz >= self.base_clock_hz {
+            0
+        } else {
+            let d = (self.base_clock_hz + 2 * hz - 1) / (2 * hz);
+            d.min(0x3FF)
+        };
+
+        self.registers.CONTROL1.modify(CONTROL1::CLK_EN::CLEAR);
+        self.registers.CONTROL1.modify(
+            CONTROL1::CLK_FREQ8.val(divider & 0xFF)
+                + CONTROL1::CLK_FREQ_MS2.val(divider >> 8)
+                + CONTROL1::DATA_TOUNIT::Max
+                + CONTROL1::CLK_INTLEN::SET,
+        );
+
+        let stable = wait_for(RESET_TIMEOUT, || {
+            self.registers.CONTROL1.is_set(CONTROL1::CLK_STABLE)
+        });
+        if !stable {
+            return Err("EMMC clock not stable");
+        }
+
+        self.registers.CONTROL1.modify(CONTROL1::CLK_EN::SET);
+
+        Ok(())
+    }
+
+    fn issue(
+        &mut self,
+        command: Command,
+        arg: u32,
+        transfer: Transfer,
+    ) -> Result<(), CommandError> {
+        let rca = self.card.map_or(0, |card| card.rca);
+
+        if command.app {
+            self.issue(cmd::APP_CMD, rca << 16, Transfer::None)?;
+        }
+
+        let uses_data_line =
+            !matches!(transfer, Transfer::None) || matches!(command.response, Response::R1b);
+
+        let idle = wait_for(COMMAND_TIMEOUT, || {
+            !self.registers.STATUS.is_set(STATUS::CMD_INHIBIT)
+                && (!uses_data_line || !self.registers.STATUS.is_set(STATUS::DAT_INHIBIT))
+        });
+        if !idle {
+            return Err(CommandError::Failed("SD command line busy"));
+        }
+
+        let mut cmdtm = CMDTM::CMD_INDEX.val(command.index);
+        cmdtm += match command.response {
+            Response::None => CMDTM::CMD_RSPNS_TYPE::None,
+            Response::R1 => {
+                CMDTM::CMD_RSPNS_TYPE::Bits48 + CMDTM::CMD_CRCCHK_EN::SET + CMDTM::CMD_IXCHK_EN::SET
+            }
+            Response::R1b => {
+                CMDTM::CMD_RSPNS_TYPE::Bits48Busy
+                    + CMDTM::CMD_CRCCHK_EN::SET
+                    + CMDTM::CMD_IXCHK_EN::SET
+            }
+            Response::R2 => CMDTM::CMD_RSPNS_TYPE::Bits136 + CMDTM::CMD_CRCCHK_EN::SET,
+            Response::R3 => CMDTM::CMD_RSPNS_TYPE::Bits48,
+        };
+
+        if !matches!(transfer, Transfer::None) {
+            cmdtm += CMDTM::CMD_ISDATA::SET + CMDTM::TM_BLKCNT_EN::SET;
+
+            if matches!(transfer, Transfer::Read) {
+                cmdtm += CMDTM::TM_DAT_DIR::CardToHost;
+            }
+
+            if self.registers.BLKSIZECNT.read(BLKSIZECNT::BLKCNT) > 1 {
+                cmdtm += CMDTM::TM_MULTI_BLOCK::SET + CMDTM::TM_AUTO_CMD_EN::Cmd12;
+            }
+        }
+
+        self.registers.INTERRUPT.set(u32::MAX);
+        self.registers.ARG1.set(arg);
+        self.registers.CMDTM.write(cmdtm);
+
+        self.wait_for_interrupt(INTERRUPT::CMD_DONE, COMMAND_TIMEOUT)?;
+
+        // The busy signal ends with DATA_DONE
+        if matches!(command.response, Response::R1b) {
+            self.wait_for_interrupt(INTERRUPT::DATA_DONE, DATA_TIMEOUT)?;
+        }
+
+        Ok(())
+    }
+
+    // Wait for the INTERRUPT flag `flag`, and clear it.
+    fn wait_for_interrupt(
+        &mut self,
+        flag: Field<u32, INTERRUPT::Register>,
+        timeout: Duration,
+    ) -> Result<(), CommandError> {
+        let mut status = 0;
+        let done = wait_for(timeout, || {
+            status = self.registers.INTERRUPT.get();
+            flag.is_set(status) || INTERRUPT::ERR.is_set(status)
+        });
+
+        if !done || INTERRUPT::ERR.is_set(status) {
+            let error = if !done || INTERRUPT::CTO_ERR.is_set(status) {
+                CommandError::Timeout
+            } else if INTERRUPT::DATA_ERR.read(status) != 0 {
+                CommandError::Failed("SD data error")
+            } else {
+                CommandError::Failed("SD command error")
+            };
+
+            self.reset_lines();
+            return Err(error);
+        }
+
+        self.registers.INTERRUPT.set(flag.mask << flag.shift);
+        Ok(())
+    }
+
+    fn response(&self, index: usize) -> u32 {
+        self.registers.RESP[index].get()
+    }
+
+    // Number of 512 byte blocks, from the CSD register. The controller strips the CRC from the
+    // 136 bit response, so CSD bit `n` is bit `n - 8` of the response.
+    fn csd_num_blocks(&self) -> Result<u64, &'static str> {
+        let resp1 = self.response(1) as u64;
+        let resp2 = self.response(2) as u64;
+        let resp3 = self.response(3);
+
+        match (resp3 >> 22) & 0b11 {
+            // CSD version 1.0, SDSC
+            0 => {
+                let c_size = ((resp1 >> 22) & 0x3FF) | ((resp2 & 0b11) << 10);
+                let c_size_mult = (resp1 >> 7) & 0b111;
+                let read_bl_len = (resp2 >> 8) & 0xF;
+
+                let bytes = (c_size + 1) << (c_size_mult + 2 + read_bl_len);
+                Ok(bytes / BLOCK_SIZE as u64)
+            }
+            // CSD version 2.0, SDHC and SDXC. Capacity is in units of 512 KiB.
+            1 => {
+                let c_size = (resp1 >> 8) & 0x3F_FFFF;
+                Ok((c_size + 1) * 1024)
+            }
+            _ => Err("Unknown SD CSD version"),
+        }
+    }
+
+    // Take the card through identification into the transfer state, see the SD Physical Layer
+    // Simplified Specification, section 4.2.
+    fn init_card(&mut self) -> Result<(), &'static str> {
+        self.card = None;
+        self.set_clock(CLOCK_IDENTIFICATION_HZ)?;
+
+        self.issue(cmd::GO_IDLE_STATE, 0, Transfer::None)?;
+
+        // Only cards compliant to version 2.00 or later answer, and only they may be high capacity
+        let v2_card = match self.issue(cmd::SEND_IF_COND, IF_COND_ARG, Transfer::None) {
+            Ok(()) => {
+                if self.response(0) & 0xFFF != IF_COND_ARG {
+                    return Err("SD card rejected the supply voltage");
+                }
+                true
+            }
+            Err(CommandError::Timeout) => false,
+            Err(e) => return Err(e.into()),
+        };
+
+        let op_cond_arg = OCR_VOLTAGE_WINDOW | if v2_card { OCR_HCS } else { 0 };
+        let mut ocr = 0;
+        let powered_up = wait_for(POWER_UP_TIMEOUT, || {
+            match self.issue(cmd::SD_SEND_OP_COND, op_cond_arg, Transfer::None) {
+                Ok(()) => ocr = self.response(0),
+                Err(_) => ocr = 0,
+            }
+            ocr & OCR_BUSY_DONE != 0
+        });
+        if !powered_up {
+            return Err("SD card did not power up");
+        }
+
+        self.issue(cmd::ALL_SEND_CID, 0, Transfer::None)?;
+        self.issue(cmd::SEND_RELATIVE_ADDR, 0, Transfer::None)?;
+        let rca = self.response(0) >> 16;
+
+        self.issue(cmd::SEND_CSD, rca << 16, Transfer::None)?;
+        let num_blocks = self.csd_num_blocks()?;
+
+        self.issue(cmd::SELECT_CARD, rca << 16, Transfer::None)?;
+
+        let block_addressing = ocr & OCR_HCS != 0;
+        if !block_addressing {
+            self.issue(cmd::SET_BLOCKLEN, BLOCK_SIZE as u32, Transfer::None)?;
+        }
+
+        self.card = Some(Card {
+            rca,
+            block_addressing,
+            num_blocks,
+        });
+
+        // All SD memory cards support 4 data lines
+        self.issue(cmd::SET_BUS_WIDTH, 0b10, Transfer::None)?;
+        self.registers.CONTROL0.modify(CONTROL0::HCTL_DWIDTH::SET);
+
+        self.set_clock(CLOCK_TRANSFER_HZ)
+    }
+
+    fn transfer(
+        &mut self,
+        first_block: u64,
+        num_blocks: usize,
+        transfer: Transfer,
+        mut for_each_block: impl FnMut(&Registers, usize),
+    ) -> Result<(), &'static str> {
+        let card = self.card.ok_or("No SD card")?;
+        let addr = if card.block_addressing {
+            first_block
+        } else {
+            first_block * BLOCK_SIZE as u64
+        };
+        let addr: u32 = addr
+            .try_into()
+            .map_err(|_| "SD card address out of range")?;
+
+        self.registers.BLKSIZECNT.write(
+            BLKSIZECNT::BLKSIZE.val(BLOCK_SIZE as u32) + BLKSIZECNT::BLKCNT.val(num_blocks as u32),
+        );
+
+        let (command, ready) = match (transfer, num_blocks) {
+            (Transfer::Read, 1) => (cmd::READ_SINGLE_BLOCK, INTERRUPT::READ_RDY),
+            (Transfer::Read, _) => (cmd::READ_MULTIPLE_BLOCK, INTERRUPT::READ_RDY),
+            (_, 1) => (cmd::WRITE_BLOCK, INTERRUPT::WRITE_RDY),
+            (_, _) => (cmd::WRITE_MULTIPLE_BLOCK, INTERRUPT::WRITE_RDY),
+        };
+
+        self.issue(command, addr, transfer)?;
+
+        for i in 0..num_blocks {
+            self.wait_for_interrupt(ready, DATA_TIMEOUT)?;
+            for_each_block(&self.registers, i);
+        }
+
+        self.wait_for_interrupt(INTERRUPT::DATA_DONE, DATA_TIMEOUT)
+            .map_err(|e| e.into())
+    }
+
+    fn read_blocks(&mut self, first_block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        // The block counter is 16 bits wide
+        for (i, chunk) in buf.chunks_mut(BLOCK_SIZE * 0xFFFF).enumerate() {
+            let first = first_block + (i * 0xFFFF) as u64;
+
+            self.transfer(
+                first,
+                chunk.len() / BLOCK_SIZE,
+                Transfer::Read,
+                |regs, block| {
+                    let block = &mut chunk[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE];
+
+                    for word in block.chunks_exact_mut(4) {
+                        word.copy_from_slice(&regs.DATA.get().to_le_bytes());
+                    }
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, first_block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        for (i, chunk) in buf.chunks(BLOCK_SIZE * 0xFFFF).enumerate() {
+            let first = first_block + (i * 0xFFFF) as u64;
+
+            self.transfer(
+                first,
+                chunk.len() / BLOCK_SIZE,
+                Transfer::Write,
+                |regs, block| {
+                    let block = &chunk[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE];
+
+                    for word in block.chunks_exact(4) {
+                        regs.DATA
+                            .set(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+                    }
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl EMMCController {
+    // Create an instance. `base_clock_hz` is used unless the controller reports its base clock
+    // itself. Overestimating it only makes the card run slower.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize, base_clock_hz: u32) -> Self {
+        Self {
+            inner: Mutex::new(EMMCInner::new(mmio_start_addr, base_clock_hz)),
+        }
+    }
+
+    // Whether `init()` found a usable card.
+    pub fn is_present(&self) -> bool {
+        self.inner.lock().card.is_some()
+    }
+}
+
+impl driver::DeviceDriver for EMMCController {
+    fn compatible(&self) -> &'static str {
+        "BCM EMMC"
+    }
+
+    // A missing or unusable card is not an error, the kernel does not depend on it.
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock();
+
+        inner.reset_host()?;
+
+        let reported_mhz = inner
+            .registers
+            .CAPABILITIES_0
+            .read(CAPABILITIES_0::BASE_CLOCK);
+        if reported_mhz != 0 {
+            inner.base_clock_hz = reported_mhz * 1_000_000;
+        }
+
+        if let Err(msg) = inner.init_card() {
+            inner.card = None;
+            kwarn!("EMMC: No usable SD card: {}", msg);
+        }
+
+        Ok(())
+    }
+}
+
+impl block::BlockDevice for EMMCController {
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.inner.lock().card.map_or(0, |card| card.num_blocks)
+    }
+
+    fn read_blocks(&self, first_block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        block::check_request(self, first_block, buf.len())?;
+
+        self.inner.lock().read_blocks(first_block, buf)
+    }
+
+    fn write_blocks(&self, first_block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        block::check_request(self, first_block, buf.len())?;
+
+        self.inner.lock().write_blocks(first_block, buf)
+    }
+}
@@ -1,158 +1,943 @@
+// BCM2837/BCM2711 GPIO driver.
+//
+// Each of the 54 pins is an input, an output, or routed to a peripheral by one of six alternate
+// functions. Inputs can latch edges and levels in the event detect registers, which also raise
+// the GPIO interrupts.
+//
+// Users claim pins as `Pin` handles, so that no two of them drive the same pin. A handle's type
+// parameter tells how the pin is configured, and only offers what makes sense for that:
+//
+// ```
+// let led = GPIO.claim(42)?.into_output();
+// led.set_high();
+//
+// let button = GPIO.claim(26)?.into_input();
+// button.set_pull(Pull::Up);
+// button.enable_event(Event::FallingEdge);
+// ```
+//
+// Pins that drivers route to their peripheral once and for all are reserved instead.
+//
+// The GPIO is an IRQ manager for the events of its pins, chained to the interrupt controller. A
+// handler registered for a pin is called for each of its detected events. Events following the
+// last call closer than the pin's debounce time are held back. At the end of that time, the
+// handler is called once more, unless the pin settled back to the level it had at the last call:
+//
+// ```
+// button.set_debounce(Duration::from_millis(20));
+// GPIO.register_handler(button.irq_number(), descriptor)?;
+// GPIO.enable(button.irq_number());
+// ```
+//
+// The end of the debounce time is only noticed with the timer of `set_debounce_timer`, and with
+// the next event otherwise.
+//
+// Levels would be detected again for as long as they last, so the detection of a pin's levels is
+// turned off when its event is taken, until `clear_event` turns it back on. Handlers of level
+// events clear them once they dealt with the cause. Events of pins without an enabled handler are
+// latched for `event_detected`.
+
+use core::{fmt, marker::PhantomData, time::Duration};
+
 use spin::Mutex;
 use tock_registers::{
-    interfaces::{ReadWriteable, Writeable},
-    registers::ReadWrite,
+    interfaces::{Readable, Writeable},
+    registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
-use crate::{bsp::device_driver::common::MMIODerefWrapper, driver};
+use super::{OneShot, SystemTimer};
+use crate::{
+    bsp::{device_driver::common::MMIODerefWrapper, exception::asynchronous::IRQNumber},
+    driver,
+    exception::{
+        self,
+        asynchronous::{exec_with_irq_masked, IRQDescriptor, IRQHandler, IRQManager},
+    },
+    kinfo, kprintln, kwarn,
+    time::{self, TimeManager},
+};
+
+pub const NUM_PINS: usize = 54;
+
+pub type GPIOIRQNumber = exception::asynchronous::IRQNumber<{ NUM_PINS - 1 }>;
 
 register_bitfields![
     u32,
-    GPFSEL1 [
-        // Pin 15
-        FSEL15 OFFSET(15) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100, // PL011 UART RX
-        ],
-
-        // Pin 14
-        FSEL14 OFFSET(12) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100, // PL011 UART TX
-        ],
-    ],
     // GPIO Pull-up/down Register
-        // BCM2837 only
+    // BCM2837 only
     GPPUD [
         PUD OFFSET(0) NUMBITS(2) [
             Off = 0b00,
             PullDown = 0b01,
             PullUp = 0b10,
         ]
-    ],
-
-    // GPIO Pull-up/down Clock Register 0
-    // BCM2837 Only
-    GPPUDCLK0 [
-        // GPIO 15
-        PUDCLK15 OFFSET(15) NUMBITS(1) [
-            NoEffect = 0,
-            AssertClock = 1,
-        ],
-        // GPIO 14
-        PUDCLK14 OFFSET(14) NUMBITS(1) [
-            NoEffect = 0,
-            AssertClock = 1,
-        ]
-    ],
-
-    // GPOP Pull-up/down Register 0
-    // BCM2711 Only
-    GPIO_PUP_PDN_CNTRL_REG0 [
-        // Pin 15
-        GPIO_PUP_PDN_CNTRL15 OFFSET(30) NUMBITS(2) [
-            NoResistor = 0b00,
-            PullUp = 0b01,
-        ],
-
-        /// Pin 14
-        GPIO_PUP_PDN_CNTRL14 OFFSET(28) NUMBITS(2) [
-            NoResistor = 0b00,
-            PullUp = 0b01
-        ]
     ]
 ];
 
+// Registers with one bit per pin come in two banks, pins 0 to 31 and 32 to 53.
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x00 => _reserved1),
-        (0x04 => GPFSEL1: ReadWrite<u32, GPFSEL1::Register>),
-        (0x08 => _reserved2),
+        // Function select, 3 bits for each of 10 pins per register
+        (0x00 => GPFSEL: [ReadWrite<u32>; 6]),
+        (0x18 => _reserved1),
+        (0x1C => GPSET: [WriteOnly<u32>; 2]),
+        (0x24 => _reserved2),
+        (0x28 => GPCLR: [WriteOnly<u32>; 2]),
+        (0x30 => _reserved3),
+        (0x34 => GPLEV: [ReadOnly<u32>; 2]),
+        (0x3C => _reserved4),
+        // Event detect status, write 1 to clear
+        (0x40 => GPEDS: [ReadWrite<u32>; 2]),
+        (0x48 => _reserved5),
+        (0x4C => GPREN: [ReadWrite<u32>; 2]),
+        (0x54 => _reserved6),
+        (0x58 => GPFEN: [ReadWrite<u32>; 2]),
+        (0x60 => _reserved7),
+        (0x64 => GPHEN: [ReadWrite<u32>; 2]),
+        (0x6C => _reserved8),
+        (0x70 => GPLEN: [ReadWrite<u32>; 2]),
+        (0x78 => _reserved9),
+        (0x7C => GPAREN: [ReadWrite<u32>; 2]),
+        (0x84 => _reserved10),
+        (0x88 => GPAFEN: [ReadWrite<u32>; 2]),
+        (0x90 => _reserved11),
+        // BCM2837 only
         (0x94 => GPPUD: ReadWrite<u32, GPPUD::Register>),
-        (0x98 => GPPUDCLK0: ReadWrite<u32, GPPUDCLK0::Register>),
-        (0x9C => _reserved3),
-        (0xE4 => GPIO_PUP_PDN_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_PDN_CNTRL_REG0::Register>),
-        (0xE8 => @END),
+        (0x98 => GPPUDCLK: [ReadWrite<u32>; 2]),
+        (0xA0 => _reserved12),
+        // BCM2711 only, 2 bits for each of 16 pins per register
+        (0xE4 => GPIO_PUP_PDN_CNTRL_REG: [ReadWrite<u32>; 4]),
+        (0xF4 => @END),
     }
 }
 
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AltFunction {
+    Alt0,
+    Alt1,
+    Alt2,
+    Alt3,
+    Alt4,
+    Alt5,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Input,
+    Output,
+    Alt(AltFunction),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    Off,
+    Down,
+    Up,
+}
+
+// What an input's event detect status latches.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    RisingEdge,
+    FallingEdge,
+    High,
+    Low,
+    // Edges not synchronized to the system clock, so that short pulses are caught as well
+    AsyncRisingEdge,
+    AsyncFallingEdge,
+}
+
+const EVENTS: [Event; 6] = [
+    Event::RisingEdge,
+    Event::FallingEdge,
+    Event::High,
+    Event::Low,
+    Event::AsyncRisingEdge,
+    Event::AsyncFallingEdge,
+];
+
 pub struct GPIOInner {
     registers: Registers,
+    // Pins held by `Pin` handles
+    claimed: u64,
+    // Pins drivers routed to their peripheral
+    reserved: u64,
+    // Events the IRQ handler took from pins without an enabled handler
+    latched: u64,
+    // Pins whose high or low level detection is off until their event is cleared
+    masked_high: u64,
+    masked_low: u64,
 }
 
 pub use GPIOInner as PanicGPIO;
+pub use GPIOInner as EarlyGPIO;
+
+#[derive(Clone, Copy)]
+struct PinIRQ {
+    descriptor: Option<IRQDescriptor>,
+    enabled: bool,
+    debounce: Duration,
+    // Uptime at the last event passed on to the handler, and the pin's level then
+    last_event: Option<Duration>,
+    last_high: bool,
+    // Events were held back since
+    bouncing: bool,
+}
+
+// Calls the GPIO's IRQ handler once the debounce time of held back events is over
+struct DebounceTimer {
+    timer: &'static SystemTimer,
+    oneshot: OneShot,
+    // The earliest call asked for that is still to come
+    armed_until: Option<Duration>,
+}
 
 pub struct GPIO {
     inner: Mutex<GPIOInner>,
+    irq_number: IRQNumber,
+    pin_irqs: Mutex<[PinIRQ; NUM_PINS]>,
+    debounce_timer: Mutex<Option<DebounceTimer>>,
+}
+
+// Pin configuration marker types for `Pin`
+pub struct Unconfigured;
+pub struct Input;
+pub struct Output;
+pub struct Alternate;
+
+// A claimed pin. It is released when the handle is dropped, and keeps its configuration.
+pub struct Pin<Mode> {
+    gpio: &'static GPIO,
+    number: usize,
+    mode: PhantomData<Mode>,
+}
+
+// Handlers for `gpio <pin> irq`, which print the events of a pin
+#[derive(Clone, Copy)]
+struct EventPrinter {
+    pin: usize,
+}
+
+static EVENT_PRINTERS: [EventPrinter; NUM_PINS] = {
+    let mut printers = [EventPrinter { pin: 0 }; NUM_PINS];
+    let mut pin = 0;
+
+    while pin < NUM_PINS {
+        printers[pin].pin = pin;
+        pin += 1;
+    }
+
+    printers
+};
+
+// The register bank of a pin, and its bit in there.
+fn bank(pin: usize) -> (usize, u32) {
+    (pin / 32, 1 << (pin % 32))
+}
+
+impl Function {
+    fn from_bits(bits: u32) -> Self {
+        match bits {
+            0b000 => Function::Input,
+            0b001 => Function::Output,
+            0b100 => Function::Alt(AltFunction::Alt0),
+            0b101 => Function::Alt(AltFunction::Alt1),
+            0b110 => Function::Alt(AltFunction::Alt2),
+            0b111 => Function::Alt(AltFunction::Alt3),
+            0b011 => Function::Alt(AltFunction::Alt4),
+            _ => Function::Alt(AltFunction::Alt5),
+        }
+    }
+
+    fn bits(&self) -> u32 {
+        match self {
+            Function::Input => 0b000,
+            Function::Output => 0b001,
+            Function::Alt(AltFunction::Alt0) => 0b100,
+            Function::Alt(AltFunction::Alt1) => 0b101,
+            Function::Alt(AltFunction::Alt2) => 0b110,
+            Function::Alt(AltFunction::Alt3) => 0b111,
+            Function::Alt(AltFunction::Alt4) => 0b011,
+            Function::Alt(AltFunction::Alt5) => 0b010,
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "in" => Function::Input,
+            "out" => Function::Output,
+            "alt0" => Function::Alt(AltFunction::Alt0),
+            "alt1" => Function::Alt(AltFunction::Alt1),
+            "alt2" => Function::Alt(AltFunction::Alt2),
+            "alt3" => Function::Alt(AltFunction::Alt3),
+            "alt4" => Function::Alt(AltFunction::Alt4),
+            "alt5" => Function::Alt(AltFunction::Alt5),
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Function::Input => f.pad("in"),
+            Function::Output => f.pad("out"),
+            Function::Alt(alt) => f.pad(match alt {
+                AltFunction::Alt0 => "alt0",
+                AltFunction::Alt1 => "alt1",
+                AltFunction::Alt2 => "alt2",
+                AltFunction::Alt3 => "alt3",
+                AltFunction::Alt4 => "alt4",
+                AltFunction::Alt5 => "alt5",
+            }),
+        }
+    }
+}
+
+impl Pull {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => Some(Pull::Off),
+            "down" => Some(Pull::Down),
+            "up" => Some(Pull::Up),
+            _ => None,
+        }
+    }
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::RisingEdge => "rising",
+            Event::FallingEdge => "falling",
+            Event::High => "high",
+            Event::Low => "low",
+            Event::AsyncRisingEdge => "async-rising",
+            Event::AsyncFallingEdge => "async-falling",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        EVENTS.iter().copied().find(|event| event.name() == s)
+    }
 }
 
 impl GPIOInner {
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
+            claimed: 0,
+            reserved: 0,
+            latched: 0,
+            masked_high: 0,
+            masked_low: 0,
         }
     }
 
-    // Disable pull-up/down on pins 14 and 15.
-    #[cfg(feature = "bsp_rpi3")]
-    fn disable_pud_14_15_bcm2837(&mut self) {
-        use crate::{time, time::TimeManager};
-        use core::time::Duration;
+    fn function(&self, pin: usize) -> Function {
+        let shift = (pin % 10) * 3;
+
+        Function::from_bits((self.registers.GPFSEL[pin / 10].get() >> shift) & 0b111)
+    }
+
+    fn set_function(&mut self, pin: usize, function: Function) {
+        let register = &self.registers.GPFSEL[pin / 10];
+        let shift = (pin % 10) * 3;
+
+        register.set(register.get() & !(0b111 << shift) | function.bits() << shift);
+    }
+
+    fn is_high(&self, pin: usize) -> bool {
+        let (bank, bit) = bank(pin);
+
+        self.registers.GPLEV[bank].get() & bit != 0
+    }
+
+    // The level the pin drives as an output. It is latched in other functions as well.
+    fn set_level(&mut self, pin: usize, high: bool) {
+        let (bank, bit) = bank(pin);
+
+        if high {
+            self.registers.GPSET[bank].set(bit);
+        } else {
+            self.registers.GPCLR[bank].set(bit);
+        }
+    }
 
+    #[cfg(feature = "bsp_rpi3")]
+    fn set_pull_bcm2837(&mut self, pin: usize, pull: Pull) {
         // The Linux 2837 GPIO driver waits for 1µs between the steps
         const DELAY: Duration = Duration::from_micros(1);
 
-        self.registers.GPPUD.write(GPPUD::PUD::Off);
+        let (bank, bit) = bank(pin);
+
+        self.registers.GPPUD.write(match pull {
+            Pull::Off => GPPUD::PUD::Off,
+            Pull::Down => GPPUD::PUD::PullDown,
+            Pull::Up => GPPUD::PUD::PullUp,
+        });
         time::time_manager().spin_for(DELAY);
 
-        self.registers
-            .GPPUDCLK0
-            .write(GPPUDCLK0::PUDCLK15::AssertClock + GPPUDCLK0::PUDCLK14::AssertClock);
+        self.registers.GPPUDCLK[bank].set(bit);
         time::time_manager().spin_for(DELAY);
 
         self.registers.GPPUD.write(GPPUD::PUD::Off);
-        self.registers.GPPUDCLK0.set(0);
+        self.registers.GPPUDCLK[bank].set(0);
     }
 
-    // Disable pull-up/down on pins 14 and 15.
     #[cfg(feature = "bsp_rpi4")]
-    fn disable_pud_14_15_bcm2711(&mut self) {
-        self.registers.GPIO_PUP_PDN_CNTRL_REG0.write(
-            GPIO_PUP_PDN_CNTRL_REG0::GPIO_PUP_PDN_CNTRL15::PullUp
-                + GPIO_PUP_PDN_CNTRL_REG0::GPIO_PUP_PDN_CNTRL14::PullUp,
-        );
+    fn set_pull_bcm2711(&mut self, pin: usize, pull: Pull) {
+        let register = &self.registers.GPIO_PUP_PDN_CNTRL_REG[pin / 16];
+        let shift = (pin % 16) * 2;
+        let bits = match pull {
+            Pull::Off => 0b00,
+            Pull::Up => 0b01,
+            Pull::Down => 0b10,
+        };
+
+        register.set(register.get() & !(0b11 << shift) | bits << shift);
+    }
+
+    fn set_pull(&mut self, pin: usize, pull: Pull) {
+        #[cfg(feature = "bsp_rpi3")]
+        self.set_pull_bcm2837(pin, pull);
+
+        #[cfg(feature = "bsp_rpi4")]
+        self.set_pull_bcm2711(pin, pull);
+    }
+
+    fn event_register(&self, event: Event) -> &[ReadWrite<u32>; 2] {
+        match event {
+            Event::RisingEdge => &self.registers.GPREN,
+            Event::FallingEdge => &self.registers.GPFEN,
+            Event::High => &self.registers.GPHEN,
+            Event::Low => &self.registers.GPLEN,
+            Event::AsyncRisingEdge => &self.registers.GPAREN,
+            Event::AsyncFallingEdge => &self.registers.GPAFEN,
+        }
+    }
+
+    fn detects(&self, pin: usize, event: Event) -> bool {
+        let (bank, bit) = bank(pin);
+
+        self.event_register(event)[bank].get() & bit != 0
+    }
+
+    // Whether the detection of `event` is off until the pin's event is cleared.
+    fn masked(&self, pin: usize, event: Event) -> bool {
+        match event {
+            Event::High => self.masked_high & 1 << pin != 0,
+            Event::Low => self.masked_low & 1 << pin != 0,
+            _ => false,
+        }
+    }
+
+    fn set_event_detection(&mut self, pin: usize, event: Event, enabled: bool) {
+        let (bank, bit) = bank(pin);
+        let register = &self.event_register(event)[bank];
+
+        if enabled {
+            register.set(register.get() | bit);
+        } else {
+            register.set(register.get() & !bit);
+        }
+
+        match event {
+            Event::High => self.masked_high &= !(1 << pin),
+            Event::Low => self.masked_low &= !(1 << pin),
+            _ => (),
+        }
+    }
+
+    // Turn off the detection of the pin's levels until its event is cleared. They would be
+    // detected again right away otherwise.
+    fn mask_levels(&mut self, pin: usize) {
+        if self.detects(pin, Event::High) {
+            self.set_event_detection(pin, Event::High, false);
+            self.masked_high |= 1 << pin;
+        }
+        if self.detects(pin, Event::Low) {
+            self.set_event_detection(pin, Event::Low, false);
+            self.masked_low |= 1 << pin;
+        }
+    }
+
+    fn unmask_levels(&mut self, pin: usize) {
+        if self.masked(pin, Event::High) {
+            self.set_event_detection(pin, Event::High, true);
+        }
+        if self.masked(pin, Event::Low) {
+            self.set_event_detection(pin, Event::Low, true);
+        }
+    }
+
+    // Whether the pin is at a level whose detection is masked.
+    fn masked_level_asserted(&self, pin: usize) -> bool {
+        let high = self.is_high(pin);
+
+        self.masked(pin, Event::High) && high || self.masked(pin, Event::Low) && !high
+    }
+
+    fn event_detected(&self, pin: usize) -> bool {
+        let (bank, bit) = bank(pin);
+
+        self.registers.GPEDS[bank].get() & bit != 0 || self.latched & 1 << pin != 0
+    }
+
+    fn clear_event(&mut self, pin: usize) {
+        let (bank, bit) = bank(pin);
+
+        self.registers.GPEDS[bank].set(bit);
+        self.latched &= !(1 << pin);
+        self.unmask_levels(pin);
+    }
+
+    // Take the detected events of all pins, as a mask of pins.
+    fn take_events(&mut self) -> u64 {
+        let events = u64::from(self.registers.GPEDS[1].get()) << 32
+            | u64::from(self.registers.GPEDS[0].get());
+
+        self.registers.GPEDS[0].set(events as u32);
+        self.registers.GPEDS[1].set((events >> 32) as u32);
+        events
+    }
+
+    // Keep an event nobody handles for `event_detected`.
+    fn latch_event(&mut self, pin: usize) {
+        self.latched |= 1 << pin;
+        self.mask_levels(pin);
     }
 
+    // Turn off event detection for all pins, and drop what was detected.
+    fn reset_events(&mut self) {
+        for event in EVENTS.iter() {
+            for register in self.event_register(*event) {
+                register.set(0);
+            }
+        }
+
+        self.take_events();
+        self.latched = 0;
+        self.masked_high = 0;
+        self.masked_low = 0;
+    }
+
+    fn claim(&mut self, pin: usize) -> Result<(), &'static str> {
+        if pin >= NUM_PINS {
+            return Err("No such pin");
+        }
+        if (self.claimed | self.reserved) & 1 << pin != 0 {
+            return Err("Pin in use");
+        }
+
+        self.claimed |= 1 << pin;
+        Ok(())
+    }
+
+    // Reserve pins for a driver. Reserving them again is fine.
+    fn reserve(&mut self, pins: u64) -> Result<(), &'static str> {
+        if self.claimed & pins != 0 {
+            return Err("Pin in use");
+        }
+
+        self.reserved |= pins;
+        Ok(())
+    }
+
+    // Pulls for the UART pins 14 and 15: none on the BCM2837, up on the BCM2711.
+    fn set_uart_pulls(&mut self) {
+        #[cfg(feature = "bsp_rpi3")]
+        let pull = Pull::Off;
+
+        #[cfg(feature = "bsp_rpi4")]
+        let pull = Pull::Up;
+
+        self.set_pull(14, pull);
+        self.set_pull(15, pull);
+    }
+
+    #[cfg(not(feature = "rpi_mini_uart_console"))]
     pub fn map_pl011_uart(&mut self) {
         // TX to pin 14
         // RX to pin 15
-        self.registers
-            .GPFSEL1
-            .modify(GPFSEL1::FSEL15::AltFunc0 + GPFSEL1::FSEL14::AltFunc0);
+        self.set_function(14, Function::Alt(AltFunction::Alt0));
+        self.set_function(15, Function::Alt(AltFunction::Alt0));
 
-        // Disable pull-up/down on pins 14 and 15.
-        #[cfg(feature = "bsp_rpi3")]
-        self.disable_pud_14_15_bcm2837();
+        self.set_uart_pulls();
+    }
 
-        #[cfg(feature = "bsp_rpi4")]
-        self.disable_pud_14_15_bcm2711();
+    #[cfg(not(feature = "rpi_mini_uart_console"))]
+    fn map_pl011_flow_control(&mut self) {
+        // CTS to pin 16
+        // RTS to pin 17
+        self.set_function(16, Function::Alt(AltFunction::Alt3));
+        self.set_function(17, Function::Alt(AltFunction::Alt3));
+    }
+
+    fn map_i2c1(&mut self) {
+        // SDA1 to pin 2
+        // SCL1 to pin 3
+        self.set_function(2, Function::Alt(AltFunction::Alt0));
+        self.set_function(3, Function::Alt(AltFunction::Alt0));
+    }
+
+    fn map_spi0(&mut self) {
+        // CE1 to pin 7
+        // CE0 to pin 8
+        // MISO to pin 9
+        // MOSI to pin 10
+        // SCLK to pin 11
+        for pin in 7..=11 {
+            self.set_function(pin, Function::Alt(AltFunction::Alt0));
+        }
+    }
+
+    #[cfg(feature = "rpi_mini_uart_console")]
+    pub fn map_mini_uart(&mut self) {
+        // TX to pin 14
+        // RX to pin 15
+        self.set_function(14, Function::Alt(AltFunction::Alt5));
+        self.set_function(15, Function::Alt(AltFunction::Alt5));
+
+        self.set_uart_pulls();
     }
 }
 
 impl GPIO {
-    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+    const UART_PINS: u64 = 1 << 14 | 1 << 15;
+
+    pub const unsafe fn new(mmio_start_addr: usize, irq_number: IRQNumber) -> Self {
         Self {
             inner: Mutex::new(GPIOInner::new(mmio_start_addr)),
+            irq_number,
+            pin_irqs: Mutex::new([PinIRQ::NONE; NUM_PINS]),
+            debounce_timer: Mutex::new(None),
+        }
+    }
+
+    // The GPIO interrupt handlers take the lock as well, so IRQs must be masked while it is held.
+    fn locked<R>(&self, f: impl FnOnce(&mut GPIOInner) -> R) -> R {
+        exec_with_irq_masked(|| f(&mut self.inner.lock()))
+    }
+
+    // Take a pin for exclusive use. It keeps its current configuration until it is changed
+    // through the handle.
+    pub fn claim(&'static self, pin: usize) -> Result<Pin<Unconfigured>, &'static str> {
+        self.locked(|inner| inner.claim(pin))?;
+
+        Ok(Pin {
+            gpio: self,
+            number: pin,
+            mode: PhantomData,
+        })
+    }
+
+    #[cfg(not(feature = "rpi_mini_uart_console"))]
+    pub fn map_pl011_uart(&self) -> Result<(), &'static str> {
+        self.locked(|inner| {
+            inner.reserve(Self::UART_PINS)?;
+            inner.map_pl011_uart();
+            Ok(())
+        })
+    }
+
+    #[cfg(not(feature = "rpi_mini_uart_console"))]
+    pub fn map_pl011_flow_control(&self) -> Result<(), &'static str> {
+        self.locked(|inner| {
+            inner.reserve(1 << 16 | 1 << 17)?;
+            inner.map_pl011_flow_control();
+            Ok(())
+        })
+    }
+
+    // The boards pull pins 2 and 3 up, the pins' own pulls are not needed.
+    pub fn map_i2c1(&self) -> Result<(), &'static str> {
+        self.locked(|inner| {
+            inner.reserve(1 << 2 | 1 << 3)?;
+            inner.map_i2c1();
+            Ok(())
+        })
+    }
+
+    pub fn map_spi0(&self) -> Result<(), &'static str> {
+        self.locked(|inner| {
+            inner.reserve(0b1_1111 << 7)?;
+            inner.map_spi0();
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "rpi_mini_uart_console")]
+    pub fn map_mini_uart(&self) -> Result<(), &'static str> {
+        self.locked(|inner| {
+            inner.reserve(Self::UART_PINS)?;
+            inner.map_mini_uart();
+            Ok(())
+        })
+    }
+
+    // `gpio`: list all pins.
+    // `gpio <pin> in [off|down|up]`: make the pin an input, with the given pull.
+    // `gpio <pin> out <0|1>`: make the pin an output driving the level.
+    // `gpio <pin> alt<n>`: route the pin to a peripheral.
+    // `gpio <pin> event [<event> [off]]`: show and clear the event status, or turn detection of
+    // an event on or off.
+    // `gpio <pin> debounce <ms>`: hold back events closer than that to the previous one.
+    // `gpio <pin> irq`: print the pin's events as they come in. A level is printed again once
+    // `gpio <pin> event` cleared it.
+    pub fn command(&'static self, args: &[&str]) -> Result<(), &'static str> {
+        let pin = match args.get(1) {
+            Some(pin) => pin.parse::<usize>().map_err(|_| "Invalid pin")?,
+            None => {
+                self.print_pins();
+                return Ok(());
+            }
+        };
+        let pin = self.claim(pin)?;
+
+        match (args.get(2).copied(), args.get(3).copied()) {
+            (Some("in"), pull) => {
+                let pin = pin.into_input();
+
+                if let Some(pull) = pull {
+                    pin.set_pull(Pull::parse(pull).ok_or("Invalid pull")?);
+                }
+                kprintln!("  {}", if pin.is_high() { "high" } else { "low" });
+            }
+            (Some("out"), Some(level)) => {
+                let high = match level {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err("Invalid level"),
+                };
+                let pin = pin.into_output();
+
+                if high {
+                    pin.set_high();
+                } else {
+                    pin.set_low();
+                }
+            }
+            (Some("event"), None) => {
+                let pin = pin.into_input();
+
+                kprintln!(
+                    "  {}",
+                    if pin.event_detected() {
+                        "detected"
+                    } else {
+                        "none"
+                    }
+                );
+                pin.clear_event();
+            }
+            (Some("event"), Some(event)) => {
+                let event = Event::parse(event).ok_or("Invalid event")?;
+                let pin = pin.into_input();
+
+                match args.get(4).copied() {
+                    None => pin.enable_event(event),
+                    Some("off") => pin.disable_event(event),
+                    Some(_) => return Err("Invalid arguments"),
+                }
+            }
+            (Some("debounce"), Some(ms)) => {
+                let ms = ms.parse::<u64>().map_err(|_| "Invalid debounce time")?;
+
+                pin.into_input().set_debounce(Duration::from_millis(ms));
+            }
+            (Some("irq"), None) => {
+                let pin = pin.into_input();
+                let descriptor = IRQDescriptor {
+                    name: "gpio shell command",
+                    handler: &EVENT_PRINTERS[pin.number],
+                };
+
+                self.register_handler(pin.irq_number(), descriptor)?;
+                self.enable(pin.irq_number());
+            }
+            (Some(function), None) => match Function::parse(function) {
+                Some(Function::Alt(alt)) => {
+                    pin.into_alternate(alt);
+                }
+                _ => return Err("Invalid arguments"),
+            },
+            _ => return Err("Invalid arguments"),
         }
+
+        Ok(())
+    }
+
+    fn print_pins(&self) {
+        self.locked(|inner| {
+            for pin in 0..NUM_PINS {
+                let owner = if inner.reserved & 1 << pin != 0 {
+                    "reserved"
+                } else if inner.claimed & 1 << pin != 0 {
+                    "claimed"
+                } else {
+                    ""
+                };
+
+                kprintln!(
+                    "  {:>2}  {:<4}  {:<4}  {:<8}",
+                    pin,
+                    inner.function(pin),
+                    if inner.is_high(pin) { "high" } else { "low" },
+                    owner
+                );
+
+                for &event in EVENTS.iter() {
+                    if inner.detects(pin, event) {
+                        kprintln!("        detects {}", event.name());
+                    } else if inner.masked(pin, event) {
+                        kprintln!("        detects {}, off until cleared", event.name());
+                    }
+                }
+            }
+        });
+    }
+
+    fn pin_irqs<R>(&self, f: impl FnOnce(&mut [PinIRQ; NUM_PINS]) -> R) -> R {
+        exec_with_irq_masked(|| f(&mut self.pin_irqs.lock()))
+    }
+
+    // Have the debounce time of held back events noticed when it is over, from the system timer's
+    // interrupt. Takes one of the timer's one-shots, which is moved for every pin.
+    pub fn set_debounce_timer(
+        &'static self,
+        timer: &'static SystemTimer,
+    ) -> Result<(), &'static str> {
+        let descriptor = IRQDescriptor {
+            name: "BCM GPIO debounce",
+            handler: self,
+        };
+        let oneshot = timer.add_oneshot(descriptor)?;
+
+        exec_with_irq_masked(|| {
+            *self.debounce_timer.lock() = Some(DebounceTimer {
+                timer,
+                oneshot,
+                armed_until: None,
+            })
+        });
+
+        Ok(())
     }
 
-    pub fn map_pl011_uart(&self) {
-        self.inner.lock().map_pl011_uart();
+    // Call the GPIO's IRQ handler at `time`, unless a call comes earlier already.
+    fn arm_debounce_timer(&self, now: Duration, time: Duration) {
+        let mut debounce_timer = self.debounce_timer.lock();
+        let debounce_timer = match &mut *debounce_timer {
+            Some(debounce_timer) => debounce_timer,
+            None => return,
+        };
+
+        if matches!(debounce_timer.armed_until, Some(armed) if armed > now && armed <= time) {
+            return;
+        }
+
+        match debounce_timer
+            .timer
+            .arm_oneshot(debounce_timer.oneshot, time - now)
+        {
+            Ok(()) => debounce_timer.armed_until = Some(time),
+            Err(msg) => kwarn!("GPIO: Debounce timer: {}", msg),
+        }
+    }
+
+    // Call the handlers for the detected events, and for the held back ones whose debounce time is
+    // over. The locks are not held while they run, so that they can use their pins.
+    fn dispatch_events(&self) -> Result<(), &'static str> {
+        let now = time::time_manager().uptime();
+        let mut handlers = [None; NUM_PINS];
+
+        self.locked(|inner| {
+            let mut events = inner.take_events();
+            let mut pin_irqs = self.pin_irqs.lock();
+
+            while events != 0 {
+                let pin = events.trailing_zeros() as usize;
+                events &= events - 1;
+
+                let irq = &mut pin_irqs[pin];
+                match irq.descriptor {
+                    Some(_) if irq.enabled => {
+                        inner.mask_levels(pin);
+
+                        if irq.debounce_end(now).is_some() {
+                            irq.bouncing = true;
+                        } else {
+                            handlers[pin] = irq.pass_on(now, inner.is_high(pin));
+                        }
+                    }
+                    _ => inner.latch_event(pin),
+                }
+            }
+
+            let mut next_end = None;
+            for (pin, irq) in pin_irqs.iter_mut().enumerate() {
+                if !irq.bouncing || !irq.enabled {
+                    continue;
+                }
+
+                if let Some(end) = irq.debounce_end(now) {
+                    next_end = Some(next_end.map_or(end, |next: Duration| next.min(end)));
+                    continue;
+                }
+
+                // Settled
+                irq.bouncing = false;
+                let high = inner.is_high(pin);
+                if high != irq.last_high || inner.masked_level_asserted(pin) {
+                    handlers[pin] = irq.pass_on(now, high);
+                }
+            }
+
+            if let Some(end) = next_end {
+                self.arm_debounce_timer(now, end);
+            }
+        });
+
+        for descriptor in handlers.iter().flatten() {
+            descriptor.handler.handle()?;
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, pin: usize) {
+        self.locked(|inner| inner.claimed &= !(1 << pin));
+    }
+}
+
+impl PinIRQ {
+    const NONE: Self = Self {
+        descriptor: None,
+        enabled: false,
+        debounce: Duration::ZERO,
+        last_event: None,
+        last_high: false,
+        bouncing: false,
+    };
+
+    // When the debounce time following the last call ends, if it has not yet.
+    fn debounce_end(&self, now: Duration) -> Option<Duration> {
+        self.last_event
+            .map(|last| last + self.debounce)
+            .filter(|&end| now < end)
+    }
+
+    // Note a call of the handler, and return it.
+    fn pass_on(&mut self, now: Duration, high: bool) -> Option<IRQDescriptor> {
+        self.last_event = Some(now);
+        self.last_high = high;
+        self.bouncing = false;
+
+        self.descriptor
     }
 }
 
@@ -160,4 +945,170 @@ impl driver::DeviceDriver for GPIO {
     fn compatible(&self) -> &'static str {
         "BCM GPIO"
     }
+
+    // Events the firmware left enabled would raise IRQs nobody handles.
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.locked(|inner| inner.reset_events());
+
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use exception::asynchronous::irq_manager;
+
+        let descriptor = IRQDescriptor {
+            name: "BCM GPIO",
+            handler: self,
+        };
+
+        irq_manager().register_handler(self.irq_number, descriptor)?;
+        irq_manager().enable(self.irq_number);
+
+        Ok(())
+    }
+}
+
+impl IRQHandler for GPIO {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.dispatch_events()
+    }
+}
+
+impl IRQManager for GPIO {
+    type IRQNumberType = GPIOIRQNumber;
+
+    fn register_handler(
+        &self,
+        irq_number: Self::IRQNumberType,
+        descriptor: IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        self.pin_irqs(|pin_irqs| {
+            let irq = &mut pin_irqs[irq_number.get()];
+
+            if irq.descriptor.is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            irq.descriptor = Some(descriptor);
+            Ok(())
+        })
+    }
+
+    fn enable(&self, irq_number: Self::IRQNumberType) {
+        self.pin_irqs(|pin_irqs| pin_irqs[irq_number.get()].enabled = true);
+    }
+
+    // The events come in through the GPIO's own IRQ, see `IRQHandler`.
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        self.dispatch_events().expect("Error handling GPIO IRQ");
+    }
+
+    fn print_handlers(&self) {
+        kinfo!("      GPIO pin handler:");
+
+        self.pin_irqs(|pin_irqs| {
+            for (pin, irq) in pin_irqs.iter().enumerate() {
+                if let Some(descriptor) = irq.descriptor {
+                    kinfo!("            {: >3}. {}", pin, descriptor.name);
+                }
+            }
+        });
+    }
+}
+
+impl IRQHandler for EventPrinter {
+    fn handle(&self) -> Result<(), &'static str> {
+        kprintln!("  GPIO {}: event", self.pin);
+
+        Ok(())
+    }
+}
+
+impl<Mode> Pin<Mode> {
+    fn with<R>(&self, f: impl FnOnce(&mut GPIOInner, usize) -> R) -> R {
+        self.gpio.locked(|inner| f(inner, self.number))
+    }
+
+    // Reconfigure the pin, handing over the claim to a handle of the new type.
+    fn into_mode<New>(self, function: Function) -> Pin<New> {
+        self.with(|inner, pin| inner.set_function(pin, function));
+
+        let pin = Pin {
+            gpio: self.gpio,
+            number: self.number,
+            mode: PhantomData,
+        };
+        core::mem::forget(self);
+
+        pin
+    }
+
+    pub fn set_pull(&self, pull: Pull) {
+        self.with(|inner, pin| inner.set_pull(pin, pull));
+    }
+
+    pub fn into_input(self) -> Pin<Input> {
+        self.into_mode(Function::Input)
+    }
+
+    // The pin drives the level last set, set it before if that matters.
+    pub fn into_output(self) -> Pin<Output> {
+        self.into_mode(Function::Output)
+    }
+
+    pub fn into_alternate(self, function: AltFunction) -> Pin<Alternate> {
+        self.into_mode(Function::Alt(function))
+    }
+}
+
+impl Pin<Input> {
+    pub fn is_high(&self) -> bool {
+        self.with(|inner, pin| inner.is_high(pin))
+    }
+
+    pub fn enable_event(&self, event: Event) {
+        self.with(|inner, pin| inner.set_event_detection(pin, event, true));
+    }
+
+    pub fn disable_event(&self, event: Event) {
+        self.with(|inner, pin| inner.set_event_detection(pin, event, false));
+    }
+
+    pub fn event_detected(&self) -> bool {
+        self.with(|inner, pin| inner.event_detected(pin))
+    }
+
+    pub fn clear_event(&self) {
+        self.with(|inner, pin| inner.clear_event(pin));
+    }
+
+    // The number to register a handler for the pin's events with at the GPIO.
+    pub fn irq_number(&self) -> GPIOIRQNumber {
+        GPIOIRQNumber::new(self.number)
+    }
+
+    // Hold back events that follow the last handled one within `debounce`.
+    pub fn set_debounce(&self, debounce: Duration) {
+        self.gpio
+            .pin_irqs(|pin_irqs| pin_irqs[self.number].debounce = debounce);
+    }
+}
+
+impl Pin<Output> {
+    pub fn set_high(&self) {
+        self.with(|inner, pin| inner.set_level(pin, true));
+    }
+
+    pub fn set_low(&self) {
+        self.with(|inner, pin| inner.set_level(pin, false));
+    }
+}
+
+impl<Mode> Drop for Pin<Mode> {
+    fn drop(&mut self) {
+        self.gpio.release(self.number);
+    }
 }
@@ -0,0 +1,341 @@
+// BCM2835 BSC (Broadcom Serial Controller) driver - an I2C master.
+//
+// A transfer moves up to 65535 bytes through a 16 byte FIFO and ends with a stop condition. There
+// is no documented repeated start, but the controller does one if the next transfer is started
+// while the current one is still active. That is how `write_read` is done, so its write part has
+// to fit the FIFO.
+//
+// A 10 bit address goes out as the address register's `0b11110` prefix with the address' two top
+// bits, followed by the low byte as the first data byte.
+
+use core::time::Duration;
+
+use spin::Mutex;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::ReadWrite,
+};
+
+use crate::{
+    boot_option,
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver,
+    i2c::{self, Address},
+    kwarn,
+    time::{self, TimeManager},
+};
+
+register_bitfields! {
+    u32,
+
+    // Control
+    C [
+        I2CEN OFFSET(15) NUMBITS(1) [],
+        // Start a transfer
+        ST OFFSET(7) NUMBITS(1) [],
+        CLEAR OFFSET(4) NUMBITS(2) [
+            ClearFifo = 0b01
+        ],
+        READ OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Status. CLKT, ERR and DONE are cleared by writing 1.
+    S [
+        // A device stretched the clock for too long
+        CLKT OFFSET(9) NUMBITS(1) [],
+        // No acknowledge
+        ERR OFFSET(8) NUMBITS(1) [],
+        RXD OFFSET(5) NUMBITS(1) [],
+        TXD OFFSET(4) NUMBITS(1) [],
+        DONE OFFSET(1) NUMBITS(1) [],
+        // Transfer active
+        TA OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Clock Divider. The core clock is divided by `CDIV`, which the controller rounds down to an
+    // even number.
+    DIV [
+        CDIV OFFSET(0) NUMBITS(16) []
+    ],
+
+    // Clock Stretch Timeout
+    CLKT [
+        // In SCL cycles, 0 waits forever
+        TOUT OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => C: ReadWrite<u32, C::Register>),
+        (0x04 => S: ReadWrite<u32, S::Register>),
+        (0x08 => DLEN: ReadWrite<u32>),
+        (0x0C => A: ReadWrite<u32>),
+        (0x10 => FIFO: ReadWrite<u32>),
+        (0x14 => DIV: ReadWrite<u32, DIV::Register>),
+        (0x18 => _reserved1),
+        (0x1C => CLKT: ReadWrite<u32, CLKT::Register>),
+        (0x20 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+const FIFO_SIZE: usize = 16;
+const MAX_TRANSFER: usize = 0xFFFF;
+
+// The longest a device may hold SCL low, SMBus' limit
+const CLOCK_STRETCH_TIMEOUT: Duration = Duration::from_millis(25);
+
+// Standard mode
+const DEFAULT_CLOCK_HZ: u32 = 100_000;
+
+// Added to the time a transfer takes on the bus
+const TRANSFER_TIMEOUT_MARGIN: Duration = Duration::from_millis(10);
+
+boot_option! {
+    // The SCL frequency in Hz the driver sets up.
+    static CLOCK_HZ: u32 = ("i2c", DEFAULT_CLOCK_HZ);
+}
+
+struct I2CControllerInner {
+    registers: Registers,
+    core_clock_hz: u32,
+    clock_hz: u32,
+}
+
+pub struct I2CController {
+    inner: Mutex<I2CControllerInner>,
+}
+
+// The address register's value, and for 10 bit addresses the byte that goes first.
+fn split_address(address: Address) -> Result<(u32, Option<u8>), &'static str> {
+    if !address.is_valid() {
+        return Err("Invalid address");
+    }
+
+    Ok(match address {
+        Address::SevenBit(address) => (u32::from(address), None),
+        Address::TenBit(address) => (0b111_1000 | u32::from(address >> 8), Some(address as u8)),
+    })
+}
+
+fn timed_out(deadline: Duration) -> bool {
+    time::time_manager().uptime() > deadline
+}
+
+impl I2CControllerInner {
+    const unsafe fn new(mmio_start_addr: usize, core_clock_hz: u32) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            core_clock_hz,
+            clock_hz: 0,
+        }
+    }
+
+    fn set_clock_hz(&mut self, hz: u32) -> Result<u32, &'static str> {
+        if hz == 0 {
+            return Err("Invalid frequency");
+        }
+
+        // Round up to an even divisor, so that the frequency does not exceed `hz`
+        let divisor = (self.core_clock_hz + hz - 1) / hz;
+        let divisor = ((divisor + 1) & !1).max(2).min(0xFFFE);
+        self.clock_hz = self.core_clock_hz / divisor;
+
+        let timeout =
+            CLOCK_STRETCH_TIMEOUT.as_micros() as u64 * u64::from(self.clock_hz) / 1_000_000;
+        self.registers.DIV.write(DIV::CDIV.val(divisor));
+        self.registers
+            .CLKT
+            .write(CLKT::TOUT.val(timeout.max(1).min(0xFFFF) as u32));
+
+        Ok(self.clock_hz)
+    }
+
+    // How long a transfer of `len` bytes may take: 9 SCL cycles per byte and the address, plus
+    // stretching.
+    fn deadline(&self, len: usize) -> Duration {
+        let bus_time =
+            Duration::from_micros((len as u64 + 2) * 9 * 1_000_000 / u64::from(self.clock_hz));
+
+        time::time_manager().uptime() + bus_time + CLOCK_STRETCH_TIMEOUT + TRANSFER_TIMEOUT_MARGIN
+    }
+
+    fn start(&mut self, len: usize, read: bool) {
+        self.registers.DLEN.set(len as u32);
+        self.registers
+            .C
+            .write(C::I2CEN::SET + C::ST::SET + if read { C::READ::SET } else { C::READ::CLEAR });
+    }
+
+    // Wait for the transfer to end, and report how it went.
+    fn finish(&mut self, deadline: Duration) -> Result<(), &'static str> {
+        while !self.registers.S.is_set(S::DONE) {
+            if timed_out(deadline) {
+                self.registers.C.write(C::I2CEN::SET + C::CLEAR::ClearFifo);
+                return Err("I2C transfer timeout");
+            }
+        }
+
+        let status = self.registers.S.extract();
+        self.registers
+            .S
+            .write(S::CLKT::SET + S::ERR::SET + S::DONE::SET);
+
+        if status.is_set(S::ERR) {
+            Err(i2c::NO_ACK)
+        } else if status.is_set(S::CLKT) {
+            Err("Clock stretch timeout")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn failed(&self) -> bool {
+        self.registers.S.is_set(S::ERR) || self.registers.S.is_set(S::CLKT)
+    }
+
+    // Write `data` and then read into `buf`, leaving out what is empty. With both, the read
+    // follows with a repeated start.
+    fn transfer(
+        &mut self,
+        address: Address,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<(), &'static str> {
+        let (address, prefix) = split_address(address)?;
+        let write_len = prefix.iter().len() + data.len();
+        let byte = |i: usize| match (prefix, i) {
+            (Some(prefix), 0) => prefix,
+            (Some(_), i) => data[i - 1],
+            (None, i) => data[i],
+        };
+
+        if write_len > MAX_TRANSFER || buf.len() > MAX_TRANSFER {
+            return Err("Transfer too long");
+        }
+        if write_len > FIFO_SIZE && !buf.is_empty() {
+            return Err("Write part longer than the FIFO");
+        }
+
+        let deadline = self.deadline(write_len + buf.len());
+        self.registers
+            .S
+            .write(S::CLKT::SET + S::ERR::SET + S::DONE::SET);
+        self.registers.C.write(C::I2CEN::SET + C::CLEAR::ClearFifo);
+        self.registers.A.set(address);
+
+        if write_len > 0 || buf.is_empty() {
+            // Fill the FIFO up front, for the read to follow the whole write part
+            let mut written = 0;
+            while written < write_len && written < FIFO_SIZE {
+                self.registers.FIFO.set(u32::from(byte(written)));
+                written += 1;
+            }
+            self.start(write_len, false);
+
+            if buf.is_empty() {
+                while written < write_len && !self.failed() {
+                    if self.registers.S.is_set(S::TXD) {
+                        self.registers.FIFO.set(u32::from(byte(written)));
+                        written += 1;
+                    } else if timed_out(deadline) {
+                        break;
+                    }
+                }
+
+                return self.finish(deadline);
+            }
+
+            // Queue the read once the write is under way
+            while !self.registers.S.is_set(S::TA) && !self.registers.S.is_set(S::DONE) {
+                if timed_out(deadline) {
+                    return self.finish(deadline);
+                }
+            }
+            if self.failed() {
+                return self.finish(deadline);
+            }
+        }
+        self.start(buf.len(), true);
+
+        let mut received = 0;
+        loop {
+            while received < buf.len() && self.registers.S.is_set(S::RXD) {
+                buf[received] = self.registers.FIFO.get() as u8;
+                received += 1;
+            }
+
+            if received == buf.len() || self.failed() || timed_out(deadline) {
+                break;
+            }
+        }
+
+        self.finish(deadline)?;
+        if received < buf.len() {
+            return Err("Short read");
+        }
+
+        Ok(())
+    }
+}
+
+impl I2CController {
+    // Create an instance.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize, core_clock_hz: u32) -> Self {
+        Self {
+            inner: Mutex::new(I2CControllerInner::new(mmio_start_addr, core_clock_hz)),
+        }
+    }
+}
+
+impl driver::DeviceDriver for I2CController {
+    fn compatible(&self) -> &'static str {
+        "BCM BSC I2C"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock();
+
+        inner.registers.C.write(C::I2CEN::SET + C::CLEAR::ClearFifo);
+        if let Err(msg) = inner.set_clock_hz(CLOCK_HZ.get()) {
+            kwarn!("BSC I2C: {} Hz: {}", CLOCK_HZ.get(), msg);
+            inner.set_clock_hz(DEFAULT_CLOCK_HZ)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl i2c::I2cBus for I2CController {
+    fn clock_hz(&self) -> u32 {
+        self.inner.lock().clock_hz
+    }
+
+    fn set_clock_hz(&self, hz: u32) -> Result<u32, &'static str> {
+        self.inner.lock().set_clock_hz(hz)
+    }
+
+    fn write(&self, address: Address, data: &[u8]) -> Result<(), &'static str> {
+        self.inner.lock().transfer(address, data, &mut [])
+    }
+
+    fn read(&self, address: Address, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.inner.lock().transfer(address, &[], buf)
+    }
+
+    fn write_read(
+        &self,
+        address: Address,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<(), &'static str> {
+        self.inner.lock().transfer(address, data, buf)
+    }
+}
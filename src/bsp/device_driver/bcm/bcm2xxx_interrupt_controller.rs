@@ -0,0 +1,138 @@
+// BCM2837 interrupt controller driver - the ARM side's controller of the VideoCore peripheral IRQs.
+//
+// The 64 peripheral IRQs are numbered as in the pending and enable register pairs. All of them
+// reach core 0 as its IRQ, which is how the ARM local interrupt controller routes them by
+// default. The basic pending register, which duplicates some of them along with the ARM-only
+// sources, is not used.
+
+use spin::Mutex;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::{ReadOnly, WriteOnly},
+};
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver,
+    exception::{self, asynchronous::exec_with_irq_masked},
+    kinfo,
+};
+
+const NUM_IRQS: usize = 64;
+
+pub type PeripheralIRQNumber = exception::asynchronous::IRQNumber<{ NUM_IRQS - 1 }>;
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x04 => PENDING: [ReadOnly<u32>; 2]),
+        (0x0C => _reserved2),
+        (0x10 => ENABLE: [WriteOnly<u32>; 2]),
+        (0x18 => _reserved3),
+        (0x1C => DISABLE: [WriteOnly<u32>; 2]),
+        (0x24 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+type HandlerTable = [Option<exception::asynchronous::IRQDescriptor>; NUM_IRQS];
+
+pub struct PeripheralIC {
+    registers: Registers,
+    handler_table: Mutex<HandlerTable>,
+}
+
+impl PeripheralIC {
+    // Create an instance.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            handler_table: Mutex::new([None; NUM_IRQS]),
+        }
+    }
+
+    fn pending(&self) -> u64 {
+        u64::from(self.registers.PENDING[1].get()) << 32
+            | u64::from(self.registers.PENDING[0].get())
+    }
+}
+
+impl driver::DeviceDriver for PeripheralIC {
+    fn compatible(&self) -> &'static str {
+        "BCM Peripheral Interrupt Controller"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        // Everything starts out disabled
+        for reg in self.registers.DISABLE.iter() {
+            reg.set(u32::MAX);
+        }
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::IRQManager for PeripheralIC {
+    type IRQNumberType = PeripheralIRQNumber;
+
+    fn register_handler(
+        &self,
+        irq_number: Self::IRQNumberType,
+        descriptor: exception::asynchronous::IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        exec_with_irq_masked(|| {
+            let mut table = self.handler_table.lock();
+            let slot = &mut table[irq_number.get()];
+
+            if slot.is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            *slot = Some(descriptor);
+            Ok(())
+        })
+    }
+
+    fn enable(&self, irq_number: Self::IRQNumberType) {
+        let irq = irq_number.get();
+
+        self.registers.ENABLE[irq / 32].set(1 << (irq % 32));
+    }
+
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        // The pending bits clear once the devices are serviced
+        let mut pending = self.pending();
+
+        while pending != 0 {
+            let irq = pending.trailing_zeros() as usize;
+            pending &= pending - 1;
+
+            let descriptor = self.handler_table.lock()[irq];
+            match descriptor {
+                None => panic!("No handler registered for IRQ {}", irq),
+                Some(descriptor) => {
+                    descriptor.handler.handle().expect("Error handling IRQ");
+                }
+            }
+        }
+    }
+
+    fn print_handlers(&self) {
+        kinfo!("      Peripheral handler:");
+
+        for (i, descriptor) in self.handler_table.lock().iter().enumerate() {
+            if let Some(descriptor) = descriptor {
+                kinfo!("            {: >3}. {}", i, descriptor.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,592 @@
+// VideoCore mailbox driver - property interface.
+//
+// The firmware running on the VideoCore answers requests for board information and settings. A
+// request is a message of tags in RAM, handed over by writing its bus address to the property
+// channel of mailbox 0. The firmware writes its answers into the same message and signals
+// completion through mailbox 1.
+//
+// ```
+// let mut message = PropertyMessage::new();
+// let revision = message.add(&GetBoardRevision)?;
+// let memory = message.add(&GetArmMemory)?;
+// mailbox.call(&mut message)?;
+//
+// let revision: u32 = message.get(&revision)?;
+// ```
+
+use core::{marker::PhantomData, mem::size_of, time::Duration};
+
+use spin::Mutex;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::{ReadOnly, WriteOnly},
+};
+
+use super::bcm2xxx_bus;
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    driver,
+    memory::cache,
+    time::{self, TimeManager},
+};
+
+register_bitfields! [
+    u32,
+    // Mailbox Status
+    STATUS [
+        // The mailbox cannot take another message.
+        FULL OFFSET(31) NUMBITS(1) [],
+
+        // There is no message to read.
+        EMPTY OFFSET(30) NUMBITS(1) []
+    ]
+];
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        // Mailbox 0, VideoCore to ARM
+        (0x00 => READ: ReadOnly<u32>),
+        (0x04 => _reserved1),
+        (0x18 => STATUS0: ReadOnly<u32, STATUS::Register>),
+        (0x1C => _reserved2),
+        // Mailbox 1, ARM to VideoCore
+        (0x20 => WRITE: WriteOnly<u32>),
+        (0x24 => _reserved3),
+        (0x38 => STATUS1: ReadOnly<u32, STATUS::Register>),
+        (0x3C => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+// ARM to VideoCore property tags
+const PROPERTY_CHANNEL: u32 = 8;
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(100);
+
+const MESSAGE_WORDS: usize = 64;
+
+// Message header and tag header codes
+const CODE_REQUEST: u32 = 0;
+const CODE_SUCCESS: u32 = 0x8000_0000;
+const TAG_RESPONSE: u32 = 1 << 31;
+const END_TAG: u32 = 0;
+
+// Words of the message header, and of a tag's header before its value.
+const MESSAGE_HEADER_WORDS: usize = 2;
+const TAG_HEADER_WORDS: usize = 3;
+
+mod interface {
+    // A property tag, with the request it sends and the response it gets back.
+    pub trait Tag {
+        type Response;
+
+        const ID: u32;
+
+        // Size of the value buffer in words, enough for both the request and the response.
+        const VALUE_WORDS: usize;
+
+        // Fill in the request. The value buffer is zeroed.
+        fn write_request(&self, _value: &mut [u32]) {}
+
+        fn read_response(value: &[u32]) -> Self::Response;
+    }
+}
+
+pub use interface::*;
+
+// A property message. Aligned to and filling whole cache lines, so that cache maintenance on it
+// does not touch anything else.
+#[repr(C, align(64))]
+pub struct PropertyMessage {
+    words: [u32; MESSAGE_WORDS],
+    // Next free word, where the end tag goes
+    len: usize,
+}
+
+// Where a tag went in a message, to get its response with.
+pub struct TagSlot<T> {
+    offset: usize,
+    _tag: PhantomData<T>,
+}
+
+struct MailboxInner {
+    registers: Registers,
+}
+
+pub struct Mailbox {
+    inner: Mutex<MailboxInner>,
+}
+
+pub struct GetFirmwareRevision;
+pub struct GetBoardRevision;
+pub struct GetBoardSerial;
+pub struct GetMacAddress;
+pub struct GetArmMemory;
+pub struct GetVcMemory;
+pub struct GetClockRate(pub ClockId);
+pub struct GetTemperature;
+pub struct GetMaxTemperature;
+pub struct GetPhysicalDisplaySize;
+pub struct SetPhysicalDisplaySize(pub DisplaySize);
+pub struct SetVirtualBufferSize(pub DisplaySize);
+pub struct SetVirtualOffset {
+    pub x: u32,
+    pub y: u32,
+}
+pub struct SetDepth(pub u32);
+pub struct SetPixelOrder(pub PixelOrder);
+pub struct AllocateBuffer {
+    pub alignment: u32,
+}
+pub struct GetPitch;
+pub struct GetDmaChannels;
+
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum ClockId {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    Sdram = 8,
+    Pwm = 10,
+    Emmc2 = 12,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DisplaySize {
+    pub width: u32,
+    pub height: u32,
+}
+
+// Order of the colors in a 32 bit pixel, starting with the lowest byte
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PixelOrder {
+    Bgr = 0,
+    Rgb = 1,
+}
+
+// A range of the physical address space
+#[derive(Clone, Copy)]
+pub struct MemoryRange {
+    pub base: usize,
+    pub size: usize,
+}
+
+impl Tag for GetFirmwareRevision {
+    type Response = u32;
+    const ID: u32 = 0x0000_0001;
+    const VALUE_WORDS: usize = 1;
+
+    fn read_response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+impl Tag for GetBoardRevision {
+    type Response = u32;
+    const ID: u32 = 0x0001_0002;
+    const VALUE_WORDS: usize = 1;
+
+    fn read_response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+impl Tag for GetBoardSerial {
+    type Response = u64;
+    const ID: u32 = 0x0001_0004;
+    const VALUE_WORDS: usize = 2;
+
+    fn read_response(value: &[u32]) -> u64 {
+        u64::from(value[1]) << 32 | u64::from(value[0])
+    }
+}
+
+impl Tag for GetMacAddress {
+    type Response = [u8; 6];
+    const ID: u32 = 0x0001_0003;
+    const VALUE_WORDS: usize = 2;
+
+    // The address is in network byte order, the first byte in the lowest address.
+    fn read_response(value: &[u32]) -> [u8; 6] {
+        let low = value[0].to_le_bytes();
+        let high = value[1].to_le_bytes();
+
+        [low[0], low[1], low[2], low[3], high[0], high[1]]
+    }
+}
+
+impl Tag for GetArmMemory {
+    type Response = MemoryRange;
+    const ID: u32 = 0x0001_0005;
+    const VALUE_WORDS: usize = 2;
+
+    fn read_response(value: &[u32]) -> MemoryRange {
+        MemoryRange {
+            base: value[0] as usize,
+            size: value[1] as usize,
+        }
+    }
+}
+
+impl Tag for GetVcMemory {
+    type Response = MemoryRange;
+    const ID: u32 = 0x0001_0006;
+    const VALUE_WORDS: usize = 2;
+
+    fn read_response(value: &[u32]) -> MemoryRange {
+        GetArmMemory::read_response(value)
+    }
+}
+
+// The clock's rate in Hz, 0 if there is no such clock.
+impl Tag for GetClockRate {
+    type Response = u32;
+    const ID: u32 = 0x0003_0002;
+    const VALUE_WORDS: usize = 2;
+
+    fn write_request(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn read_response(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+// The SoC temperature in thousandths of a degree Celsius.
+impl Tag for GetTemperature {
+    type Response = u32;
+    const ID: u32 = 0x0003_0006;
+    const VALUE_WORDS: usize = 2;
+
+    // Temperature sensor 0, the only one there is
+    fn read_response(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+// The temperature the firmware starts to throttle the clocks at, in thousandths of a degree
+// Celsius.
+impl Tag for GetMaxTemperature {
+    type Response = u32;
+    const ID: u32 = 0x0003_000A;
+    const VALUE_WORDS: usize = 2;
+
+    fn read_response(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+// The size of the attached display, or 0x0 if there is none.
+impl Tag for GetPhysicalDisplaySize {
+    type Response = DisplaySize;
+    const ID: u32 = 0x0004_0003;
+    const VALUE_WORDS: usize = 2;
+
+    fn read_response(value: &[u32]) -> DisplaySize {
+        DisplaySize {
+            width: value[0],
+            height: value[1],
+        }
+    }
+}
+
+// Responds with the size that was set, which may differ from the requested one.
+impl Tag for SetPhysicalDisplaySize {
+    type Response = DisplaySize;
+    const ID: u32 = 0x0004_8003;
+    const VALUE_WORDS: usize = 2;
+
+    fn write_request(&self, value: &mut [u32]) {
+        value[0] = self.0.width;
+        value[1] = self.0.height;
+    }
+
+    fn read_response(value: &[u32]) -> DisplaySize {
+        GetPhysicalDisplaySize::read_response(value)
+    }
+}
+
+// The size of the framebuffer, of which the display shows a part as big as itself. Responds with
+// the size that was set.
+impl Tag for SetVirtualBufferSize {
+    type Response = DisplaySize;
+    const ID: u32 = 0x0004_8004;
+    const VALUE_WORDS: usize = 2;
+
+    fn write_request(&self, value: &mut [u32]) {
+        value[0] = self.0.width;
+        value[1] = self.0.height;
+    }
+
+    fn read_response(value: &[u32]) -> DisplaySize {
+        GetPhysicalDisplaySize::read_response(value)
+    }
+}
+
+// Where in the framebuffer the part the display shows starts.
+impl Tag for SetVirtualOffset {
+    type Response = ();
+    const ID: u32 = 0x0004_8009;
+    const VALUE_WORDS: usize = 2;
+
+    fn write_request(&self, value: &mut [u32]) {
+        value[0] = self.x;
+        value[1] = self.y;
+    }
+
+    fn read_response(_value: &[u32]) {}
+}
+
+// Bits per pixel. Responds with the depth that was set.
+impl Tag for SetDepth {
+    type Response = u32;
+    const ID: u32 = 0x0004_8005;
+    const VALUE_WORDS: usize = 1;
+
+    fn write_request(&self, value: &mut [u32]) {
+        value[0] = self.0;
+    }
+
+    fn read_response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+// Responds with the order that was set.
+impl Tag for SetPixelOrder {
+    type Response = PixelOrder;
+    const ID: u32 = 0x0004_8006;
+    const VALUE_WORDS: usize = 1;
+
+    fn write_request(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn read_response(value: &[u32]) -> PixelOrder {
+        if value[0] == PixelOrder::Rgb as u32 {
+            PixelOrder::Rgb
+        } else {
+            PixelOrder::Bgr
+        }
+    }
+}
+
+// Allocate the framebuffer with the settings of the other tags in the message. The range is empty
+// if that failed.
+impl Tag for AllocateBuffer {
+    type Response = MemoryRange;
+    const ID: u32 = 0x0004_0001;
+    const VALUE_WORDS: usize = 2;
+
+    fn write_request(&self, value: &mut [u32]) {
+        value[0] = self.alignment;
+    }
+
+    // The firmware answers with a bus address
+    fn read_response(value: &[u32]) -> MemoryRange {
+        MemoryRange {
+            base: bcm2xxx_bus::ram_address(value[0]),
+            size: value[1] as usize,
+        }
+    }
+}
+
+// Bytes per row of the framebuffer
+impl Tag for GetPitch {
+    type Response = u32;
+    const ID: u32 = 0x0004_0008;
+    const VALUE_WORDS: usize = 1;
+
+    fn read_response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+// A mask of the DMA channels the firmware leaves to the ARM.
+impl Tag for GetDmaChannels {
+    type Response = u32;
+    const ID: u32 = 0x0006_0001;
+    const VALUE_WORDS: usize = 1;
+
+    fn read_response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+impl PropertyMessage {
+    pub const fn new() -> Self {
+        Self {
+            words: [0; MESSAGE_WORDS],
+            len: MESSAGE_HEADER_WORDS,
+        }
+    }
+
+    // Append a tag. The returned slot gets the response after the message went through `call()`.
+    pub fn add<T: Tag>(&mut self, tag: &T) -> Result<TagSlot<T>, &'static str> {
+        let offset = self.len;
+        let end = offset + TAG_HEADER_WORDS + T::VALUE_WORDS;
+
+        // One word for the end tag
+        if end + 1 > MESSAGE_WORDS {
+            return Err("Property message full");
+        }
+
+        self.words[offset] = T::ID;
+        self.words[offset + 1] = (T::VALUE_WORDS * size_of::<u32>()) as u32;
+        self.words[offset + 2] = 0;
+
+        let value = &mut self.words[offset + TAG_HEADER_WORDS..end];
+        value.fill(0);
+        tag.write_request(value);
+
+        self.len = end;
+
+        Ok(TagSlot {
+            offset,
+            _tag: PhantomData,
+        })
+    }
+
+    // The response to the tag in `slot`.
+    pub fn get<T: Tag>(&self, slot: &TagSlot<T>) -> Result<T::Response, &'static str> {
+        if self.words[1] != CODE_SUCCESS {
+            return Err("Property message not answered");
+        }
+
+        // The firmware sets the response bit, and the length of the value it wants to return.
+        // That may be longer than the buffer, then the value is truncated.
+        let code = self.words[slot.offset + 2];
+        if code & TAG_RESPONSE == 0 {
+            return Err("Property tag not answered");
+        }
+
+        let start = slot.offset + TAG_HEADER_WORDS;
+        let value = &self.words[start..start + T::VALUE_WORDS];
+
+        Ok(T::read_response(value))
+    }
+
+    // Fill in the header and the end tag.
+    fn finish(&mut self) {
+        self.words[self.len] = END_TAG;
+        self.words[0] = (MESSAGE_WORDS * size_of::<u32>()) as u32;
+        self.words[1] = CODE_REQUEST;
+    }
+}
+
+impl MailboxInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+
+    // Hand the message at `bus_addr` to the firmware, and wait for the answer.
+    fn exchange(&mut self, bus_addr: u32) -> Result<(), &'static str> {
+        let request = bus_addr | PROPERTY_CHANNEL;
+
+        if !wait_for(|| !self.registers.STATUS1.is_set(STATUS::FULL)) {
+            return Err("Mailbox full");
+        }
+        self.registers.WRITE.set(request);
+
+        // Answers to other channels are not expected, since nothing else uses the mailbox. They
+        // are dropped.
+        loop {
+            if !wait_for(|| !self.registers.STATUS0.is_set(STATUS::EMPTY)) {
+                return Err("Mailbox response timeout");
+            }
+
+            if self.registers.READ.get() == request {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Spin until `done()` returns true or `RESPONSE_TIMEOUT` passed. Returns whether `done()` returned
+// true.
+fn wait_for(mut done: impl FnMut() -> bool) -> bool {
+    let deadline = time::time_manager().uptime() + RESPONSE_TIMEOUT;
+
+    loop {
+        if done() {
+            return true;
+        }
+
+        if time::time_manager().uptime() > deadline {
+            return false;
+        }
+    }
+}
+
+impl Mailbox {
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: Mutex::new(MailboxInner::new(mmio_start_addr)),
+        }
+    }
+
+    // Send a message to the firmware and wait for the answer, which replaces the request in
+    // `message`.
+    //
+    // The message must be in the first GiB of RAM, which the VideoCore can see. The kernel's
+    // stack, data and bss are.
+    pub fn call(&self, message: &mut PropertyMessage) -> Result<(), &'static str> {
+        message.finish();
+
+        let addr = &message.words as *const _ as usize;
+        let size = size_of::<[u32; MESSAGE_WORDS]>();
+        let bus_addr = bcm2xxx_bus::ram_bus_address(addr)
+            .ok_or("Property message out of the VideoCore's reach")?;
+
+        // Make the request visible to the VideoCore, and make sure no stale lines of the message
+        // remain to hide the answer.
+        cache::clean_invalidate_dcache_range(addr, size);
+
+        self.inner.lock().exchange(bus_addr)?;
+
+        // Lines may have been fetched speculatively while the firmware was writing. The message
+        // covers whole cache lines, so nothing else is lost.
+        unsafe { cache::invalidate_dcache_range(addr, size) };
+
+        if message.words[1] != CODE_SUCCESS {
+            return Err("Property request failed");
+        }
+
+        Ok(())
+    }
+
+    // Send a single tag and return its response.
+    pub fn property<T: Tag>(&self, tag: &T) -> Result<T::Response, &'static str> {
+        let mut message = PropertyMessage::new();
+        let slot = message.add(tag)?;
+
+        self.call(&mut message)?;
+        message.get(&slot)
+    }
+}
+
+impl driver::DeviceDriver for Mailbox {
+    fn compatible(&self) -> &'static str {
+        "BCM VideoCore Mailbox"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        // Drop answers left over from the firmware or a previous kernel
+        let inner = self.inner.lock();
+        while !inner.registers.STATUS0.is_set(STATUS::EMPTY) {
+            inner.registers.READ.get();
+        }
+
+        Ok(())
+    }
+}
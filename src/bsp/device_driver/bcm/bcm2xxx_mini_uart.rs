@@ -0,0 +1,297 @@
+// Mini UART driver - BCM2835 AUX UART1.
+//
+// The mini UART is a cut-down 16550 in the AUX block, which it shares with the two SPI masters.
+// Its baud rate is derived from the VPU core clock, so it changes if the firmware scales that
+// clock; `core_freq` should be fixed in config.txt (`enable_uart=1` does that on the RPi3).
+//
+// Input is polled, there is no interrupt controller driver for the RPi yet.
+
+use core::fmt::{self, Write};
+
+use spin::Mutex;
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    registers::{ReadOnly, ReadWrite},
+};
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper, console, cpu, driver,
+    exception::asynchronous::exec_with_irq_masked,
+};
+
+const BAUD_RATE: u32 = 115_200;
+
+register_bitfields! [
+    u32,
+    // Auxiliary enables
+    AUX_ENABLES [
+        MINI_UART OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1,
+        ]
+    ],
+
+    // Interrupt Identify register. Writes clear the FIFOs.
+    AUX_MU_IIR [
+        CLEAR_TX_FIFO OFFSET(2) NUMBITS(1) [],
+        CLEAR_RX_FIFO OFFSET(1) NUMBITS(1) []
+    ],
+
+    // Line Control register
+    AUX_MU_LCR [
+        // The BCM2835 ARM Peripherals document says bit 0 alone selects 8 bit mode, but both
+        // bits are needed, as its errata point out.
+        DATA_SIZE OFFSET(0) NUMBITS(2) [
+            SevenBit = 0b00,
+            EightBit = 0b11,
+        ]
+    ],
+
+    // Line Status register
+    AUX_MU_LSR [
+        // The transmit FIFO is empty and the transmitter is idle.
+        TX_IDLE OFFSET(6) NUMBITS(1) [],
+
+        // The transmit FIFO can accept at least one byte.
+        TX_EMPTY OFFSET(5) NUMBITS(1) [],
+
+        // The receive FIFO holds at least one byte.
+        DATA_READY OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Extra Control register
+    AUX_MU_CNTL [
+        TX_ENABLE OFFSET(1) NUMBITS(1) [],
+        RX_ENABLE OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Baudrate register. The baud rate is `core_clock / (8 * (BAUDRATE + 1))`.
+    AUX_MU_BAUD [
+        BAUDRATE OFFSET(0) NUMBITS(16) []
+    ]
+];
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x04 => AUX_ENABLES: ReadWrite<u32, AUX_ENABLES::Register>),
+        (0x08 => _reserved2),
+        (0x40 => AUX_MU_IO: ReadWrite<u32>),
+        (0x44 => AUX_MU_IER: ReadWrite<u32>),
+        (0x48 => AUX_MU_IIR: ReadWrite<u32, AUX_MU_IIR::Register>),
+        (0x4C => AUX_MU_LCR: ReadWrite<u32, AUX_MU_LCR::Register>),
+        (0x50 => AUX_MU_MCR: ReadWrite<u32>),
+        (0x54 => AUX_MU_LSR: ReadOnly<u32, AUX_MU_LSR::Register>),
+        (0x58 => _reserved3),
+        (0x60 => AUX_MU_CNTL: ReadWrite<u32, AUX_MU_CNTL::Register>),
+        (0x64 => _reserved4),
+        (0x68 => AUX_MU_BAUD: ReadWrite<u32, AUX_MU_BAUD::Register>),
+        (0x6C => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+pub struct MiniUartInner {
+    registers: Registers,
+    core_clock_hz: u32,
+    chars_read: usize,
+    chars_written: usize,
+}
+
+pub use MiniUartInner as PanicMiniUart;
+
+pub struct MiniUart {
+    inner: Mutex<MiniUartInner>,
+}
+
+impl MiniUartInner {
+    // # Safety
+    //
+    // - The user must ensure to provide the correct MMIO start address of the AUX block.
+    pub const unsafe fn new(mmio_start_addr: usize, core_clock_hz: u32) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            core_clock_hz,
+            chars_read: 0,
+            chars_written: 0,
+        }
+    }
+
+    fn baud_divisor(&self) -> u32 {
+        // Rounded to the nearest divisor
+        (self.core_clock_hz + 4 * BAUD_RATE) / (8 * BAUD_RATE) - 1
+    }
+
+    // Set up 8N1 at `BAUD_RATE`, without interrupts and flow control.
+    pub fn init(&mut self) {
+        // Already set up like this, by the early console. Turning it off and on again could only
+        // lose characters.
+        if self.is_configured() {
+            return;
+        }
+
+        // Let queued characters go out first, in case the UART was running.
+        if self
+            .registers
+            .AUX_ENABLES
+            .matches_all(AUX_ENABLES::MINI_UART::Enabled)
+        {
+            self.flush();
+        }
+
+        self.registers
+            .AUX_ENABLES
+            .modify(AUX_ENABLES::MINI_UART::Enabled);
+
+        // Transmitter and receiver off while changing the settings
+        self.registers.AUX_MU_CNTL.set(0);
+        self.registers.AUX_MU_IER.set(0);
+        self.registers.AUX_MU_MCR.set(0);
+        self.registers
+            .AUX_MU_LCR
+            .write(AUX_MU_LCR::DATA_SIZE::EightBit);
+        self.registers
+            .AUX_MU_BAUD
+            .write(AUX_MU_BAUD::BAUDRATE.val(self.baud_divisor()));
+        self.registers
+            .AUX_MU_IIR
+            .write(AUX_MU_IIR::CLEAR_TX_FIFO::SET + AUX_MU_IIR::CLEAR_RX_FIFO::SET);
+
+        self.registers
+            .AUX_MU_CNTL
+            .write(AUX_MU_CNTL::TX_ENABLE::SET + AUX_MU_CNTL::RX_ENABLE::SET);
+    }
+
+    // Whether the UART is on with the settings `init()` programs.
+    fn is_configured(&self) -> bool {
+        self.registers
+            .AUX_ENABLES
+            .matches_all(AUX_ENABLES::MINI_UART::Enabled)
+            && self
+                .registers
+                .AUX_MU_CNTL
+                .matches_all(AUX_MU_CNTL::TX_ENABLE::SET + AUX_MU_CNTL::RX_ENABLE::SET)
+            && self
+                .registers
+                .AUX_MU_LCR
+                .matches_all(AUX_MU_LCR::DATA_SIZE::EightBit)
+            && self.registers.AUX_MU_BAUD.read(AUX_MU_BAUD::BAUDRATE) == self.baud_divisor()
+    }
+
+    fn write_char(&mut self, c: char) {
+        // Spin until the TX FIFO has room
+        while !self.registers.AUX_MU_LSR.is_set(AUX_MU_LSR::TX_EMPTY) {
+            cpu::nop();
+        }
+
+        self.registers.AUX_MU_IO.set(c as u32);
+
+        self.chars_written += 1;
+    }
+
+    // Block execution until the last buffered character has been physically put on the TX wire.
+    fn flush(&self) {
+        while !self.registers.AUX_MU_LSR.is_set(AUX_MU_LSR::TX_IDLE) {
+            cpu::nop();
+        }
+    }
+
+    fn read_char_converting(&mut self) -> Option<char> {
+        if !self.registers.AUX_MU_LSR.is_set(AUX_MU_LSR::DATA_READY) {
+            return None;
+        }
+
+        let mut ret = self.registers.AUX_MU_IO.get() as u8 as char;
+
+        // Convert carriage return to newline
+        if ret == '\r' {
+            ret = '\n';
+        }
+
+        self.chars_read += 1;
+        Some(ret)
+    }
+}
+
+impl fmt::Write for MiniUartInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+impl MiniUart {
+    // # Safety
+    //
+    // - The user must ensure to provide the correct MMIO start address of the AUX block.
+    pub const unsafe fn new(mmio_start_addr: usize, core_clock_hz: u32) -> Self {
+        Self {
+            inner: Mutex::new(MiniUartInner::new(mmio_start_addr, core_clock_hz)),
+        }
+    }
+
+    // Consoles can be written from IRQ context, so IRQs must be masked while the lock is held.
+    fn locked<R>(&self, f: impl FnOnce(&mut MiniUartInner) -> R) -> R {
+        exec_with_irq_masked(|| f(&mut self.inner.lock()))
+    }
+}
+
+impl driver::DeviceDriver for MiniUart {
+    fn compatible(&self) -> &'static str {
+        "BCM Mini UART"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.locked(|inner| inner.init());
+        Ok(())
+    }
+}
+
+impl console::Write for MiniUart {
+    fn write_char(&self, c: char) -> fmt::Result {
+        self.locked(|inner| inner.write_char(c));
+        Ok(())
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.locked(|inner| inner.write_fmt(args))
+    }
+
+    fn flush(&self) -> fmt::Result {
+        self.locked(|inner| inner.flush());
+        Ok(())
+    }
+}
+
+impl console::Read for MiniUart {
+    fn read_char(&self) -> Result<char, fmt::Error> {
+        // Poll without holding the lock, so that IRQs can be taken while waiting
+        loop {
+            if let Some(c) = self.locked(|inner| inner.read_char_converting()) {
+                return Ok(c);
+            }
+
+            cpu::nop();
+        }
+    }
+
+    fn clear_rx(&self) -> fmt::Result {
+        while self.locked(|inner| inner.read_char_converting()).is_some() {}
+        Ok(())
+    }
+}
+
+impl console::Statistics for MiniUart {
+    fn chars_written(&self) -> usize {
+        self.locked(|inner| inner.chars_written)
+    }
+
+    fn chars_read(&self) -> usize {
+        self.locked(|inner| inner.chars_read)
+    }
+}
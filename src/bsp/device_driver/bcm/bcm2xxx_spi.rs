@@ -0,0 +1,345 @@
+// BCM2835 SPI0 driver - an SPI master with two chip select lines.
+//
+// Short transfers are polled: the CPU keeps the transmit FIFO filled and the receive FIFO drained
+// while the controller shifts. Once the BSP hands the driver a DMA controller, longer ones of whole
+// words go through two DMA channels and bounce buffers instead. The first word the transmit channel
+// writes to the FIFO then sets up the transfer, with its length in the top half and the low byte
+// of the CS register in the bottom one. The controller drives the chip select line for the whole
+// transfer.
+
+use core::time::Duration;
+
+use spin::Mutex;
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    registers::ReadWrite,
+};
+
+use crate::{
+    boot_option,
+    bsp::device_driver::{
+        common::MMIODerefWrapper, DMAController, DmaBuffer, DmaChannel, Endpoint,
+    },
+    driver, kwarn,
+    spi::{self, Config},
+    time::{self, TimeManager},
+};
+
+register_bitfields! {
+    u32,
+
+    // Control and Status
+    CS [
+        // Chip select polarity of CS1 and CS0, set for active high
+        CSPOL1 OFFSET(22) NUMBITS(1) [],
+        CSPOL0 OFFSET(21) NUMBITS(1) [],
+        // The transmit FIFO can take data
+        TXD OFFSET(18) NUMBITS(1) [],
+        // The receive FIFO holds data
+        RXD OFFSET(17) NUMBITS(1) [],
+        DONE OFFSET(16) NUMBITS(1) [],
+        // Deassert chip select at the end of a DMA transfer
+        ADCS OFFSET(11) NUMBITS(1) [],
+        DMAEN OFFSET(8) NUMBITS(1) [],
+        // Transfer active
+        TA OFFSET(7) NUMBITS(1) [],
+        CLEAR OFFSET(4) NUMBITS(2) [
+            ClearTxRx = 0b11
+        ],
+        CPOL OFFSET(3) NUMBITS(1) [],
+        CPHA OFFSET(2) NUMBITS(1) [],
+        CS OFFSET(0) NUMBITS(2) []
+    ],
+
+    // Clock Divider. The core clock is divided by `CDIV`, which the controller rounds down to an
+    // even number.
+    CLK [
+        CDIV OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => FIFO: ReadWrite<u32>),
+        (0x08 => CLK: ReadWrite<u32, CLK::Register>),
+        (0x0C => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+const NUM_CHIP_SELECTS: usize = 2;
+
+const DEFAULT_CLOCK_HZ: u32 = 1_000_000;
+
+// Added to the time a transfer takes on the bus
+const TRANSFER_TIMEOUT_MARGIN: Duration = Duration::from_millis(10);
+
+// Transfers of whole words from this length up to the bounce buffers' size go through DMA
+const DMA_MIN_TRANSFER: usize = 32;
+const DMA_BUFFER_SIZE: usize = 4096;
+
+// The DREQ lines the controller paces DMA with
+const DREQ_TX: u32 = 6;
+const DREQ_RX: u32 = 7;
+
+boot_option! {
+    // The SCLK frequency in Hz the driver sets up.
+    static CLOCK_HZ: u32 = ("spi", DEFAULT_CLOCK_HZ);
+}
+
+struct SPIDma {
+    tx: DmaChannel,
+    rx: DmaChannel,
+    fifo_bus_addr: u32,
+}
+
+struct SPIControllerInner {
+    registers: Registers,
+    mmio_start_addr: usize,
+    core_clock_hz: u32,
+    clock_hz: u32,
+    dma: Option<SPIDma>,
+    // The setup word and the bytes to send
+    tx_buffer: DmaBuffer<{ DMA_BUFFER_SIZE + 4 }>,
+    rx_buffer: DmaBuffer<DMA_BUFFER_SIZE>,
+}
+
+pub struct SPIController {
+    inner: Mutex<SPIControllerInner>,
+}
+
+impl SPIControllerInner {
+    const unsafe fn new(mmio_start_addr: usize, core_clock_hz: u32) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            mmio_start_addr,
+            core_clock_hz,
+            clock_hz: 0,
+            dma: None,
+            tx_buffer: DmaBuffer::new(),
+            rx_buffer: DmaBuffer::new(),
+        }
+    }
+
+    fn set_clock_hz(&mut self, hz: u32) -> Result<u32, &'static str> {
+        if hz == 0 {
+            return Err("Invalid frequency");
+        }
+
+        // Round up to an even divisor, so that the frequency does not exceed `hz`
+        let divisor = (self.core_clock_hz + hz - 1) / hz;
+        let divisor = ((divisor + 1) & !1).max(2).min(0xFFFE);
+
+        self.registers.CLK.write(CLK::CDIV.val(divisor));
+        self.clock_hz = self.core_clock_hz / divisor;
+
+        Ok(self.clock_hz)
+    }
+
+    // How long a transfer of `len` bytes may take.
+    fn timeout(&self, len: usize) -> Duration {
+        let bus_time = Duration::from_micros(len as u64 * 8 * 1_000_000 / u64::from(self.clock_hz));
+
+        bus_time + TRANSFER_TIMEOUT_MARGIN
+    }
+
+    fn deadline(&self, len: usize) -> Duration {
+        time::time_manager().uptime() + self.timeout(len)
+    }
+
+    fn enable_dma(&mut self, dma: &'static DMAController) -> Result<(), &'static str> {
+        self.dma = Some(SPIDma {
+            tx: dma.allocate()?,
+            rx: dma.allocate()?,
+            fifo_bus_addr: dma.peripheral_bus_address(self.mmio_start_addr + 0x04),
+        });
+
+        Ok(())
+    }
+
+    fn transfer(&mut self, config: Config, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str> {
+        if config.chip_select >= NUM_CHIP_SELECTS {
+            return Err("No such chip select");
+        }
+
+        let len = tx.len().max(rx.len());
+        let cs_polarity = if config.chip_select == 0 {
+            CS::CSPOL0.val(config.cs_active_high as u32)
+        } else {
+            CS::CSPOL1.val(config.cs_active_high as u32)
+        };
+        let setup = CS::CS.val(config.chip_select as u32)
+            + CS::CPOL.val(config.mode.cpol() as u32)
+            + CS::CPHA.val(config.mode.cpha() as u32);
+
+        // The line's polarity has to be in place before the transfer selects it
+        self.registers.CS.modify(cs_polarity);
+        self.registers.CS.modify(setup + CS::CLEAR::ClearTxRx);
+
+        if self.dma.is_some() && (DMA_MIN_TRANSFER..=DMA_BUFFER_SIZE).contains(&len) && len % 4 == 0
+        {
+            return self.transfer_dma(setup.value, tx, rx, len);
+        }
+
+        self.transfer_polled(tx, rx, len)
+    }
+
+    fn transfer_polled(
+        &mut self,
+        tx: &[u8],
+        rx: &mut [u8],
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let deadline = self.deadline(len);
+
+        self.registers.CS.modify(CS::TA::SET);
+
+        let (mut sent, mut received) = (0, 0);
+        let mut result = Ok(());
+        while received < len {
+            while sent < len && self.registers.CS.is_set(CS::TXD) {
+                self.registers
+                    .FIFO
+                    .set(u32::from(tx.get(sent).copied().unwrap_or(0)));
+                sent += 1;
+            }
+
+            while received < len && self.registers.CS.is_set(CS::RXD) {
+                let byte = self.registers.FIFO.get() as u8;
+
+                if let Some(slot) = rx.get_mut(received) {
+                    *slot = byte;
+                }
+                received += 1;
+            }
+
+            if time::time_manager().uptime() > deadline {
+                result = Err("SPI transfer timeout");
+                break;
+            }
+        }
+
+        while result.is_ok() && !self.registers.CS.is_set(CS::DONE) {
+            if time::time_manager().uptime() > deadline {
+                result = Err("SPI transfer timeout");
+            }
+        }
+
+        self.registers.CS.modify(CS::TA::CLEAR);
+        result
+    }
+
+    // `setup` holds the chip select and the clock's polarity and phase.
+    fn transfer_dma(
+        &mut self,
+        setup: u32,
+        tx: &[u8],
+        rx: &mut [u8],
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let dma = self.dma.as_ref().ok_or("No DMA")?;
+        let tx_bus_addr = self.tx_buffer.bus_address()?;
+        let rx_bus_addr = self.rx_buffer.bus_address()?;
+        let setup_word = (len as u32) << 16 | setup | CS::TA::SET.value;
+
+        self.tx_buffer[..4].copy_from_slice(&setup_word.to_le_bytes());
+        for (i, byte) in self.tx_buffer[4..4 + len].iter_mut().enumerate() {
+            *byte = tx.get(i).copied().unwrap_or(0);
+        }
+        self.tx_buffer.sync_for_device();
+        self.rx_buffer.sync_for_device();
+
+        let fifo = |dreq| Endpoint::Peripheral {
+            bus_addr: dma.fifo_bus_addr,
+            dreq,
+        };
+        self.registers.CS.modify(CS::DMAEN::SET + CS::ADCS::SET);
+
+        // The receiving side has to be ready before anything is shifted in
+        let mut result = dma
+            .rx
+            .start(fifo(DREQ_RX), Endpoint::Memory(rx_bus_addr), len)
+            .and_then(|_| {
+                dma.tx
+                    .start(Endpoint::Memory(tx_bus_addr), fifo(DREQ_TX), len + 4)
+            });
+        if result.is_ok() {
+            let timeout = self.timeout(len) + DMAController::transfer_timeout(len);
+
+            result = dma.tx.wait(timeout).and(dma.rx.wait(timeout));
+        }
+        if result.is_err() {
+            dma.rx.abort();
+            dma.tx.abort();
+        }
+
+        self.registers
+            .CS
+            .modify(CS::DMAEN::CLEAR + CS::ADCS::CLEAR + CS::TA::CLEAR + CS::CLEAR::ClearTxRx);
+        result?;
+
+        self.rx_buffer.sync_for_cpu();
+        let received = rx.len().min(len);
+        rx[..received].copy_from_slice(&self.rx_buffer[..received]);
+
+        Ok(())
+    }
+}
+
+impl SPIController {
+    // Create an instance.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize, core_clock_hz: u32) -> Self {
+        Self {
+            inner: Mutex::new(SPIControllerInner::new(mmio_start_addr, core_clock_hz)),
+        }
+    }
+
+    // Let long transfers go through DMA, on two channels of `dma`.
+    pub fn enable_dma(&self, dma: &'static DMAController) -> Result<(), &'static str> {
+        self.inner.lock().enable_dma(dma)
+    }
+}
+
+impl driver::DeviceDriver for SPIController {
+    fn compatible(&self) -> &'static str {
+        "BCM SPI0"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock();
+
+        // Idle, with both chip select lines active low and the FIFOs empty
+        inner.registers.CS.write(CS::CLEAR::ClearTxRx);
+        if let Err(msg) = inner.set_clock_hz(CLOCK_HZ.get()) {
+            kwarn!("SPI0: {} Hz: {}", CLOCK_HZ.get(), msg);
+            inner.set_clock_hz(DEFAULT_CLOCK_HZ)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl spi::SpiBus for SPIController {
+    fn num_chip_selects(&self) -> usize {
+        NUM_CHIP_SELECTS
+    }
+
+    fn clock_hz(&self) -> u32 {
+        self.inner.lock().clock_hz
+    }
+
+    fn set_clock_hz(&self, hz: u32) -> Result<u32, &'static str> {
+        self.inner.lock().set_clock_hz(hz)
+    }
+
+    fn transfer(&self, config: Config, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str> {
+        self.inner.lock().transfer(config, tx, rx)
+    }
+}
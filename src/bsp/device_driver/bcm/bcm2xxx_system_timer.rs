@@ -0,0 +1,249 @@
+// BCM2835 system timer driver - periodic and one-shot callbacks.
+//
+// The system timer is a free running 1 MHz counter with four compare channels. The VideoCore uses
+// channels 0 and 2, the driver takes channel 3. It calls handlers at fixed periods or once after a
+// delay from the channel's interrupt, the compare value always set to the next one due.
+//
+// One-shots keep their slot once added, and are armed again and again with `arm_oneshot`.
+
+use core::time::Duration;
+
+use spin::Mutex;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::{ReadOnly, ReadWrite},
+};
+
+use crate::{
+    bsp::{device_driver::common::MMIODerefWrapper, exception::asynchronous::IRQNumber},
+    driver,
+    exception::{
+        self,
+        asynchronous::{exec_with_irq_masked, IRQDescriptor, IRQHandler},
+    },
+};
+
+register_bitfields! {
+    u32,
+
+    // Control/Status. A match is cleared by writing 1.
+    CS [
+        M3 OFFSET(3) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        // The counter's lower 32 bits
+        (0x04 => CLO: ReadOnly<u32>),
+        (0x08 => CHI: ReadOnly<u32>),
+        (0x0C => C0: ReadWrite<u32>),
+        (0x10 => C1: ReadWrite<u32>),
+        (0x14 => C2: ReadWrite<u32>),
+        (0x18 => C3: ReadWrite<u32>),
+        (0x1C => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+const MAX_PERIODIC: usize = 4;
+
+// How far ahead of the counter a compare value is set for handlers that are due already
+const MIN_AHEAD: u32 = 2;
+
+// Periods are counted in counter ticks, which are microseconds. A period of 0 is a one-shot, which
+// is only called while armed.
+#[derive(Clone, Copy)]
+struct Periodic {
+    period: u32,
+    next: u32,
+    armed: bool,
+    descriptor: IRQDescriptor,
+}
+
+// A one-shot's slot, from `add_oneshot`
+#[derive(Clone, Copy)]
+pub struct OneShot(usize);
+
+pub struct SystemTimer {
+    registers: Registers,
+    irq_number: IRQNumber,
+    periodic: Mutex<[Option<Periodic>; MAX_PERIODIC]>,
+}
+
+// Whether the counter reached `time`, for times within 2^31 us, about 35 minutes, of `now`.
+fn reached(now: u32, time: u32) -> bool {
+    now.wrapping_sub(time) as i32 >= 0
+}
+
+impl SystemTimer {
+    // Create an instance.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize, irq_number: IRQNumber) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            irq_number,
+            periodic: Mutex::new([None; MAX_PERIODIC]),
+        }
+    }
+
+    fn locked<R>(&self, f: impl FnOnce(&mut [Option<Periodic>; MAX_PERIODIC]) -> R) -> R {
+        exec_with_irq_masked(|| f(&mut self.periodic.lock()))
+    }
+
+    // Set the compare value to the next time a handler is due.
+    fn schedule(&self, periodic: &[Option<Periodic>]) {
+        let now = self.registers.CLO.get();
+        let next = periodic
+            .iter()
+            .flatten()
+            .filter(|p| p.armed)
+            .map(|p| p.next)
+            .min_by_key(|&next| next.wrapping_sub(now) as i32);
+        let mut compare = match next {
+            Some(next) => next,
+            None => return,
+        };
+
+        // The channel only matches when the counter equals the compare value. One that is passed
+        // already, or by the time it is written, would not match before the counter wraps, over an
+        // hour later. Move it ahead of the counter instead, the due handlers run from that match.
+        loop {
+            self.registers.C3.set(compare);
+
+            let now = self.registers.CLO.get();
+            if !reached(now, compare) {
+                return;
+            }
+            compare = now.wrapping_add(MIN_AHEAD);
+        }
+    }
+
+    fn add(
+        &self,
+        period: u32,
+        delay: u32,
+        descriptor: IRQDescriptor,
+    ) -> Result<usize, &'static str> {
+        self.locked(|periodic| {
+            let index = periodic
+                .iter()
+                .position(|p| p.is_none())
+                .ok_or("Too many timer handlers")?;
+
+            periodic[index] = Some(Periodic {
+                period,
+                next: self.registers.CLO.get().wrapping_add(delay),
+                armed: period != 0,
+                descriptor,
+            });
+            self.schedule(periodic);
+
+            Ok(index)
+        })
+    }
+
+    // Call `descriptor`'s handler every `period`, from the timer's interrupt.
+    pub fn add_periodic(
+        &self,
+        period: Duration,
+        descriptor: IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        let period = period.as_micros();
+
+        if period == 0 || period > i32::MAX as u128 {
+            return Err("Period out of range");
+        }
+
+        self.add(period as u32, period as u32, descriptor)
+            .map(|_| ())
+    }
+
+    // Take a slot for calling `descriptor`'s handler once per `arm_oneshot`.
+    pub fn add_oneshot(&self, descriptor: IRQDescriptor) -> Result<OneShot, &'static str> {
+        self.add(0, 0, descriptor).map(OneShot)
+    }
+
+    // Call the handler of `oneshot` once, `delay` from now, from the timer's interrupt. Moves the
+    // call if it is armed already.
+    pub fn arm_oneshot(&self, oneshot: OneShot, delay: Duration) -> Result<(), &'static str> {
+        let delay = delay.as_micros();
+
+        if delay > i32::MAX as u128 {
+            return Err("Delay out of range");
+        }
+
+        self.locked(|periodic| {
+            let p = periodic[oneshot.0].as_mut().ok_or("No such one-shot")?;
+
+            p.next = self.registers.CLO.get().wrapping_add(delay as u32);
+            p.armed = true;
+            self.schedule(periodic);
+
+            Ok(())
+        })
+    }
+}
+
+impl driver::DeviceDriver for SystemTimer {
+    fn compatible(&self) -> &'static str {
+        "BCM System Timer"
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use exception::asynchronous::irq_manager;
+
+        let descriptor = IRQDescriptor {
+            name: "BCM System Timer",
+            handler: self,
+        };
+
+        irq_manager().register_handler(self.irq_number, descriptor)?;
+        irq_manager().enable(self.irq_number);
+
+        Ok(())
+    }
+}
+
+impl IRQHandler for SystemTimer {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.registers.CS.write(CS::M3::SET);
+
+        // The handlers run without the lock held, so that they can add more and arm one-shots
+        let mut due = [None; MAX_PERIODIC];
+        let mut periodic = self.periodic.lock();
+        let now = self.registers.CLO.get();
+
+        for (p, due) in periodic.iter_mut().flatten().zip(due.iter_mut()) {
+            if !p.armed || !reached(now, p.next) {
+                continue;
+            }
+            *due = Some(p.descriptor);
+
+            if p.period == 0 {
+                p.armed = false;
+                continue;
+            }
+            p.next = p.next.wrapping_add(p.period);
+
+            // Skip the periods that were missed
+            if reached(now, p.next) {
+                p.next = now.wrapping_add(p.period);
+            }
+        }
+        self.schedule(&*periodic);
+        drop(periodic);
+
+        for descriptor in due.iter().flatten() {
+            descriptor.handler.handle()?;
+        }
+
+        Ok(())
+    }
+}
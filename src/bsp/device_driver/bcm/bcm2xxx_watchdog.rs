@@ -0,0 +1,210 @@
+// BCM2835 power management watchdog driver.
+//
+// The watchdog is part of the power management block. It counts down in ticks of 1/65536 s from at
+// most 2^20 - 1 ticks, about 16 s, and does a full reset when it gets to 0. Writes to the block only
+// take with its password in the top byte.
+//
+// The reset status register tells what caused the last reset. The firmware keeps its even bits
+// as the partition to boot from after a reset, and takes partition 63 as a request to halt. That
+// is the closest the board gets to powering off. The kernel's own resets go through the watchdog,
+// so they read as watchdog resets.
+
+use core::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::ReadWrite,
+};
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper, cpu, driver, power::ResetReason, watchdog,
+};
+
+register_bitfields! {
+    u32,
+
+    // Reset Control
+    RSTC [
+        PASSWORD OFFSET(24) NUMBITS(8) [
+            Password = 0x5A
+        ],
+        // What a timeout does
+        WRCFG OFFSET(4) NUMBITS(2) [
+            Clear = 0b00,
+            FullReset = 0b10
+        ],
+        // Set along with WRCFG cleared to stop the watchdog
+        RESET OFFSET(8) NUMBITS(1) [],
+        RESET_ALT OFFSET(1) NUMBITS(1) []
+    ],
+
+    // Reset Status
+    RSTS [
+        PASSWORD OFFSET(24) NUMBITS(8) [
+            Password = 0x5A
+        ],
+        // Power-on reset
+        HADPOR OFFSET(12) NUMBITS(1) [],
+        // Full reset by software, and by the watchdog
+        HADSRF OFFSET(9) NUMBITS(1) [],
+        HADWRF OFFSET(5) NUMBITS(1) []
+    ],
+
+    // Watchdog
+    WDOG [
+        PASSWORD OFFSET(24) NUMBITS(8) [
+            Password = 0x5A
+        ],
+        // Ticks left
+        TIME OFFSET(0) NUMBITS(20) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x1C => RSTC: ReadWrite<u32, RSTC::Register>),
+        (0x20 => RSTS: ReadWrite<u32, RSTS::Register>),
+        (0x24 => WDOG: ReadWrite<u32, WDOG::Register>),
+        (0x28 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+const TICKS_PER_SECOND: u64 = 65536;
+const MAX_TICKS: u32 = (1 << 20) - 1;
+
+// The timeout of a reset asked for, about 150 us
+const RESET_TICKS: u32 = 10;
+
+// The partition bits of RSTS, and the partition that makes the firmware halt
+const RSTS_PARTITION_MASK: u32 = 0x555;
+const RSTS_PARTITION_HALT: u32 = 0x555;
+
+pub struct PMWatchdog {
+    registers: Registers,
+    // The timeout of the last `start`, for `pet`. Not behind a lock, so that the panic handler can
+    // start the watchdog whatever the panicking code held.
+    timeout_ticks: AtomicU32,
+    // RSTS as found at boot
+    boot_status: AtomicU32,
+}
+
+impl PMWatchdog {
+    // Create an instance.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            timeout_ticks: AtomicU32::new(MAX_TICKS),
+            boot_status: AtomicU32::new(0),
+        }
+    }
+
+    fn arm(&self, ticks: u32) {
+        self.registers
+            .WDOG
+            .write(WDOG::PASSWORD::Password + WDOG::TIME.val(ticks));
+
+        // The other bits of RSTC stay as the firmware set them
+        let keep = self.registers.RSTC.get()
+            & !(RSTC::PASSWORD.mask << RSTC::PASSWORD.shift
+                | RSTC::WRCFG.mask << RSTC::WRCFG.shift);
+        self.registers
+            .RSTC
+            .set(keep | (RSTC::PASSWORD::Password + RSTC::WRCFG::FullReset).value);
+    }
+
+    // Reset the board right away.
+    pub fn reset(&self) -> ! {
+        self.arm(RESET_TICKS);
+        cpu::wait_forever()
+    }
+
+    // Reset into the firmware's halt.
+    pub fn poweroff(&self) -> ! {
+        let status = self.registers.RSTS.get()
+            & !(RSTS::PASSWORD.mask << RSTS::PASSWORD.shift | RSTS_PARTITION_MASK);
+        self.registers
+            .RSTS
+            .set(status | RSTS_PARTITION_HALT | RSTS::PASSWORD::Password.value);
+
+        self.reset()
+    }
+
+    pub fn reset_reason(&self) -> ResetReason {
+        let status = self.boot_status.load(Ordering::Relaxed);
+
+        if status & RSTS::HADWRF::SET.value != 0 {
+            ResetReason::Watchdog
+        } else if status & RSTS::HADSRF::SET.value != 0 {
+            ResetReason::Software
+        } else if status & RSTS::HADPOR::SET.value != 0 {
+            ResetReason::PowerOn
+        } else {
+            ResetReason::Unknown
+        }
+    }
+}
+
+impl driver::DeviceDriver for PMWatchdog {
+    fn compatible(&self) -> &'static str {
+        "BCM PM Watchdog"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.boot_status
+            .store(self.registers.RSTS.get(), Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+impl watchdog::Watchdog for PMWatchdog {
+    fn max_timeout(&self) -> Duration {
+        Duration::from_micros(u64::from(MAX_TICKS) * 1_000_000 / TICKS_PER_SECOND)
+    }
+
+    fn start(&self, timeout: Duration) -> Result<(), &'static str> {
+        let ticks = timeout.as_micros() as u64 * TICKS_PER_SECOND / 1_000_000;
+
+        if ticks == 0 || ticks > u64::from(MAX_TICKS) {
+            return Err("Timeout out of range");
+        }
+
+        self.timeout_ticks.store(ticks as u32, Ordering::Relaxed);
+        self.arm(ticks as u32);
+
+        Ok(())
+    }
+
+    fn pet(&self) {
+        if self.is_running() {
+            self.arm(self.timeout_ticks.load(Ordering::Relaxed));
+        }
+    }
+
+    fn stop(&self) {
+        self.registers
+            .RSTC
+            .write(RSTC::PASSWORD::Password + RSTC::RESET::SET + RSTC::RESET_ALT::SET);
+    }
+
+    fn is_running(&self) -> bool {
+        self.registers.RSTC.matches_all(RSTC::WRCFG::FullReset)
+    }
+
+    fn time_left(&self) -> Duration {
+        let ticks = self.registers.WDOG.read(WDOG::TIME);
+
+        Duration::from_micros(u64::from(ticks) * 1_000_000 / TICKS_PER_SECOND)
+    }
+}
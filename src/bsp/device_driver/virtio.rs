@@ -0,0 +1,26 @@
+mod blk;
+mod mmio;
+mod virtqueue;
+
+pub use blk::*;
+
+// Device status bits, see VirtIO 1.1, section 2.1.
+mod status {
+    pub const ACKNOWLEDGE: u32 = 1;
+    pub const DRIVER: u32 = 2;
+    pub const DRIVER_OK: u32 = 4;
+    pub const FEATURES_OK: u32 = 8;
+    pub const FAILED: u32 = 128;
+}
+
+// Device-independent feature bits.
+mod feature {
+    // The device complies with VirtIO 1.x. Without it, only the legacy interface is offered.
+    pub const VERSION_1: u64 = 1 << 32;
+}
+
+// Device types, see VirtIO 1.1, section 5.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Block = 2,
+}
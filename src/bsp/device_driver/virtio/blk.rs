@@ -0,0 +1,222 @@
+// VirtIO block device driver, see VirtIO 1.1, section 5.2.
+//
+// Requests are synchronous: each one is submitted and then polled for completion, with the
+// device's interrupt left unused.
+
+use core::{mem::size_of, ptr};
+
+use spin::Mutex;
+
+use super::{
+    mmio::Transport,
+    virtqueue::{Buffer, Virtqueue},
+    DeviceType,
+};
+use crate::{block, cpu, driver};
+
+const QUEUE_SIZE: usize = 16;
+const REQUEST_QUEUE: u32 = 0;
+
+// Requests always address the device in 512 byte sectors.
+const SECTOR_SIZE: usize = 512;
+
+// The device is read-only
+const FEATURE_RO: u64 = 1 << 5;
+
+// Config space offsets
+const CONFIG_CAPACITY: usize = 0x00;
+
+// Request types
+const REQ_TYPE_IN: u32 = 0;
+const REQ_TYPE_OUT: u32 = 1;
+
+// Request status values written by the device
+const REQ_STATUS_OK: u8 = 0;
+const REQ_STATUS_IOERR: u8 = 1;
+
+#[repr(C)]
+struct RequestHeader {
+    req_type: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+struct VirtIOBlkInner {
+    transport: Option<Transport>,
+    queue: Virtqueue<QUEUE_SIZE>,
+    header: RequestHeader,
+    status: u8,
+    capacity: u64,
+    read_only: bool,
+}
+
+pub struct VirtIOBlk {
+    mmio_start_addr: usize,
+    slot_size: usize,
+    num_slots: usize,
+    inner: Mutex<VirtIOBlkInner>,
+}
+
+impl VirtIOBlkInner {
+    const fn new() -> Self {
+        Self {
+            transport: None,
+            queue: Virtqueue::new(),
+            header: RequestHeader {
+                req_type: 0,
+                reserved: 0,
+                sector: 0,
+            },
+            status: 0,
+            capacity: 0,
+            read_only: false,
+        }
+    }
+
+    fn init(&mut self, transport: Transport) -> Result<(), &'static str> {
+        let features = transport.negotiate_features(FEATURE_RO)?;
+
+        self.queue.reset();
+        transport.setup_queue(REQUEST_QUEUE, &self.queue)?;
+        transport.driver_ok();
+
+        self.capacity = transport.read_config_u64(CONFIG_CAPACITY);
+        self.read_only = features & FEATURE_RO != 0;
+        self.transport = Some(transport);
+
+        Ok(())
+    }
+
+    // Submit a request and wait for the device to complete it. `data` is read by the device for
+    // writes and written by it for reads.
+    fn request(
+        &mut self,
+        req_type: u32,
+        sector: u64,
+        data: *mut u8,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let transport = self.transport.as_ref().ok_or("No VirtIO block device")?;
+
+        self.header = RequestHeader {
+            req_type,
+            reserved: 0,
+            sector,
+        };
+        self.status = 0xFF;
+
+        let buffers = [
+            Buffer {
+                addr: &self.header as *const _ as usize,
+                len: size_of::<RequestHeader>(),
+                device_writable: false,
+            },
+            Buffer {
+                addr: data as usize,
+                len,
+                device_writable: req_type == REQ_TYPE_IN,
+            },
+            Buffer {
+                addr: &self.status as *const _ as usize,
+                len: size_of::<u8>(),
+                device_writable: true,
+            },
+        ];
+
+        let id = unsafe { self.queue.add(&buffers)? };
+        transport.notify(REQUEST_QUEUE);
+
+        loop {
+            match self.queue.pop_used() {
+                Some((used_id, _)) if used_id == id => break,
+                Some(_) => return Err("Unexpected VirtIO block request completed"),
+                None => cpu::nop(),
+            }
+        }
+
+        match unsafe { ptr::read_volatile(&self.status) } {
+            REQ_STATUS_OK => Ok(()),
+            REQ_STATUS_IOERR => Err("VirtIO block device I/O error"),
+            _ => Err("VirtIO block request unsupported"),
+        }
+    }
+}
+
+impl VirtIOBlk {
+    // Create an instance. The device is looked for in `num_slots` VirtIO MMIO transports of
+    // `slot_size` bytes each, starting at `mmio_start_addr`.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO region.
+    pub const unsafe fn new(mmio_start_addr: usize, slot_size: usize, num_slots: usize) -> Self {
+        Self {
+            mmio_start_addr,
+            slot_size,
+            num_slots,
+            inner: Mutex::new(VirtIOBlkInner::new()),
+        }
+    }
+
+    // Whether `init()` found a device.
+    pub fn is_present(&self) -> bool {
+        self.inner.lock().transport.is_some()
+    }
+}
+
+impl driver::DeviceDriver for VirtIOBlk {
+    fn compatible(&self) -> &'static str {
+        "VirtIO block device"
+    }
+
+    // Use the first block device found. Not finding one is not an error, the disk is optional.
+    //
+    // QEMU assigns the transports from the top of the region downwards, so searching in that order
+    // finds devices in command line order.
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        for slot in (0..self.num_slots).rev() {
+            let transport = Transport::new(self.mmio_start_addr + slot * self.slot_size);
+
+            if transport.is_device(DeviceType::Block) {
+                return self.inner.lock().init(transport);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl block::BlockDevice for VirtIOBlk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.inner.lock().capacity
+    }
+
+    fn read_blocks(&self, first_block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        block::check_request(self, first_block, buf.len())?;
+
+        self.inner
+            .lock()
+            .request(REQ_TYPE_IN, first_block, buf.as_mut_ptr(), buf.len())
+    }
+
+    fn write_blocks(&self, first_block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        block::check_request(self, first_block, buf.len())?;
+
+        let mut inner = self.inner.lock();
+        if inner.read_only {
+            return Err("VirtIO block device is read-only");
+        }
+
+        // The device only reads the buffer for writes
+        inner.request(
+            REQ_TYPE_OUT,
+            first_block,
+            buf.as_ptr() as *mut u8,
+            buf.len(),
+        )
+    }
+}
@@ -0,0 +1,196 @@
+// VirtIO over MMIO, see VirtIO 1.1, section 4.2.
+//
+// Only the non-legacy interface (version 2) is supported.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+
+use super::{feature, status, virtqueue::Virtqueue, DeviceType};
+use crate::{bsp::device_driver::common::MMIODerefWrapper, cpu};
+
+// "virt" in little endian
+const MAGIC: u32 = 0x7472_6976;
+const VERSION: u32 = 2;
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => MagicValue: ReadOnly<u32>),
+        (0x004 => Version: ReadOnly<u32>),
+        (0x008 => DeviceID: ReadOnly<u32>),
+        (0x00C => VendorID: ReadOnly<u32>),
+        (0x010 => DeviceFeatures: ReadOnly<u32>),
+        (0x014 => DeviceFeaturesSel: WriteOnly<u32>),
+        (0x018 => _reserved1),
+        (0x020 => DriverFeatures: WriteOnly<u32>),
+        (0x024 => DriverFeaturesSel: WriteOnly<u32>),
+        (0x028 => _reserved2),
+        (0x030 => QueueSel: WriteOnly<u32>),
+        (0x034 => QueueNumMax: ReadOnly<u32>),
+        (0x038 => QueueNum: WriteOnly<u32>),
+        (0x03C => _reserved3),
+        (0x044 => QueueReady: ReadWrite<u32>),
+        (0x048 => _reserved4),
+        (0x050 => QueueNotify: WriteOnly<u32>),
+        (0x054 => _reserved5),
+        (0x060 => _reserved6),
+        (0x070 => Status: ReadWrite<u32>),
+        (0x074 => _reserved7),
+        (0x080 => QueueDescLow: WriteOnly<u32>),
+        (0x084 => QueueDescHigh: WriteOnly<u32>),
+        (0x088 => _reserved8),
+        (0x090 => QueueDriverLow: WriteOnly<u32>),
+        (0x094 => QueueDriverHigh: WriteOnly<u32>),
+        (0x098 => _reserved9),
+        (0x0A0 => QueueDeviceLow: WriteOnly<u32>),
+        (0x0A4 => QueueDeviceHigh: WriteOnly<u32>),
+        (0x0A8 => _reserved10),
+        (0x0FC => ConfigGeneration: ReadOnly<u32>),
+        (0x100 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+// Device-specific configuration space, following the registers.
+const CONFIG_OFFSET: usize = 0x100;
+
+pub struct Transport {
+    registers: Registers,
+    mmio_start_addr: usize,
+}
+
+impl Transport {
+    // Create an instance.
+    //
+    // # Safety
+    //
+    // - The user must ensure to provide a correct MMIO start address.
+    pub unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            mmio_start_addr,
+        }
+    }
+
+    // Whether a device of `device_type` sits behind this transport.
+    pub fn is_device(&self, device_type: DeviceType) -> bool {
+        self.registers.MagicValue.get() == MAGIC
+            && self.registers.DeviceID.get() == device_type as u32
+    }
+
+    // Reset the device and negotiate features, see VirtIO 1.1, section 3.1.1. Returns the features
+    // out of `driver_features` that the device accepted.
+    pub fn negotiate_features(&self, driver_features: u64) -> Result<u64, &'static str> {
+        if self.registers.Version.get() != VERSION {
+            return Err("Legacy VirtIO MMIO device (QEMU: -global virtio-mmio.force-legacy=false)");
+        }
+
+        self.registers.Status.set(0);
+        self.add_status(status::ACKNOWLEDGE);
+        self.add_status(status::DRIVER);
+
+        let device_features = self.read_features();
+        if device_features & feature::VERSION_1 == 0 {
+            self.add_status(status::FAILED);
+            return Err("Device does not support VirtIO 1.x");
+        }
+
+        let features = device_features & (driver_features | feature::VERSION_1);
+        self.write_features(features);
+
+        self.add_status(status::FEATURES_OK);
+        if self.registers.Status.get() & status::FEATURES_OK == 0 {
+            self.add_status(status::FAILED);
+            return Err("Device rejected the negotiated features");
+        }
+
+        Ok(features)
+    }
+
+    // Hand the memory of virtqueue `index` to the device.
+    pub fn setup_queue<const SIZE: usize>(
+        &self,
+        index: u32,
+        queue: &Virtqueue<SIZE>,
+    ) -> Result<(), &'static str> {
+        self.registers.QueueSel.set(index);
+
+        if self.registers.QueueReady.get() != 0 {
+            return Err("Virtqueue already in use");
+        }
+
+        if (self.registers.QueueNumMax.get() as usize) < SIZE {
+            return Err("Virtqueue too small");
+        }
+
+        let (desc, driver, device) = queue.addresses();
+
+        self.registers.QueueNum.set(SIZE as u32);
+        self.registers.QueueDescLow.set(desc as u32);
+        self.registers.QueueDescHigh.set((desc >> 32) as u32);
+        self.registers.QueueDriverLow.set(driver as u32);
+        self.registers.QueueDriverHigh.set((driver >> 32) as u32);
+        self.registers.QueueDeviceLow.set(device as u32);
+        self.registers.QueueDeviceHigh.set((device >> 32) as u32);
+        self.registers.QueueReady.set(1);
+
+        Ok(())
+    }
+
+    // Conclude initialization. The device is live afterwards.
+    pub fn driver_ok(&self) {
+        self.add_status(status::DRIVER_OK);
+    }
+
+    // Tell the device that there are new buffers in virtqueue `index`.
+    pub fn notify(&self, index: u32) {
+        self.registers.QueueNotify.set(index);
+    }
+
+    // Read a little-endian `u32` from the configuration space.
+    pub fn read_config_u32(&self, offset: usize) -> u32 {
+        let addr = (self.mmio_start_addr + CONFIG_OFFSET + offset) as *const u32;
+
+        u32::from_le(unsafe { core::ptr::read_volatile(addr) })
+    }
+
+    // Read a little-endian `u64` from the configuration space. It takes two accesses, so retry
+    // until the device did not change the configuration in between.
+    pub fn read_config_u64(&self, offset: usize) -> u64 {
+        loop {
+            let generation = self.registers.ConfigGeneration.get();
+            let low = self.read_config_u32(offset) as u64;
+            let high = self.read_config_u32(offset + 4) as u64;
+
+            if generation == self.registers.ConfigGeneration.get() {
+                return (high << 32) | low;
+            }
+
+            cpu::nop();
+        }
+    }
+
+    fn add_status(&self, bits: u32) {
+        let current = self.registers.Status.get();
+        self.registers.Status.set(current | bits);
+    }
+
+    fn read_features(&self) -> u64 {
+        self.registers.DeviceFeaturesSel.set(0);
+        let low = self.registers.DeviceFeatures.get() as u64;
+        self.registers.DeviceFeaturesSel.set(1);
+        let high = self.registers.DeviceFeatures.get() as u64;
+
+        (high << 32) | low
+    }
+
+    fn write_features(&self, features: u64) {
+        self.registers.DriverFeaturesSel.set(0);
+        self.registers.DriverFeatures.set(features as u32);
+        self.registers.DriverFeaturesSel.set(1);
+        self.registers.DriverFeatures.set((features >> 32) as u32);
+    }
+}
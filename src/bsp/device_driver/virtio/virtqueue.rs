@@ -0,0 +1,214 @@
+// Split virtqueues, see VirtIO 1.1, section 2.6.
+//
+// The device accesses the queue memory directly, so it lives inside the `Virtqueue` itself, which
+// must therefore stay put, e.g. by being part of a `static`. Addresses handed to the device are
+// the kernel's addresses, which relies on the kernel being identity mapped.
+
+use core::{
+    ptr,
+    sync::atomic::{fence, Ordering},
+};
+
+// Descriptor flags
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+// The driver polls the used ring and does not want to be interrupted.
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct AvailRing<const SIZE: usize> {
+    flags: u16,
+    idx: u16,
+    ring: [u16; SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct UsedRing<const SIZE: usize> {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; SIZE],
+    avail_event: u16,
+}
+
+// A buffer that is part of a request.
+pub struct Buffer {
+    pub addr: usize,
+    pub len: usize,
+    // Whether the device writes to the buffer, as opposed to reading from it.
+    pub device_writable: bool,
+}
+
+// The descriptor table needs 16 byte alignment, the rings less. Page alignment keeps all of them
+// within one or few pages.
+#[repr(C, align(4096))]
+pub struct Virtqueue<const SIZE: usize> {
+    desc: [Descriptor; SIZE],
+    avail: AvailRing<SIZE>,
+    used: UsedRing<SIZE>,
+
+    // Driver-private bookkeeping
+    free_head: u16,
+    num_free: usize,
+    last_used_idx: u16,
+}
+
+impl<const SIZE: usize> Virtqueue<SIZE> {
+    pub const fn new() -> Self {
+        const EMPTY_DESC: Descriptor = Descriptor {
+            addr: 0,
+            len: 0,
+            flags: 0,
+            next: 0,
+        };
+        const EMPTY_USED: UsedElem = UsedElem { id: 0, len: 0 };
+
+        Self {
+            desc: [EMPTY_DESC; SIZE],
+            avail: AvailRing {
+                flags: 0,
+                idx: 0,
+                ring: [0; SIZE],
+                used_event: 0,
+            },
+            used: UsedRing {
+                flags: 0,
+                idx: 0,
+                ring: [EMPTY_USED; SIZE],
+                avail_event: 0,
+            },
+            free_head: 0,
+            num_free: 0,
+            last_used_idx: 0,
+        }
+    }
+
+    // Put the queue in its initial state, all descriptors being free. Must be done before the queue
+    // is handed to the device.
+    pub fn reset(&mut self) {
+        for (i, desc) in self.desc.iter_mut().enumerate() {
+            *desc = Descriptor {
+                addr: 0,
+                len: 0,
+                flags: 0,
+                next: (i + 1) as u16,
+            };
+        }
+
+        self.avail.flags = AVAIL_F_NO_INTERRUPT;
+        self.avail.idx = 0;
+        self.used.idx = 0;
+        self.free_head = 0;
+        self.num_free = SIZE;
+        self.last_used_idx = 0;
+    }
+
+    // Addresses of the descriptor table, the available (driver) ring and the used (device) ring.
+    pub fn addresses(&self) -> (u64, u64, u64) {
+        (
+            &self.desc as *const _ as u64,
+            &self.avail as *const _ as u64,
+            &self.used as *const _ as u64,
+        )
+    }
+
+    // Make a request, made up of `buffers`, available to the device. Returns the ID of the request,
+    // which `pop_used()` reports once the device is done with it.
+    //
+    // # Safety
+    //
+    // - The buffers must stay valid, and must not be accessed by the kernel, until the device is
+    //   done with the request.
+    pub unsafe fn add(&mut self, buffers: &[Buffer]) -> Result<u16, &'static str> {
+        if buffers.is_empty() || buffers.len() > self.num_free {
+            return Err("Not enough free virtqueue descriptors");
+        }
+
+        let head = self.free_head;
+
+        for (i, buffer) in buffers.iter().enumerate() {
+            let index = self.free_head;
+            let desc = &mut self.desc[index as usize];
+
+            desc.addr = buffer.addr as u64;
+            desc.len = buffer.len as u32;
+            desc.flags = if buffer.device_writable {
+                DESC_F_WRITE
+            } else {
+                0
+            };
+            if i + 1 < buffers.len() {
+                desc.flags |= DESC_F_NEXT;
+            }
+
+            self.free_head = desc.next;
+        }
+        self.num_free -= buffers.len();
+
+        let avail_idx = self.avail.idx;
+        self.avail.ring[avail_idx as usize % SIZE] = head;
+
+        // The device must see the descriptors and the ring entry before the new index
+        fence(Ordering::SeqCst);
+        ptr::write_volatile(&mut self.avail.idx, avail_idx.wrapping_add(1));
+        fence(Ordering::SeqCst);
+
+        Ok(head)
+    }
+
+    // Take the next request the device is done with, if any. Returns its ID and the number of bytes
+    // the device wrote.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = unsafe { ptr::read_volatile(&self.used.idx) };
+        if used_idx == self.last_used_idx {
+            return None;
+        }
+
+        // Read the entry only after having seen the index
+        fence(Ordering::SeqCst);
+
+        let elem =
+            unsafe { ptr::read_volatile(&self.used.ring[self.last_used_idx as usize % SIZE]) };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        self.free_chain(elem.id as u16);
+
+        Some((elem.id as u16, elem.len))
+    }
+
+    // Return the descriptor chain starting at `head` to the free list.
+    fn free_chain(&mut self, head: u16) {
+        let mut index = head;
+
+        loop {
+            self.num_free += 1;
+
+            let desc = &mut self.desc[index as usize];
+            if desc.flags & DESC_F_NEXT == 0 {
+                desc.next = self.free_head;
+                break;
+            }
+
+            index = desc.next;
+        }
+
+        self.free_head = head;
+    }
+}
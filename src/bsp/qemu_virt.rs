@@ -0,0 +1,45 @@
+// BSP for QEMU's `virt` machine.
+//
+// Everything is emulated, which makes this a fast target for development and CI. The memory map is
+// the one QEMU uses for `-M virt`, see `hw/arm/virt.c` in the QEMU sources.
+
+pub mod console;
+pub mod cpu;
+pub mod driver;
+pub mod exception;
+pub mod memory;
+pub mod power;
+
+use super::device_driver;
+
+// The PL011's reference clock, the `apb-pclk` of QEMU's device tree. QEMU ignores the baud rate,
+// but the divisor is still computed from it.
+const PL011_UART_CLOCK_HZ: u32 = 24_000_000;
+
+static PL011_UART: device_driver::PL011Uart = unsafe {
+    device_driver::PL011Uart::new(
+        memory::map::mmio::PL011_UART_START,
+        PL011_UART_CLOCK_HZ,
+        Some(exception::asynchronous::irq_map::PL011_UART),
+    )
+};
+
+static INTERRUPT_CONTROLLER: exception::asynchronous::InterruptController = unsafe {
+    exception::asynchronous::InterruptController::new(
+        memory::map::mmio::GICD_START,
+        memory::map::mmio::GICC_START,
+        memory::map::mmio::GICR_START,
+    )
+};
+
+static VIRTIO_BLK: device_driver::VirtIOBlk = unsafe {
+    device_driver::VirtIOBlk::new(
+        memory::map::mmio::VIRTIO_MMIO_START,
+        memory::map::mmio::VIRTIO_MMIO_SIZE,
+        memory::map::mmio::VIRTIO_MMIO_COUNT,
+    )
+};
+
+pub fn board_name() -> &'static str {
+    "QEMU virt"
+}
@@ -0,0 +1,50 @@
+use core::fmt;
+
+use crate::bsp::device_driver;
+
+use super::memory;
+
+/// In case of a panic, the panic handler uses this function to take a last shot at printing
+/// something before the system is halted.
+///
+/// The panic version of the UART is not protected with synchronization primitives, which increases
+/// chances that we get to print something, even when the kernel's default UART instance happens to
+/// be locked at the time of the panic.
+///
+/// # Safety
+///
+/// - Use only for printing during a panic.
+pub unsafe fn panic_console_out() -> impl fmt::Write {
+    let mut panic_uart = device_driver::PanicUart::new(
+        memory::map::mmio::PL011_UART_START,
+        super::PL011_UART_CLOCK_HZ,
+    );
+
+    panic_uart.init();
+    panic_uart
+}
+
+/// Output for `kprint!` and the log macros until the console driver is initialized.
+///
+/// The UART is set up on first use, the same way its driver does it, so that the driver can take
+/// over without reprogramming it. No locks are taken, since there is no MMU yet for the atomic
+/// instructions they need.
+///
+/// # Safety
+///
+/// - Use only during single-threaded kernel init, before the console driver is initialized.
+pub unsafe fn early_console_out() -> impl fmt::Write {
+    static mut INITIALIZED: bool = false;
+
+    let mut early_uart = device_driver::EarlyUart::new(
+        memory::map::mmio::PL011_UART_START,
+        super::PL011_UART_CLOCK_HZ,
+    );
+
+    if !INITIALIZED {
+        early_uart.init();
+        INITIALIZED = true;
+    }
+
+    early_uart
+}
@@ -0,0 +1,16 @@
+#[no_mangle]
+#[link_section = ".text._start_arguments"]
+pub static BOOT_CORE_ID: u64 = 0;
+
+// Where the device tree blob is expected if `x0` is zero at entry. When QEMU boots an ELF kernel
+// with `-kernel`, it does not pass the address, but places the blob at the start of RAM.
+pub const FALLBACK_DTB_ADDR: Option<usize> = Some(super::memory::map::DRAM_START);
+
+// Where the GICv3 is, for `_start` to configure its secure side when entered in EL3.
+#[no_mangle]
+#[link_section = ".text._start_arguments"]
+pub static BOOT_GICD_START: u64 = super::memory::map::mmio::GICD_START as u64;
+
+#[no_mangle]
+#[link_section = ".text._start_arguments"]
+pub static BOOT_GICR_START: u64 = super::memory::map::mmio::GICR_START as u64;
@@ -0,0 +1,50 @@
+use crate::{
+    block, console,
+    driver::{self, DeviceDriver},
+    exception, shell_command,
+};
+
+struct BSPDriverManager {
+    device_drivers: [&'static (dyn DeviceDriver + Sync); 3],
+}
+
+// The interrupt controller comes first, so that it is ready before any driver registers an IRQ
+// handler.
+static BSP_DRIVER_MANAGER: BSPDriverManager = BSPDriverManager {
+    device_drivers: [
+        &super::INTERRUPT_CONTROLLER,
+        &super::PL011_UART,
+        &super::VIRTIO_BLK,
+    ],
+};
+
+shell_command!(
+    "pl011",
+    "pl011 [<baud>[,8N1][,rtscts]]: Show or change the console UART's line settings",
+    pl011_command
+);
+
+pub fn driver_manager() -> &'static impl driver::DriverManager {
+    &BSP_DRIVER_MANAGER
+}
+
+impl driver::DriverManager for BSPDriverManager {
+    fn all_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)] {
+        &self.device_drivers[..]
+    }
+
+    fn post_device_driver_init(&self) {
+        exception::asynchronous::register_irq_manager(&super::INTERRUPT_CONTROLLER);
+        console::console_manager()
+            .register("pl011", &super::PL011_UART)
+            .unwrap();
+
+        if super::VIRTIO_BLK.is_present() {
+            block::register_block_device("vda", &super::VIRTIO_BLK).unwrap();
+        }
+    }
+}
+
+fn pl011_command(args: &[&str]) -> Result<(), &'static str> {
+    super::PL011_UART.line_settings_command(args)
+}
@@ -0,0 +1,84 @@
+// QEMU's `virt` machine comes with either a GICv2 or a GICv3, depending on `-M virt,gic-version=`.
+// Both are instantiated at their respective addresses, and the one matching the CPU's interface is
+// used.
+
+use crate::{bsp::device_driver, driver, exception};
+
+pub type IRQNumber = device_driver::GICIRQNumber;
+
+pub mod irq_map {
+    use super::IRQNumber;
+
+    // SPI 1
+    pub const PL011_UART: IRQNumber = IRQNumber::new(33);
+}
+
+pub struct InterruptController {
+    gicv2: device_driver::GICv2,
+    gicv3: device_driver::GICv3,
+}
+
+type GICManager = dyn exception::asynchronous::IRQManager<IRQNumberType = IRQNumber> + Sync;
+
+impl InterruptController {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO start addresses.
+    pub const unsafe fn new(
+        gicd_mmio_start_addr: usize,
+        gicc_mmio_start_addr: usize,
+        gicr_mmio_start_addr: usize,
+    ) -> Self {
+        Self {
+            gicv2: device_driver::GICv2::new(gicd_mmio_start_addr, gicc_mmio_start_addr),
+            gicv3: device_driver::GICv3::new(gicd_mmio_start_addr, gicr_mmio_start_addr),
+        }
+    }
+
+    fn active(&self) -> (&(dyn driver::DeviceDriver + Sync), &GICManager) {
+        if device_driver::GICv3::is_present() {
+            (&self.gicv3, &self.gicv3)
+        } else {
+            (&self.gicv2, &self.gicv2)
+        }
+    }
+}
+
+impl driver::DeviceDriver for InterruptController {
+    fn compatible(&self) -> &'static str {
+        self.active().0.compatible()
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.active().0.init()
+    }
+}
+
+impl exception::asynchronous::IRQManager for InterruptController {
+    type IRQNumberType = IRQNumber;
+
+    fn register_handler(
+        &self,
+        irq_number: Self::IRQNumberType,
+        descriptor: exception::asynchronous::IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        self.active().1.register_handler(irq_number, descriptor)
+    }
+
+    fn enable(&self, irq_number: Self::IRQNumberType) {
+        self.active().1.enable(irq_number)
+    }
+
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        self.active().1.handle_pending_irqs(ic)
+    }
+
+    fn print_handlers(&self) {
+        self.active().1.print_handlers()
+    }
+}
@@ -0,0 +1,79 @@
+use core::cell::UnsafeCell;
+
+// BSP Memory Management.
+//
+// The physical memory layout.
+//
+// QEMU places the device tree blob at the start of RAM when booting an ELF kernel. The kernel is
+// linked 2 MiB above that, with the boot core's stack in between.
+//
+// +---------------------------------------+
+// |                                       | 0x4000_0000
+// | Device tree blob                      |
+// |                                       |
+// +---------------------------------------+
+// |                                       | 0x4010_0000                    ^
+// | Boot-core Stack                       |                                | stack
+// |                                       |                                | growth
+// |                                       |                                | direction
+// +---------------------------------------+
+// |                                       | code_start @ 0x4020_0000
+// | .text                                 |
+// | .rodata                               |
+// | .got                                  |
+// |                                       |
+// +---------------------------------------+
+// |                                       | code_end_exclusive
+// | .data                                 |
+// | .bss                                  |
+// |                                       |
+// +---------------------------------------+
+// |                                       |
+// |                                       |
+
+pub mod mmu;
+
+extern "Rust" {
+    static __code_start: UnsafeCell<()>;
+    static __code_end_exclusive: UnsafeCell<()>;
+}
+
+#[rustfmt::skip]
+pub(super) mod map {
+    pub const DRAM_START:                 usize = 0x4000_0000;
+
+    // END_INCLUSIVE + 1 = 4GiB, the first GiB being MMIO
+    pub const END_INCLUSIVE:              usize = 0xFFFF_FFFF;
+
+    pub mod mmio {
+        pub const START:             usize = 0x0800_0000;
+        pub const GICD_START:        usize = 0x0800_0000;
+        pub const GICC_START:        usize = 0x0801_0000;
+        pub const PL011_UART_START:  usize = 0x0900_0000;
+        pub const GICR_START:        usize = 0x080A_0000;
+        pub const VIRTIO_MMIO_START: usize = 0x0A00_0000;
+        pub const VIRTIO_MMIO_SIZE:  usize = 0x200;
+        pub const VIRTIO_MMIO_COUNT: usize = 32;
+        // END_INCLUSIVE + 1 = DRAM_START
+        pub const END_INCLUSIVE:     usize = 0x3FFF_FFFF;
+    }
+}
+
+// Whether the `size` bytes at `start` are RAM, mapped one to one. That is all memory above the
+// MMIO, as far as the address space goes.
+pub fn is_mapped_ram(start: usize, size: usize) -> bool {
+    start >= map::DRAM_START
+        && matches!(start.checked_add(size), Some(end) if end - 1 <= map::END_INCLUSIVE)
+}
+
+// Start page address of the code segment
+#[inline(always)]
+fn code_start() -> usize {
+    unsafe { __code_start.get() as usize }
+}
+
+// Exclusive end page address of the code segment
+#[inline(always)]
+fn code_end_exclusive() -> usize {
+    unsafe { __code_end_exclusive.get() as usize }
+}
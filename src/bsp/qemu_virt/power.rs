@@ -0,0 +1,71 @@
+// Reset and power off through PSCI, the Arm Power State Coordination Interface, which QEMU
+// implements itself unless it emulates EL3.
+//
+// The device tree's `/psci` node tells whether calls go through `hvc` or `smc`. Without the node,
+// there is nobody to handle the calls, and the core is parked instead. QEMU does not tell why the
+// machine reset.
+
+use crate::{
+    cpu, exception,
+    fdt::DeviceTree,
+    power::{self, ResetReason},
+};
+
+const PSCI_SYSTEM_OFF: u64 = 0x8400_0008;
+const PSCI_SYSTEM_RESET: u64 = 0x8400_0009;
+
+struct BSPPowerManager;
+
+#[derive(Clone, Copy)]
+enum Conduit {
+    Hvc,
+    Smc,
+}
+
+static BSP_POWER_MANAGER: BSPPowerManager = BSPPowerManager;
+
+fn conduit() -> Option<Conduit> {
+    let dt = DeviceTree::from_boot()?;
+
+    match dt.property_str("/psci", "method")? {
+        "hvc" => Some(Conduit::Hvc),
+        "smc" => Some(Conduit::Smc),
+        _ => None,
+    }
+}
+
+// PSCI calls for the system power state do not return on success.
+fn psci_call(function_id: u64) {
+    unsafe {
+        match conduit() {
+            Some(Conduit::Hvc) => asm!("hvc #0", inout("x0") function_id => _, options(nostack)),
+            Some(Conduit::Smc) => asm!("smc #0", inout("x0") function_id => _, options(nostack)),
+            None => (),
+        }
+    }
+}
+
+pub fn power_manager() -> &'static impl power::PowerManager {
+    &BSP_POWER_MANAGER
+}
+
+impl power::PowerManager for BSPPowerManager {
+    fn reset(&self) -> ! {
+        psci_call(PSCI_SYSTEM_RESET);
+        self.halt()
+    }
+
+    fn poweroff(&self) -> ! {
+        psci_call(PSCI_SYSTEM_OFF);
+        self.halt()
+    }
+
+    fn halt(&self) -> ! {
+        unsafe { exception::asynchronous::local_irq_mask() };
+        cpu::wait_forever()
+    }
+
+    fn reset_reason(&self) -> ResetReason {
+        ResetReason::Unknown
+    }
+}
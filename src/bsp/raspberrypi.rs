@@ -1,25 +1,98 @@
+mod board;
+mod framebuffer;
+
 pub mod console;
 pub mod cpu;
 pub mod driver;
+pub mod exception;
 pub mod memory;
 pub mod power;
 
 use super::device_driver;
 
-static GPIO: device_driver::GPIO =
-    unsafe { device_driver::GPIO::new(memory::map::mmio::GPIO_START) };
+pub use board::board_name;
+
+static MAILBOX: device_driver::Mailbox =
+    unsafe { device_driver::Mailbox::new(memory::map::mmio::MAILBOX_START) };
+
+#[cfg(feature = "bsp_rpi3")]
+static INTERRUPT_CONTROLLER: exception::asynchronous::InterruptController =
+    unsafe { exception::asynchronous::InterruptController::new(memory::map::mmio::IC_START) };
+
+#[cfg(feature = "bsp_rpi4")]
+static INTERRUPT_CONTROLLER: exception::asynchronous::InterruptController = unsafe {
+    exception::asynchronous::InterruptController::new(
+        memory::map::mmio::GICD_START,
+        memory::map::mmio::GICC_START,
+    )
+};
+
+static SYSTEM_TIMER: device_driver::SystemTimer = unsafe {
+    device_driver::SystemTimer::new(
+        memory::map::mmio::SYSTEM_TIMER_START,
+        exception::asynchronous::irq_map::SYSTEM_TIMER,
+    )
+};
+
+static WATCHDOG: device_driver::PMWatchdog =
+    unsafe { device_driver::PMWatchdog::new(memory::map::mmio::PM_START) };
+
+// The BCM2711's channels 11 to 14 are DMA4 ones, which the driver does not handle.
+#[cfg(feature = "bsp_rpi3")]
+const DMA_SUPPORTED_CHANNELS: u16 = 0x7FFF;
+#[cfg(feature = "bsp_rpi4")]
+const DMA_SUPPORTED_CHANNELS: u16 = 0x07FF;
+
+static DMA: device_driver::DMAController = unsafe {
+    device_driver::DMAController::new(
+        memory::map::mmio::DMA_START,
+        memory::map::mmio::START,
+        exception::asynchronous::irq_map::DMA,
+        DMA_SUPPORTED_CHANNELS,
+    )
+};
+
+static GPIO: device_driver::GPIO = unsafe {
+    device_driver::GPIO::new(
+        memory::map::mmio::GPIO_START,
+        exception::asynchronous::irq_map::GPIO,
+    )
+};
+
+// The PL011's reference clock, set with `init_uart_clock=48000000` in config.txt.
+const PL011_UART_CLOCK_HZ: u32 = 48_000_000;
+
+static PL011_UART: device_driver::PL011Uart = unsafe {
+    device_driver::PL011Uart::new(
+        memory::map::mmio::PL011_UART_START,
+        PL011_UART_CLOCK_HZ,
+        None,
+    )
+};
+
+// The VPU core clock the mini UART's baud rate is derived from. `enable_uart=1` in config.txt
+// fixes it at this value on the RPi3; on the RPi4 it is the firmware's default.
+#[cfg(feature = "bsp_rpi3")]
+const CORE_CLOCK_HZ: u32 = 250_000_000;
+#[cfg(feature = "bsp_rpi4")]
+const CORE_CLOCK_HZ: u32 = 500_000_000;
+
+static MINI_UART: device_driver::MiniUart =
+    unsafe { device_driver::MiniUart::new(memory::map::mmio::AUX_START, CORE_CLOCK_HZ) };
+
+static I2C1: device_driver::I2CController =
+    unsafe { device_driver::I2CController::new(memory::map::mmio::I2C1_START, CORE_CLOCK_HZ) };
 
-static PL011_UART: device_driver::PL011Uart =
-    unsafe { device_driver::PL011Uart::new(memory::map::mmio::PL011_UART_START) };
+static SPI0: device_driver::SPIController =
+    unsafe { device_driver::SPIController::new(memory::map::mmio::SPI0_START, CORE_CLOCK_HZ) };
 
-pub fn board_name() -> &'static str {
-    #[cfg(feature = "bsp_rpi3")]
-    {
-        "Raspberry Pi 3"
-    }
+// The EMMC base clock the firmware sets up, or rather an upper bound of it. The controllers do not
+// report it.
+#[cfg(feature = "bsp_rpi3")]
+const EMMC_BASE_CLOCK_HZ: u32 = 250_000_000;
+#[cfg(feature = "bsp_rpi4")]
+const EMMC_BASE_CLOCK_HZ: u32 = 200_000_000;
 
-    #[cfg(feature = "bsp_rpi4")]
-    {
-        "Raspberry Pi 4"
-    }
-}
+static EMMC: device_driver::EMMCController = unsafe {
+    device_driver::EMMCController::new(memory::map::mmio::EMMC_START, EMMC_BASE_CLOCK_HZ)
+};
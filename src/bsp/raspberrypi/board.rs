@@ -0,0 +1,154 @@
+// What the firmware knows about the board.
+
+use super::device_driver::{
+    ClockId, GetArmMemory, GetBoardRevision, GetBoardSerial, GetClockRate, GetFirmwareRevision,
+    GetMacAddress, GetMaxTemperature, GetTemperature, GetVcMemory, MemoryRange, PropertyMessage,
+};
+use crate::{kprintln, shell_command};
+
+// New-style revision codes have this bit set, and describe the board in bit fields.
+const NEW_STYLE_REVISION: u32 = 1 << 23;
+
+shell_command!(
+    "board",
+    "Show what the firmware reports about the board",
+    board_command
+);
+
+// The model's name, from the type field of the revision code. Only models with a 64 bit CPU are
+// known.
+fn model_name(revision: u32) -> Option<&'static str> {
+    if revision & NEW_STYLE_REVISION == 0 {
+        return None;
+    }
+
+    let name = match (revision >> 4) & 0xFF {
+        0x04 => "Raspberry Pi 2 Model B",
+        0x08 => "Raspberry Pi 3 Model B",
+        0x0A => "Raspberry Pi Compute Module 3",
+        0x0D => "Raspberry Pi 3 Model B+",
+        0x0E => "Raspberry Pi 3 Model A+",
+        0x10 => "Raspberry Pi Compute Module 3+",
+        0x11 => "Raspberry Pi 4 Model B",
+        0x12 => "Raspberry Pi Zero 2 W",
+        0x13 => "Raspberry Pi 400",
+        0x14 => "Raspberry Pi Compute Module 4",
+        0x15 => "Raspberry Pi Compute Module 4S",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+// The RAM size the revision code states, in MiB.
+fn ram_mib(revision: u32) -> Option<u32> {
+    if revision & NEW_STYLE_REVISION == 0 {
+        return None;
+    }
+
+    Some(256 << ((revision >> 20) & 0x7))
+}
+
+// The detected model, or the SoC family the kernel was built for.
+pub fn board_name() -> &'static str {
+    let fallback = if cfg!(feature = "bsp_rpi3") {
+        "Raspberry Pi 3"
+    } else {
+        "Raspberry Pi 4"
+    };
+
+    super::MAILBOX
+        .property(&GetBoardRevision)
+        .ok()
+        .and_then(model_name)
+        .unwrap_or(fallback)
+}
+
+fn print_memory(name: &str, range: MemoryRange) {
+    // Some firmware reports a range it does not have as empty
+    if range.size == 0 {
+        kprintln!("  {:<12} none", name);
+        return;
+    }
+
+    kprintln!(
+        "  {:<12} {:#010x} - {:#010x} ({} MiB)",
+        name,
+        range.base,
+        range.base + (range.size - 1),
+        range.size >> 20
+    );
+}
+
+// Thousandths of a degree as `48.312`
+fn print_temperature(name: &str, millidegrees: u32) {
+    kprintln!(
+        "  {:<12} {}.{:03} C",
+        name,
+        millidegrees / 1000,
+        millidegrees % 1000
+    );
+}
+
+fn board_command(_args: &[&str]) -> Result<(), &'static str> {
+    const CLOCKS: [(&str, ClockId); 7] = [
+        ("arm", ClockId::Arm),
+        ("core", ClockId::Core),
+        ("sdram", ClockId::Sdram),
+        ("uart", ClockId::Uart),
+        ("emmc", ClockId::Emmc),
+        ("emmc2", ClockId::Emmc2),
+        ("pwm", ClockId::Pwm),
+    ];
+
+    let mut message = PropertyMessage::new();
+    let firmware = message.add(&GetFirmwareRevision)?;
+    let revision = message.add(&GetBoardRevision)?;
+    let serial = message.add(&GetBoardSerial)?;
+    let mac = message.add(&GetMacAddress)?;
+    let arm_memory = message.add(&GetArmMemory)?;
+    let vc_memory = message.add(&GetVcMemory)?;
+    let temperature = message.add(&GetTemperature)?;
+    let max_temperature = message.add(&GetMaxTemperature)?;
+    super::MAILBOX.call(&mut message)?;
+
+    let revision = message.get(&revision)?;
+    kprintln!(
+        "  {:<12} {} (revision {:06x})",
+        "Model",
+        model_name(revision).unwrap_or("Unknown"),
+        revision
+    );
+    if let Some(mib) = ram_mib(revision) {
+        kprintln!("  {:<12} {} MiB", "RAM", mib);
+    }
+    kprintln!("  {:<12} {:016x}", "Serial", message.get(&serial)?);
+    kprintln!("  {:<12} {:#010x}", "Firmware", message.get(&firmware)?);
+
+    let mac = message.get(&mac)?;
+    kprintln!(
+        "  {:<12} {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        "MAC",
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5]
+    );
+
+    print_memory("ARM memory", message.get(&arm_memory)?);
+    print_memory("VC memory", message.get(&vc_memory)?);
+    print_temperature("Temperature", message.get(&temperature)?);
+    print_temperature("Throttling", message.get(&max_temperature)?);
+
+    // One message per clock, they do not all fit into one. Clocks the SoC does not have read 0.
+    for (name, id) in CLOCKS {
+        match super::MAILBOX.property(&GetClockRate(id))? {
+            0 => (),
+            hz => kprintln!("  {:<12} {} Hz", name, hz),
+        }
+    }
+
+    Ok(())
+}
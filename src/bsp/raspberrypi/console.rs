@@ -1,9 +1,44 @@
 use core::fmt;
 
-use crate::{bsp::device_driver, console};
+use crate::bsp::device_driver;
 
 use super::memory;
 
+// The unlocked version of the UART on GPIO 14 and 15, for the panic and the early console.
+#[cfg(not(feature = "rpi_mini_uart_console"))]
+unsafe fn unlocked_console_uart() -> device_driver::PanicUart {
+    device_driver::PanicUart::new(
+        memory::map::mmio::PL011_UART_START,
+        super::PL011_UART_CLOCK_HZ,
+    )
+}
+
+#[cfg(feature = "rpi_mini_uart_console")]
+unsafe fn unlocked_console_uart() -> device_driver::PanicMiniUart {
+    device_driver::PanicMiniUart::new(memory::map::mmio::AUX_START, super::CORE_CLOCK_HZ)
+}
+
+fn map_console_uart(gpio: &mut device_driver::PanicGPIO) {
+    #[cfg(not(feature = "rpi_mini_uart_console"))]
+    gpio.map_pl011_uart();
+
+    #[cfg(feature = "rpi_mini_uart_console")]
+    gpio.map_mini_uart();
+}
+
+// The panic message goes to the framebuffer as well, if there is a text console on it.
+struct PanicOut<U> {
+    uart: U,
+}
+
+impl<U: fmt::Write> fmt::Write for PanicOut<U> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Safe, since this is used only while panicking
+        let _ = unsafe { super::framebuffer::TEXT_CONSOLE.panic_write_str(s) };
+        self.uart.write_str(s)
+    }
+}
+
 /// In case of a panic, the panic handler uses this function to take a last shot at printing
 /// something before the system is halted.
 ///
@@ -16,13 +51,34 @@ use super::memory;
 /// - Use only for printing during a panic.
 pub unsafe fn panic_console_out() -> impl fmt::Write {
     let mut panic_gpio = device_driver::PanicGPIO::new(memory::map::mmio::GPIO_START);
-    let mut panic_uart = device_driver::PanicUart::new(memory::map::mmio::PL011_UART_START);
+    let mut panic_uart = unlocked_console_uart();
 
-    panic_gpio.map_pl011_uart();
+    map_console_uart(&mut panic_gpio);
     panic_uart.init();
-    panic_uart
+    PanicOut { uart: panic_uart }
 }
 
-pub fn console() -> &'static impl console::Console {
-    &super::PL011_UART
+/// Output for `kprint!` and the log macros until the console driver is initialized.
+///
+/// The GPIO and the UART are set up on first use, the same way their drivers do it, so that the
+/// drivers can take over without reprogramming the UART. No locks are taken, since there is no
+/// MMU yet for the atomic instructions they need.
+///
+/// # Safety
+///
+/// - Use only during single-threaded kernel init, before the console driver is initialized.
+pub unsafe fn early_console_out() -> impl fmt::Write {
+    static mut INITIALIZED: bool = false;
+
+    let mut early_uart = unlocked_console_uart();
+
+    if !INITIALIZED {
+        let mut early_gpio = device_driver::EarlyGPIO::new(memory::map::mmio::GPIO_START);
+
+        map_console_uart(&mut early_gpio);
+        early_uart.init();
+        INITIALIZED = true;
+    }
+
+    early_uart
 }
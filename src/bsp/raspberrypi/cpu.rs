@@ -1,3 +1,16 @@
 #[no_mangle]
 #[link_section = ".text._start_arguments"]
 pub static BOOT_CORE_ID: u64 = 0;
+
+// The boards have no GICv3 for `_start` to configure when entered in EL3.
+#[no_mangle]
+#[link_section = ".text._start_arguments"]
+pub static BOOT_GICD_START: u64 = 0;
+
+#[no_mangle]
+#[link_section = ".text._start_arguments"]
+pub static BOOT_GICR_START: u64 = 0;
+
+// The firmware passes the device tree address in `x0`. When the chainloader started the kernel,
+// `x0` may hold anything, and there are no bootargs from a device tree.
+pub const FALLBACK_DTB_ADDR: Option<usize> = None;
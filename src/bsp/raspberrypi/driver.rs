@@ -1,13 +1,62 @@
-use crate::driver::{self, DeviceDriver};
+use super::device_driver::{ClockId, GetClockRate, GetDmaChannels};
+use crate::{
+    block, boot_option, console,
+    driver::{self, DeviceDriver},
+    exception::{self, asynchronous::IRQDescriptor},
+    i2c, kwarn, shell_command, spi, watchdog,
+};
+
+boot_option! {
+    // Route I2C1 to GPIO 2 and 3 and register its bus. Off leaves the pins to plain GPIO use.
+    static I2C1_ENABLED: bool = ("i2c1", false);
+}
+
+boot_option! {
+    // Route SPI0 to GPIO 7 to 11 and register its bus. Off leaves the pins to plain GPIO use.
+    static SPI0_ENABLED: bool = ("spi0", false);
+}
 
 struct BSPDriverManager {
-    device_drivers: [&'static (dyn DeviceDriver + Sync); 2],
+    device_drivers: [&'static (dyn DeviceDriver + Sync); 11],
 }
 
+// The interrupt controller comes first, so that it is ready before any driver registers an IRQ
+// handler. The mailbox follows, so that the firmware can be asked about the hardware from then on.
 static BSP_DRIVER_MANAGER: BSPDriverManager = BSPDriverManager {
-    device_drivers: [&super::GPIO, &super::PL011_UART],
+    device_drivers: [
+        &super::INTERRUPT_CONTROLLER,
+        &super::MAILBOX,
+        &super::SYSTEM_TIMER,
+        &super::WATCHDOG,
+        &super::DMA,
+        &super::GPIO,
+        &super::PL011_UART,
+        &super::MINI_UART,
+        &super::I2C1,
+        &super::SPI0,
+        &super::EMMC,
+    ],
 };
 
+shell_command!(
+    "pl011",
+    "pl011 [<baud>[,8N1][,rtscts]]: Show or change the PL011 UART's line settings",
+    pl011_command
+);
+
+shell_command!(
+    "gpio",
+    "gpio [<pin> in [off|down|up] | out <0|1> | alt<n> | event [<event> [off]] | debounce <ms> | irq]: \
+     Show or change pins",
+    gpio_command
+);
+
+shell_command!(
+    "dma",
+    "dma [copy <len>]: Show the DMA channels, or test one with a copy",
+    dma_command
+);
+
 pub fn driver_manager() -> &'static impl driver::DriverManager {
     &BSP_DRIVER_MANAGER
 }
@@ -18,6 +67,103 @@ impl driver::DriverManager for BSPDriverManager {
     }
 
     fn post_device_driver_init(&self) {
-        super::GPIO.map_pl011_uart();
+        exception::asynchronous::register_irq_manager(&super::INTERRUPT_CONTROLLER);
+
+        let petter = IRQDescriptor {
+            name: "watchdog",
+            handler: &watchdog::PETTER,
+        };
+        super::SYSTEM_TIMER
+            .add_periodic(watchdog::PET_INTERVAL, petter)
+            .unwrap();
+        watchdog::register_watchdog(&super::WATCHDOG);
+        super::GPIO
+            .set_debounce_timer(&super::SYSTEM_TIMER)
+            .unwrap();
+
+        // The PL011 was set up for the clock config.txt asks for. Correct that, in case the
+        // firmware did not comply.
+        match super::MAILBOX.property(&GetClockRate(ClockId::Uart)) {
+            Ok(clock_hz) if clock_hz != super::PL011_UART_CLOCK_HZ => {
+                if let Err(msg) = super::PL011_UART.set_clock_hz(clock_hz) {
+                    kwarn!("PL011 UART: {} Hz clock: {}", clock_hz, msg);
+                }
+            }
+            Ok(_) => (),
+            Err(msg) => kwarn!("PL011 UART: Clock rate unknown: {}", msg),
+        }
+
+        // GPIO 14 and 15 carry the console. The other UART is not routed anywhere by the kernel,
+        // on many boards the firmware connects it to Bluetooth.
+        #[cfg(not(feature = "rpi_mini_uart_console"))]
+        {
+            map_pl011_pins().unwrap();
+            console::console_manager()
+                .register("pl011", &super::PL011_UART)
+                .unwrap();
+        }
+
+        #[cfg(feature = "rpi_mini_uart_console")]
+        {
+            super::GPIO.map_mini_uart().unwrap();
+            console::console_manager()
+                .register("miniuart", &super::MINI_UART)
+                .unwrap();
+        }
+
+        match super::MAILBOX.property(&GetDmaChannels) {
+            Ok(mask) => super::DMA.set_usable_channels(mask),
+            Err(msg) => kwarn!("DMA: Channels unknown: {}", msg),
+        }
+
+        // The header pins of the buses are only taken from GPIO when asked for
+        if I2C1_ENABLED.get() {
+            super::GPIO.map_i2c1().unwrap();
+            i2c::register_i2c_bus("i2c1", &super::I2C1).unwrap();
+        }
+
+        if SPI0_ENABLED.get() {
+            super::GPIO.map_spi0().unwrap();
+            if let Err(msg) = super::SPI0.enable_dma(&super::DMA) {
+                kwarn!("SPI0: No DMA: {}", msg);
+            }
+            spi::register_spi_bus("spi0", &super::SPI0).unwrap();
+        }
+
+        if super::EMMC.is_present() {
+            block::register_block_device("mmcblk0", &super::EMMC).unwrap();
+        }
+
+        super::framebuffer::init();
+    }
+}
+
+// Route the PL011 to GPIO 14 and 15, and RTS/CTS to 16 and 17 if flow control is on. The latter
+// stay routed when it is turned off again.
+#[cfg(not(feature = "rpi_mini_uart_console"))]
+fn map_pl011_pins() -> Result<(), &'static str> {
+    super::GPIO.map_pl011_uart()?;
+
+    if super::PL011_UART.line_settings().flow_control {
+        super::GPIO.map_pl011_flow_control()?;
     }
+
+    Ok(())
+}
+
+fn pl011_command(args: &[&str]) -> Result<(), &'static str> {
+    super::PL011_UART.line_settings_command(args)?;
+
+    #[cfg(not(feature = "rpi_mini_uart_console"))]
+    map_pl011_pins()?;
+
+    Ok(())
+}
+
+fn gpio_command(args: &[&str]) -> Result<(), &'static str> {
+    super::GPIO.command(args)
+}
+
+fn dma_command(args: &[&str]) -> Result<(), &'static str> {
+    super::DMA.command(args)
 }
@@ -0,0 +1,56 @@
+// The BCM2837 has its own interrupt controller for the 64 VideoCore peripheral IRQs. The BCM2711
+// has a GIC-400, on which the same IRQs are SPIs, starting at INTID 96.
+
+use crate::bsp::device_driver;
+
+#[cfg(feature = "bsp_rpi3")]
+pub type IRQNumber = device_driver::PeripheralIRQNumber;
+
+#[cfg(feature = "bsp_rpi4")]
+pub type IRQNumber = device_driver::GICIRQNumber;
+
+#[cfg(feature = "bsp_rpi3")]
+pub type InterruptController = device_driver::PeripheralIC;
+
+#[cfg(feature = "bsp_rpi4")]
+pub type InterruptController = device_driver::GICv2;
+
+pub mod irq_map {
+    use super::IRQNumber;
+
+    // System timer compare channel 3
+    #[cfg(feature = "bsp_rpi3")]
+    pub const SYSTEM_TIMER: IRQNumber = IRQNumber::new(3);
+    #[cfg(feature = "bsp_rpi4")]
+    pub const SYSTEM_TIMER: IRQNumber = IRQNumber::new(96 + 3);
+
+    // Raised for events on any pin, gpio_int[3]
+    #[cfg(feature = "bsp_rpi3")]
+    pub const GPIO: IRQNumber = IRQNumber::new(52);
+    #[cfg(feature = "bsp_rpi4")]
+    pub const GPIO: IRQNumber = IRQNumber::new(96 + 52);
+
+    // Per DMA channel. Channels 11 to 14 share one on the BCM2837, 7 and 8 as well as 9 and 10 on
+    // the BCM2711.
+    #[cfg(feature = "bsp_rpi3")]
+    pub const DMA: [IRQNumber; 15] = {
+        let mut irqs = [IRQNumber::new(27); 15];
+        let mut channel = 0;
+        while channel <= 10 {
+            irqs[channel] = IRQNumber::new(16 + channel);
+            channel += 1;
+        }
+        irqs
+    };
+    #[cfg(feature = "bsp_rpi4")]
+    pub const DMA: [IRQNumber; 15] = {
+        const LINES: [usize; 15] = [16, 17, 18, 19, 20, 21, 22, 23, 23, 24, 24, 25, 26, 27, 28];
+        let mut irqs = [IRQNumber::new(96); 15];
+        let mut channel = 0;
+        while channel < 15 {
+            irqs[channel] = IRQNumber::new(96 + LINES[channel]);
+            channel += 1;
+        }
+        irqs
+    };
+}
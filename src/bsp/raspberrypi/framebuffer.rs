@@ -0,0 +1,144 @@
+// The framebuffer the firmware shows on HDMI, with a text console on it.
+//
+// QEMU's `raspi3` emulates it too, take a look with the monitor's `screendump` command.
+
+use core::fmt;
+
+use super::device_driver::{
+    AllocateBuffer, DisplaySize, GetPhysicalDisplaySize, GetPitch, PixelOrder, PropertyMessage,
+    SetDepth, SetPhysicalDisplaySize, SetPixelOrder, SetVirtualBufferSize, SetVirtualOffset,
+};
+use crate::{
+    boot_option,
+    bootargs::BootOptionValue,
+    console,
+    graphics::{FrameBuffer, PixelFormat, TextConsole},
+    kinfo, kwarn,
+};
+
+// Used when the firmware knows of no display
+const FALLBACK_SIZE: DisplaySize = DisplaySize {
+    width: 640,
+    height: 480,
+};
+const DEFAULT_DEPTH: u32 = 32;
+
+// From this width on, the font is drawn twice as big
+const DOUBLE_SCALE_WIDTH: usize = 1280;
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Off,
+    // The size of the attached display
+    Auto,
+    Size(DisplaySize, u32),
+}
+
+boot_option! {
+    // `off`, `auto`, or `<width>x<height>[x<depth>]`
+    static FB: Mode = ("fb", Mode::Auto);
+}
+
+pub(super) static TEXT_CONSOLE: TextConsole = TextConsole::new();
+
+impl BootOptionValue for Mode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => return Some(Mode::Off),
+            "on" | "auto" => return Some(Mode::Auto),
+            _ => (),
+        }
+
+        let mut numbers = s.split('x').map(|n| n.parse::<u32>().ok());
+        let width = numbers.next()??;
+        let height = numbers.next()??;
+        let depth = numbers.next().unwrap_or(Some(DEFAULT_DEPTH))?;
+
+        if numbers.next().is_some() || width == 0 || height == 0 {
+            return None;
+        }
+
+        Some(Mode::Size(DisplaySize { width, height }, depth))
+    }
+
+    fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Off => f.write_str("off"),
+            Mode::Auto => f.write_str("auto"),
+            Mode::Size(size, depth) => write!(f, "{}x{}x{}", size.width, size.height, depth),
+        }
+    }
+}
+
+// Have the firmware allocate a framebuffer, as big as the display shows.
+fn allocate(size: DisplaySize, depth: u32) -> Result<FrameBuffer, &'static str> {
+    let mut message = PropertyMessage::new();
+    message.add(&SetPhysicalDisplaySize(size))?;
+    let size = message.add(&SetVirtualBufferSize(size))?;
+    message.add(&SetVirtualOffset { x: 0, y: 0 })?;
+    let depth = message.add(&SetDepth(depth))?;
+    let order = message.add(&SetPixelOrder(PixelOrder::Rgb))?;
+    let buffer = message.add(&AllocateBuffer { alignment: 4096 })?;
+    let pitch = message.add(&GetPitch)?;
+    super::MAILBOX.call(&mut message)?;
+
+    let size = message.get(&size)?;
+    let buffer = message.get(&buffer)?;
+    let pitch = message.get(&pitch)? as usize;
+    let format = match (message.get(&depth)?, message.get(&order)?) {
+        (16, _) => PixelFormat::Rgb565,
+        (32, PixelOrder::Rgb) => PixelFormat::Rgbx8888,
+        (32, PixelOrder::Bgr) => PixelFormat::Bgrx8888,
+        _ => return Err("Unsupported depth"),
+    };
+
+    let (width, height) = (size.width as usize, size.height as usize);
+    if buffer.base == 0 || buffer.size < height * pitch || pitch < width * format.bytes_per_pixel()
+    {
+        return Err("Allocation failed");
+    }
+
+    // The firmware's memory is mapped like all RAM, and the firmware leaves it to the kernel.
+    Ok(unsafe { FrameBuffer::new(buffer.base, width, height, pitch, format) })
+}
+
+// Set up the framebuffer and add the text console on it to the consoles.
+pub(super) fn init() {
+    let (size, depth) = match FB.get() {
+        Mode::Off => return,
+        Mode::Auto => match super::MAILBOX.property(&GetPhysicalDisplaySize) {
+            Ok(size) if size.width > 0 && size.height > 0 => (size, DEFAULT_DEPTH),
+            _ => (FALLBACK_SIZE, DEFAULT_DEPTH),
+        },
+        Mode::Size(size, depth) => (size, depth),
+    };
+
+    let fb = match allocate(size, depth) {
+        Ok(fb) => fb,
+        Err(msg) => {
+            kwarn!("Framebuffer: {}", msg);
+            return;
+        }
+    };
+
+    let scale = if fb.width() >= DOUBLE_SCALE_WIDTH {
+        2
+    } else {
+        1
+    };
+    TEXT_CONSOLE.attach(fb, scale);
+
+    if let Err(msg) = console::console_manager().register("fb", &TEXT_CONSOLE) {
+        kwarn!("Framebuffer: {}", msg);
+        return;
+    }
+
+    let (columns, rows) = TEXT_CONSOLE.size();
+    kinfo!(
+        "Framebuffer: {}x{}, text console with {}x{} characters",
+        fb.width(),
+        fb.height(),
+        columns,
+        rows
+    );
+}
@@ -37,11 +37,26 @@ extern "Rust" {
 
 #[rustfmt::skip]
 pub(super) mod map {
+    // The BCM2837's interrupt controller for the peripheral IRQs
+    #[cfg(feature = "bsp_rpi3")]
+    pub const IC_OFFSET:                  usize = 0x0000_B200;
+    pub const SYSTEM_TIMER_OFFSET:        usize = 0x0000_3000;
+    // The DMA controller's channels 0 to 14
+    pub const DMA_OFFSET:                 usize = 0x0000_7000;
+    pub const MAILBOX_OFFSET:             usize = 0x0000_B880;
     pub const GPIO_OFFSET:                usize = 0x0020_0000;
     pub const UART_OFFSET:                usize = 0x0020_1000;
-    pub const PM_RSTC_OFFSET:             usize = 0x0010_001c;
-    pub const PM_RSTS_OFFSET:             usize = 0x0010_0020;
-    pub const PM_WDOG_OFFSET:             usize = 0x0010_0024;
+    pub const SPI0_OFFSET:                usize = 0x0020_4000;
+    pub const AUX_OFFSET:                 usize = 0x0021_5000;
+    // BSC1, the I2C controller on the header
+    pub const I2C1_OFFSET:                usize = 0x0080_4000;
+    // Power management, with the watchdog
+    pub const PM_OFFSET:                  usize = 0x0010_0000;
+    #[cfg(feature = "bsp_rpi3")]
+    pub const EMMC_OFFSET:                usize = 0x0030_0000;
+    // EMMC2, the controller the SD card slot is wired to
+    #[cfg(feature = "bsp_rpi4")]
+    pub const EMMC_OFFSET:                usize = 0x0034_0000;
 
     // END_INCLUSIVE + 1 = 4GiB (although RPi3 has only 1GiB of RAM)
     pub const END_INCLUSIVE:              usize = 0xFFFF_FFFF;
@@ -51,11 +66,17 @@ pub(super) mod map {
         use super::*;
 
         pub const START:             usize = 0x3F00_0000;
+        pub const IC_START:          usize = START + IC_OFFSET;
+        pub const SYSTEM_TIMER_START: usize = START + SYSTEM_TIMER_OFFSET;
+        pub const DMA_START:         usize = START + DMA_OFFSET;
+        pub const MAILBOX_START:     usize = START + MAILBOX_OFFSET;
         pub const GPIO_START:        usize = START + GPIO_OFFSET;
         pub const PL011_UART_START:  usize = START + UART_OFFSET;
-        pub const PM_RSTC_START:     usize = START + PM_RSTC_OFFSET;
-        pub const PM_RSTS_START:     usize = START + PM_RSTS_OFFSET;
-        pub const PM_WDOG_START:     usize = START + PM_WDOG_OFFSET;
+        pub const SPI0_START:        usize = START + SPI0_OFFSET;
+        pub const AUX_START:         usize = START + AUX_OFFSET;
+        pub const I2C1_START:        usize = START + I2C1_OFFSET;
+        pub const EMMC_START:        usize = START + EMMC_OFFSET;
+        pub const PM_START:          usize = START + PM_OFFSET;
         // END_INCLUSIVE + 1 = 1GiB
         pub const END_INCLUSIVE:     usize = 0x4000_FFFF;
     }
@@ -65,29 +86,27 @@ pub(super) mod map {
         use super::*;
 
         pub const START:             usize = 0xFE00_0000;
+        pub const SYSTEM_TIMER_START: usize = START + SYSTEM_TIMER_OFFSET;
+        pub const DMA_START:         usize = START + DMA_OFFSET;
+        pub const MAILBOX_START:     usize = START + MAILBOX_OFFSET;
         pub const GPIO_START:        usize = START + GPIO_OFFSET;
         pub const PL011_UART_START:  usize = START + UART_OFFSET;
+        pub const SPI0_START:        usize = START + SPI0_OFFSET;
+        pub const AUX_START:         usize = START + AUX_OFFSET;
+        pub const I2C1_START:        usize = START + I2C1_OFFSET;
+        pub const EMMC_START:        usize = START + EMMC_OFFSET;
+        pub const PM_START:          usize = START + PM_OFFSET;
+        pub const GICD_START:        usize = 0xFF84_1000;
+        pub const GICC_START:        usize = 0xFF84_2000;
         // END_INCLUSIVE + 1 = 4GiB - 8MiB
         pub const END_INCLUSIVE:     usize = 0xFF84_FFFF;
     }
 }
 
-#[inline(always)]
-#[cfg(feature = "bsp_rpi3")]
-pub fn board_pm_rstc() -> *const u32 {
-    map::mmio::PM_RSTC_START as _
-}
-
-#[inline(always)]
-#[cfg(feature = "bsp_rpi3")]
-pub fn board_pm_rsts() -> *const u32 {
-    map::mmio::PM_RSTS_START as _
-}
-
-#[inline(always)]
-#[cfg(feature = "bsp_rpi3")]
-pub fn board_pm_wdog() -> *const u32 {
-    map::mmio::PM_WDOG_START as _
+// Whether the `size` bytes at `start` are RAM, mapped one to one. That is all memory below the
+// MMIO, though the top of it may be the VideoCore's.
+pub fn is_mapped_ram(start: usize, size: usize) -> bool {
+    matches!(start.checked_add(size), Some(end) if end <= map::mmio::START)
 }
 
 // Start page address of the code segment
@@ -1,17 +1,37 @@
-use super::memory::*;
-
-const PM_WDOG_MAGIC: u32 = 0x5A00_0000;
-const PM_RSTC_FULLRST: u32 = 0x0000_0020;
-
-#[allow(dead_code)]
-pub fn board_reset() -> ! {
-    let mut r = unsafe { core::ptr::read(board_pm_rsts()) };
-    r &= !0xFFFF_FAAA;
-    unsafe {
-        core::ptr::write_volatile(board_pm_rsts() as *mut _, PM_WDOG_MAGIC | r);
-        core::ptr::write_volatile(board_pm_wdog() as *mut _, PM_WDOG_MAGIC | 10);
-        core::ptr::write_volatile(board_pm_rstc() as *mut _, PM_WDOG_MAGIC | PM_RSTC_FULLRST);
-    };
-    // Should never be executed
-    loop {}
+// Power control through the power management block's watchdog.
+
+use crate::{
+    cpu, exception,
+    power::{self, ResetReason},
+    watchdog::Watchdog,
+};
+
+struct BSPPowerManager;
+
+static BSP_POWER_MANAGER: BSPPowerManager = BSPPowerManager;
+
+pub fn power_manager() -> &'static impl power::PowerManager {
+    &BSP_POWER_MANAGER
+}
+
+impl power::PowerManager for BSPPowerManager {
+    fn reset(&self) -> ! {
+        super::WATCHDOG.reset()
+    }
+
+    // The firmware halts, and starts again when GPIO 3 is pulled low.
+    fn poweroff(&self) -> ! {
+        super::WATCHDOG.poweroff()
+    }
+
+    fn halt(&self) -> ! {
+        unsafe { exception::asynchronous::local_irq_mask() };
+        super::WATCHDOG.stop();
+
+        cpu::wait_forever()
+    }
+
+    fn reset_reason(&self) -> ResetReason {
+        super::WATCHDOG.reset_reason()
+    }
 }
@@ -1,3 +1,16 @@
+// Consoles.
+//
+// Drivers for devices the kernel can talk through implement `Console`, and the BSP registers them
+// with the console manager under a short name. Output goes to every enabled console whose log
+// level lets it through, input comes from the one picked as input. All of it can be changed at
+// runtime with the `console` shell command.
+
+use core::fmt;
+
+use spin::Mutex;
+
+use crate::{exception::asynchronous::exec_with_irq_masked, kprintln, print::Level, shell_command};
+
 mod interface {
     use core::fmt;
 
@@ -27,7 +40,252 @@ mod interface {
         }
     }
 
-    pub trait Console = Write + Read + Statistics;
+    // A real trait rather than an alias, so that it can be used as a trait object.
+    pub trait Console: Write + Read + Statistics {}
+
+    impl<T: Write + Read + Statistics> Console for T {}
 }
 
 pub use interface::*;
+
+const MAX_CONSOLES: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Sink {
+    name: &'static str,
+    console: &'static (dyn Console + Sync),
+    enabled: bool,
+    // Log messages above this level are not shown. Plain `kprint!` output always is.
+    level: Level,
+}
+
+struct ConsoleManagerInner {
+    sinks: [Option<Sink>; MAX_CONSOLES],
+    input: Option<usize>,
+}
+
+// Multiplexes the registered consoles, and is a console itself.
+pub struct ConsoleManager {
+    inner: Mutex<ConsoleManagerInner>,
+}
+
+static CONSOLE_MANAGER: ConsoleManager = ConsoleManager::new();
+
+shell_command!(
+    "console",
+    "console [input|enable|disable <name> | level <name> <level>]: Show or change consoles",
+    console_command
+);
+
+pub fn console_manager() -> &'static ConsoleManager {
+    &CONSOLE_MANAGER
+}
+
+impl ConsoleManager {
+    const fn new() -> Self {
+        Self {
+            inner: Mutex::new(ConsoleManagerInner {
+                sinks: [None; MAX_CONSOLES],
+                input: None,
+            }),
+        }
+    }
+
+    // Output may come from IRQ context, so IRQs must be masked while the lock is held.
+    fn locked<R>(&self, f: impl FnOnce(&mut ConsoleManagerInner) -> R) -> R {
+        exec_with_irq_masked(|| f(&mut self.inner.lock()))
+    }
+
+    // A copy of the sinks, so that the lock is not held while writing to them.
+    fn sinks(&self) -> [Option<Sink>; MAX_CONSOLES] {
+        self.locked(|inner| inner.sinks)
+    }
+
+    fn input(&self) -> Option<&'static (dyn Console + Sync)> {
+        self.locked(|inner| inner.input.and_then(|i| inner.sinks[i]).map(|s| s.console))
+    }
+
+    fn with_sink(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut ConsoleManagerInner, usize),
+    ) -> Result<(), &'static str> {
+        self.locked(|inner| {
+            let i = inner
+                .sinks
+                .iter()
+                .position(|s| matches!(s, Some(s) if s.name == name))
+                .ok_or("No such console")?;
+
+            f(inner, i);
+            Ok(())
+        })
+    }
+
+    // Add a console as an enabled output for everything. The first one also becomes the input.
+    pub fn register(
+        &self,
+        name: &'static str,
+        console: &'static (dyn Console + Sync),
+    ) -> Result<(), &'static str> {
+        self.locked(|inner| {
+            if inner.sinks.iter().flatten().any(|s| s.name == name) {
+                return Err("Console name already taken");
+            }
+
+            let i = inner
+                .sinks
+                .iter()
+                .position(|s| s.is_none())
+                .ok_or("Too many consoles")?;
+
+            inner.sinks[i] = Some(Sink {
+                name,
+                console,
+                enabled: true,
+                level: Level::Trace,
+            });
+            if inner.input.is_none() {
+                inner.input = Some(i);
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn set_input(&self, name: &str) -> Result<(), &'static str> {
+        self.with_sink(name, |inner, i| inner.input = Some(i))
+    }
+
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> Result<(), &'static str> {
+        self.with_sink(name, |inner, i| {
+            if let Some(sink) = &mut inner.sinks[i] {
+                sink.enabled = enabled;
+            }
+        })
+    }
+
+    pub fn set_level(&self, name: &str, level: Level) -> Result<(), &'static str> {
+        self.with_sink(name, |inner, i| {
+            if let Some(sink) = &mut inner.sinks[i] {
+                sink.level = level;
+            }
+        })
+    }
+
+    // Write a log message to the consoles whose level lets it through.
+    pub fn write_log(&self, level: Level, args: fmt::Arguments) -> fmt::Result {
+        let mut result = Ok(());
+
+        for sink in self.sinks().iter().flatten() {
+            if sink.enabled && level <= sink.level {
+                result = result.and(sink.console.write_fmt(args));
+            }
+        }
+
+        result
+    }
+
+    // Call `f` for every registered console. The manager is not locked while `f` runs.
+    pub fn for_each_console(&self, mut f: impl FnMut(&'static str, &'static (dyn Console + Sync))) {
+        for sink in self.sinks().iter().flatten() {
+            f(sink.name, sink.console);
+        }
+    }
+
+    pub fn print_consoles(&self) {
+        let (sinks, input) = self.locked(|inner| (inner.sinks, inner.input));
+
+        for (i, sink) in sinks.iter().enumerate() {
+            if let Some(sink) = sink {
+                kprintln!(
+                    "  {:<10} {:<8} level {:<5}{}",
+                    sink.name,
+                    if sink.enabled { "enabled" } else { "disabled" },
+                    sink.level.name(),
+                    if input == Some(i) { "  input" } else { "" }
+                );
+            }
+        }
+    }
+}
+
+impl Write for ConsoleManager {
+    fn write_char(&self, c: char) -> fmt::Result {
+        let mut result = Ok(());
+
+        for sink in self.sinks().iter().flatten().filter(|s| s.enabled) {
+            result = result.and(sink.console.write_char(c));
+        }
+
+        result
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        let mut result = Ok(());
+
+        for sink in self.sinks().iter().flatten().filter(|s| s.enabled) {
+            result = result.and(sink.console.write_fmt(args));
+        }
+
+        result
+    }
+
+    fn flush(&self) -> fmt::Result {
+        let mut result = Ok(());
+
+        for sink in self.sinks().iter().flatten().filter(|s| s.enabled) {
+            result = result.and(sink.console.flush());
+        }
+
+        result
+    }
+}
+
+impl Read for ConsoleManager {
+    fn read_char(&self) -> Result<char, fmt::Error> {
+        // Blocks without holding the lock, so switching the input takes effect after the next
+        // character.
+        self.input().ok_or(fmt::Error)?.read_char()
+    }
+
+    fn clear_rx(&self) -> fmt::Result {
+        match self.input() {
+            Some(input) => input.clear_rx(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Statistics for ConsoleManager {
+    fn chars_written(&self) -> usize {
+        let mut total = 0;
+        self.for_each_console(|_, console| total += console.chars_written());
+        total
+    }
+
+    fn chars_read(&self) -> usize {
+        let mut total = 0;
+        self.for_each_console(|_, console| total += console.chars_read());
+        total
+    }
+}
+
+fn console_command(args: &[&str]) -> Result<(), &'static str> {
+    let manager = console_manager();
+
+    match args.get(1..) {
+        Some([]) | None => (),
+        Some(["input", name]) => manager.set_input(name)?,
+        Some(["enable", name]) => manager.set_enabled(name, true)?,
+        Some(["disable", name]) => manager.set_enabled(name, false)?,
+        Some(["level", name, level]) => {
+            manager.set_level(name, Level::parse(level).ok_or("Invalid level")?)?
+        }
+        _ => return Err("Invalid arguments"),
+    }
+
+    manager.print_consoles();
+
+    Ok(())
+}
@@ -5,3 +5,4 @@ mod arch_cpu;
 mod boot;
 
 pub use arch_cpu::{nop, wait_forever};
+pub use boot::boot_dtb_addr;
@@ -1,3 +1,5 @@
 #[cfg(target_arch = "aarch64")]
 #[path = "../_arch/aarch64/cpu/boot.rs"]
 mod arch_boot;
+
+pub use arch_boot::boot_dtb_addr;
@@ -7,6 +7,13 @@ mod interface {
         unsafe fn init(&self) -> Result<(), &'static str> {
             Ok(())
         }
+
+        // Called by the kernel to register and enable the device's IRQ handler, if it has one.
+        //
+        // Rust's type system prevents registering `&self` as a handler unless it is `'static`.
+        fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+            Ok(())
+        }
     }
 
     // Device driver management
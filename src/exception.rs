@@ -6,10 +6,13 @@ pub mod asynchronous;
 
 pub use arch_exception::{current_privillege_level, handling_init};
 
+pub(crate) use arch_exception::record_boot_privilege_level;
+
 #[derive(PartialEq)]
 pub enum PrivilegeLevel {
     User,
     Kernel,
     Hypervisor,
+    Monitor,
     Unknown,
 }
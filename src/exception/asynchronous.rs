@@ -1,13 +1,18 @@
 #[cfg(target_arch = "aarch64")]
 #[path = "../_arch/aarch64/exception/asynchronous.rs"]
 mod arch_asynchronous;
+mod null_irq_manager;
 
 use core::{
     fmt::{self, Display, Formatter},
     marker::PhantomData,
 };
 
-pub use arch_asynchronous::print_state;
+use spin::Mutex;
+
+use crate::bsp;
+
+pub use arch_asynchronous::{local_irq_mask, local_irq_unmask, print_state};
 
 #[derive(Clone, Copy)]
 pub struct IRQDescriptor {
@@ -67,6 +72,14 @@ use self::arch_asynchronous::{local_irq_mask_save, local_irq_restore};
 #[derive(Clone, Copy)]
 pub struct IRQNumber<const MAX_INCLUSIVE: usize>(usize);
 
+type BSPIRQManager =
+    dyn interface::IRQManager<IRQNumberType = bsp::exception::asynchronous::IRQNumber> + Sync;
+
+// The IRQ manager in use. Until the BSP registers its interrupt controller, this is a stand-in
+// that refuses handler registration.
+static CUR_IRQ_MANAGER: Mutex<&'static BSPIRQManager> =
+    Mutex::new(&null_irq_manager::NULL_IRQ_MANAGER);
+
 impl<'ctxt> IRQContext<'ctxt> {
     // Creates an IRQContext token.
     //
@@ -117,3 +130,18 @@ pub fn exec_with_irq_masked<T>(f: impl FnOnce() -> T) -> T {
 
     ret
 }
+
+// Register a new IRQ manager.
+//
+// Supposed to be called by the BSP once its interrupt controller driver is initialized, before IRQs
+// are unmasked for the first time.
+pub fn register_irq_manager(new_manager: &'static BSPIRQManager) {
+    exec_with_irq_masked(|| *CUR_IRQ_MANAGER.lock() = new_manager);
+}
+
+// Return a reference to the currently registered IRQ manager.
+//
+// This is the IRQ manager used by the architectural interrupt handling code.
+pub fn irq_manager() -> &'static BSPIRQManager {
+    exec_with_irq_masked(|| *CUR_IRQ_MANAGER.lock())
+}
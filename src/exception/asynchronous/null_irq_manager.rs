@@ -0,0 +1,28 @@
+// The IRQ manager in use until the BSP registers a real one.
+
+use super::{interface, IRQContext, IRQDescriptor};
+use crate::{bsp, kinfo};
+
+pub struct NullIRQManager;
+
+pub static NULL_IRQ_MANAGER: NullIRQManager = NullIRQManager;
+
+impl interface::IRQManager for NullIRQManager {
+    type IRQNumberType = bsp::exception::asynchronous::IRQNumber;
+
+    fn register_handler(
+        &self,
+        _irq_number: Self::IRQNumberType,
+        _descriptor: IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        Err("No IRQ controller registered")
+    }
+
+    fn enable(&self, _irq_number: Self::IRQNumberType) {}
+
+    fn handle_pending_irqs<'irq_context>(&'irq_context self, _ic: &IRQContext<'irq_context>) {}
+
+    fn print_handlers(&self) {
+        kinfo!("      No IRQ controller registered");
+    }
+}
@@ -0,0 +1,207 @@
+// Minimal, read-only Flattened Device Tree (DTB) walker.
+//
+// Only what the kernel needs to pull single properties (like `/chosen/bootargs`) out of the blob
+// that the firmware hands over at boot. See the Devicetree Specification, chapter 5, for the
+// format.
+
+use core::{mem::size_of, slice, str};
+
+use crate::{bsp, cpu};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+// The oldest version of the format whose layout is understood here.
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+// Structure block tokens
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+// The header at the start of every device tree blob. All fields are big-endian.
+#[repr(C)]
+struct Header {
+    magic: u32,
+    totalsize: u32,
+    off_dt_struct: u32,
+    off_dt_strings: u32,
+    off_mem_rsvmap: u32,
+    version: u32,
+    last_comp_version: u32,
+    boot_cpuid_phys: u32,
+    size_dt_strings: u32,
+    size_dt_struct: u32,
+}
+
+// A device tree blob somewhere in memory.
+pub struct DeviceTree {
+    structs: &'static [u8],
+    strings: &'static [u8],
+    total_size: usize,
+}
+
+impl DeviceTree {
+    /// Create an instance from the blob at `addr`, validating its header.
+    ///
+    /// # Safety
+    ///
+    /// - `addr` must be mapped and readable. If the header checks out, the whole blob of
+    ///   `totalsize` bytes must be mapped and readable as well, and must not change afterwards.
+    pub unsafe fn from_addr(addr: usize) -> Result<Self, &'static str> {
+        if addr == 0 || addr % size_of::<u32>() != 0 {
+            return Err("Invalid device tree address");
+        }
+
+        let header = &*(addr as *const Header);
+        if u32::from_be(header.magic) != FDT_MAGIC {
+            return Err("Bad device tree magic");
+        }
+
+        if u32::from_be(header.last_comp_version) > FDT_LAST_COMP_VERSION {
+            return Err("Unsupported device tree version");
+        }
+
+        let total_size = u32::from_be(header.totalsize) as usize;
+        let off_struct = u32::from_be(header.off_dt_struct) as usize;
+        let size_struct = u32::from_be(header.size_dt_struct) as usize;
+        let off_strings = u32::from_be(header.off_dt_strings) as usize;
+        let size_strings = u32::from_be(header.size_dt_strings) as usize;
+
+        if off_struct + size_struct > total_size || off_strings + size_strings > total_size {
+            return Err("Device tree blocks out of bounds");
+        }
+
+        Ok(Self {
+            structs: slice::from_raw_parts((addr + off_struct) as *const u8, size_struct),
+            strings: slice::from_raw_parts((addr + off_strings) as *const u8, size_strings),
+            total_size,
+        })
+    }
+
+    // The blob passed at boot, if there is one in RAM.
+    pub fn from_boot() -> Option<Self> {
+        let addr = cpu::boot_dtb_addr()?;
+
+        // Loaders other than the firmware, like the chainloader, may leave anything in `x0`.
+        // Reading outside of RAM could fault before there is a console to report it, so only an
+        // aligned address with header and blob in RAM is taken for a device tree.
+        if addr % size_of::<u64>() != 0 || !bsp::memory::is_mapped_ram(addr, size_of::<Header>()) {
+            return None;
+        }
+
+        let dt = unsafe { Self::from_addr(addr) }.ok()?;
+        if !bsp::memory::is_mapped_ram(addr, dt.total_size) {
+            return None;
+        }
+
+        Some(dt)
+    }
+
+    // Size of the whole blob in bytes.
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    // Return the raw value of property `name` of the node at the absolute `path`.
+    //
+    // Path components without a unit address (`memory`) also match nodes that have one
+    // (`memory@0`).
+    pub fn property(&self, path: &str, name: &str) -> Option<&'static [u8]> {
+        let depth_wanted = path.split('/').filter(|c| !c.is_empty()).count();
+        let mut components = path.split('/').filter(|c| !c.is_empty());
+
+        // `depth` counts the nodes we are nested in, the root node being depth 1. `matched` is the
+        // number of path components matched by the chain of nodes we are currently in.
+        let mut depth = 0;
+        let mut matched = 0;
+        let mut offset = 0;
+
+        loop {
+            let token = self.read_u32(offset)?;
+            offset += size_of::<u32>();
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let node_name = self.cstr_at(self.structs, offset)?;
+                    offset = align_up_4(offset + node_name.len() + 1);
+
+                    depth += 1;
+                    // The root node has an empty name and is matched implicitly.
+                    if depth > 1 && matched == depth - 2 && matched < depth_wanted {
+                        if let Some(c) = components.clone().next() {
+                            if node_name_matches(node_name, c) {
+                                components.next();
+                                matched += 1;
+                            }
+                        }
+                    }
+                }
+                FDT_END_NODE => {
+                    // Leaving the deepest matched node means we are past the subtree we searched.
+                    if depth > 1 && matched == depth - 1 {
+                        return None;
+                    }
+                    depth -= 1;
+                }
+                FDT_PROP => {
+                    let len = self.read_u32(offset)? as usize;
+                    let name_off = self.read_u32(offset + 4)? as usize;
+                    let value_start = offset + 8;
+                    offset = align_up_4(value_start + len);
+
+                    if depth >= 1 && matched == depth_wanted && matched == depth - 1 {
+                        let prop_name = self.cstr_at(self.strings, name_off)?;
+                        if prop_name == name {
+                            return self.structs.get(value_start..value_start + len);
+                        }
+                    }
+                }
+                FDT_NOP => (),
+                FDT_END => return None,
+                _ => return None,
+            }
+        }
+    }
+
+    // Return property `name` of the node at `path` as a string, without the trailing NUL.
+    pub fn property_str(&self, path: &str, name: &str) -> Option<&'static str> {
+        let value = self.property(path, name)?;
+        let value = match value.iter().position(|&b| b == 0) {
+            Some(end) => &value[..end],
+            None => value,
+        };
+
+        str::from_utf8(value).ok()
+    }
+
+    fn read_u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.structs.get(offset..offset + size_of::<u32>())?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn cstr_at(&self, block: &'static [u8], offset: usize) -> Option<&'static str> {
+        let rest = block.get(offset..)?;
+        let end = rest.iter().position(|&b| b == 0)?;
+
+        str::from_utf8(&rest[..end]).ok()
+    }
+}
+
+#[inline(always)]
+fn align_up_4(x: usize) -> usize {
+    (x + 3) & !3
+}
+
+fn node_name_matches(node_name: &str, component: &str) -> bool {
+    if node_name == component {
+        return true;
+    }
+
+    // Allow leaving out the unit address
+    !component.contains('@')
+        && node_name
+            .split_once('@')
+            .map_or(false, |(base, _)| base == component)
+}
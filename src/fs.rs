@@ -0,0 +1,332 @@
+// File systems and the virtual file system on top of them.
+//
+// Mounted file systems form a single tree. A file system names its inodes by number, and an
+// `Inode` pairs such a number with the file system it belongs to. Devices implement `File` and
+// show up in /dev. Open files are kept in per-task `FdTable`s.
+//
+// Paths are absolute. "." and ".." are resolved on the path itself before any lookup, so ".." of
+// a mount point leads to the directory the file system is mounted on.
+
+pub mod devfs;
+pub mod fat32;
+pub mod initramfs;
+
+mod fd;
+
+pub use fd::*;
+
+use spin::Mutex;
+
+use crate::{block, kinfo, kprintln, kwarn, shell_command};
+
+mod interface;
+
+pub use interface::*;
+
+const MAX_MOUNTS: usize = 8;
+const MAX_PATH_DEPTH: usize = 32;
+
+#[derive(Clone, Copy)]
+pub struct Inode {
+    fs: &'static (dyn FileSystem + Sync),
+    number: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Mount {
+    path: &'static str,
+    fs: &'static (dyn FileSystem + Sync),
+}
+
+// A path split into its components, with "." and ".." resolved.
+struct Components<'a> {
+    parts: [&'a str; MAX_PATH_DEPTH],
+    len: usize,
+}
+
+shell_command!("ls", "ls [dir]: List a directory", ls);
+shell_command!("cat", "cat <file>: Print a file", cat);
+
+static MOUNTS: Mutex<[Option<Mount>; MAX_MOUNTS]> = Mutex::new([None; MAX_MOUNTS]);
+
+// The boot partition, mounted at /boot.
+static BOOT_FS: fat32::Fat32 = fat32::Fat32::new();
+
+impl<'a> Components<'a> {
+    fn parse(path: &'a str) -> Result<Self, &'static str> {
+        if !path.starts_with('/') {
+            return Err("Path is not absolute");
+        }
+
+        let mut components = Self {
+            parts: [""; MAX_PATH_DEPTH],
+            len: 0,
+        };
+
+        for part in path.split('/') {
+            match part {
+                "" | "." => (),
+                ".." => components.len = components.len.saturating_sub(1),
+                part if part.len() > NAME_MAX => return Err("File name too long"),
+                part => {
+                    if components.len == MAX_PATH_DEPTH {
+                        return Err("Path too deep");
+                    }
+                    components.parts[components.len] = part;
+                    components.len += 1;
+                }
+            }
+        }
+
+        Ok(components)
+    }
+
+    fn as_slice(&self) -> &[&'a str] {
+        &self.parts[..self.len]
+    }
+}
+
+// The file system mounted closest to `components`, and the components within it.
+fn find_mount<'a, 'b>(
+    components: &'b Components<'a>,
+) -> Result<(&'static (dyn FileSystem + Sync), &'b [&'a str]), &'static str> {
+    let mut best: Option<(&'static (dyn FileSystem + Sync), usize)> = None;
+
+    for mount in MOUNTS.lock().iter().flatten() {
+        // Mount points were checked when mounting.
+        let mount_components = Components::parse(mount.path).unwrap();
+        let depth = mount_components.len;
+
+        if components
+            .as_slice()
+            .starts_with(mount_components.as_slice())
+            && best.map_or(true, |(_, best_depth)| depth > best_depth)
+        {
+            best = Some((mount.fs, depth));
+        }
+    }
+
+    match best {
+        Some((fs, depth)) => Ok((fs, &components.as_slice()[depth..])),
+        None => Err("No file system mounted"),
+    }
+}
+
+impl Inode {
+    pub fn file_system(&self) -> &'static (dyn FileSystem + Sync) {
+        self.fs
+    }
+
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    pub fn metadata(&self) -> Result<Metadata, &'static str> {
+        self.fs.metadata(self.number)
+    }
+
+    pub fn lookup(&self, name: &str) -> Result<Inode, &'static str> {
+        Ok(Inode {
+            fs: self.fs,
+            number: self.fs.lookup(self.number, name)?,
+        })
+    }
+
+    pub fn create(&self, name: &str) -> Result<Inode, &'static str> {
+        Ok(Inode {
+            fs: self.fs,
+            number: self.fs.create(self.number, name)?,
+        })
+    }
+
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, &'static str> {
+        self.fs.read_at(self.number, offset, buf)
+    }
+
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, &'static str> {
+        self.fs.write_at(self.number, offset, buf)
+    }
+
+    pub fn truncate(&self, len: u64) -> Result<(), &'static str> {
+        self.fs.truncate(self.number, len)
+    }
+
+    pub fn read_dir(&self, cookie: &mut u64) -> Result<Option<DirEntry>, &'static str> {
+        self.fs.read_dir(self.number, cookie)
+    }
+}
+
+// Attach `fs` to the tree at `path`. The mount point does not need to exist.
+pub fn mount(path: &'static str, fs: &'static (dyn FileSystem + Sync)) -> Result<(), &'static str> {
+    // Keep mount points in canonical form, so they compare equal.
+    let canonical = path == "/"
+        || (path.starts_with('/') && path[1..].split('/').all(|c| !matches!(c, "" | "." | "..")));
+    if !canonical {
+        return Err("Mount point is not a canonical absolute path");
+    }
+
+    let mut mounts = MOUNTS.lock();
+
+    if mounts.iter().flatten().any(|m| m.path == path) {
+        return Err("Mount point already in use");
+    }
+
+    match mounts.iter_mut().find(|m| m.is_none()) {
+        Some(slot) => {
+            *slot = Some(Mount { path, fs });
+            Ok(())
+        }
+        None => Err("Too many mounts"),
+    }
+}
+
+// Walk from the root of the tree along `components`.
+fn walk(components: &Components) -> Result<Inode, &'static str> {
+    let (fs, rest) = find_mount(components)?;
+
+    let mut inode = Inode {
+        fs,
+        number: fs.root(),
+    };
+    for name in rest {
+        inode = inode.lookup(name)?;
+    }
+
+    Ok(inode)
+}
+
+// Find the inode at `path`.
+pub fn resolve(path: &str) -> Result<Inode, &'static str> {
+    walk(&Components::parse(path)?)
+}
+
+// Find the directory that contains `path`, and the last component of `path`.
+pub fn resolve_parent(path: &str) -> Result<(Inode, &str), &'static str> {
+    let mut components = Components::parse(path)?;
+    if components.len == 0 {
+        return Err("Path has no parent directory");
+    }
+
+    components.len -= 1;
+    let name = components.parts[components.len];
+
+    Ok((walk(&components)?, name))
+}
+
+pub fn print_mounts() {
+    for m in MOUNTS.lock().iter().flatten() {
+        kinfo!("      {}: {}", m.path, m.fs.name());
+    }
+}
+
+// Mount the first FAT32 partition found on any block device at /boot.
+fn mount_boot_partition() -> Result<(), &'static str> {
+    let mut result = Err("No FAT32 partition found");
+
+    block::for_each_block_device(|name, device| {
+        if result.is_ok() {
+            return;
+        }
+
+        let table = match block::partition::PartitionTable::read(device) {
+            Ok(table) => table,
+            Err(_) => return,
+        };
+
+        for partition in table.partitions().filter(|p| p.may_be_fat()) {
+            if BOOT_FS
+                .mount(device, partition.first_block, partition.num_blocks)
+                .is_ok()
+            {
+                kinfo!("Boot partition: {} partition {}", name, partition.number);
+                result = Ok(());
+                return;
+            }
+        }
+    });
+
+    result?;
+    mount("/boot", &BOOT_FS)
+}
+
+// Set up "/" from the initramfs, /dev and /boot, and give the kernel task the console as fds 0, 1
+// and 2.
+pub fn init() -> Result<(), &'static str> {
+    // "/" is mounted even without an initramfs, as an empty directory, so there always is a root.
+    if let Err(msg) = initramfs::init() {
+        kwarn!("Initramfs not usable: {}", msg);
+    }
+    mount("/", &initramfs::INITRAMFS)?;
+
+    devfs::init()?;
+    mount("/dev", &devfs::DEVFS)?;
+
+    if let Err(msg) = mount_boot_partition() {
+        kwarn!("Boot partition not mounted: {}", msg);
+    }
+
+    let mut fd_table = current_fd_table();
+    for _ in 0..3 {
+        fd_table.open("/dev/console", OpenFlags::READ | OpenFlags::WRITE)?;
+    }
+
+    Ok(())
+}
+
+fn ls(args: &[&str]) -> Result<(), &'static str> {
+    let path = args.get(1).copied().unwrap_or("/");
+    let mut fds = current_fd_table();
+
+    let fd = fds.open(path, OpenFlags::READ)?;
+    let mut result = Ok(());
+
+    loop {
+        match fds.read_dir(fd) {
+            Ok(Some(entry)) => {
+                let suffix = match entry.file_type() {
+                    FileType::Directory => "/",
+                    _ => "",
+                };
+                kprintln!("  {}{}", entry.name(), suffix);
+            }
+            Ok(None) => break,
+            Err(msg) => {
+                result = Err(msg);
+                break;
+            }
+        }
+    }
+
+    fds.close(fd)?;
+    result
+}
+
+fn cat(args: &[&str]) -> Result<(), &'static str> {
+    const STDOUT: usize = 1;
+
+    let path = args.get(1).ok_or("Missing file name")?;
+    let mut fds = current_fd_table();
+
+    let fd = fds.open(path, OpenFlags::READ)?;
+    let mut buf = [0u8; 512];
+    let mut result = Ok(());
+
+    loop {
+        match fds.read(fd, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if let Err(msg) = fds.write(STDOUT, &buf[..n]) {
+                    result = Err(msg);
+                    break;
+                }
+            }
+            Err(msg) => {
+                result = Err(msg);
+                break;
+            }
+        }
+    }
+
+    fds.close(fd)?;
+    result
+}
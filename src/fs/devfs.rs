@@ -0,0 +1,183 @@
+// The device file system, mounted at /dev.
+//
+// Devices register a `File` under a name and appear as character devices in a single flat
+// directory. The console is always there, as /dev/console.
+
+use spin::Mutex;
+
+use super::{DirEntry, File, FileSystem, FileType, Metadata};
+use crate::console;
+
+const MAX_DEVICES: usize = 8;
+const ROOT: u64 = 0;
+
+#[derive(Clone, Copy)]
+struct Device {
+    name: &'static str,
+    file: &'static (dyn File + Sync),
+}
+
+pub struct DevFs {
+    devices: Mutex<[Option<Device>; MAX_DEVICES]>,
+}
+
+struct ConsoleFile;
+
+pub static DEVFS: DevFs = DevFs::new();
+
+static CONSOLE: ConsoleFile = ConsoleFile;
+
+impl DevFs {
+    const fn new() -> Self {
+        Self {
+            devices: Mutex::new([None; MAX_DEVICES]),
+        }
+    }
+
+    // Inode numbers are the slot index plus one, the root directory is zero.
+    fn device(&self, inode: u64) -> Result<Device, &'static str> {
+        let slot = inode.checked_sub(1).ok_or("Is a directory")?;
+
+        self.devices
+            .lock()
+            .get(slot as usize)
+            .copied()
+            .flatten()
+            .ok_or("No such device")
+    }
+
+    pub fn register(
+        &self,
+        name: &'static str,
+        file: &'static (dyn File + Sync),
+    ) -> Result<(), &'static str> {
+        let mut devices = self.devices.lock();
+
+        if devices.iter().flatten().any(|d| d.name == name) {
+            return Err("Device name already taken");
+        }
+
+        match devices.iter_mut().find(|d| d.is_none()) {
+            Some(slot) => {
+                *slot = Some(Device { name, file });
+                Ok(())
+            }
+            None => Err("Too many devices"),
+        }
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> u64 {
+        ROOT
+    }
+
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, &'static str> {
+        if dir != ROOT {
+            return Err("Not a directory");
+        }
+
+        self.devices
+            .lock()
+            .iter()
+            .position(|d| matches!(d, Some(d) if d.name == name))
+            .map(|slot| slot as u64 + 1)
+            .ok_or("No such file or directory")
+    }
+
+    fn metadata(&self, inode: u64) -> Result<Metadata, &'static str> {
+        let file_type = if inode == ROOT {
+            FileType::Directory
+        } else {
+            self.device(inode)?;
+            FileType::CharDevice
+        };
+
+        Ok(Metadata { file_type, size: 0 })
+    }
+
+    fn read_at(&self, inode: u64, _offset: u64, buf: &mut [u8]) -> Result<usize, &'static str> {
+        self.device(inode)?.file.read(buf)
+    }
+
+    fn write_at(&self, inode: u64, _offset: u64, buf: &[u8]) -> Result<usize, &'static str> {
+        self.device(inode)?.file.write(buf)
+    }
+
+    fn read_dir(&self, dir: u64, cookie: &mut u64) -> Result<Option<DirEntry>, &'static str> {
+        if dir != ROOT {
+            return Err("Not a directory");
+        }
+
+        let devices = self.devices.lock();
+
+        while let Some(slot) = devices.get(*cookie as usize) {
+            *cookie += 1;
+
+            if let Some(device) = slot {
+                return Ok(Some(DirEntry::new(device.name, FileType::CharDevice)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl File for ConsoleFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        use console::Read;
+
+        let c = console::console_manager()
+            .read_char()
+            .map_err(|_| "Console read failed")?;
+
+        if c.len_utf8() > buf.len() {
+            return Err("Buffer too small");
+        }
+
+        Ok(c.encode_utf8(buf).len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, &'static str> {
+        use console::Write;
+
+        let console = console::console_manager();
+        let mut rest = buf;
+
+        // Invalid UTF-8 is shown as replacement characters.
+        while !rest.is_empty() {
+            let (valid, skip) = match core::str::from_utf8(rest) {
+                Ok(s) => (s, rest.len()),
+                Err(e) => {
+                    let valid_len = e.valid_up_to();
+                    let invalid_len = e.error_len().unwrap_or(rest.len() - valid_len);
+
+                    // Safety: checked by from_utf8() above
+                    let valid = unsafe { core::str::from_utf8_unchecked(&rest[..valid_len]) };
+                    (valid, valid_len + invalid_len)
+                }
+            };
+
+            for c in valid.chars() {
+                console.write_char(c).map_err(|_| "Console write failed")?;
+            }
+            if skip > valid.len() {
+                console
+                    .write_char(core::char::REPLACEMENT_CHARACTER)
+                    .map_err(|_| "Console write failed")?;
+            }
+
+            rest = &rest[skip..];
+        }
+
+        Ok(buf.len())
+    }
+}
+
+pub fn init() -> Result<(), &'static str> {
+    DEVFS.register("console", &CONSOLE)
+}
@@ -0,0 +1,1403 @@
+// FAT32 on top of a `BlockDevice`.
+//
+// Metadata and file data go through a single write-back sector cache, which is flushed at the end
+// of every modifying operation. Only 512 byte sectors are supported. Timestamps are not kept
+// because there is no real-time clock; new entries are dated 1980-01-01.
+//
+// `DirEntry` values are snapshots. After `write()` or `truncate()` the caller's copy is updated,
+// other copies of the same file go stale.
+
+use spin::Mutex;
+
+use crate::{
+    block::BlockDevice,
+    fs::{self, NAME_MAX},
+};
+
+const SECTOR_SIZE: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+const DIR_ENTRIES_PER_SECTOR: u32 = (SECTOR_SIZE / DIR_ENTRY_SIZE) as u32;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+const ATTR_LONG_NAME_MASK: u8 = 0x3F;
+
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+// A leading 0x05 stands for a name that really starts with 0xE5.
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+const NTRES_LOWER_BASE: u8 = 0x08;
+const NTRES_LOWER_EXT: u8 = 0x10;
+
+const LFN_LAST: u8 = 0x40;
+const LFN_SEQ_MASK: u8 = 0x1F;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+const LFN_MAX_ENTRIES: usize = 20;
+// Longest long name in UTF-16 units. The entries have room for a few more.
+const LFN_MAX_UNITS: usize = 255;
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const FAT_FREE: u32 = 0;
+const FAT_EOC_MIN: u32 = 0x0FFF_FFF8;
+const FAT_EOC: u32 = 0x0FFF_FFFF;
+const FIRST_CLUSTER: u32 = 2;
+
+const FS_INFO_LEAD_SIG: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIG: u32 = 0x6141_7272;
+const FS_INFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+// 1980-01-01
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+#[derive(Clone, Copy)]
+pub struct DirEntry {
+    name: [u8; NAME_MAX],
+    name_len: usize,
+    short_name: [u8; 11],
+    attributes: u8,
+    first_cluster: u32,
+    size: u32,
+    // Sector and byte offset of the entry on the device. `None` for the root directory.
+    location: Option<(u64, usize)>,
+}
+
+// Position in a directory, as handed out by `Fat32::read_dir()`.
+pub struct DirCursor {
+    // Zero once the end of the directory was reached.
+    cluster: u32,
+    index: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Geometry {
+    volume_start: u64,
+    sectors_per_cluster: u32,
+    fat_start: u32,
+    fat_size: u32,
+    num_fats: u32,
+    data_start: u32,
+    num_clusters: u32,
+    root_cluster: u32,
+    fs_info_sector: u32,
+}
+
+struct Fat32Inner {
+    device: &'static (dyn BlockDevice + Sync),
+    geometry: Geometry,
+    cache: [u8; SECTOR_SIZE],
+    cached_sector: Option<u64>,
+    dirty: bool,
+    next_free: u32,
+    fs_info_invalidated: bool,
+}
+
+pub struct Fat32 {
+    inner: Mutex<Option<Fat32Inner>>,
+}
+
+// Long file name being assembled from its directory entries, which come last part first.
+struct LongName {
+    chars: [u16; LFN_MAX_ENTRIES * LFN_CHARS_PER_ENTRY],
+    // Sequence number of the part seen last, zero if there is no long name.
+    seq: u8,
+    checksum: u8,
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    (u16_at(buf, offset + 2) as u32) << 16 | u16_at(buf, offset) as u32
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn short_name_checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &c| (sum >> 1 | sum << 7).wrapping_add(c))
+}
+
+fn is_short_name_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"$%'-_@~`!(){}^#&".contains(&c)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= LFN_MAX_UNITS
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+// Append `c` to `buf`, returning false if it does not fit.
+fn push_char(buf: &mut [u8], len: &mut usize, c: char) -> bool {
+    if *len + c.len_utf8() > buf.len() {
+        return false;
+    }
+
+    c.encode_utf8(&mut buf[*len..]);
+    *len += c.len_utf8();
+    true
+}
+
+// Split a raw short name into its base and extension, without the padding.
+fn short_name_parts(raw: &[u8; 11]) -> (&[u8], &[u8]) {
+    let base_len = raw[..8]
+        .iter()
+        .rposition(|&c| c != b' ')
+        .map_or(0, |i| i + 1);
+    let ext_len = raw[8..]
+        .iter()
+        .rposition(|&c| c != b' ')
+        .map_or(0, |i| i + 1);
+
+    (&raw[..base_len], &raw[8..8 + ext_len])
+}
+
+// The short name as "BASE.EXT", honoring the lower case flags.
+fn format_short_name(raw: &[u8; 11], ntres: u8, buf: &mut [u8], len: &mut usize) {
+    fn push_part(part: &[u8], lower: bool, buf: &mut [u8], len: &mut usize) {
+        for (i, &c) in part.iter().enumerate() {
+            let c = match c {
+                ENTRY_KANJI_E5 if i == 0 => '?',
+                c if !c.is_ascii() => '?',
+                c if lower => c.to_ascii_lowercase() as char,
+                c => c as char,
+            };
+            push_char(buf, len, c);
+        }
+    }
+
+    let (base, ext) = short_name_parts(raw);
+
+    push_part(base, ntres & NTRES_LOWER_BASE != 0, buf, len);
+    if !ext.is_empty() {
+        push_char(buf, len, '.');
+        push_part(ext, ntres & NTRES_LOWER_EXT != 0, buf, len);
+    }
+}
+
+// Short name for `name`, if it already is a valid 8.3 name once upper-cased.
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (name, ""),
+    };
+
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+    if !base
+        .bytes()
+        .chain(ext.bytes())
+        .all(|c| is_short_name_char(c.to_ascii_uppercase()))
+    {
+        return None;
+    }
+
+    let mut raw = [b' '; 11];
+    for (i, c) in base.bytes().enumerate() {
+        raw[i] = c.to_ascii_uppercase();
+    }
+    for (i, c) in ext.bytes().enumerate() {
+        raw[8 + i] = c.to_ascii_uppercase();
+    }
+
+    Some(raw)
+}
+
+// Short name "BASE~N.EXT" derived from `name`.
+fn numbered_short_name(name: &str, n: u32) -> [u8; 11] {
+    let name = name.trim_start_matches('.');
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (name, ""),
+    };
+
+    let convert = |c: char| -> Option<u8> {
+        match c {
+            ' ' | '.' => None,
+            c if c.is_ascii() && is_short_name_char(c.to_ascii_uppercase() as u8) => {
+                Some(c.to_ascii_uppercase() as u8)
+            }
+            _ => Some(b'_'),
+        }
+    };
+
+    // "~N", with N written backwards first.
+    let mut tail = [b'~'; 8];
+    let mut tail_len = 1;
+    let mut digits = n;
+    while digits > 0 || tail_len == 1 {
+        tail[tail_len] = b'0' + (digits % 10) as u8;
+        tail_len += 1;
+        digits /= 10;
+    }
+    tail[1..tail_len].reverse();
+
+    let mut raw = [b' '; 11];
+    let mut base_len = 0;
+    for c in base.chars().filter_map(convert).take(8 - tail_len) {
+        raw[base_len] = c;
+        base_len += 1;
+    }
+    raw[base_len..base_len + tail_len].copy_from_slice(&tail[..tail_len]);
+
+    for (i, c) in ext.chars().filter_map(convert).take(3).enumerate() {
+        raw[8 + i] = c;
+    }
+
+    raw
+}
+
+impl LongName {
+    const fn new() -> Self {
+        Self {
+            chars: [0; LFN_MAX_ENTRIES * LFN_CHARS_PER_ENTRY],
+            seq: 0,
+            checksum: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.seq = 0;
+    }
+
+    fn add(&mut self, entry: &[u8]) {
+        let ord = entry[0];
+        let seq = ord & LFN_SEQ_MASK;
+        let checksum = entry[13];
+
+        if ord & LFN_LAST != 0 {
+            if seq == 0 || seq as usize > LFN_MAX_ENTRIES {
+                self.reset();
+                return;
+            }
+            self.chars = [0xFFFF; LFN_MAX_ENTRIES * LFN_CHARS_PER_ENTRY];
+            self.checksum = checksum;
+        } else if self.seq == 0 || seq + 1 != self.seq || checksum != self.checksum {
+            self.reset();
+            return;
+        }
+
+        self.seq = seq;
+        let start = (seq as usize - 1) * LFN_CHARS_PER_ENTRY;
+        for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            self.chars[start + i] = u16_at(entry, offset);
+        }
+    }
+
+    // Convert the long name to UTF-8, if it belongs to the short entry with `short_name`.
+    fn take(&mut self, short_name: &[u8], buf: &mut [u8], len: &mut usize) -> bool {
+        let complete = self.seq == 1 && short_name_checksum(short_name) == self.checksum;
+        self.reset();
+
+        if !complete {
+            return false;
+        }
+
+        let units = self
+            .chars
+            .iter()
+            .copied()
+            .take_while(|&c| c != 0 && c != 0xFFFF);
+
+        // Too long to be valid. The buffer holds any valid name, so none is cut short.
+        if units.clone().count() > LFN_MAX_UNITS {
+            return false;
+        }
+
+        for c in core::char::decode_utf16(units) {
+            push_char(buf, len, c.unwrap_or(core::char::REPLACEMENT_CHARACTER));
+        }
+
+        true
+    }
+}
+
+impl DirEntry {
+    fn root(root_cluster: u32) -> Self {
+        let mut name = [0; NAME_MAX];
+        name[0] = b'/';
+
+        Self {
+            name,
+            name_len: 1,
+            short_name: [b' '; 11],
+            attributes: ATTR_DIRECTORY,
+            first_cluster: root_cluster,
+            size: 0,
+            location: None,
+        }
+    }
+
+    // The entry in `raw`, found at `location`, with its short name.
+    fn parse(raw: &[u8; DIR_ENTRY_SIZE], location: (u64, usize)) -> Self {
+        let mut entry = Self {
+            name: [0; NAME_MAX],
+            name_len: 0,
+            short_name: [0; 11],
+            attributes: raw[11],
+            first_cluster: (u16_at(raw, 20) as u32) << 16 | u16_at(raw, 26) as u32,
+            size: u32_at(raw, 28),
+            location: Some(location),
+        };
+        entry.short_name.copy_from_slice(&raw[..11]);
+        format_short_name(
+            &entry.short_name,
+            raw[12],
+            &mut entry.name,
+            &mut entry.name_len,
+        );
+
+        entry
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        if self.name().eq_ignore_ascii_case(name) {
+            return true;
+        }
+
+        let mut buf = [0u8; 12];
+        let mut len = 0;
+        format_short_name(&self.short_name, 0, &mut buf, &mut len);
+
+        buf[..len].eq_ignore_ascii_case(name.as_bytes())
+    }
+}
+
+impl Geometry {
+    fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster as u64 * SECTOR_SIZE as u64
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..self.num_clusters + FIRST_CLUSTER).contains(&cluster)
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.volume_start
+            + self.data_start as u64
+            + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster as u64
+    }
+
+    // Sector and byte offset of the FAT entry of `cluster`, in the first FAT.
+    fn fat_entry_location(&self, cluster: u32) -> (u64, usize) {
+        let byte = cluster as u64 * 4;
+
+        (
+            self.volume_start + self.fat_start as u64 + byte / SECTOR_SIZE as u64,
+            (byte % SECTOR_SIZE as u64) as usize,
+        )
+    }
+
+    fn is_in_first_fat(&self, sector: u64) -> bool {
+        let start = self.volume_start + self.fat_start as u64;
+
+        (start..start + self.fat_size as u64).contains(&sector)
+    }
+
+    fn dir_entry_location(&self, cursor: &DirCursor) -> (u64, usize) {
+        (
+            self.cluster_sector(cursor.cluster) + (cursor.index / DIR_ENTRIES_PER_SECTOR) as u64,
+            (cursor.index % DIR_ENTRIES_PER_SECTOR) as usize * DIR_ENTRY_SIZE,
+        )
+    }
+}
+
+impl Fat32Inner {
+    // Sector cache
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        let sector = match self.cached_sector {
+            Some(sector) if self.dirty => sector,
+            _ => return Ok(()),
+        };
+
+        self.device.write_blocks(sector, &self.cache)?;
+
+        // Keep the other FATs in sync with the first one.
+        if self.geometry.is_in_first_fat(sector) {
+            for i in 1..self.geometry.num_fats {
+                let mirror = sector + (i * self.geometry.fat_size) as u64;
+                self.device.write_blocks(mirror, &self.cache)?;
+            }
+        }
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn load(&mut self, sector: u64) -> Result<(), &'static str> {
+        if self.cached_sector == Some(sector) {
+            return Ok(());
+        }
+
+        self.flush()?;
+        self.cached_sector = None;
+        self.device.read_blocks(sector, &mut self.cache)?;
+        self.cached_sector = Some(sector);
+
+        Ok(())
+    }
+
+    // Cache `sector` for overwriting it completely, without reading it first.
+    fn load_for_overwrite(&mut self, sector: u64) -> Result<(), &'static str> {
+        if self.cached_sector != Some(sector) {
+            self.flush()?;
+            self.cached_sector = Some(sector);
+        }
+
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn modify(&mut self, sector: u64) -> Result<&mut [u8; SECTOR_SIZE], &'static str> {
+        self.load(sector)?;
+        self.dirty = true;
+
+        Ok(&mut self.cache)
+    }
+
+    // File allocation table
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, &'static str> {
+        let (sector, offset) = self.geometry.fat_entry_location(cluster);
+        self.load(sector)?;
+
+        Ok(u32_at(&self.cache, offset) & FAT_ENTRY_MASK)
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), &'static str> {
+        self.invalidate_fs_info()?;
+
+        let (sector, offset) = self.geometry.fat_entry_location(cluster);
+        let cache = self.modify(sector)?;
+
+        // The upper four bits are reserved and must be preserved.
+        let old = u32_at(cache, offset);
+        put_u32(
+            cache,
+            offset,
+            (old & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK),
+        );
+
+        Ok(())
+    }
+
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, &'static str> {
+        let next = self.fat_entry(cluster)?;
+
+        if next >= FAT_EOC_MIN {
+            Ok(None)
+        } else if self.geometry.is_valid_cluster(next) {
+            Ok(Some(next))
+        } else {
+            Err("Corrupt cluster chain")
+        }
+    }
+
+    // Allocate a cluster and append it to the chain ending in `last`, if any.
+    fn allocate_cluster(&mut self, last: Option<u32>) -> Result<u32, &'static str> {
+        let num_clusters = self.geometry.num_clusters;
+
+        for i in 0..num_clusters {
+            let cluster = FIRST_CLUSTER + (self.next_free - FIRST_CLUSTER + i) % num_clusters;
+
+            if self.fat_entry(cluster)? == FAT_FREE {
+                self.set_fat_entry(cluster, FAT_EOC)?;
+                if let Some(last) = last {
+                    self.set_fat_entry(last, cluster)?;
+                }
+
+                self.next_free = if cluster + 1 < num_clusters + FIRST_CLUSTER {
+                    cluster + 1
+                } else {
+                    FIRST_CLUSTER
+                };
+                return Ok(cluster);
+            }
+        }
+
+        Err("No space left on device")
+    }
+
+    fn free_chain(&mut self, first: u32) -> Result<(), &'static str> {
+        let mut cluster = Some(first);
+
+        while let Some(c) = cluster {
+            cluster = self.next_cluster(c)?;
+            self.set_fat_entry(c, FAT_FREE)?;
+        }
+
+        Ok(())
+    }
+
+    // The n-th cluster of the chain starting at `first`, extending the chain if `allocate` is set.
+    fn nth_cluster(&mut self, first: u32, n: u64, allocate: bool) -> Result<u32, &'static str> {
+        let mut cluster = first;
+
+        for _ in 0..n {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None if allocate => self.allocate_cluster(Some(cluster))?,
+                None => return Err("Cluster chain shorter than the file"),
+            };
+        }
+
+        Ok(cluster)
+    }
+
+    // The free cluster count is not kept up to date, so mark it unknown before the first change.
+    fn invalidate_fs_info(&mut self) -> Result<(), &'static str> {
+        if self.fs_info_invalidated {
+            return Ok(());
+        }
+        self.fs_info_invalidated = true;
+
+        let sector = self.geometry.volume_start + self.geometry.fs_info_sector as u64;
+        let cache = self.modify(sector)?;
+
+        if u32_at(cache, 0) == FS_INFO_LEAD_SIG && u32_at(cache, 484) == FS_INFO_STRUCT_SIG {
+            put_u32(cache, 488, FS_INFO_UNKNOWN);
+            put_u32(cache, 492, FS_INFO_UNKNOWN);
+        }
+
+        Ok(())
+    }
+
+    // Directories
+
+    fn dir_cluster(&self, dir: &DirEntry) -> u32 {
+        // ".." entries that point to the root directory contain cluster zero.
+        if dir.first_cluster == 0 {
+            self.geometry.root_cluster
+        } else {
+            dir.first_cluster
+        }
+    }
+
+    // Step to the next entry slot. Returns false at the end of the cluster chain.
+    fn advance(&mut self, cursor: &mut DirCursor) -> Result<bool, &'static str> {
+        cursor.index += 1;
+
+        if cursor.index == self.geometry.sectors_per_cluster * DIR_ENTRIES_PER_SECTOR {
+            match self.next_cluster(cursor.cluster)? {
+                Some(next) => {
+                    cursor.cluster = next;
+                    cursor.index = 0;
+                }
+                None => {
+                    cursor.cluster = 0;
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn next_entry(&mut self, cursor: &mut DirCursor) -> Result<Option<DirEntry>, &'static str> {
+        let mut long_name = LongName::new();
+
+        while cursor.cluster != 0 {
+            let (sector, offset) = self.geometry.dir_entry_location(cursor);
+            self.load(sector)?;
+
+            let mut raw = [0u8; DIR_ENTRY_SIZE];
+            raw.copy_from_slice(&self.cache[offset..offset + DIR_ENTRY_SIZE]);
+
+            match raw[0] {
+                ENTRY_END => {
+                    cursor.cluster = 0;
+                    break;
+                }
+                ENTRY_DELETED => {
+                    long_name.reset();
+                    self.advance(cursor)?;
+                    continue;
+                }
+                _ => (),
+            }
+
+            self.advance(cursor)?;
+
+            let attributes = raw[11];
+            if attributes & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+                long_name.add(&raw);
+                continue;
+            }
+            if attributes & ATTR_VOLUME_ID != 0 {
+                long_name.reset();
+                continue;
+            }
+
+            // A long name replaces the short one.
+            let mut entry = DirEntry::parse(&raw, (sector, offset));
+            let mut name_len = 0;
+            if long_name.take(&raw[..11], &mut entry.name, &mut name_len) {
+                entry.name_len = name_len;
+            }
+
+            return Ok(Some(entry));
+        }
+
+        Ok(None)
+    }
+
+    fn find_in_dir(
+        &mut self,
+        dir: &DirEntry,
+        name: &str,
+    ) -> Result<Option<DirEntry>, &'static str> {
+        let mut cursor = DirCursor {
+            cluster: self.dir_cluster(dir),
+            index: 0,
+        };
+
+        while let Some(entry) = self.next_entry(&mut cursor)? {
+            if entry.matches(name) {
+                return Ok(Some(entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn short_name_exists(
+        &mut self,
+        dir: &DirEntry,
+        short_name: &[u8; 11],
+    ) -> Result<bool, &'static str> {
+        let mut cursor = DirCursor {
+            cluster: self.dir_cluster(dir),
+            index: 0,
+        };
+
+        while let Some(entry) = self.next_entry(&mut cursor)? {
+            if &entry.short_name == short_name {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn lookup(&mut self, path: &str) -> Result<DirEntry, &'static str> {
+        let mut entry = DirEntry::root(self.geometry.root_cluster);
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !entry.is_dir() {
+                return Err("Not a directory");
+            }
+
+            entry = self
+                .find_in_dir(&entry, component)?
+                .ok_or("No such file or directory")?;
+        }
+
+        // ".." of a first level directory
+        if entry.is_dir() && entry.first_cluster == 0 {
+            entry = DirEntry::root(self.geometry.root_cluster);
+        }
+
+        Ok(entry)
+    }
+
+    // Inode numbers are derived from the position of the directory entry, zero is the root.
+    fn inode_number(entry: &DirEntry) -> u64 {
+        match entry.location {
+            Some((sector, offset)) => {
+                sector * DIR_ENTRIES_PER_SECTOR as u64 + (offset / DIR_ENTRY_SIZE) as u64
+            }
+            None => 0,
+        }
+    }
+
+    fn entry_at(&mut self, inode: u64) -> Result<DirEntry, &'static str> {
+        if inode == 0 {
+            return Ok(DirEntry::root(self.geometry.root_cluster));
+        }
+
+        let sector = inode / DIR_ENTRIES_PER_SECTOR as u64;
+        let offset = (inode % DIR_ENTRIES_PER_SECTOR as u64) as usize * DIR_ENTRY_SIZE;
+        self.load(sector)?;
+
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw.copy_from_slice(&self.cache[offset..offset + DIR_ENTRY_SIZE]);
+        if matches!(raw[0], ENTRY_END | ENTRY_DELETED) {
+            return Err("Stale inode");
+        }
+
+        Ok(DirEntry::parse(&raw, (sector, offset)))
+    }
+
+    // Find `count` consecutive free entry slots in `dir`, growing it if needed.
+    fn find_free_slots(&mut self, dir: &DirEntry, count: u32) -> Result<DirCursor, &'static str> {
+        let mut cursor = DirCursor {
+            cluster: self.dir_cluster(dir),
+            index: 0,
+        };
+        let mut run_start = DirCursor {
+            cluster: 0,
+            index: 0,
+        };
+        let mut run_len = 0;
+
+        loop {
+            let (sector, offset) = self.geometry.dir_entry_location(&cursor);
+            self.load(sector)?;
+
+            if matches!(self.cache[offset], ENTRY_END | ENTRY_DELETED) {
+                if run_len == 0 {
+                    run_start = DirCursor {
+                        cluster: cursor.cluster,
+                        index: cursor.index,
+                    };
+                }
+                run_len += 1;
+                if run_len == count {
+                    return Ok(run_start);
+                }
+            } else {
+                run_len = 0;
+            }
+
+            let last_cluster = cursor.cluster;
+            if !self.advance(&mut cursor)? {
+                // End of the chain, append an empty cluster and continue the run there.
+                let cluster = self.allocate_cluster(Some(last_cluster))?;
+                self.zero_cluster(cluster)?;
+
+                cursor = DirCursor { cluster, index: 0 };
+            }
+        }
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), &'static str> {
+        let first = self.geometry.cluster_sector(cluster);
+
+        for sector in first..first + self.geometry.sectors_per_cluster as u64 {
+            self.load_for_overwrite(sector)?;
+            self.cache.fill(0);
+        }
+
+        Ok(())
+    }
+
+    fn write_dir_slot(
+        &mut self,
+        cursor: &DirCursor,
+        raw: &[u8; DIR_ENTRY_SIZE],
+    ) -> Result<(u64, usize), &'static str> {
+        let (sector, offset) = self.geometry.dir_entry_location(cursor);
+        self.modify(sector)?[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(raw);
+
+        Ok((sector, offset))
+    }
+
+    fn create(&mut self, path: &str) -> Result<DirEntry, &'static str> {
+        let (parent, name) = match path.trim_end_matches('/').rsplit_once('/') {
+            Some((parent, name)) => (parent, name),
+            None => ("", path),
+        };
+
+        let dir = self.lookup(parent)?;
+        self.create_in(&dir, name)
+    }
+
+    fn create_in(&mut self, dir: &DirEntry, name: &str) -> Result<DirEntry, &'static str> {
+        if !dir.is_dir() {
+            return Err("Not a directory");
+        }
+        if !is_valid_name(name) {
+            return Err("Invalid file name");
+        }
+        if self.find_in_dir(dir, name)?.is_some() {
+            return Err("File exists");
+        }
+
+        // Use the name as its own short name if possible, otherwise add a long name and pick a
+        // numbered short name that is not taken yet.
+        let short_name = match exact_short_name(name) {
+            Some(short_name) if !self.short_name_exists(dir, &short_name)? => short_name,
+            _ => {
+                let mut n = 1;
+                loop {
+                    let candidate = numbered_short_name(name, n);
+                    if !self.short_name_exists(dir, &candidate)? {
+                        break candidate;
+                    }
+                    n += 1;
+                    if n > 999_999 {
+                        return Err("No free short name");
+                    }
+                }
+            }
+        };
+
+        let mut formatted = [0u8; 12];
+        let mut formatted_len = 0;
+        format_short_name(&short_name, 0, &mut formatted, &mut formatted_len);
+
+        let num_lfn_entries = if &formatted[..formatted_len] == name.as_bytes() {
+            0
+        } else {
+            (name.encode_utf16().count() + LFN_CHARS_PER_ENTRY - 1) / LFN_CHARS_PER_ENTRY
+        };
+
+        let mut cursor = self.find_free_slots(dir, num_lfn_entries as u32 + 1)?;
+        let checksum = short_name_checksum(&short_name);
+
+        for seq in (1..=num_lfn_entries).rev() {
+            let mut raw = [0u8; DIR_ENTRY_SIZE];
+            raw[0] = seq as u8 | if seq == num_lfn_entries { LFN_LAST } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = checksum;
+
+            // The name is terminated by a zero, the rest of the last part is padded with 0xFFFF.
+            let mut units = name
+                .encode_utf16()
+                .chain(core::iter::once(0))
+                .skip((seq - 1) * LFN_CHARS_PER_ENTRY);
+            for &offset in LFN_CHAR_OFFSETS.iter() {
+                put_u16(&mut raw, offset, units.next().unwrap_or(0xFFFF));
+            }
+
+            self.write_dir_slot(&cursor, &raw)?;
+            self.advance(&mut cursor)?;
+        }
+
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw[..11].copy_from_slice(&short_name);
+        raw[11] = ATTR_ARCHIVE;
+        put_u16(&mut raw, 16, DEFAULT_DATE);
+        put_u16(&mut raw, 18, DEFAULT_DATE);
+        put_u16(&mut raw, 24, DEFAULT_DATE);
+        let location = self.write_dir_slot(&cursor, &raw)?;
+
+        let mut entry = DirEntry {
+            name: [0; NAME_MAX],
+            name_len: 0,
+            short_name,
+            attributes: ATTR_ARCHIVE,
+            first_cluster: 0,
+            size: 0,
+            location: Some(location),
+        };
+        for c in name.chars() {
+            push_char(&mut entry.name, &mut entry.name_len, c);
+        }
+
+        self.flush()?;
+        Ok(entry)
+    }
+
+    // Write the first cluster and size of `file` back to its directory entry.
+    fn update_dir_entry(&mut self, file: &DirEntry) -> Result<(), &'static str> {
+        let (sector, offset) = file.location.ok_or("Cannot modify the root directory")?;
+        let raw = &mut self.modify(sector)?[offset..offset + DIR_ENTRY_SIZE];
+
+        put_u16(raw, 20, (file.first_cluster >> 16) as u16);
+        put_u16(raw, 26, file.first_cluster as u16);
+        put_u32(raw, 28, file.size);
+        raw[11] |= ATTR_ARCHIVE;
+
+        Ok(())
+    }
+
+    // Files
+
+    fn read(
+        &mut self,
+        file: &DirEntry,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, &'static str> {
+        if file.is_dir() {
+            return Err("Is a directory");
+        }
+        if offset >= file.size as u64 {
+            return Ok(0);
+        }
+
+        if !self.geometry.is_valid_cluster(file.first_cluster) {
+            return Err("Corrupt cluster chain");
+        }
+
+        let len = buf.len().min((file.size as u64 - offset) as usize);
+        let cluster_size = self.geometry.cluster_size();
+        let mut cluster = self.nth_cluster(file.first_cluster, offset / cluster_size, false)?;
+        let mut pos = offset;
+        let mut done = 0;
+
+        while done < len {
+            if done > 0 && pos % cluster_size == 0 {
+                cluster = self
+                    .next_cluster(cluster)?
+                    .ok_or("Cluster chain shorter than the file")?;
+            }
+
+            let sector =
+                self.geometry.cluster_sector(cluster) + (pos % cluster_size) / SECTOR_SIZE as u64;
+            let sector_offset = (pos % SECTOR_SIZE as u64) as usize;
+            let n = (SECTOR_SIZE - sector_offset).min(len - done);
+
+            self.load(sector)?;
+            buf[done..done + n].copy_from_slice(&self.cache[sector_offset..sector_offset + n]);
+
+            pos += n as u64;
+            done += n;
+        }
+
+        Ok(len)
+    }
+
+    fn write(
+        &mut self,
+        file: &mut DirEntry,
+        offset: u64,
+        buf: &[u8],
+    ) -> Result<usize, &'static str> {
+        if file.is_dir() {
+            return Err("Is a directory");
+        }
+        if file.attributes & ATTR_READ_ONLY != 0 {
+            return Err("File is read-only");
+        }
+        if offset + buf.len() as u64 > u32::MAX as u64 {
+            return Err("File too large");
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Writing past the end leaves a hole, which gets filled with zeros.
+        if offset > file.size as u64 {
+            self.resize(file, offset)?;
+        }
+
+        if file.first_cluster == 0 {
+            file.first_cluster = self.allocate_cluster(None)?;
+        } else if !self.geometry.is_valid_cluster(file.first_cluster) {
+            return Err("Corrupt cluster chain");
+        }
+
+        let cluster_size = self.geometry.cluster_size();
+        let mut cluster = self.nth_cluster(file.first_cluster, offset / cluster_size, true)?;
+        let mut pos = offset;
+        let mut done = 0;
+
+        while done < buf.len() {
+            if done > 0 && pos % cluster_size == 0 {
+                cluster = match self.next_cluster(cluster)? {
+                    Some(next) => next,
+                    None => self.allocate_cluster(Some(cluster))?,
+                };
+            }
+
+            let sector =
+                self.geometry.cluster_sector(cluster) + (pos % cluster_size) / SECTOR_SIZE as u64;
+            let sector_offset = (pos % SECTOR_SIZE as u64) as usize;
+            let n = (SECTOR_SIZE - sector_offset).min(buf.len() - done);
+
+            if n == SECTOR_SIZE {
+                self.load_for_overwrite(sector)?;
+            } else {
+                self.modify(sector)?;
+            }
+            self.cache[sector_offset..sector_offset + n].copy_from_slice(&buf[done..done + n]);
+
+            pos += n as u64;
+            done += n;
+        }
+
+        file.size = file.size.max(pos as u32);
+        self.update_dir_entry(file)?;
+
+        Ok(done)
+    }
+
+    fn resize(&mut self, file: &mut DirEntry, len: u64) -> Result<(), &'static str> {
+        if file.is_dir() {
+            return Err("Is a directory");
+        }
+        if len > u32::MAX as u64 {
+            return Err("File too large");
+        }
+
+        let size = file.size as u64;
+
+        if len > size {
+            let zeros = [0u8; SECTOR_SIZE];
+            let mut pos = size;
+
+            while pos < len {
+                let n = (len - pos).min(SECTOR_SIZE as u64) as usize;
+                pos += self.write(file, pos, &zeros[..n])? as u64;
+            }
+
+            return Ok(());
+        }
+
+        if len == 0 {
+            if file.first_cluster != 0 {
+                self.free_chain(file.first_cluster)?;
+            }
+            file.first_cluster = 0;
+        } else {
+            let cluster_size = self.geometry.cluster_size();
+            let last = self.nth_cluster(file.first_cluster, (len - 1) / cluster_size, false)?;
+
+            if let Some(rest) = self.next_cluster(last)? {
+                self.set_fat_entry(last, FAT_EOC)?;
+                self.free_chain(rest)?;
+            }
+        }
+
+        file.size = len as u32;
+        self.update_dir_entry(file)
+    }
+}
+
+impl Fat32 {
+    fn with_inner<R>(
+        &self,
+        f: impl FnOnce(&mut Fat32Inner) -> Result<R, &'static str>,
+    ) -> Result<R, &'static str> {
+        let mut inner = self.inner.lock();
+
+        match inner.as_mut() {
+            Some(inner) => f(inner),
+            None => Err("File system not mounted"),
+        }
+    }
+
+    // Like `with_inner()`, for operations that modify the file system.
+    fn with_inner_flushed<R>(
+        &self,
+        f: impl FnOnce(&mut Fat32Inner) -> Result<R, &'static str>,
+    ) -> Result<R, &'static str> {
+        self.with_inner(|inner| {
+            let result = f(inner);
+            inner.flush()?;
+
+            result
+        })
+    }
+}
+
+impl DirEntry {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("?")
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.attributes & ATTR_READ_ONLY != 0
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+}
+
+impl Fat32 {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(None),
+        }
+    }
+
+    // Mount the FAT32 file system in the `num_blocks` blocks starting at `first_block`, which is
+    // usually a partition.
+    pub fn mount(
+        &self,
+        device: &'static (dyn BlockDevice + Sync),
+        first_block: u64,
+        num_blocks: u64,
+    ) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock();
+        if inner.is_some() {
+            return Err("File system already mounted");
+        }
+
+        if device.block_size() != SECTOR_SIZE {
+            return Err("FAT32 needs 512 byte blocks");
+        }
+
+        let mut boot_sector = [0u8; SECTOR_SIZE];
+        device.read_blocks(first_block, &mut boot_sector)?;
+
+        if boot_sector[510..512] != [0x55, 0xAA] {
+            return Err("No FAT boot sector");
+        }
+
+        let bytes_per_sector = u16_at(&boot_sector, 11) as usize;
+        let sectors_per_cluster = boot_sector[13] as u32;
+        let reserved_sectors = u16_at(&boot_sector, 14) as u32;
+        let num_fats = boot_sector[16] as u32;
+        let root_entry_count = u16_at(&boot_sector, 17);
+        let total_sectors_16 = u16_at(&boot_sector, 19);
+        let fat_size_16 = u16_at(&boot_sector, 22);
+        let total_sectors = u32_at(&boot_sector, 32);
+        let fat_size = u32_at(&boot_sector, 36);
+        let root_cluster = u32_at(&boot_sector, 44);
+        let fs_info_sector = u16_at(&boot_sector, 48) as u32;
+
+        // FAT12 and FAT16 have a fixed root directory and 16 bit sizes.
+        if root_entry_count != 0 || total_sectors_16 != 0 || fat_size_16 != 0 {
+            return Err("Not a FAT32 file system");
+        }
+        if bytes_per_sector != SECTOR_SIZE {
+            return Err("Unsupported FAT sector size");
+        }
+        if !sectors_per_cluster.is_power_of_two() || num_fats == 0 || fat_size == 0 {
+            return Err("Bad FAT32 boot sector");
+        }
+        if total_sectors as u64 > num_blocks {
+            return Err("FAT32 file system larger than its partition");
+        }
+
+        let data_start = reserved_sectors + num_fats * fat_size;
+        if data_start >= total_sectors {
+            return Err("Bad FAT32 boot sector");
+        }
+
+        // The FAT may have room for fewer clusters than the data region.
+        let num_clusters = ((total_sectors - data_start) / sectors_per_cluster)
+            .min(fat_size * (SECTOR_SIZE as u32 / 4) - FIRST_CLUSTER);
+
+        let geometry = Geometry {
+            volume_start: first_block,
+            sectors_per_cluster,
+            fat_start: reserved_sectors,
+            fat_size,
+            num_fats,
+            data_start,
+            num_clusters,
+            root_cluster,
+            fs_info_sector,
+        };
+
+        if !geometry.is_valid_cluster(root_cluster) {
+            return Err("Bad FAT32 root directory cluster");
+        }
+
+        // Start looking for free clusters where the last user of the volume left off.
+        let mut fs_info = [0u8; SECTOR_SIZE];
+        device.read_blocks(first_block + fs_info_sector as u64, &mut fs_info)?;
+
+        let mut next_free = FIRST_CLUSTER;
+        if u32_at(&fs_info, 0) == FS_INFO_LEAD_SIG && u32_at(&fs_info, 484) == FS_INFO_STRUCT_SIG {
+            let hint = u32_at(&fs_info, 492);
+            if geometry.is_valid_cluster(hint) {
+                next_free = hint;
+            }
+        }
+
+        *inner = Some(Fat32Inner {
+            device,
+            geometry,
+            cache: [0; SECTOR_SIZE],
+            cached_sector: None,
+            dirty: false,
+            next_free,
+            fs_info_invalidated: false,
+        });
+
+        Ok(())
+    }
+
+    pub fn cluster_size(&self) -> Result<u64, &'static str> {
+        self.with_inner(|inner| Ok(inner.geometry.cluster_size()))
+    }
+
+    // Look up a file or directory. Paths are relative to the root directory, names are compared
+    // ignoring ASCII case, and both long and short names match.
+    pub fn lookup(&self, path: &str) -> Result<DirEntry, &'static str> {
+        self.with_inner(|inner| inner.lookup(path))
+    }
+
+    // Start listing the directory `dir`.
+    pub fn read_dir(&self, dir: &DirEntry) -> Result<DirCursor, &'static str> {
+        if !dir.is_dir() {
+            return Err("Not a directory");
+        }
+
+        self.with_inner(|inner| {
+            Ok(DirCursor {
+                cluster: inner.dir_cluster(dir),
+                index: 0,
+            })
+        })
+    }
+
+    // The next entry of a directory listing, `None` at the end.
+    pub fn next_entry(&self, cursor: &mut DirCursor) -> Result<Option<DirEntry>, &'static str> {
+        self.with_inner(|inner| inner.next_entry(cursor))
+    }
+
+    // Create an empty file. The parent directory must exist.
+    pub fn create(&self, path: &str) -> Result<DirEntry, &'static str> {
+        self.with_inner_flushed(|inner| inner.create(path))
+    }
+
+    // Read from `file` at `offset`. Returns the number of bytes read, zero at the end of the file.
+    pub fn read(
+        &self,
+        file: &DirEntry,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, &'static str> {
+        self.with_inner(|inner| inner.read(file, offset, buf))
+    }
+
+    // Write to `file` at `offset`, growing it as needed.
+    pub fn write(
+        &self,
+        file: &mut DirEntry,
+        offset: u64,
+        buf: &[u8],
+    ) -> Result<usize, &'static str> {
+        self.with_inner_flushed(|inner| inner.write(file, offset, buf))
+    }
+
+    // Cut `file` to `len` bytes, or grow it with zeros.
+    pub fn truncate(&self, file: &mut DirEntry, len: u64) -> Result<(), &'static str> {
+        self.with_inner_flushed(|inner| inner.resize(file, len))
+    }
+}
+
+impl fs::FileSystem for Fat32 {
+    fn name(&self) -> &'static str {
+        "fat32"
+    }
+
+    fn root(&self) -> u64 {
+        0
+    }
+
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, &'static str> {
+        self.with_inner(|inner| {
+            let dir = inner.entry_at(dir)?;
+            if !dir.is_dir() {
+                return Err("Not a directory");
+            }
+
+            match inner.find_in_dir(&dir, name)? {
+                Some(entry) => Ok(Fat32Inner::inode_number(&entry)),
+                None => Err("No such file or directory"),
+            }
+        })
+    }
+
+    fn metadata(&self, inode: u64) -> Result<fs::Metadata, &'static str> {
+        self.with_inner(|inner| {
+            let entry = inner.entry_at(inode)?;
+
+            Ok(fs::Metadata {
+                file_type: if entry.is_dir() {
+                    fs::FileType::Directory
+                } else {
+                    fs::FileType::Regular
+                },
+                size: entry.size as u64,
+            })
+        })
+    }
+
+    fn read_at(&self, inode: u64, offset: u64, buf: &mut [u8]) -> Result<usize, &'static str> {
+        self.with_inner(|inner| {
+            let entry = inner.entry_at(inode)?;
+            inner.read(&entry, offset, buf)
+        })
+    }
+
+    fn write_at(&self, inode: u64, offset: u64, buf: &[u8]) -> Result<usize, &'static str> {
+        self.with_inner_flushed(|inner| {
+            let mut entry = inner.entry_at(inode)?;
+            inner.write(&mut entry, offset, buf)
+        })
+    }
+
+    fn create(&self, dir: u64, name: &str) -> Result<u64, &'static str> {
+        self.with_inner_flushed(|inner| {
+            let dir = inner.entry_at(dir)?;
+            let entry = inner.create_in(&dir, name)?;
+
+            Ok(Fat32Inner::inode_number(&entry))
+        })
+    }
+
+    fn truncate(&self, inode: u64, len: u64) -> Result<(), &'static str> {
+        self.with_inner_flushed(|inner| {
+            let mut entry = inner.entry_at(inode)?;
+            inner.resize(&mut entry, len)
+        })
+    }
+
+    fn read_dir(&self, dir: u64, cookie: &mut u64) -> Result<Option<fs::DirEntry>, &'static str> {
+        // The cookie holds the cursor, with the cluster in the upper half.
+        const END: u64 = u64::MAX;
+
+        if *cookie == END {
+            return Ok(None);
+        }
+
+        self.with_inner(|inner| {
+            let mut cursor = if *cookie == 0 {
+                let dir = inner.entry_at(dir)?;
+                if !dir.is_dir() {
+                    return Err("Not a directory");
+                }
+
+                DirCursor {
+                    cluster: inner.dir_cluster(&dir),
+                    index: 0,
+                }
+            } else {
+                DirCursor {
+                    cluster: (*cookie >> 32) as u32,
+                    index: *cookie as u32,
+                }
+            };
+
+            let entry = inner.next_entry(&mut cursor)?;
+            *cookie = if cursor.cluster == 0 {
+                END
+            } else {
+                (cursor.cluster as u64) << 32 | cursor.index as u64
+            };
+
+            Ok(entry.map(|e| {
+                let file_type = if e.is_dir() {
+                    fs::FileType::Directory
+                } else {
+                    fs::FileType::Regular
+                };
+
+                fs::DirEntry::new(e.name(), file_type)
+            }))
+        })
+    }
+}
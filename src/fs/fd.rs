@@ -0,0 +1,192 @@
+// File descriptors.
+//
+// Every task has a table of open files, indexed by file descriptor. There are no tasks besides
+// the kernel yet, so `current_fd_table()` always returns the kernel's table.
+
+use core::ops::BitOr;
+
+use spin::{Mutex, MutexGuard};
+
+use super::{DirEntry, FileType, Inode};
+
+pub const MAX_FDS: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(u32);
+
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+#[derive(Clone, Copy)]
+struct OpenFile {
+    inode: Inode,
+    file_type: FileType,
+    flags: OpenFlags,
+    // Byte offset for regular files, the read_dir() cookie for directories.
+    offset: u64,
+}
+
+pub struct FdTable {
+    files: [Option<OpenFile>; MAX_FDS],
+}
+
+static KERNEL_FD_TABLE: Mutex<FdTable> = Mutex::new(FdTable::new());
+
+impl OpenFlags {
+    pub const READ: Self = Self(1 << 0);
+    pub const WRITE: Self = Self(1 << 1);
+    // Create the file if it does not exist.
+    pub const CREATE: Self = Self(1 << 2);
+    // Cut the file to zero length when opening it.
+    pub const TRUNCATE: Self = Self(1 << 3);
+    // Write at the end of the file, wherever the offset is.
+    pub const APPEND: Self = Self(1 << 4);
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self {
+            files: [None; MAX_FDS],
+        }
+    }
+
+    fn file(&mut self, fd: usize) -> Result<&mut OpenFile, &'static str> {
+        match self.files.get_mut(fd) {
+            Some(Some(file)) => Ok(file),
+            _ => Err("Bad file descriptor"),
+        }
+    }
+
+    // Open `path` and return the lowest free file descriptor.
+    pub fn open(&mut self, path: &str, flags: OpenFlags) -> Result<usize, &'static str> {
+        let fd = self
+            .files
+            .iter()
+            .position(|f| f.is_none())
+            .ok_or("Too many open files")?;
+
+        let inode = match super::resolve(path) {
+            Ok(inode) => inode,
+            Err(_) if flags.contains(OpenFlags::CREATE) => {
+                let (dir, name) = super::resolve_parent(path)?;
+                dir.create(name)?
+            }
+            Err(msg) => return Err(msg),
+        };
+
+        let file_type = inode.metadata()?.file_type;
+        if file_type == FileType::Directory && flags.contains(OpenFlags::WRITE) {
+            return Err("Is a directory");
+        }
+        if flags.contains(OpenFlags::TRUNCATE) && file_type == FileType::Regular {
+            inode.truncate(0)?;
+        }
+
+        self.files[fd] = Some(OpenFile {
+            inode,
+            file_type,
+            flags,
+            offset: 0,
+        });
+
+        Ok(fd)
+    }
+
+    pub fn close(&mut self, fd: usize) -> Result<(), &'static str> {
+        self.file(fd)?;
+        self.files[fd] = None;
+
+        Ok(())
+    }
+
+    pub fn read(&mut self, fd: usize, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let file = self.file(fd)?;
+
+        if !file.flags.contains(OpenFlags::READ) {
+            return Err("File not open for reading");
+        }
+        if file.file_type == FileType::Directory {
+            return Err("Is a directory");
+        }
+
+        let n = file.inode.read_at(file.offset, buf)?;
+        if file.file_type == FileType::Regular {
+            file.offset += n as u64;
+        }
+
+        Ok(n)
+    }
+
+    pub fn write(&mut self, fd: usize, buf: &[u8]) -> Result<usize, &'static str> {
+        let file = self.file(fd)?;
+
+        if !file.flags.contains(OpenFlags::WRITE) {
+            return Err("File not open for writing");
+        }
+        if file.flags.contains(OpenFlags::APPEND) && file.file_type == FileType::Regular {
+            file.offset = file.inode.metadata()?.size;
+        }
+
+        let n = file.inode.write_at(file.offset, buf)?;
+        if file.file_type == FileType::Regular {
+            file.offset += n as u64;
+        }
+
+        Ok(n)
+    }
+
+    // Move the offset of a regular file. Returns the new offset.
+    pub fn seek(&mut self, fd: usize, pos: SeekFrom) -> Result<u64, &'static str> {
+        let file = self.file(fd)?;
+
+        if file.file_type != FileType::Regular {
+            return Err("File is not seekable");
+        }
+
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::Current(delta) => (file.offset, delta),
+            SeekFrom::End(delta) => (file.inode.metadata()?.size, delta),
+        };
+
+        let offset = if delta < 0 {
+            base.checked_sub(delta.unsigned_abs())
+        } else {
+            base.checked_add(delta as u64)
+        };
+
+        file.offset = offset.ok_or("Invalid seek offset")?;
+        Ok(file.offset)
+    }
+
+    // The next entry of an open directory, `None` at the end.
+    pub fn read_dir(&mut self, fd: usize) -> Result<Option<DirEntry>, &'static str> {
+        let file = self.file(fd)?;
+
+        if file.file_type != FileType::Directory {
+            return Err("Not a directory");
+        }
+
+        file.inode.read_dir(&mut file.offset)
+    }
+}
+
+// The file descriptor table of the running task.
+pub fn current_fd_table() -> MutexGuard<'static, FdTable> {
+    KERNEL_FD_TABLE.lock()
+}
@@ -0,0 +1,375 @@
+// Initial RAM file system.
+//
+// An archive built into the kernel with `make INITRAMFS=<file>` ends up in the `.initramfs` linker
+// section. Both cpio "newc" archives (`find . | cpio -o -H newc`) and ustar archives (`tar
+// --format=ustar`) are understood. The archive is mounted read-only at "/", and file contents are
+// used in place. Without an archive, or with one that cannot be parsed, "/" is an empty directory.
+//
+// Symlinks, device nodes and ustar entries that need the name prefix, i.e. paths longer than 100
+// bytes, are skipped. Appending the archive to kernel8.img does not work, because the memory
+// behind the image is the kernel's .bss, which is zeroed at boot.
+
+use core::{cell::UnsafeCell, str};
+
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, FileType, Metadata};
+use crate::kinfo;
+
+const MAX_ENTRIES: usize = 256;
+const ROOT: u64 = 0;
+
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_MAGIC: &[u8; 6] = b"070701";
+const CPIO_MAGIC_CRC: &[u8; 6] = b"070702";
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+const TAR_BLOCK_SIZE: usize = 512;
+const TAR_MAGIC: &[u8; 5] = b"ustar";
+
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_REGULAR: u32 = 0o100000;
+
+#[cfg(initramfs)]
+const ARCHIVE_LEN: usize = include_bytes!(env!("INITRAMFS_PATH")).len();
+
+#[cfg(initramfs)]
+#[used]
+#[link_section = ".initramfs"]
+static ARCHIVE: [u8; ARCHIVE_LEN] = *include_bytes!(env!("INITRAMFS_PATH"));
+
+#[derive(Clone, Copy)]
+struct Entry {
+    // Path within the archive, without leading "./" or "/".
+    path: &'static str,
+    parent: u64,
+    file_type: FileType,
+    data: &'static [u8],
+}
+
+pub struct Initramfs {
+    entries: Mutex<[Option<Entry>; MAX_ENTRIES]>,
+}
+
+pub static INITRAMFS: Initramfs = Initramfs::new();
+
+// The archive between the linker symbols, empty if none was built in.
+fn archive() -> &'static [u8] {
+    extern "Rust" {
+        static __initramfs_start: UnsafeCell<()>;
+        static __initramfs_end_exclusive: UnsafeCell<()>;
+    }
+
+    unsafe {
+        let start = __initramfs_start.get() as usize;
+        let end = __initramfs_end_exclusive.get() as usize;
+
+        core::slice::from_raw_parts(start as *const u8, end - start)
+    }
+}
+
+fn parse_number(field: &[u8], radix: u32) -> Result<usize, &'static str> {
+    // ustar numbers may be padded with NULs or spaces.
+    let digits = str::from_utf8(field)
+        .map_err(|_| "Bad number in archive header")?
+        .trim_matches(|c| c == '\0' || c == ' ');
+
+    if digits.is_empty() {
+        return Ok(0);
+    }
+
+    usize::from_str_radix(digits, radix).map_err(|_| "Bad number in archive header")
+}
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+fn slice(archive: &[u8], start: usize, len: usize) -> Result<&[u8], &'static str> {
+    start
+        .checked_add(len)
+        .and_then(|end| archive.get(start..end))
+        .ok_or("Truncated archive")
+}
+
+impl Entry {
+    fn name(&self) -> &'static str {
+        self.path.rsplit('/').next().unwrap_or(self.path)
+    }
+}
+
+impl Initramfs {
+    const fn new() -> Self {
+        Self {
+            entries: Mutex::new([None; MAX_ENTRIES]),
+        }
+    }
+
+    fn entry(&self, inode: u64) -> Result<Entry, &'static str> {
+        if inode == ROOT {
+            return Ok(Entry {
+                path: "",
+                parent: ROOT,
+                file_type: FileType::Directory,
+                data: &[],
+            });
+        }
+
+        self.entries
+            .lock()
+            .get(inode as usize - 1)
+            .copied()
+            .flatten()
+            .ok_or("Stale inode")
+    }
+
+    // Add `path` and any of its parent directories that are not there yet. A file that is in the
+    // archive twice takes the later contents.
+    fn add(
+        entries: &mut [Option<Entry>; MAX_ENTRIES],
+        path: &'static str,
+        file_type: FileType,
+        data: &'static [u8],
+    ) -> Result<u64, &'static str> {
+        if let Some(i) = entries
+            .iter()
+            .position(|e| matches!(e, Some(e) if e.path == path))
+        {
+            if file_type != FileType::Directory {
+                entries[i] = Some(Entry {
+                    file_type,
+                    data,
+                    ..entries[i].unwrap()
+                });
+            }
+            return Ok(i as u64 + 1);
+        }
+
+        let parent = match path.rsplit_once('/') {
+            Some((dir, _)) => Self::add(entries, dir, FileType::Directory, &[])?,
+            None => ROOT,
+        };
+
+        let slot = entries
+            .iter()
+            .position(|e| e.is_none())
+            .ok_or("Too many files in initramfs")?;
+        entries[slot] = Some(Entry {
+            path,
+            parent,
+            file_type,
+            data,
+        });
+
+        Ok(slot as u64 + 1)
+    }
+
+    // Add an archive member. Returns false if its type is not supported.
+    fn add_member(
+        entries: &mut [Option<Entry>; MAX_ENTRIES],
+        name: &'static str,
+        file_type: Option<FileType>,
+        data: &'static [u8],
+    ) -> Result<bool, &'static str> {
+        let path = name
+            .trim_start_matches("./")
+            .trim_start_matches('/')
+            .trim_end_matches('/');
+
+        // The root directory itself
+        if path.is_empty() || path == "." {
+            return Ok(true);
+        }
+
+        match file_type {
+            Some(file_type) => Self::add(entries, path, file_type, data).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    // Returns the number of members that were skipped.
+    fn parse_cpio(
+        entries: &mut [Option<Entry>; MAX_ENTRIES],
+        archive: &'static [u8],
+    ) -> Result<usize, &'static str> {
+        let mut pos = 0;
+        let mut skipped = 0;
+
+        loop {
+            let header = slice(archive, pos, CPIO_HEADER_SIZE)?;
+            if &header[..6] != CPIO_MAGIC && &header[..6] != CPIO_MAGIC_CRC {
+                return Err("Bad cpio header");
+            }
+
+            // Thirteen 8 digit hex fields follow the magic.
+            let field = |i: usize| parse_number(&header[6 + i * 8..14 + i * 8], 16);
+            let mode = field(1)? as u32;
+            let file_size = field(6)?;
+            let name_size = field(11)?;
+
+            // The name is NUL terminated, name and data are padded to four bytes.
+            let name = slice(archive, pos + CPIO_HEADER_SIZE, name_size.saturating_sub(1))?;
+            let name = str::from_utf8(name).map_err(|_| "File name in cpio archive not UTF-8")?;
+            let data_start = align_up(pos + CPIO_HEADER_SIZE + name_size, 4);
+            let data = slice(archive, data_start, file_size)?;
+            pos = align_up(data_start + file_size, 4);
+
+            if name == CPIO_TRAILER {
+                return Ok(skipped);
+            }
+
+            let file_type = match mode & MODE_TYPE_MASK {
+                MODE_REGULAR => Some(FileType::Regular),
+                MODE_DIRECTORY => Some(FileType::Directory),
+                _ => None,
+            };
+            if !Self::add_member(entries, name, file_type, data)? {
+                skipped += 1;
+            }
+        }
+    }
+
+    // Returns the number of members that were skipped.
+    fn parse_tar(
+        entries: &mut [Option<Entry>; MAX_ENTRIES],
+        archive: &'static [u8],
+    ) -> Result<usize, &'static str> {
+        let mut pos = 0;
+        let mut skipped = 0;
+
+        // The archive ends with zeroed blocks.
+        while let Ok(header) = slice(archive, pos, TAR_BLOCK_SIZE) {
+            if header.iter().all(|&b| b == 0) {
+                break;
+            }
+
+            let c_string = |field: &'static [u8]| {
+                let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+                str::from_utf8(&field[..len]).map_err(|_| "File name in tar archive not UTF-8")
+            };
+
+            let name = c_string(&header[0..100])?;
+            let size = parse_number(&header[124..136], 8)?;
+            let type_flag = header[156];
+            let prefix = if &header[257..262] == TAR_MAGIC {
+                c_string(&header[345..500])?
+            } else {
+                ""
+            };
+
+            let data = slice(archive, pos + TAR_BLOCK_SIZE, size)?;
+            pos += TAR_BLOCK_SIZE + align_up(size, TAR_BLOCK_SIZE);
+
+            let file_type = match type_flag {
+                b'0' | 0 => Some(FileType::Regular),
+                b'5' => Some(FileType::Directory),
+                _ => None,
+            };
+            if !prefix.is_empty() || !Self::add_member(entries, name, file_type, data)? {
+                skipped += 1;
+            }
+        }
+
+        Ok(skipped)
+    }
+}
+
+impl FileSystem for Initramfs {
+    fn name(&self) -> &'static str {
+        "initramfs"
+    }
+
+    fn root(&self) -> u64 {
+        ROOT
+    }
+
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, &'static str> {
+        if self.entry(dir)?.file_type != FileType::Directory {
+            return Err("Not a directory");
+        }
+
+        self.entries
+            .lock()
+            .iter()
+            .position(|e| matches!(e, Some(e) if e.parent == dir && e.name() == name))
+            .map(|i| i as u64 + 1)
+            .ok_or("No such file or directory")
+    }
+
+    fn metadata(&self, inode: u64) -> Result<Metadata, &'static str> {
+        let entry = self.entry(inode)?;
+
+        Ok(Metadata {
+            file_type: entry.file_type,
+            size: entry.data.len() as u64,
+        })
+    }
+
+    fn read_at(&self, inode: u64, offset: u64, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let entry = self.entry(inode)?;
+        if entry.file_type == FileType::Directory {
+            return Err("Is a directory");
+        }
+
+        let data = entry.data.get(offset as usize..).unwrap_or(&[]);
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+
+        Ok(n)
+    }
+
+    fn read_dir(&self, dir: u64, cookie: &mut u64) -> Result<Option<DirEntry>, &'static str> {
+        if self.entry(dir)?.file_type != FileType::Directory {
+            return Err("Not a directory");
+        }
+
+        let entries = self.entries.lock();
+
+        while let Some(slot) = entries.get(*cookie as usize) {
+            *cookie += 1;
+
+            match slot {
+                Some(e) if e.parent == dir => {
+                    return Ok(Some(DirEntry::new(e.name(), e.file_type)))
+                }
+                _ => (),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+// Parse the built-in archive, if there is one.
+pub fn init() -> Result<(), &'static str> {
+    let archive = archive();
+    if archive.is_empty() {
+        return Ok(());
+    }
+
+    let mut entries = INITRAMFS.entries.lock();
+
+    let parsed = if archive.starts_with(&CPIO_MAGIC[..5]) {
+        Initramfs::parse_cpio(&mut entries, archive)
+    } else if archive.len() >= TAR_BLOCK_SIZE && &archive[257..262] == TAR_MAGIC {
+        Initramfs::parse_tar(&mut entries, archive)
+    } else {
+        Err("Unknown archive format")
+    };
+
+    // Leave an empty root rather than part of the archive.
+    let skipped = parsed.map_err(|msg| {
+        *entries = [None; MAX_ENTRIES];
+        msg
+    })?;
+
+    kinfo!(
+        "Initramfs: {} entries in {} KiB, {} skipped",
+        entries.iter().flatten().count(),
+        archive.len() / 1024,
+        skipped
+    );
+
+    Ok(())
+}
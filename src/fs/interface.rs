@@ -0,0 +1,96 @@
+// The interface of file systems and devices, and the values passed through it.
+//
+// Kept in a file of its own, so that the host-side tests build file systems against it.
+
+// Longest file name in bytes. VFAT long names have up to 255 UTF-16 units, each of which takes up
+// to three bytes in UTF-8.
+pub const NAME_MAX: usize = 255 * 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileType {
+    Regular,
+    Directory,
+    CharDevice,
+}
+
+#[derive(Clone, Copy)]
+pub struct Metadata {
+    pub file_type: FileType,
+    pub size: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct DirEntry {
+    name: [u8; NAME_MAX],
+    name_len: usize,
+    file_type: FileType,
+}
+
+impl DirEntry {
+    pub fn new(name: &str, file_type: FileType) -> Self {
+        let mut entry = Self {
+            name: [0; NAME_MAX],
+            name_len: 0,
+            file_type,
+        };
+
+        // Cut long names at a character boundary.
+        for c in name.chars() {
+            if entry.name_len + c.len_utf8() > NAME_MAX {
+                break;
+            }
+            c.encode_utf8(&mut entry.name[entry.name_len..]);
+            entry.name_len += c.len_utf8();
+        }
+
+        entry
+    }
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("?")
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+}
+
+// A mounted file system. Inode numbers are chosen by the file system, and stay valid as long
+// as the file exists.
+pub trait FileSystem {
+    fn name(&self) -> &'static str;
+
+    // Inode number of the root directory.
+    fn root(&self) -> u64;
+
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, &'static str>;
+
+    fn metadata(&self, inode: u64) -> Result<Metadata, &'static str>;
+
+    // Read from `inode` at `offset`. Returns the number of bytes read, zero at the end.
+    fn read_at(&self, inode: u64, offset: u64, buf: &mut [u8]) -> Result<usize, &'static str>;
+
+    fn write_at(&self, _inode: u64, _offset: u64, _buf: &[u8]) -> Result<usize, &'static str> {
+        Err("Read-only file system")
+    }
+
+    // Create an empty file in `dir`.
+    fn create(&self, _dir: u64, _name: &str) -> Result<u64, &'static str> {
+        Err("Read-only file system")
+    }
+
+    fn truncate(&self, _inode: u64, _len: u64) -> Result<(), &'static str> {
+        Err("Read-only file system")
+    }
+
+    // The next entry of `dir`. `cookie` starts at zero and is advanced by the file system.
+    fn read_dir(&self, dir: u64, cookie: &mut u64) -> Result<Option<DirEntry>, &'static str>;
+}
+
+// Something that is read and written as a stream of bytes, i.e. a character device.
+pub trait File {
+    // Read at least one byte, blocking if there is none yet.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, &'static str>;
+
+    fn write(&self, buf: &[u8]) -> Result<usize, &'static str>;
+}
@@ -0,0 +1,305 @@
+// Drawing on linear framebuffers.
+//
+// A `FrameBuffer` is memory that a display controller scans out. Drawing clips to its bounds, and
+// writes what was drawn back from the data cache, since the display controller reads the memory
+// directly.
+
+pub mod font;
+mod text_console;
+
+use core::{cmp, ptr};
+
+use crate::memory::cache;
+
+pub use text_console::TextConsole;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+// How pixels are laid out in memory
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    // 16 bits per pixel: 5 bits red in the top bits, 6 bits green, 5 bits blue
+    Rgb565,
+    // 32 bits per pixel: red in the lowest byte, then green and blue
+    Rgbx8888,
+    // 32 bits per pixel: blue in the lowest byte, then green and red
+    Bgrx8888,
+}
+
+#[derive(Clone, Copy)]
+pub struct FrameBuffer {
+    base: usize,
+    width: usize,
+    height: usize,
+    // Bytes from the start of one row to the next
+    pitch: usize,
+    format: PixelFormat,
+}
+
+impl Color {
+    pub const BLACK: Self = Self::new(0, 0, 0);
+    pub const LIGHT_GREY: Self = Self::new(0xAA, 0xAA, 0xAA);
+    pub const WHITE: Self = Self::new(0xFF, 0xFF, 0xFF);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Rgbx8888 | PixelFormat::Bgrx8888 => 4,
+        }
+    }
+
+    fn encode(&self, color: Color) -> u32 {
+        let (r, g, b) = (u32::from(color.r), u32::from(color.g), u32::from(color.b));
+
+        match self {
+            PixelFormat::Rgb565 => (r >> 3) << 11 | (g >> 2) << 5 | b >> 3,
+            PixelFormat::Rgbx8888 => b << 16 | g << 8 | r,
+            PixelFormat::Bgrx8888 => r << 16 | g << 8 | b,
+        }
+    }
+}
+
+impl FrameBuffer {
+    /// Describe a framebuffer.
+    ///
+    /// # Safety
+    ///
+    /// - `base` must be the address of mapped memory of at least `height * pitch` bytes, which
+    ///   nothing else uses.
+    pub const unsafe fn new(
+        base: usize,
+        width: usize,
+        height: usize,
+        pitch: usize,
+        format: PixelFormat,
+    ) -> Self {
+        Self {
+            base,
+            width,
+            height,
+            pitch,
+            format,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    fn pixel_addr(&self, x: usize, y: usize) -> usize {
+        self.base + y * self.pitch + x * self.format.bytes_per_pixel()
+    }
+
+    // Store an encoded pixel, without bounds checks and without writing it back from the cache.
+    fn store(&mut self, x: usize, y: usize, value: u32) {
+        let addr = self.pixel_addr(x, y);
+
+        unsafe {
+            match self.format.bytes_per_pixel() {
+                2 => ptr::write_volatile(addr as *mut u16, value as u16),
+                _ => ptr::write_volatile(addr as *mut u32, value),
+            }
+        }
+    }
+
+    // The part of the rectangle that lies on the screen, if any.
+    fn clip(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<(usize, usize, usize, usize)> {
+        if x >= self.width || y >= self.height || width == 0 || height == 0 {
+            return None;
+        }
+
+        Some((
+            x,
+            y,
+            cmp::min(width, self.width - x),
+            cmp::min(height, self.height - y),
+        ))
+    }
+
+    // Write a drawn rectangle back from the data cache, so that the display controller sees it.
+    fn flush(&self, x: usize, y: usize, width: usize, height: usize) {
+        let row_bytes = width * self.format.bytes_per_pixel();
+
+        // Full rows are contiguous, apart from padding at the ends
+        if width == self.width {
+            let start = self.pixel_addr(0, y);
+            cache::clean_dcache_range(start, (height - 1) * self.pitch + row_bytes);
+            return;
+        }
+
+        for row in y..y + height {
+            cache::clean_dcache_range(self.pixel_addr(x, row), row_bytes);
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let (x, y, width, height) = match self.clip(x, y, width, height) {
+            Some(clipped) => clipped,
+            None => return,
+        };
+        let value = self.format.encode(color);
+
+        for row in y..y + height {
+            for column in x..x + width {
+                self.store(column, row, value);
+            }
+        }
+
+        self.flush(x, y, width, height);
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    // A line from (x0, y0) to (x1, y1), both ends included. Parts off the screen are clipped.
+    pub fn line(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, color: Color) {
+        let value = self.format.encode(color);
+        let (x0, y0, x1, y1) = (x0 as isize, y0 as isize, x1 as isize, y1 as isize);
+
+        // Bresenham's algorithm, for all octants
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+
+        loop {
+            if (x as usize) < self.width && (y as usize) < self.height {
+                self.store(x as usize, y as usize, value);
+            }
+
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let error2 = 2 * error;
+            if error2 >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if error2 <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+
+        let (left, top) = (cmp::min(x0, x1) as usize, cmp::min(y0, y1) as usize);
+        let (width, height) = ((dx + 1) as usize, (-dy + 1) as usize);
+        if let Some((left, top, width, height)) = self.clip(left, top, width, height) {
+            self.flush(left, top, width, height);
+        }
+    }
+
+    // Draw an image with rows of `width` pixels at (x, y).
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, pixels: &[Color]) {
+        if width == 0 {
+            return;
+        }
+
+        let height = pixels.len() / width;
+        let (x, y, clipped_width, clipped_height) = match self.clip(x, y, width, height) {
+            Some(clipped) => clipped,
+            None => return,
+        };
+
+        for row in 0..clipped_height {
+            for column in 0..clipped_width {
+                let value = self.format.encode(pixels[row * width + column]);
+                self.store(x + column, y + row, value);
+            }
+        }
+
+        self.flush(x, y, clipped_width, clipped_height);
+    }
+
+    // Copy a rectangle of the screen to (dst_x, dst_y). The two may overlap.
+    pub fn copy_rect(
+        &mut self,
+        src_x: usize,
+        src_y: usize,
+        width: usize,
+        height: usize,
+        dst_x: usize,
+        dst_y: usize,
+    ) {
+        let (src_x, src_y, width, height) = match self.clip(src_x, src_y, width, height) {
+            Some(clipped) => clipped,
+            None => return,
+        };
+        let (dst_x, dst_y, width, height) = match self.clip(dst_x, dst_y, width, height) {
+            Some(clipped) => clipped,
+            None => return,
+        };
+        let row_bytes = width * self.format.bytes_per_pixel();
+
+        // Go against the direction of the move, so that rows are read before they are overwritten
+        let copy_row = |row: usize| unsafe {
+            ptr::copy(
+                self.pixel_addr(src_x, src_y + row) as *const u8,
+                self.pixel_addr(dst_x, dst_y + row) as *mut u8,
+                row_bytes,
+            )
+        };
+        if dst_y <= src_y {
+            (0..height).for_each(copy_row);
+        } else {
+            (0..height).rev().for_each(copy_row);
+        }
+
+        self.flush(dst_x, dst_y, width, height);
+    }
+
+    // Draw a glyph of the built-in font at (x, y), each of its pixels `scale` times as wide and
+    // high.
+    pub fn draw_glyph(&mut self, x: usize, y: usize, c: char, scale: usize, fg: Color, bg: Color) {
+        let size = (font::WIDTH * scale, font::HEIGHT * scale);
+        let (x, y, width, height) = match self.clip(x, y, size.0, size.1) {
+            Some(clipped) => clipped,
+            None => return,
+        };
+        let glyph = font::glyph(c);
+        let (fg, bg) = (self.format.encode(fg), self.format.encode(bg));
+
+        for row in 0..height {
+            let bits = glyph[row / scale];
+
+            for column in 0..width {
+                let value = if bits & (1 << (column / scale)) != 0 {
+                    fg
+                } else {
+                    bg
+                };
+                self.store(x + column, y + row, value);
+            }
+        }
+
+        self.flush(x, y, width, height);
+    }
+}
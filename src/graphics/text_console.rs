@@ -0,0 +1,202 @@
+// A text console on a framebuffer, in the built-in font.
+//
+// Output only. Text wraps at the right edge, and the screen scrolls up when the cursor moves past
+// the last line.
+
+use core::fmt;
+
+use spin::Mutex;
+
+use super::{font, Color, FrameBuffer};
+use crate::{console, exception::asynchronous::exec_with_irq_masked};
+
+const TAB_WIDTH: usize = 8;
+
+struct TextConsoleInner {
+    fb: Option<FrameBuffer>,
+    // Font pixels are drawn as squares of this size
+    scale: usize,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    fg: Color,
+    bg: Color,
+    chars_written: usize,
+}
+
+pub struct TextConsole {
+    inner: Mutex<TextConsoleInner>,
+}
+
+impl TextConsoleInner {
+    const fn new() -> Self {
+        Self {
+            fb: None,
+            scale: 1,
+            columns: 0,
+            rows: 0,
+            column: 0,
+            row: 0,
+            fg: Color::LIGHT_GREY,
+            bg: Color::BLACK,
+            chars_written: 0,
+        }
+    }
+
+    fn attach(&mut self, mut fb: FrameBuffer, scale: usize) {
+        let scale = scale.max(1);
+
+        fb.clear(self.bg);
+
+        self.scale = scale;
+        self.columns = fb.width() / (font::WIDTH * scale);
+        self.rows = fb.height() / (font::HEIGHT * scale);
+        self.column = 0;
+        self.row = 0;
+        self.fb = Some(fb);
+    }
+
+    fn newline(&mut self) {
+        self.column = 0;
+
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+
+        // Scroll up by one line of text
+        if let Some(fb) = &mut self.fb {
+            let line_height = font::HEIGHT * self.scale;
+            let text_height = self.rows * line_height;
+
+            fb.copy_rect(0, line_height, fb.width(), text_height - line_height, 0, 0);
+            fb.fill_rect(
+                0,
+                text_height - line_height,
+                fb.width(),
+                line_height,
+                self.bg,
+            );
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        let fb = match &mut self.fb {
+            Some(fb) if self.columns > 0 && self.rows > 0 => fb,
+            _ => return,
+        };
+
+        match c {
+            '\n' => self.newline(),
+            '\r' => self.column = 0,
+            '\t' => {
+                let next = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+
+                if next >= self.columns {
+                    self.newline();
+                } else {
+                    self.column = next;
+                }
+            }
+            // Backspace moves left, the next character overwrites
+            '\x08' => self.column = self.column.saturating_sub(1),
+            _ => {
+                let (width, height) = (font::WIDTH * self.scale, font::HEIGHT * self.scale);
+
+                fb.draw_glyph(
+                    self.column * width,
+                    self.row * height,
+                    c,
+                    self.scale,
+                    self.fg,
+                    self.bg,
+                );
+
+                self.column += 1;
+                if self.column == self.columns {
+                    self.newline();
+                }
+            }
+        }
+
+        self.chars_written += 1;
+    }
+}
+
+impl fmt::Write for TextConsoleInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+impl TextConsole {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(TextConsoleInner::new()),
+        }
+    }
+
+    // Output may come from IRQ context, so IRQs must be masked while the lock is held.
+    fn locked<R>(&self, f: impl FnOnce(&mut TextConsoleInner) -> R) -> R {
+        exec_with_irq_masked(|| f(&mut self.inner.lock()))
+    }
+
+    // Start drawing on `fb`, which is cleared. Font pixels are drawn `scale` times as wide and
+    // high.
+    pub fn attach(&self, fb: FrameBuffer, scale: usize) {
+        self.locked(|inner| inner.attach(fb, scale));
+    }
+
+    // Size of the console in characters, as columns and rows.
+    pub fn size(&self) -> (usize, usize) {
+        self.locked(|inner| (inner.columns, inner.rows))
+    }
+
+    /// Write even if the console is locked, which it may be by the code that panicked.
+    ///
+    /// # Safety
+    ///
+    /// - Use only from the panic handler, with nothing else running anymore.
+    pub unsafe fn panic_write_str(&self, s: &str) -> fmt::Result {
+        use fmt::Write;
+
+        if self.inner.is_locked() {
+            self.inner.force_unlock();
+        }
+
+        self.inner.lock().write_str(s)
+    }
+}
+
+impl console::Write for TextConsole {
+    fn write_char(&self, c: char) -> fmt::Result {
+        self.locked(|inner| inner.write_char(c));
+        Ok(())
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.locked(|inner| fmt::Write::write_fmt(inner, args))
+    }
+
+    // Everything drawn is written back from the cache right away.
+    fn flush(&self) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl console::Read for TextConsole {
+    fn clear_rx(&self) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl console::Statistics for TextConsole {
+    fn chars_written(&self) -> usize {
+        self.locked(|inner| inner.chars_written)
+    }
+}
@@ -0,0 +1,242 @@
+// I2C buses.
+//
+// Drivers for I2C controllers implement `I2cBus`. The BSP registers the buses it drives under a
+// short name, and the rest of the kernel looks them up here.
+
+use core::{convert::TryFrom, fmt};
+
+use spin::Mutex;
+
+use crate::{kprint, kprintln, shell::parse_number, shell_command};
+
+mod interface {
+    use super::Address;
+
+    // The master side of an I2C bus. Transfers end with a stop condition.
+    pub trait I2cBus {
+        // The SCL frequency in Hz.
+        fn clock_hz(&self) -> u32;
+
+        // Set the SCL frequency to the closest one not above `hz`, and return that.
+        fn set_clock_hz(&self, hz: u32) -> Result<u32, &'static str>;
+
+        // Write `data` to the device at `address`.
+        fn write(&self, address: Address, data: &[u8]) -> Result<(), &'static str>;
+
+        // Fill `buf` with bytes read from the device at `address`.
+        fn read(&self, address: Address, buf: &mut [u8]) -> Result<(), &'static str>;
+
+        // Write `data`, then read into `buf` after a repeated start. Devices take this as reading
+        // from the register `data` selects.
+        fn write_read(
+            &self,
+            address: Address,
+            data: &[u8],
+            buf: &mut [u8],
+        ) -> Result<(), &'static str>;
+    }
+}
+
+pub use interface::*;
+
+// The error for a device that does not acknowledge its address or a byte written to it.
+pub const NO_ACK: &str = "No acknowledge";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Address {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+const MAX_I2C_BUSES: usize = 2;
+
+// Most bytes the `i2c` shell command transfers at once
+const MAX_SHELL_TRANSFER: usize = 64;
+
+#[derive(Clone, Copy)]
+struct RegisteredBus {
+    name: &'static str,
+    bus: &'static (dyn I2cBus + Sync),
+}
+
+static I2C_BUSES: Mutex<[Option<RegisteredBus>; MAX_I2C_BUSES]> = Mutex::new([None; MAX_I2C_BUSES]);
+
+shell_command!(
+    "i2c",
+    "i2c [<bus> scan | clock <hz> | read <addr> <len> | write <addr> <byte>.. | \
+     writeread <addr> <len> <byte>..]: Talk to I2C devices",
+    i2c_command
+);
+
+impl Address {
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Address::SevenBit(address) => address <= 0x7F,
+            Address::TenBit(address) => address <= 0x3FF,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::SevenBit(address) => write!(f, "{:#04x}", address),
+            Address::TenBit(address) => write!(f, "{:#05x} (10 bit)", address),
+        }
+    }
+}
+
+// Make `bus` known under `name`.
+pub fn register_i2c_bus(
+    name: &'static str,
+    bus: &'static (dyn I2cBus + Sync),
+) -> Result<(), &'static str> {
+    let mut buses = I2C_BUSES.lock();
+
+    if buses.iter().flatten().any(|b| b.name == name) {
+        return Err("I2C bus name already taken");
+    }
+
+    match buses.iter_mut().find(|b| b.is_none()) {
+        Some(slot) => {
+            *slot = Some(RegisteredBus { name, bus });
+            Ok(())
+        }
+        None => Err("Too many I2C buses"),
+    }
+}
+
+// Look up an I2C bus by name.
+pub fn i2c_bus(name: &str) -> Option<&'static (dyn I2cBus + Sync)> {
+    I2C_BUSES
+        .lock()
+        .iter()
+        .flatten()
+        .find(|b| b.name == name)
+        .map(|b| b.bus)
+}
+
+// Addresses above 0x7F are taken as 10 bit ones.
+fn parse_address(arg: Option<&&str>) -> Result<Address, &'static str> {
+    let address = parse_number(arg.ok_or("Missing address")?)?;
+
+    let address = match u8::try_from(address) {
+        Ok(address) if address <= 0x7F => Address::SevenBit(address),
+        _ => Address::TenBit(u16::try_from(address).map_err(|_| "Invalid address")?),
+    };
+    if !address.is_valid() {
+        return Err("Invalid address");
+    }
+
+    Ok(address)
+}
+
+fn parse_len(arg: Option<&&str>) -> Result<usize, &'static str> {
+    match parse_number(arg.ok_or("Missing length")?)? as usize {
+        len @ 1..=MAX_SHELL_TRANSFER => Ok(len),
+        _ => Err("Invalid length"),
+    }
+}
+
+// Parse the bytes to write into `buf`, and return them.
+fn parse_bytes<'a>(args: &[&str], buf: &'a mut [u8]) -> Result<&'a [u8], &'static str> {
+    if args.is_empty() {
+        return Err("Missing bytes");
+    }
+
+    for (arg, byte) in args.iter().zip(buf.iter_mut()) {
+        *byte = u8::try_from(parse_number(arg)?).map_err(|_| "Byte out of range")?;
+    }
+
+    Ok(&buf[..args.len()])
+}
+
+fn print_bytes(bytes: &[u8]) {
+    for line in bytes.chunks(16) {
+        kprint!(" ");
+        for byte in line {
+            kprint!(" {:02x}", byte);
+        }
+        kprintln!();
+    }
+}
+
+// Print the 7 bit addresses that acknowledge a read, leaving out the reserved ones.
+fn scan(bus: &dyn I2cBus) -> Result<(), &'static str> {
+    let mut byte = [0];
+
+    kprintln!("       0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f");
+    for row in (0..0x80).step_by(16) {
+        kprint!("  {:02x}:", row);
+
+        for address in row..row + 16 {
+            if !(0x08..=0x77).contains(&address) {
+                kprint!("   ");
+                continue;
+            }
+
+            match bus.read(Address::SevenBit(address), &mut byte) {
+                Ok(()) => kprint!(" {:02x}", address),
+                Err(NO_ACK) => kprint!(" --"),
+                Err(msg) => {
+                    kprintln!();
+                    return Err(msg);
+                }
+            }
+        }
+        kprintln!();
+    }
+
+    Ok(())
+}
+
+fn i2c_command(args: &[&str]) -> Result<(), &'static str> {
+    let name = match args.get(1) {
+        Some(name) => name,
+        None => {
+            for b in I2C_BUSES.lock().iter().flatten() {
+                kprintln!("  {:<6} {} Hz", b.name, b.bus.clock_hz());
+            }
+            return Ok(());
+        }
+    };
+    let bus = i2c_bus(name).ok_or("No such I2C bus")?;
+    let mut buf = [0; MAX_SHELL_TRANSFER];
+    let mut data = [0; MAX_SHELL_TRANSFER];
+
+    match args.get(2).copied() {
+        Some("scan") => scan(bus)?,
+        Some("clock") => {
+            let hz = parse_number(args.get(3).ok_or("Missing frequency")?)?;
+            let hz = bus.set_clock_hz(u32::try_from(hz).map_err(|_| "Invalid frequency")?)?;
+
+            kprintln!("  {} Hz", hz);
+        }
+        Some("read") => {
+            let address = parse_address(args.get(3))?;
+            let buf = &mut buf[..parse_len(args.get(4))?];
+
+            bus.read(address, buf)?;
+            print_bytes(buf);
+        }
+        Some("write") => {
+            let address = parse_address(args.get(3))?;
+
+            bus.write(address, parse_bytes(&args[4.min(args.len())..], &mut data)?)?;
+        }
+        Some("writeread") => {
+            let address = parse_address(args.get(3))?;
+            let buf = &mut buf[..parse_len(args.get(4))?];
+
+            bus.write_read(
+                address,
+                parse_bytes(&args[5.min(args.len())..], &mut data)?,
+                buf,
+            )?;
+            print_bytes(buf);
+        }
+        _ => return Err("Invalid arguments"),
+    }
+
+    Ok(())
+}
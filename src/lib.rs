@@ -13,15 +13,25 @@
 #![reexport_test_harness_main = "test_main"]
 #![test_runner(crate::test_runner)]
 
+pub mod block;
+pub mod bootargs;
 pub mod bsp;
 pub mod console;
 pub mod cpu;
 pub mod driver;
 pub mod exception;
+pub mod fdt;
+pub mod fs;
+pub mod graphics;
+pub mod i2c;
 pub mod memory;
 pub mod panic_wait;
+pub mod power;
 pub mod print;
+pub mod shell;
+pub mod spi;
 pub mod time;
+pub mod watchdog;
 
 #[macro_use]
 extern crate tock_registers;
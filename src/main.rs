@@ -2,8 +2,31 @@
 #![no_main]
 #![no_std]
 
+use core::time::Duration;
+
 use libkernel::*;
 
+boot_option_enum! {
+    // The self-tests `kernel_main()` can run.
+    enum SelfTests {
+        Disabled => "none",
+        Timer => "timer",
+        // Ends in a panic, the shell is never reached
+        Faults => "faults",
+        All => "all",
+    }
+}
+
+boot_option! {
+    // Which self-tests to run at boot.
+    static SELFTESTS: SelfTests = ("selftests", SelfTests::Timer);
+}
+
+boot_option! {
+    // How long the timer self-test spins.
+    static SELFTEST_SPIN: Duration = ("selftest.spin", Duration::from_secs(1));
+}
+
 #[no_mangle]
 unsafe fn kernel_init() -> ! {
     use driver::DriverManager;
@@ -15,23 +38,40 @@ unsafe fn kernel_init() -> ! {
         panic!("MMU: {}", string);
     }
 
+    bootargs::init();
+    print::init();
+
     for i in bsp::driver::driver_manager().all_device_drivers().iter() {
         if let Err(x) = i.init() {
             panic!("Error loading driver: {}: {}", i.compatible(), x);
         }
     }
     bsp::driver::driver_manager().post_device_driver_init();
-    // kprintln! is usable from here on
+    // Output goes to the registered consoles from here on
+    print::console_ready();
+
+    // Let device drivers register and enable their handlers with the interrupt controller
+    for i in bsp::driver::driver_manager().all_device_drivers() {
+        if let Err(msg) = i.register_and_enable_irq_handler() {
+            panic!("Error registering IRQ handler: {}: {}", i.compatible(), msg);
+        }
+    }
+
+    if let Err(msg) = fs::init() {
+        panic!("Error setting up file systems: {}", msg);
+    }
+
+    // Unmask interrupts on the boot core
+    exception::asynchronous::local_irq_unmask();
 
     // Transition from unsafe to safe
     kernel_main()
 }
 
 fn kernel_main() -> ! {
-    use bsp::console::console;
-    use console::Console;
-    use core::time::Duration;
+    use console::Read;
     use driver::DriverManager;
+    use power::PowerManager;
     use time::TimeManager;
 
     kinfo!(
@@ -40,6 +80,9 @@ fn kernel_main() -> ! {
         env!("CARGO_PKG_VERSION")
     );
     kinfo!("Booting on: {}", bsp::board_name());
+    kinfo!("Last reset: {}", power::power_manager().reset_reason());
+
+    bootargs::print_summary();
 
     kinfo!("MMU online. Special regions:");
     bsp::memory::mmu::virt_mem_layout().print_layout();
@@ -64,9 +107,32 @@ fn kernel_main() -> ! {
         kinfo!("      {}. {}", i + 1, driver.compatible());
     }
 
-    kinfo!("Timer test, spinning for 1 second");
-    time::time_manager().spin_for(Duration::from_secs(1));
+    kinfo!("Block devices:");
+    block::print_block_devices();
+
+    kinfo!("Mounted file systems:");
+    fs::print_mounts();
+
+    kinfo!("Registered IRQ handlers:");
+    exception::asynchronous::irq_manager().print_handlers();
+
+    let selftests = SELFTESTS.get();
+
+    if matches!(selftests, SelfTests::Timer | SelfTests::All) {
+        kinfo!("Timer test, spinning for {:?}", SELFTEST_SPIN.get());
+        time::time_manager().spin_for(SELFTEST_SPIN.get());
+    }
+
+    if matches!(selftests, SelfTests::Faults | SelfTests::All) {
+        fault_selftest();
+    }
+
+    console::console_manager().clear_rx().unwrap();
+
+    shell::run()
+}
 
+fn fault_selftest() {
     // Cause an exception by accessing a virtual address for which no translation was set up. This
     // code accesses the address 8 GiB, which is outside the mapped address space.
     //
@@ -87,12 +153,4 @@ fn kernel_main() -> ! {
     kinfo!("Trying to read from address 9 GiB...");
     big_addr = 9 * 1024 * 1024 * 1024;
     unsafe { core::ptr::read_volatile(big_addr as *mut u64) };
-
-    kinfo!("Echoing input now");
-    console().clear_rx().unwrap();
-
-    loop {
-        let c = console().read_char().unwrap();
-        console().write_char(c).unwrap();
-    }
 }
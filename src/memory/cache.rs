@@ -0,0 +1,20 @@
+// Cache maintenance.
+//
+// RAM is mapped cacheable, but devices that read or write it directly only see what has reached
+// memory. Buffers shared with them are cleaned before a device reads them, and invalidated before
+// the CPU reads what a device wrote. Before the MMU is on, the caches are off and the maintenance
+// does nothing harmful.
+//
+// Code written to memory, as when loading a program, only runs once `sync_icache_range` got it to
+// the instruction side. The whole-cache operations work by set and way, on the caches of the
+// calling core: they are for bringing the caches up or down, not for sharing data with devices.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/memory/cache.rs"]
+mod arch_cache;
+
+pub use arch_cache::{
+    clean_dcache_all, clean_dcache_range, clean_invalidate_dcache_all,
+    clean_invalidate_dcache_range, dcache_line_size, icache_line_size, invalidate_dcache_all,
+    invalidate_dcache_range, invalidate_icache_all, sync_icache_range,
+};
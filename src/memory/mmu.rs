@@ -4,6 +4,8 @@ mod arch_mmu;
 
 pub use arch_mmu::mmu;
 
+pub mod tlb;
+
 mod translation_table;
 
 #[derive(Debug)]
@@ -0,0 +1,11 @@
+// TLB maintenance.
+//
+// The TLBs keep translations after the tables they came from changed. After an entry is changed
+// or removed, its translations have to be invalidated before the new one is relied on. The
+// invalidations reach all cores.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../../_arch/aarch64/memory/mmu/tlb.rs"]
+mod arch_tlb;
+
+pub use arch_tlb::{invalidate_all, invalidate_asid, invalidate_va};
@@ -1,6 +1,15 @@
-use crate::{bsp, cpu};
+use crate::{
+    bsp, cpu, exception,
+    power::{self, PowerManager},
+    print,
+    time::{self, TimeManager},
+    watchdog,
+};
 use core::{fmt, panic::PanicInfo};
 
+// How many of the last log messages to show.
+const LOG_TAIL: usize = 16;
+
 fn _panic_print(args: fmt::Arguments) {
     use fmt::Write;
     unsafe { bsp::console::panic_console_out().write_fmt(args).unwrap() };
@@ -18,10 +27,26 @@ macro_rules! panic_println {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Nothing is serviced anymore from here on
+    unsafe { exception::asynchronous::local_irq_mask() };
+    let reboot_delay = unsafe { watchdog::arm_for_panic() };
+
     if let Some(args) = info.message() {
         panic_println!("\nKernel panic: {}", args);
     } else {
         panic_println!("\nKernel panic!");
     }
+
+    panic_println!("Last log messages:");
+    print::dmesg::for_each_record(LOG_TAIL, |record| panic_println!("  {}", record));
+
+    if let Some(delay) = reboot_delay {
+        panic_println!("Rebooting in {:?}", delay);
+
+        // The watchdog may get there first
+        time::time_manager().spin_for(delay);
+        power::power_manager().reset()
+    }
+
     cpu::wait_forever()
 }
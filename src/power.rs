@@ -0,0 +1,47 @@
+// Board power control.
+//
+// The BSP's `power_manager()` resets, powers off or halts the board, and tells why it came up.
+
+use core::fmt;
+
+pub use crate::bsp::power::power_manager;
+
+mod interface {
+    use super::ResetReason;
+
+    pub trait PowerManager {
+        fn reset(&self) -> !;
+
+        // Power off, or come as close to it as the board can. Where that fails, halt.
+        fn poweroff(&self) -> !;
+
+        // Stop for good with the power on, no watchdog interfering.
+        fn halt(&self) -> !;
+
+        // Why the board last reset, as found at boot.
+        fn reset_reason(&self) -> ResetReason;
+    }
+}
+
+pub use interface::*;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ResetReason {
+    PowerOn,
+    Watchdog,
+    Software,
+    Unknown,
+}
+
+impl fmt::Display for ResetReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            ResetReason::PowerOn => "power-on",
+            ResetReason::Watchdog => "watchdog",
+            ResetReason::Software => "software",
+            ResetReason::Unknown => "unknown",
+        };
+
+        write!(f, "{}", reason)
+    }
+}
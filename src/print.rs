@@ -1,11 +1,386 @@
-use crate::{bsp, console};
-use core::fmt;
+// Console printing and leveled logging.
+//
+// `kerror!` to `ktrace!` print a message with a timestamp, a level marker and the module it comes
+// from. Messages above the level picked with one of the `max_level_*` cargo features are compiled
+// out. The rest are filtered at runtime with a filter spec like "info,fs=debug,block::sdhci=trace",
+// set with the `log` boot option or the `log` shell command. A rule for a module also covers its
+// submodules, and the longest matching rule wins.
+//
+// Log messages are also kept in the `dmesg` ring buffer. Until `console_ready()` is called, all
+// output goes to the BSP's early console, which works from the first instruction on. Until
+// `init()`, i.e. while the MMU is still off, logging also avoids the atomic read-modify-write
+// instructions locks are built from.
+//
+// With the `log` cargo feature, records from crates using the `log` facade go through the same
+// filter and output.
+
+pub mod dmesg;
+
+use crate::{
+    boot_option, bootargs, bsp, console, exception::asynchronous::exec_with_irq_masked, kprintln,
+    shell_command,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+use spin::Mutex;
+
+const MAX_FILTER_RULES: usize = 8;
+const MODULE_NAME_MAX: usize = 48;
+
+// Crate prefix that is left out of module tags.
+const CRATE_PREFIX: &str = "libkernel::";
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    // Only used in filters, to silence everything.
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+// Messages above this level are compiled out.
+pub const STATIC_MAX_LEVEL: Level = if cfg!(feature = "max_level_off") {
+    Level::Off
+} else if cfg!(feature = "max_level_error") {
+    Level::Error
+} else if cfg!(feature = "max_level_warn") {
+    Level::Warn
+} else if cfg!(feature = "max_level_info") {
+    Level::Info
+} else if cfg!(feature = "max_level_debug") {
+    Level::Debug
+} else {
+    Level::Trace
+};
+
+#[derive(Clone, Copy)]
+struct FilterRule {
+    module: [u8; MODULE_NAME_MAX],
+    module_len: usize,
+    level: Level,
+}
+
+// A default level plus per-module rules.
+#[derive(Clone, Copy)]
+pub struct LogFilter {
+    default: Level,
+    rules: [Option<FilterRule>; MAX_FILTER_RULES],
+}
+
+boot_option! {
+    // Log filter, e.g. "debug" or "warn,fs=info".
+    static LOG: LogFilter = ("log", LogFilter::new(Level::Info));
+}
+
+shell_command!(
+    "log",
+    "log [filter]: Show or set the log filter",
+    log_command
+);
+
+// Only locked with IRQs masked, as IRQ handlers log too.
+static FILTER: Mutex<LogFilter> = Mutex::new(LogFilter::new(Level::Info));
+
+// The highest level any rule of `FILTER` lets through, checked before taking the lock.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+static CONSOLE_READY: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "log")]
+struct LogFacade;
+
+#[cfg(feature = "log")]
+static LOG_FACADE: LogFacade = LogFacade;
+
+impl Level {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => Some(Level::Off),
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    // Shown in front of the timestamp.
+    fn marker(self) -> char {
+        match self {
+            Level::Off | Level::Info => ' ',
+            Level::Error => 'E',
+            Level::Warn => 'W',
+            Level::Debug => 'D',
+            Level::Trace => 'T',
+        }
+    }
+}
+
+impl FilterRule {
+    fn module(&self) -> &str {
+        // Only copied from a `&str` in whole.
+        core::str::from_utf8(&self.module[..self.module_len]).unwrap_or("")
+    }
+
+    fn matches(&self, tag: &str) -> bool {
+        let module = self.module();
+
+        match tag.strip_prefix(module) {
+            Some(rest) => rest.is_empty() || rest.starts_with("::"),
+            None => false,
+        }
+    }
+}
+
+impl LogFilter {
+    pub const fn new(default: Level) -> Self {
+        Self {
+            default,
+            rules: [None; MAX_FILTER_RULES],
+        }
+    }
+
+    // Parse a comma separated list of a default level and `module=level` rules.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut filter = Self::new(Level::Info);
+        let mut num_rules = 0;
+
+        for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match item.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim().trim_start_matches(CRATE_PREFIX);
+                    if module.is_empty() || module.len() > MODULE_NAME_MAX {
+                        return None;
+                    }
+
+                    let mut rule = FilterRule {
+                        module: [0; MODULE_NAME_MAX],
+                        module_len: module.len(),
+                        level: Level::parse(level.trim())?,
+                    };
+                    rule.module[..module.len()].copy_from_slice(module.as_bytes());
+
+                    *filter.rules.get_mut(num_rules)? = Some(rule);
+                    num_rules += 1;
+                }
+                None => filter.default = Level::parse(item)?,
+            }
+        }
+
+        Some(filter)
+    }
+
+    // The level that applies to messages tagged `tag`.
+    fn level_for(&self, tag: &str) -> Level {
+        self.rules
+            .iter()
+            .flatten()
+            .filter(|rule| rule.matches(tag))
+            .max_by_key(|rule| rule.module_len)
+            .map_or(self.default, |rule| rule.level)
+    }
+
+    fn max_level(&self) -> Level {
+        self.rules
+            .iter()
+            .flatten()
+            .map(|rule| rule.level)
+            .fold(self.default, Level::max)
+    }
+}
+
+impl bootargs::BootOptionValue for LogFilter {
+    fn parse(s: &str) -> Option<Self> {
+        LogFilter::parse(s)
+    }
+
+    fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.default.name())?;
+
+        for rule in self.rules.iter().flatten() {
+            write!(f, ",{}={}", rule.module(), rule.level.name())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        bootargs::BootOptionValue::fmt_value(self, f)
+    }
+}
+
+#[cfg(feature = "log")]
+impl From<log::Level> for Level {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => Level::Error,
+            log::Level::Warn => Level::Warn,
+            log::Level::Info => Level::Info,
+            log::Level::Debug => Level::Debug,
+            log::Level::Trace => Level::Trace,
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+fn to_log_level_filter(level: Level) -> log::LevelFilter {
+    match level {
+        Level::Off => log::LevelFilter::Off,
+        Level::Error => log::LevelFilter::Error,
+        Level::Warn => log::LevelFilter::Warn,
+        Level::Info => log::LevelFilter::Info,
+        Level::Debug => log::LevelFilter::Debug,
+        Level::Trace => log::LevelFilter::Trace,
+    }
+}
+
+#[cfg(feature = "log")]
+impl log::Log for LogFacade {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        enabled(metadata.level().into(), metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        let level = record.level().into();
+
+        if enabled(level, record.target()) {
+            _log(level, record.target(), *record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// The module path without the crate name, as shown in messages and matched by filter rules.
+fn tag(module_path: &str) -> &str {
+    module_path
+        .strip_prefix(CRATE_PREFIX)
+        .unwrap_or(module_path)
+}
+
+// Log messages carry their level, so that consoles can filter them.
+fn write(level: Option<Level>, args: fmt::Arguments) {
+    if CONSOLE_READY.load(Ordering::Acquire) {
+        use console::Write;
+
+        let manager = console::console_manager();
+
+        // There is nowhere to report a failing console.
+        let _ = match level {
+            Some(level) => manager.write_log(level, args),
+            None => manager.write_fmt(args),
+        };
+    } else {
+        use fmt::Write;
+
+        // Safety: the console driver is not up yet, so boot is still single-threaded.
+        unsafe { bsp::console::early_console_out().write_fmt(args).unwrap() };
+    }
+}
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    use console::Write;
+    write(None, args);
+}
+
+#[doc(hidden)]
+pub fn enabled(level: Level, module_path: &str) -> bool {
+    if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    // Before `init()` the filter is the default one, without rules.
+    if !is_initialized() {
+        return true;
+    }
+
+    level <= exec_with_irq_masked(|| FILTER.lock().level_for(tag(module_path)))
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, module_path: &str, args: fmt::Arguments) {
+    use crate::time::TimeManager;
+
+    let timestamp = crate::time::time_manager().uptime();
+    let tag = tag(module_path);
+
+    dmesg::push(level, timestamp, format_args!("{}: {}", tag, args));
+
+    let prefix = dmesg::Prefix { level, timestamp };
+    write(Some(level), format_args_nl!("{} {}: {}", prefix, tag, args));
+}
 
-    bsp::console::console().write_fmt(args).unwrap();
+// The filter in effect.
+pub fn filter() -> LogFilter {
+    exec_with_irq_masked(|| *FILTER.lock())
+}
+
+pub fn set_filter(filter: LogFilter) {
+    let max_level = filter.max_level();
+
+    exec_with_irq_masked(|| *FILTER.lock() = filter);
+    MAX_LEVEL.store(max_level as u8, Ordering::Relaxed);
+
+    #[cfg(feature = "log")]
+    log::set_max_level(to_log_level_filter(max_level.min(STATIC_MAX_LEVEL)));
+}
+
+// Take the filter from the command line, and connect the `log` facade if enabled. Must be called
+// with the MMU on.
+pub fn init() {
+    set_filter(LOG.get());
+    INITIALIZED.store(true, Ordering::Release);
+
+    #[cfg(feature = "log")]
+    if log::set_logger(&LOG_FACADE).is_err() {
+        crate::kwarn!("Another logger is already registered with the log crate");
+    }
+}
+
+// Switch from the early console to the consoles registered with the console manager.
+pub fn console_ready() {
+    CONSOLE_READY.store(true, Ordering::Release);
+}
+
+fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::Acquire)
+}
+
+fn log_command(args: &[&str]) -> Result<(), &'static str> {
+    if let Some(spec) = args.get(1) {
+        set_filter(LogFilter::parse(spec).ok_or("Invalid filter")?);
+    }
+
+    kprintln!("  {}", filter());
+    if STATIC_MAX_LEVEL < Level::Trace {
+        kprintln!(
+            "  Messages above {} are compiled out",
+            STATIC_MAX_LEVEL.name()
+        );
+    }
+
+    Ok(())
 }
 
 #[macro_export]
@@ -15,76 +390,52 @@ macro_rules! kprint {
 
 #[macro_export]
 macro_rules! kprintln {
-    () => ($crate::print!("\n"));
+    () => ($crate::kprint!("\n"));
     ($($arg:tt)*) => ({
         $crate::print::_print(format_args_nl!($($arg)*));
     })
 }
 
-/// Prints an info, with a newline.
+/// Logs a message at the given level, with a newline.
 #[macro_export]
-macro_rules! kinfo {
-    ($string:expr) => ({
-        #[allow(unused_imports)]
-        use crate::time::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        #[allow(unused_imports)]
-        use crate::time::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000,
-            $($arg)*
-        ));
+macro_rules! klog {
+    ($level:expr, $($arg:tt)+) => ({
+        let level: $crate::print::Level = $level;
+
+        if level <= $crate::print::STATIC_MAX_LEVEL
+            && $crate::print::enabled(level, module_path!())
+        {
+            $crate::print::_log(level, module_path!(), format_args!($($arg)+));
+        }
     })
 }
 
-/// Prints a warning, with a newline.
+/// Logs an error, with a newline.
+#[macro_export]
+macro_rules! kerror {
+    ($($arg:tt)+) => ($crate::klog!($crate::print::Level::Error, $($arg)+));
+}
+
+/// Logs a warning, with a newline.
 #[macro_export]
 macro_rules! kwarn {
-    ($string:expr) => ({
-        #[allow(unused_imports)]
-        use crate::time::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:03}{:03}] ", $string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        #[allow(unused_imports)]
-        use crate::time::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:03}{:03}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000,
-            $($arg)*
-        ));
-    })
+    ($($arg:tt)+) => ($crate::klog!($crate::print::Level::Warn, $($arg)+));
+}
+
+/// Logs an info, with a newline.
+#[macro_export]
+macro_rules! kinfo {
+    ($($arg:tt)+) => ($crate::klog!($crate::print::Level::Info, $($arg)+));
+}
+
+/// Logs a debug message, with a newline.
+#[macro_export]
+macro_rules! kdebug {
+    ($($arg:tt)+) => ($crate::klog!($crate::print::Level::Debug, $($arg)+));
+}
+
+/// Logs a trace message, with a newline.
+#[macro_export]
+macro_rules! ktrace {
+    ($($arg:tt)+) => ($crate::klog!($crate::print::Level::Trace, $($arg)+));
 }
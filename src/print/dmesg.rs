@@ -0,0 +1,205 @@
+// Kernel log ring buffer.
+//
+// Every log message is kept in one of a fixed number of slots, so it can be shown again with the
+// `dmesg` shell command and dumped by the panic handler. Messages longer than a slot are cut
+// short.
+//
+// Writers only take a sequence number with an atomic increment, so logging never waits, not even
+// from an exception handler that interrupted another writer. Each slot carries the sequence number
+// of the message in it, which works like a seqlock: it is invalidated before the slot is written,
+// and readers drop a message whose number changed while they copied it out.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    sync::atomic::{fence, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use super::Level;
+use crate::{kprintln, shell, shell_command};
+
+const NUM_SLOTS: usize = 256;
+const TEXT_MAX: usize = 160;
+
+// Tag of a slot that is empty or being written.
+const INVALID: u64 = 0;
+
+#[derive(Clone, Copy)]
+pub struct Record {
+    timestamp: Duration,
+    level: Level,
+    len: usize,
+    text: [u8; TEXT_MAX],
+}
+
+struct Slot {
+    // Sequence number plus one of the message in the slot, so that an all zero ring is empty and
+    // can live in .bss.
+    tag: AtomicU64,
+    record: UnsafeCell<Record>,
+}
+
+struct Ring {
+    // Sequence number of the next message, i.e. the number of messages so far.
+    next: AtomicU64,
+    slots: [Slot; NUM_SLOTS],
+}
+
+// Formats into a record's text, dropping what does not fit.
+struct TextWriter<'a> {
+    record: &'a mut Record,
+}
+
+// The "[W   1.234567]" in front of a log line.
+pub(super) struct Prefix {
+    pub level: Level,
+    pub timestamp: Duration,
+}
+
+// Slots are only accessed through the sequence number protocol described at the top.
+unsafe impl Sync for Ring {}
+
+// Only used as the initializer of `RING.slots`, which needs a constant.
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Slot = Slot {
+    tag: AtomicU64::new(INVALID),
+    record: UnsafeCell::new(Record {
+        timestamp: Duration::ZERO,
+        level: Level::Off,
+        len: 0,
+        text: [0; TEXT_MAX],
+    }),
+};
+
+static RING: Ring = Ring {
+    next: AtomicU64::new(0),
+    slots: [EMPTY_SLOT; NUM_SLOTS],
+};
+
+shell_command!("dmesg", "dmesg [count]: Show the kernel log", dmesg);
+
+impl Record {
+    pub fn text(&self) -> &str {
+        // Only whole characters are copied in.
+        core::str::from_utf8(&self.text[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = Prefix {
+            level: self.level,
+            timestamp: self.timestamp,
+        };
+
+        write!(f, "{} {}", prefix, self.text())
+    }
+}
+
+impl fmt::Write for TextWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let record = &mut *self.record;
+        let room = TEXT_MAX - record.len;
+
+        let mut n = s.len().min(room);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+
+        record.text[record.len..record.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        record.len += n;
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}{:>4}.{:06}]",
+            self.level.marker(),
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_micros()
+        )
+    }
+}
+
+// Add a message to the ring, overwriting the oldest one if it is full.
+pub(super) fn push(level: Level, timestamp: Duration, args: fmt::Arguments) {
+    use fmt::Write;
+
+    let seq = if super::is_initialized() {
+        RING.next.fetch_add(1, Ordering::Relaxed)
+    } else {
+        // Still single-threaded and without the MMU, which exclusive loads and stores need.
+        let seq = RING.next.load(Ordering::Relaxed);
+        RING.next.store(seq + 1, Ordering::Relaxed);
+        seq
+    };
+    let slot = &RING.slots[seq as usize % NUM_SLOTS];
+
+    slot.tag.store(INVALID, Ordering::Relaxed);
+    fence(Ordering::Release);
+
+    // Safety: readers check the tag around their copy and drop it if the slot changed meanwhile.
+    let record = unsafe { &mut *slot.record.get() };
+    record.timestamp = timestamp;
+    record.level = level;
+    record.len = 0;
+    // Running out of room is not an error for `TextWriter`.
+    let _ = TextWriter { record }.write_fmt(args);
+
+    slot.tag.store(seq + 1, Ordering::Release);
+}
+
+// Copy out message `seq`, unless it was overwritten or is being written.
+fn read(seq: u64) -> Option<Record> {
+    let slot = &RING.slots[seq as usize % NUM_SLOTS];
+
+    if slot.tag.load(Ordering::Acquire) != seq + 1 {
+        return None;
+    }
+
+    // Safety: the copy may be torn by a concurrent writer, which the second check catches.
+    let record = unsafe { core::ptr::read_volatile(slot.record.get()) };
+
+    fence(Ordering::Acquire);
+    if slot.tag.load(Ordering::Relaxed) != seq + 1 {
+        return None;
+    }
+
+    Some(record)
+}
+
+// Number of messages logged since boot, including the ones that were overwritten.
+pub fn total() -> u64 {
+    RING.next.load(Ordering::Acquire)
+}
+
+// Call `f` with the last `count` messages that are still in the ring, oldest first.
+pub fn for_each_record(count: usize, mut f: impl FnMut(&Record)) {
+    let next = total();
+    let first = next.saturating_sub(count.min(NUM_SLOTS) as u64);
+
+    for record in (first..next).filter_map(read) {
+        f(&record);
+    }
+}
+
+fn dmesg(args: &[&str]) -> Result<(), &'static str> {
+    let count = match args.get(1) {
+        Some(count) => shell::parse_number(count)? as usize,
+        None => NUM_SLOTS,
+    };
+
+    let overwritten = total().saturating_sub(NUM_SLOTS as u64);
+    if overwritten > 0 && count >= NUM_SLOTS {
+        kprintln!("({} earlier messages overwritten)", overwritten);
+    }
+
+    for_each_record(count, |record| kprintln!("{}", record));
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+// Interactive kernel shell on the console.
+//
+// Commands are declared with `shell_command!` anywhere in the kernel. The linker collects them in
+// the `.shell_commands` section, so there is no central list to keep up to date.
+
+mod builtins;
+mod line_editor;
+
+use core::{cell::UnsafeCell, mem::size_of, slice};
+
+use crate::{fs, kprintln};
+
+use line_editor::LineEditor;
+
+const PROMPT: &str = "> ";
+const MAX_ARGS: usize = 16;
+
+// A shell command. `args[0]` is the command name.
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub handler: fn(args: &[&str]) -> Result<(), &'static str>,
+}
+
+// Declare a shell command.
+//
+// ```
+// shell_command!("uptime", "Time since boot", uptime);
+// ```
+#[macro_export]
+macro_rules! shell_command {
+    ($name:literal, $help:literal, $handler:path) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".shell_commands"]
+            static COMMAND: $crate::shell::Command = $crate::shell::Command {
+                name: $name,
+                help: $help,
+                handler: $handler,
+            };
+        };
+    };
+}
+
+// All commands, in link order.
+pub fn commands() -> &'static [Command] {
+    extern "Rust" {
+        static __shell_commands_start: UnsafeCell<()>;
+        static __shell_commands_end_exclusive: UnsafeCell<()>;
+    }
+
+    unsafe {
+        let start = __shell_commands_start.get() as usize;
+        let end = __shell_commands_end_exclusive.get() as usize;
+        let len = (end - start) / size_of::<Command>();
+
+        slice::from_raw_parts(start as *const Command, len)
+    }
+}
+
+// Parse a number given in decimal or, with a "0x" prefix, in hex.
+pub fn parse_number(s: &str) -> Result<u64, &'static str> {
+    let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+
+    result.map_err(|_| "Invalid number")
+}
+
+fn execute(line: &str) {
+    let mut args = [""; MAX_ARGS];
+    let mut num_args = 0;
+
+    for arg in line.split_whitespace() {
+        if num_args == MAX_ARGS {
+            kprintln!("Too many arguments");
+            return;
+        }
+        args[num_args] = arg;
+        num_args += 1;
+    }
+
+    if num_args == 0 {
+        return;
+    }
+
+    match commands().iter().find(|c| c.name == args[0]) {
+        Some(command) => {
+            if let Err(msg) = (command.handler)(&args[..num_args]) {
+                kprintln!("{}: {}", args[0], msg);
+            }
+        }
+        None => kprintln!("{}: command not found, try \"help\"", args[0]),
+    }
+}
+
+// Read and run commands forever. Input and echo go through file descriptors 0 and 1.
+pub fn run() -> ! {
+    let mut editor = LineEditor::new();
+
+    kprintln!("Kernel shell, type \"help\" for a list of commands");
+
+    loop {
+        let line = editor.read_line(PROMPT, &mut fs::current_fd_table());
+        execute(line);
+    }
+}
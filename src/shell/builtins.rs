@@ -0,0 +1,150 @@
+// Commands that come with the shell.
+
+use core::time::Duration;
+
+use super::parse_number;
+use crate::{
+    bsp, console, driver, exception, kprintln,
+    power::{self, PowerManager},
+    shell_command, time,
+};
+
+shell_command!("help", "List the commands", help);
+shell_command!("uptime", "Time since boot", uptime);
+shell_command!("drivers", "List the loaded device drivers", drivers);
+shell_command!("layout", "Show the kernel's virtual memory layout", layout);
+shell_command!("irqs", "List the registered IRQ handlers", irqs);
+shell_command!("stats", "Console statistics", stats);
+shell_command!("reboot", "Reset the board", reboot);
+shell_command!("poweroff", "Power the board off", poweroff);
+shell_command!("halt", "Stop the kernel, leaving the board on", halt);
+shell_command!("lastreset", "Show why the board last reset", last_reset);
+shell_command!("peek", "peek <addr> [count]: Read 32 bit words", peek);
+shell_command!("poke", "poke <addr> <value>: Write a 32 bit word", poke);
+
+fn help(_args: &[&str]) -> Result<(), &'static str> {
+    let width = super::commands()
+        .iter()
+        .map(|c| c.name.len())
+        .max()
+        .unwrap_or(0);
+
+    for command in super::commands() {
+        kprintln!("  {:width$}  {}", command.name, command.help, width = width);
+    }
+
+    Ok(())
+}
+
+fn uptime(_args: &[&str]) -> Result<(), &'static str> {
+    use time::TimeManager;
+
+    let uptime: Duration = time::time_manager().uptime();
+    let secs = uptime.as_secs();
+
+    kprintln!(
+        "up {}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        uptime.subsec_millis()
+    );
+
+    Ok(())
+}
+
+fn drivers(_args: &[&str]) -> Result<(), &'static str> {
+    use driver::DriverManager;
+
+    for (i, driver) in bsp::driver::driver_manager()
+        .all_device_drivers()
+        .iter()
+        .enumerate()
+    {
+        kprintln!("  {}. {}", i + 1, driver.compatible());
+    }
+
+    Ok(())
+}
+
+fn layout(_args: &[&str]) -> Result<(), &'static str> {
+    bsp::memory::mmu::virt_mem_layout().print_layout();
+
+    Ok(())
+}
+
+fn irqs(_args: &[&str]) -> Result<(), &'static str> {
+    exception::asynchronous::irq_manager().print_handlers();
+
+    Ok(())
+}
+
+fn stats(_args: &[&str]) -> Result<(), &'static str> {
+    console::console_manager().for_each_console(|name, console| {
+        kprintln!(
+            "  {:<10} chars written: {:<10} chars read: {}",
+            name,
+            console.chars_written(),
+            console.chars_read()
+        );
+    });
+
+    Ok(())
+}
+
+fn reboot(_args: &[&str]) -> Result<(), &'static str> {
+    power::power_manager().reset()
+}
+
+fn poweroff(_args: &[&str]) -> Result<(), &'static str> {
+    power::power_manager().poweroff()
+}
+
+fn halt(_args: &[&str]) -> Result<(), &'static str> {
+    power::power_manager().halt()
+}
+
+fn last_reset(_args: &[&str]) -> Result<(), &'static str> {
+    kprintln!("  {}", power::power_manager().reset_reason());
+
+    Ok(())
+}
+
+// Addresses are used as they are, an unmapped one ends in a synchronous exception.
+fn word_address(arg: Option<&&str>) -> Result<*mut u32, &'static str> {
+    let addr = parse_number(arg.ok_or("Missing address")?)?;
+
+    if addr % 4 != 0 {
+        return Err("Address is not 4 byte aligned");
+    }
+
+    Ok(addr as usize as *mut u32)
+}
+
+fn peek(args: &[&str]) -> Result<(), &'static str> {
+    let addr = word_address(args.get(1))?;
+    let count = match args.get(2) {
+        Some(count) => parse_number(count)?,
+        None => 1,
+    };
+
+    for i in 0..count as usize {
+        // Safety: the user asked for it
+        let value = unsafe { core::ptr::read_volatile(addr.add(i)) };
+        kprintln!("  {:#018x}: {:#010x}", addr as usize + i * 4, value);
+    }
+
+    Ok(())
+}
+
+fn poke(args: &[&str]) -> Result<(), &'static str> {
+    let addr = word_address(args.get(1))?;
+    let value: u32 = parse_number(args.get(2).ok_or("Missing value")?)?
+        .try_into()
+        .map_err(|_| "Value does not fit 32 bits")?;
+
+    // Safety: the user asked for it
+    unsafe { core::ptr::write_volatile(addr, value) };
+
+    Ok(())
+}
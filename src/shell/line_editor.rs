@@ -0,0 +1,310 @@
+// Line editing for the shell.
+//
+// Understands the VT100 sequences common terminals send for the arrow, Home, End and Delete keys,
+// and a few Emacs-style control keys. Only ASCII is accepted, so that every byte is one column.
+
+use core::cmp::Ordering;
+
+use crate::fs::FdTable;
+
+const LINE_MAX: usize = 128;
+const HISTORY_LEN: usize = 16;
+
+const STDIN: usize = 0;
+const STDOUT: usize = 1;
+
+const CTRL_A: u8 = 0x01;
+const CTRL_C: u8 = 0x03;
+const CTRL_E: u8 = 0x05;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1B;
+const DELETE: u8 = 0x7F;
+
+#[derive(Clone, Copy)]
+struct Line {
+    buf: [u8; LINE_MAX],
+    len: usize,
+}
+
+pub struct LineEditor {
+    line: Line,
+    cursor: usize,
+    // Ring buffer of previous lines, `history_next` is where the next one goes.
+    history: [Line; HISTORY_LEN],
+    history_len: usize,
+    history_next: usize,
+}
+
+// Keys after decoding escape sequences.
+enum Key {
+    Char(u8),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Cancel,
+    Kill,
+    Ignored,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            buf: [0; LINE_MAX],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only ASCII gets in.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+fn read_byte(fds: &mut FdTable) -> u8 {
+    let mut buf = [0u8; 4];
+
+    loop {
+        // Characters outside ASCII are dropped.
+        if let Ok(1) = fds.read(STDIN, &mut buf) {
+            return buf[0];
+        }
+    }
+}
+
+fn write(fds: &mut FdTable, bytes: &[u8]) {
+    // There is nowhere to report a failing console.
+    let _ = fds.write(STDOUT, bytes);
+}
+
+// The rest of a control sequence after `ESC [`: parameters, then intermediate bytes, then the
+// final byte. The whole sequence is read even when it means nothing here, so that none of it ends
+// up in the line. Modifiers, as in `ESC [ 1 ; 5 C` for Ctrl-Right, are ignored.
+fn read_csi(fds: &mut FdTable) -> Key {
+    // The first parameter, which tells the keys ending in `~` apart
+    let mut first: Option<u32> = None;
+    let mut in_first = true;
+
+    let last = loop {
+        match read_byte(fds) {
+            c @ b'0'..=b'9' if in_first => {
+                let digit = u32::from(c - b'0');
+                first = Some(first.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+            }
+            0x20..=0x3F => in_first = false,
+            c @ 0x40..=0x7E => break c,
+            // Not a control sequence after all
+            _ => return Key::Ignored,
+        }
+    };
+
+    match (last, first) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) => Key::Home,
+        (b'F', _) => Key::End,
+        (b'~', Some(1)) | (b'~', Some(7)) => Key::Home,
+        (b'~', Some(3)) => Key::Delete,
+        (b'~', Some(4)) | (b'~', Some(8)) => Key::End,
+        _ => Key::Ignored,
+    }
+}
+
+fn read_key(fds: &mut FdTable) -> Key {
+    match read_byte(fds) {
+        b'\r' | b'\n' => Key::Enter,
+        BACKSPACE | DELETE => Key::Backspace,
+        CTRL_A => Key::Home,
+        CTRL_E => Key::End,
+        CTRL_C => Key::Cancel,
+        CTRL_U => Key::Kill,
+        ESC => match read_byte(fds) {
+            b'[' => read_csi(fds),
+            // SS3, a single final byte without parameters
+            b'O' => match read_byte(fds) {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Right,
+                b'D' => Key::Left,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                _ => Key::Ignored,
+            },
+            _ => Key::Ignored,
+        },
+        c if (b' '..DELETE).contains(&c) => Key::Char(c),
+        _ => Key::Ignored,
+    }
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        Self {
+            line: Line::new(),
+            cursor: 0,
+            history: [Line::new(); HISTORY_LEN],
+            history_len: 0,
+            history_next: 0,
+        }
+    }
+
+    // Redraw the line from `from` on, and put the terminal cursor back where it belongs.
+    fn redraw(&self, fds: &mut FdTable, from: usize) {
+        write(fds, &self.line.buf[from..self.line.len]);
+        // Clear to the end of the terminal line
+        write(fds, b"\x1b[K");
+
+        let back = self.line.len - self.cursor;
+        if back > 0 {
+            let mut seq = [0u8; 8];
+            let len = format_cursor_left(back, &mut seq);
+            write(fds, &seq[..len]);
+        }
+    }
+
+    fn move_cursor(&mut self, fds: &mut FdTable, to: usize) {
+        match to.cmp(&self.cursor) {
+            Ordering::Less => {
+                let mut seq = [0u8; 8];
+                let len = format_cursor_left(self.cursor - to, &mut seq);
+                write(fds, &seq[..len]);
+            }
+            // Writing the characters again moves the cursor right.
+            Ordering::Greater => write(fds, &self.line.buf[self.cursor..to]),
+            Ordering::Equal => (),
+        }
+
+        self.cursor = to;
+    }
+
+    fn replace_line(&mut self, fds: &mut FdTable, line: Line) {
+        self.move_cursor(fds, 0);
+        self.line = line;
+        self.cursor = line.len;
+        write(fds, &self.line.buf[..self.line.len]);
+        write(fds, b"\x1b[K");
+    }
+
+    // The `back`-th most recent history entry, starting at 1.
+    fn history_entry(&self, back: usize) -> Line {
+        self.history[(self.history_next + HISTORY_LEN - back) % HISTORY_LEN]
+    }
+
+    fn add_to_history(&mut self) {
+        let is_repeat =
+            self.history_len > 0 && self.history_entry(1).as_str() == self.line.as_str();
+
+        if self.line.as_str().trim().is_empty() || is_repeat {
+            return;
+        }
+
+        self.history[self.history_next] = self.line;
+        self.history_next = (self.history_next + 1) % HISTORY_LEN;
+        self.history_len = (self.history_len + 1).min(HISTORY_LEN);
+    }
+
+    // Show `prompt` and read one line.
+    pub fn read_line(&mut self, prompt: &str, fds: &mut FdTable) -> &str {
+        self.line = Line::new();
+        self.cursor = 0;
+
+        // Position in the history while browsing it with up and down, zero is the line being
+        // edited, which is kept in `draft`.
+        let mut history_pos = 0;
+        let mut draft = Line::new();
+
+        write(fds, prompt.as_bytes());
+
+        loop {
+            match read_key(fds) {
+                Key::Char(c) => {
+                    if self.line.len == LINE_MAX {
+                        continue;
+                    }
+
+                    let cursor = self.cursor;
+                    self.line.buf.copy_within(cursor..self.line.len, cursor + 1);
+                    self.line.buf[cursor] = c;
+                    self.line.len += 1;
+                    self.cursor += 1;
+                    self.redraw(fds, cursor);
+                }
+                Key::Enter => {
+                    write(fds, b"\r\n");
+                    self.add_to_history();
+
+                    return self.line.as_str();
+                }
+                Key::Backspace if self.cursor > 0 => {
+                    let cursor = self.cursor;
+                    self.line.buf.copy_within(cursor..self.line.len, cursor - 1);
+                    self.line.len -= 1;
+                    self.move_cursor(fds, cursor - 1);
+                    self.redraw(fds, cursor - 1);
+                }
+                Key::Delete if self.cursor < self.line.len => {
+                    let cursor = self.cursor;
+                    self.line.buf.copy_within(cursor + 1..self.line.len, cursor);
+                    self.line.len -= 1;
+                    self.redraw(fds, cursor);
+                }
+                Key::Left if self.cursor > 0 => self.move_cursor(fds, self.cursor - 1),
+                Key::Right if self.cursor < self.line.len => self.move_cursor(fds, self.cursor + 1),
+                Key::Home => self.move_cursor(fds, 0),
+                Key::End => self.move_cursor(fds, self.line.len),
+                Key::Up if history_pos < self.history_len => {
+                    if history_pos == 0 {
+                        draft = self.line;
+                    }
+                    history_pos += 1;
+                    self.replace_line(fds, self.history_entry(history_pos));
+                }
+                Key::Down if history_pos > 0 => {
+                    history_pos -= 1;
+                    let line = if history_pos == 0 {
+                        draft
+                    } else {
+                        self.history_entry(history_pos)
+                    };
+                    self.replace_line(fds, line);
+                }
+                Key::Cancel => {
+                    write(fds, b"^C\r\n");
+                    self.line = Line::new();
+
+                    return self.line.as_str();
+                }
+                Key::Kill => self.replace_line(fds, Line::new()),
+                _ => (),
+            }
+        }
+    }
+}
+
+// Format "ESC [ <n> D", which moves the terminal cursor `n` columns left.
+fn format_cursor_left(n: usize, buf: &mut [u8; 8]) -> usize {
+    buf[0] = ESC;
+    buf[1] = b'[';
+
+    let mut len = 2;
+    let mut divisor = 100;
+    while divisor > 1 && n < divisor {
+        divisor /= 10;
+    }
+    while divisor > 0 {
+        buf[len] = b'0' + (n / divisor % 10) as u8;
+        len += 1;
+        divisor /= 10;
+    }
+
+    buf[len] = b'D';
+    len + 1
+}
@@ -0,0 +1,199 @@
+// SPI buses.
+//
+// Drivers for SPI controllers implement `SpiBus`. The BSP registers the buses it drives under a
+// short name, and the rest of the kernel looks them up here.
+
+use core::convert::TryFrom;
+
+use spin::Mutex;
+
+use crate::{kprint, kprintln, shell::parse_number, shell_command};
+
+mod interface {
+    use super::Config;
+
+    // The master side of an SPI bus.
+    pub trait SpiBus {
+        // Number of chip select lines, they are numbered from 0.
+        fn num_chip_selects(&self) -> usize;
+
+        // The SCLK frequency in Hz.
+        fn clock_hz(&self) -> u32;
+
+        // Set the SCLK frequency to the closest one not above `hz`, and return that.
+        fn set_clock_hz(&self, hz: u32) -> Result<u32, &'static str>;
+
+        // Select the device, and shift out `tx` while shifting in `rx`. Where one of them is
+        // shorter, zeros go out, or the bytes coming in are dropped.
+        fn transfer(&self, config: Config, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str>;
+    }
+}
+
+pub use interface::*;
+
+// Clock polarity and phase, as numbered by Motorola.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // Clock idles low, data is sampled on the rising edge
+    Mode0,
+    // Clock idles low, data is sampled on the falling edge
+    Mode1,
+    // Clock idles high, data is sampled on the falling edge
+    Mode2,
+    // Clock idles high, data is sampled on the rising edge
+    Mode3,
+}
+
+// How to talk to a device.
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub chip_select: usize,
+    pub mode: Mode,
+    // Most devices are selected by pulling their chip select line low
+    pub cs_active_high: bool,
+}
+
+const MAX_SPI_BUSES: usize = 2;
+
+// Most bytes the `spi` shell command transfers at once
+const MAX_SHELL_TRANSFER: usize = 64;
+
+#[derive(Clone, Copy)]
+struct RegisteredBus {
+    name: &'static str,
+    bus: &'static (dyn SpiBus + Sync),
+}
+
+static SPI_BUSES: Mutex<[Option<RegisteredBus>; MAX_SPI_BUSES]> = Mutex::new([None; MAX_SPI_BUSES]);
+
+shell_command!(
+    "spi",
+    "spi [<bus> clock <hz> | <bus> transfer <cs> <mode> <byte>..]: Talk to SPI devices",
+    spi_command
+);
+
+impl Mode {
+    // Whether the clock idles high.
+    pub fn cpol(&self) -> bool {
+        matches!(self, Mode::Mode2 | Mode::Mode3)
+    }
+
+    // Whether data is sampled on the clock's second edge.
+    pub fn cpha(&self) -> bool {
+        matches!(self, Mode::Mode1 | Mode::Mode3)
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "0" => Some(Mode::Mode0),
+            "1" => Some(Mode::Mode1),
+            "2" => Some(Mode::Mode2),
+            "3" => Some(Mode::Mode3),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    pub const fn new(chip_select: usize, mode: Mode) -> Self {
+        Self {
+            chip_select,
+            mode,
+            cs_active_high: false,
+        }
+    }
+}
+
+// Make `bus` known under `name`.
+pub fn register_spi_bus(
+    name: &'static str,
+    bus: &'static (dyn SpiBus + Sync),
+) -> Result<(), &'static str> {
+    let mut buses = SPI_BUSES.lock();
+
+    if buses.iter().flatten().any(|b| b.name == name) {
+        return Err("SPI bus name already taken");
+    }
+
+    match buses.iter_mut().find(|b| b.is_none()) {
+        Some(slot) => {
+            *slot = Some(RegisteredBus { name, bus });
+            Ok(())
+        }
+        None => Err("Too many SPI buses"),
+    }
+}
+
+// Look up an SPI bus by name.
+pub fn spi_bus(name: &str) -> Option<&'static (dyn SpiBus + Sync)> {
+    SPI_BUSES
+        .lock()
+        .iter()
+        .flatten()
+        .find(|b| b.name == name)
+        .map(|b| b.bus)
+}
+
+// `spi <bus> transfer <cs> <mode> <byte>..`: print the bytes read while writing.
+fn transfer_command(bus: &dyn SpiBus, args: &[&str]) -> Result<(), &'static str> {
+    let chip_select = parse_number(args.get(0).ok_or("Missing chip select")?)? as usize;
+    let mode = Mode::parse(args.get(1).ok_or("Missing mode")?).ok_or("Invalid mode")?;
+    let bytes = &args[2.min(args.len())..];
+    let len = bytes.len();
+
+    if len == 0 {
+        return Err("Missing bytes");
+    }
+    if len > MAX_SHELL_TRANSFER {
+        return Err("Transfer too long");
+    }
+
+    let mut tx = [0; MAX_SHELL_TRANSFER];
+    let mut rx = [0; MAX_SHELL_TRANSFER];
+    for (arg, byte) in bytes.iter().zip(tx.iter_mut()) {
+        *byte = u8::try_from(parse_number(arg)?).map_err(|_| "Byte out of range")?;
+    }
+
+    bus.transfer(Config::new(chip_select, mode), &tx[..len], &mut rx[..len])?;
+
+    for line in rx[..len].chunks(16) {
+        kprint!(" ");
+        for byte in line {
+            kprint!(" {:02x}", byte);
+        }
+        kprintln!();
+    }
+
+    Ok(())
+}
+
+fn spi_command(args: &[&str]) -> Result<(), &'static str> {
+    let name = match args.get(1) {
+        Some(name) => name,
+        None => {
+            for b in SPI_BUSES.lock().iter().flatten() {
+                kprintln!(
+                    "  {:<6} {} Hz, {} chip selects",
+                    b.name,
+                    b.bus.clock_hz(),
+                    b.bus.num_chip_selects()
+                );
+            }
+            return Ok(());
+        }
+    };
+    let bus = spi_bus(name).ok_or("No such SPI bus")?;
+
+    match args.get(2).copied() {
+        Some("clock") => {
+            let hz = parse_number(args.get(3).ok_or("Missing frequency")?)?;
+            let hz = bus.set_clock_hz(u32::try_from(hz).map_err(|_| "Invalid frequency")?)?;
+
+            kprintln!("  {} Hz", hz);
+        }
+        Some("transfer") => transfer_command(bus, &args[3.min(args.len())..])?,
+        _ => return Err("Invalid arguments"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+// Hardware watchdog.
+//
+// A watchdog resets the board unless it is petted before its timeout runs out. The BSP registers
+// the one it drives, and pets it from a periodic timer interrupt through `PETTER`. So the board
+// recovers when the kernel stops taking interrupts, not from everything that can go wrong.
+//
+// The `watchdog` boot option starts it at boot. The `panic` boot option reboots the board that
+// long after a panic. The watchdog is armed with that delay as the panic starts, in case
+// reporting the panic gets stuck.
+
+use core::time::Duration;
+
+use spin::Mutex;
+
+use crate::{
+    boot_option,
+    exception::asynchronous::{exec_with_irq_masked, IRQHandler},
+    kinfo, kprintln, kwarn,
+    shell::parse_number,
+    shell_command,
+};
+
+mod interface {
+    use core::time::Duration;
+
+    pub trait Watchdog {
+        // The longest timeout the hardware can count.
+        fn max_timeout(&self) -> Duration;
+
+        // Reset the board unless petted within `timeout`. Restarts a running watchdog.
+        fn start(&self, timeout: Duration) -> Result<(), &'static str>;
+
+        // Restart the countdown with the timeout of the last `start`.
+        fn pet(&self);
+
+        fn stop(&self);
+
+        fn is_running(&self) -> bool;
+
+        // Time until the reset.
+        fn time_left(&self) -> Duration;
+    }
+}
+
+pub use interface::*;
+
+// How often the BSP's timer has to call `PETTER`. Timeouts must be longer.
+pub const PET_INTERVAL: Duration = Duration::from_millis(500);
+
+boot_option! {
+    // Start the watchdog with this timeout at boot. 0 leaves it off.
+    static TIMEOUT: Duration = ("watchdog", Duration::from_secs(0));
+}
+
+boot_option! {
+    // Reboot this long after a panic. 0 waits forever.
+    static PANIC_REBOOT: Duration = ("panic", Duration::from_secs(0));
+}
+
+struct State {
+    watchdog: Option<&'static (dyn Watchdog + Sync)>,
+    // Whether `PETTER` keeps the watchdog alive
+    petting: bool,
+}
+
+// Pets the watchdog, for the BSP's periodic timer interrupt.
+pub struct Petter;
+
+pub static PETTER: Petter = Petter;
+
+static STATE: Mutex<State> = Mutex::new(State {
+    watchdog: None,
+    petting: false,
+});
+
+shell_command!(
+    "watchdog",
+    "watchdog [start <seconds> [nopet] | stop | pet]: Show or control the watchdog",
+    watchdog_command
+);
+
+fn locked<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    exec_with_irq_masked(|| f(&mut STATE.lock()))
+}
+
+fn start(
+    watchdog: &(dyn Watchdog + Sync),
+    timeout: Duration,
+    petting: bool,
+) -> Result<(), &'static str> {
+    if timeout <= PET_INTERVAL * 2 {
+        return Err("Timeout too short");
+    }
+
+    watchdog.start(timeout)?;
+    locked(|state| state.petting = petting);
+
+    Ok(())
+}
+
+// Make `watchdog` the one to pet, and start it if the `watchdog` boot option asks for it.
+pub fn register_watchdog(watchdog: &'static (dyn Watchdog + Sync)) {
+    locked(|state| state.watchdog = Some(watchdog));
+
+    if PANIC_REBOOT.get() > watchdog.max_timeout() {
+        kwarn!("Watchdog: panic reboot cut to {:?}", watchdog.max_timeout());
+    }
+
+    let timeout = TIMEOUT.get();
+    if timeout == Duration::from_secs(0) {
+        return;
+    }
+
+    match start(watchdog, timeout, true) {
+        Ok(()) => kinfo!("Watchdog started, {:?} timeout", timeout),
+        Err(msg) => kwarn!("Watchdog: {:?} timeout: {}", timeout, msg),
+    }
+}
+
+/// Stop petting and arm the watchdog for the reboot the `panic` boot option asks for. Returns the
+/// delay of that reboot, which is up to the caller if there is no watchdog. With a watchdog, the
+/// delay is cut to the longest timeout it can count.
+///
+/// # Safety
+///
+/// - Use only from the panic handler, with nothing else running anymore.
+pub unsafe fn arm_for_panic() -> Option<Duration> {
+    let mut delay = PANIC_REBOOT.get();
+
+    if STATE.is_locked() {
+        STATE.force_unlock();
+    }
+    let mut state = STATE.lock();
+
+    state.petting = false;
+    if delay == Duration::from_secs(0) {
+        return None;
+    }
+
+    if let Some(watchdog) = state.watchdog {
+        delay = delay.min(watchdog.max_timeout());
+        watchdog.start(delay).ok();
+    }
+
+    Some(delay)
+}
+
+impl IRQHandler for Petter {
+    fn handle(&self) -> Result<(), &'static str> {
+        let state = STATE.lock();
+
+        if let (Some(watchdog), true) = (state.watchdog, state.petting) {
+            watchdog.pet();
+        }
+
+        Ok(())
+    }
+}
+
+fn watchdog_command(args: &[&str]) -> Result<(), &'static str> {
+    let (watchdog, petting) = locked(|state| (state.watchdog, state.petting));
+    let watchdog = watchdog.ok_or("No watchdog")?;
+
+    match args.get(1).copied() {
+        None => {
+            if watchdog.is_running() {
+                kprintln!(
+                    "  Running, {:?} left{}",
+                    watchdog.time_left(),
+                    if petting { ", petted" } else { "" }
+                );
+            } else {
+                kprintln!("  Stopped, timeouts up to {:?}", watchdog.max_timeout());
+            }
+        }
+        Some("start") => {
+            let timeout = parse_number(args.get(2).ok_or("Missing timeout")?)?;
+            let petting = match args.get(3).copied() {
+                None => true,
+                Some("nopet") => false,
+                Some(_) => return Err("Invalid arguments"),
+            };
+
+            start(watchdog, Duration::from_secs(timeout), petting)?;
+        }
+        Some("stop") => {
+            locked(|state| state.petting = false);
+            watchdog.stop();
+        }
+        Some("pet") => watchdog.pet(),
+        Some(_) => return Err("Invalid arguments"),
+    }
+
+    Ok(())
+}